@@ -0,0 +1,211 @@
+use crate::gist::{self, GistError};
+use crate::git;
+use crate::log::{debug, info, warn};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::Client;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use tokio::sync::Mutex;
+
+// Installation tokens are renewed when they expire within this many seconds, so a request
+// never goes out with a token about to expire
+const REFRESH_MARGIN_SECS: i64 = 300;
+// Lifetime of the JWTs authenticating as the app, GitHub accepts at most 10 minutes
+const JWT_LIFETIME_SECS: i64 = 540;
+// Environment variables configuring GitHub App authentication outside of accounts
+const APP_ID_ENV: &str = "LOCAL_GIST_APP_ID";
+const APP_KEY_ENV: &str = "LOCAL_GIST_APP_PRIVATE_KEY";
+const APP_INSTALLATION_ENV: &str = "LOCAL_GIST_APP_INSTALLATION_ID";
+// Scope classic tokens need to see secret gists and to create, star or delete gists
+const GIST_SCOPE: &str = "gist";
+// What a token without access to gists still allows, and what it doesn't
+const MISSING_GIST_ACCESS: &str = "public gists are still listed and downloaded with the \
+    authenticated rate limit, but secret gists are left out, and creating, starring and \
+    deleting gists (tui, import --republish) will fail";
+
+// Credentials of the account picked with `--account`, or of the app configured in the
+// environment, set once at startup
+static CREDENTIALS: OnceLock<Credentials> = OnceLock::new();
+// Whether git's credential helpers are asked for a token when none is configured
+static USE_GIT_CREDENTIAL: AtomicBool = AtomicBool::new(true);
+// Token given by git's credential helpers, looked up once when first needed
+static GIT_CREDENTIAL: OnceLock<Option<String>> = OnceLock::new();
+
+/// How GitHub requests are authenticated, besides `GITHUB_TOKEN`.
+pub enum Credentials {
+    /// A personal access token
+    Token(String),
+    /// Installation tokens of a GitHub App, renewed as they expire
+    App(GitHubApp),
+}
+
+/// A GitHub App installation, authenticating with short-lived installation tokens minted
+/// with the private key of the app.
+pub struct GitHubApp {
+    app_id: String,
+    key: EncodingKey,
+    installation_id: u64,
+    // Current installation token and when it expires
+    token: Mutex<Option<(String, DateTime<Utc>)>>,
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iat: i64,
+    exp: i64,
+    iss: &'a str,
+}
+
+impl GitHubApp {
+    /// Loads the private key (PEM) of the app.
+    pub fn new(app_id: &str, private_key: &str, installation_id: u64) -> Result<Self, GistError> {
+        let pem = std::fs::read(private_key)?;
+        let key = EncodingKey::from_rsa_pem(&pem)
+            .map_err(|e| GistError::Auth(format!("{}: {}", private_key, e)))?;
+        Ok(GitHubApp {
+            app_id: app_id.to_string(),
+            key,
+            installation_id,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Returns the JWT authenticating as the app itself, backdated a minute against
+    /// clock drift.
+    fn jwt(&self) -> Result<String, GistError> {
+        let now = Utc::now().timestamp();
+        let claims = Claims {
+            iat: now - 60,
+            exp: now + JWT_LIFETIME_SECS,
+            iss: &self.app_id,
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, &self.key)
+            .map_err(|e| GistError::Auth(format!("app {}: {}", self.app_id, e)))
+    }
+
+    /// Returns a valid installation token, minting a new one when there is none yet or
+    /// the current one is about to expire. Concurrent callers wait for the same renewal.
+    async fn token(&self, client: &Client) -> Result<String, GistError> {
+        let mut current = self.token.lock().await;
+        let margin = chrono::Duration::seconds(REFRESH_MARGIN_SECS);
+        if let Some((token, expires_at)) = current.as_ref() {
+            if *expires_at - margin > Utc::now() {
+                return Ok(token.clone());
+            }
+        }
+        let (token, expires_at) =
+            gist::create_installation_token(client, &self.jwt()?, self.installation_id).await?;
+        info!(
+            "Authenticated as installation {} of app {}, token valid until {}",
+            self.installation_id, self.app_id, expires_at
+        );
+        *current = Some((token.clone(), expires_at));
+        Ok(token)
+    }
+}
+
+/// Makes GitHub requests use these credentials instead of `GITHUB_TOKEN`.
+pub fn use_credentials(credentials: Credentials) {
+    let _ = CREDENTIALS.set(credentials);
+}
+
+/// Returns the GitHub App configured with `LOCAL_GIST_APP_ID`, `LOCAL_GIST_APP_PRIVATE_KEY`
+/// and `LOCAL_GIST_APP_INSTALLATION_ID`, when the app id is set.
+pub fn app_from_env() -> Result<Option<GitHubApp>, GistError> {
+    let Some(app_id) = std::env::var(APP_ID_ENV).ok().filter(|id| !id.is_empty()) else {
+        return Ok(None);
+    };
+    let missing = |name: &str| GistError::Auth(format!("{} is set but {} isn't", APP_ID_ENV, name));
+    let private_key = std::env::var(APP_KEY_ENV).map_err(|_| missing(APP_KEY_ENV))?;
+    let installation_id = std::env::var(APP_INSTALLATION_ENV)
+        .map_err(|_| missing(APP_INSTALLATION_ENV))?
+        .parse()
+        .map_err(|_| GistError::Auth(format!("{} must be a number", APP_INSTALLATION_ENV)))?;
+    GitHubApp::new(&app_id, &private_key, installation_id).map(Some)
+}
+
+/// Disables asking git's credential helpers for a token, with `--no-git-credential`.
+pub fn use_git_credential(enabled: bool) {
+    USE_GIT_CREDENTIAL.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether GitHub requests are authenticated, without minting a token.
+pub fn has_token() -> bool {
+    CREDENTIALS.get().is_some() || env_token().is_some() || git_credential().is_some()
+}
+
+fn git_credential() -> Option<String> {
+    if !USE_GIT_CREDENTIAL.load(Ordering::Relaxed) {
+        return None;
+    }
+    GIT_CREDENTIAL
+        .get_or_init(|| {
+            let token = git::credential_token();
+            if token.is_some() {
+                info!("Using the GitHub token of git's credential helper");
+            }
+            token
+        })
+        .clone()
+}
+
+fn env_token() -> Option<String> {
+    std::env::var("GITHUB_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+}
+
+/// Returns the token GitHub requests are authenticated with: the token of the account
+/// picked with `--account` or an installation token of the configured GitHub App,
+/// otherwise `GITHUB_TOKEN`, otherwise the token git's credential helpers have for
+/// github.com.
+pub async fn github_token(client: &Client) -> Result<Option<String>, GistError> {
+    match CREDENTIALS.get() {
+        Some(Credentials::Token(token)) => Ok(Some(token.clone())),
+        Some(Credentials::App(app)) => app.token(client).await.map(Some),
+        None => Ok(env_token().or_else(git_credential)),
+    }
+}
+
+/// Warns when the token used for GitHub can't access gists, saying what will and won't
+/// work, rather than leaving secret gists silently missing from the archive. Problems
+/// checking are only logged, the run goes on.
+pub async fn check_token(client: &Client) {
+    // Installation tokens never see secret gists, which the README says
+    if let Some(Credentials::App(_)) = CREDENTIALS.get() {
+        return;
+    }
+    let token = match github_token(client).await {
+        Ok(Some(token)) => token,
+        Ok(None) => return,
+        Err(e) => return debug!("Couldn't get the token to check it: {}", e),
+    };
+    match gist::token_scopes(client, &token).await {
+        Ok(Some(scopes)) if scopes.iter().any(|scope| scope == GIST_SCOPE) => {
+            debug!("The token has the {} scope", GIST_SCOPE)
+        }
+        Ok(Some(scopes)) => warn!(
+            "The token lacks the {} scope (it has: {}): {}. Add the scope at \
+             https://github.com/settings/tokens",
+            GIST_SCOPE,
+            match scopes.is_empty() {
+                true => "none".to_string(),
+                false => scopes.join(", "),
+            },
+            MISSING_GIST_ACCESS
+        ),
+        // Fine-grained tokens have permissions instead of scopes, probe the gists one
+        Ok(None) => match gist::can_read_own_gists(client, &token).await {
+            Ok(true) => debug!("The token can read gists"),
+            Ok(false) => warn!(
+                "The token has no access to gists: {}. Grant it the Gists permission, or \
+                 use a classic token with the {} scope",
+                MISSING_GIST_ACCESS, GIST_SCOPE
+            ),
+            Err(e) => debug!("Couldn't check the permissions of the token: {}", e),
+        },
+        Err(e) => debug!("Couldn't check the scopes of the token: {}", e),
+    }
+}
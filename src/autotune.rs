@@ -0,0 +1,148 @@
+use crate::log::debug;
+use crate::metrics;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+// Downloads in flight when auto-tuning starts, kept low until the server proved it copes
+const AUTO_START: usize = 2;
+// Most downloads in flight auto-tuning goes up to
+const AUTO_MAX: usize = 64;
+// Share of failed downloads in a window above which concurrency is halved
+const MAX_ERROR_RATE: f64 = 0.1;
+// Requests left in the rate limit window below which concurrency is halved
+const LOW_RATE_HEADROOM: i64 = 100;
+// Throughput change between two windows below which it is taken as noise
+const NOISE: f64 = 0.1;
+// Fewest downloads a window is judged on, single downloads are too noisy
+const MIN_WINDOW: usize = 8;
+
+/// How many downloads run at the same time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Concurrency {
+    Fixed(usize),
+    /// Adjusted during the run, see [`AutoTune`]
+    Auto,
+}
+
+impl Concurrency {
+    /// Downloads in flight at the start of a run.
+    pub fn initial(self) -> usize {
+        match self {
+            Concurrency::Fixed(n) => n,
+            Concurrency::Auto => AUTO_START,
+        }
+    }
+}
+
+impl FromStr for Concurrency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Concurrency::Auto),
+            _ => match s.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Concurrency::Fixed(n)),
+                _ => Err(format!("expected a positive number or `auto`, got {}", s)),
+            },
+        }
+    }
+}
+
+struct Window {
+    limit: usize,
+    // Permits to take out of the semaphore as downloads finish, after going down
+    debt: usize,
+    completed: usize,
+    failed: usize,
+    latency: Duration,
+    started: Instant,
+    // Downloads per second of the previous window
+    previous: Option<f64>,
+}
+
+/// Adjusts the downloads in flight from what each window of downloads (as many as are in
+/// flight, at least 8) went through: halved on errors or when the rate limit runs low,
+/// otherwise up by one while that raises the throughput and down by one when it drops.
+pub struct AutoTune {
+    semaphore: Arc<Semaphore>,
+    window: Mutex<Window>,
+}
+
+impl AutoTune {
+    pub fn new() -> Self {
+        AutoTune {
+            semaphore: Arc::new(Semaphore::new(AUTO_START)),
+            window: Mutex::new(Window {
+                limit: AUTO_START,
+                debt: 0,
+                completed: 0,
+                failed: 0,
+                latency: Duration::ZERO,
+                started: Instant::now(),
+                previous: None,
+            }),
+        }
+    }
+
+    /// Semaphore the downloads take their permit from.
+    pub fn semaphore(&self) -> Arc<Semaphore> {
+        self.semaphore.clone()
+    }
+
+    /// Records a finished download, adjusting the concurrency at the end of a window.
+    pub fn record(&self, latency: Duration, ok: bool) {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        if window.debt > 0 {
+            window.debt -= self.semaphore.forget_permits(window.debt);
+        }
+        window.completed += 1;
+        window.failed += usize::from(!ok);
+        window.latency += latency;
+        if window.completed < window.limit.max(MIN_WINDOW) {
+            return;
+        }
+
+        let mean = window.latency / window.completed as u32;
+        let error_rate = window.failed as f64 / window.completed as f64;
+        let throughput = window.completed as f64 / window.started.elapsed().as_secs_f64();
+        let low_headroom = metrics::rate_remaining().is_some_and(|n| n < LOW_RATE_HEADROOM);
+        let previous = window.previous.unwrap_or(0.0);
+        let limit = if error_rate > MAX_ERROR_RATE || low_headroom {
+            (window.limit / 2).max(1)
+        } else if throughput > previous * (1.0 + NOISE) {
+            (window.limit + 1).min(AUTO_MAX)
+        } else if throughput < previous * (1.0 - NOISE) {
+            (window.limit - 1).max(1)
+        } else {
+            window.limit
+        };
+        window.previous = Some(throughput);
+
+        if limit > window.limit {
+            let raise = limit - window.limit;
+            let paid = raise.min(window.debt);
+            window.debt -= paid;
+            self.semaphore.add_permits(raise - paid);
+        } else if limit < window.limit {
+            let lower = window.limit - limit;
+            window.debt += lower - self.semaphore.forget_permits(lower);
+        }
+        if limit != window.limit {
+            debug!(
+                "Concurrency {} -> {} ({:.1} gists/s, mean latency {:?}, {:.0}% errors)",
+                window.limit,
+                limit,
+                throughput,
+                mean,
+                error_rate * 100.0
+            );
+        }
+        window.limit = limit;
+        window.completed = 0;
+        window.failed = 0;
+        window.latency = Duration::ZERO;
+        window.started = Instant::now();
+    }
+}
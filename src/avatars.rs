@@ -0,0 +1,106 @@
+use crate::gist::{self, GistError};
+use crate::log::{debug, warn};
+use crate::paths::long_path;
+use crate::store::Folder;
+use reqwest::Client;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Directory inside the archive caching the avatars of owners and commenters, shared by
+// every gist
+pub const AVATARS_DIR: &str = ".avatars";
+/// Size in pixels of the avatars shown next to names, twice their displayed size
+pub const SMALL: u32 = 40;
+/// Size in pixels of the avatar shown on the page of a gist
+pub const LARGE: u32 = 120;
+// Image types GitHub serves avatars as, and the extension they are saved with
+const IMAGE_TYPES: [(&str, &str); 4] = [
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+];
+
+// Logins whose avatars were already looked at during this run, so the gists of an owner
+// don't each check them again
+static SEEN: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+// Logins are letters, digits and hyphens, anything else can't be a file name of the cache
+fn valid_login(login: &str) -> bool {
+    !login.is_empty() && login.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// Returns the file name of the cached avatar of `login` at `size` pixels, `None` when it
+/// wasn't downloaded.
+pub fn cached(folder: &Path, login: &str, size: u32) -> Option<String> {
+    if !valid_login(login) {
+        return None;
+    }
+    IMAGE_TYPES
+        .iter()
+        .map(|(_, extension)| format!("{}-{}.{}", login, size, extension))
+        .find(|name| long_path(folder.join(AVATARS_DIR).join(name)).is_file())
+}
+
+/// Returns the path and content type of a cached avatar from its file name, `None` when no
+/// avatar can be saved under that name.
+pub fn lookup(folder: &Path, name: &str) -> Option<(PathBuf, &'static str)> {
+    let (stem, extension) = name.rsplit_once('.')?;
+    let (login, size) = stem.rsplit_once('-')?;
+    if !valid_login(login) || size.parse::<u32>().is_err() {
+        return None;
+    }
+    let (content_type, _) = IMAGE_TYPES.iter().find(|(_, e)| *e == extension)?;
+    Some((long_path(folder.join(AVATARS_DIR).join(name)), content_type))
+}
+
+async fn save(
+    client: &Client,
+    archive: &Folder<'_>,
+    login: &str,
+    avatar_url: &str,
+    size: u32,
+) -> Result<(), GistError> {
+    let (content, content_type) = gist::fetch_avatar(client, avatar_url, size).await?;
+    let extension = IMAGE_TYPES
+        .iter()
+        .find(|(image_type, _)| {
+            content_type
+                .as_deref()
+                .is_some_and(|t| t.starts_with(image_type))
+        })
+        .map_or("png", |(_, extension)| extension);
+    let dir = long_path(archive.root.join(AVATARS_DIR));
+    let path = dir.join(format!("{}-{}.{}", login, size, extension));
+    archive.check_inside(&path)?;
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(path, content)?;
+    Ok(())
+}
+
+/// Downloads the avatar of `login` in every size into the archive, unless it's cached
+/// already and `force` isn't set. Failures are only logged, pages then go without it.
+pub async fn download(
+    client: &Client,
+    archive: &Folder<'_>,
+    login: &str,
+    avatar_url: &str,
+    force: bool,
+) {
+    if !valid_login(login) {
+        return debug!("Not caching the avatar of {:?}, not a GitHub login", login);
+    }
+    if !SEEN.lock().unwrap().insert(login.to_string()) {
+        return;
+    }
+    for size in [SMALL, LARGE] {
+        if !force && cached(archive.root, login, size).is_some() {
+            continue;
+        }
+        match save(client, archive, login, avatar_url, size).await {
+            Ok(()) => debug!("Cached the {}px avatar of {}", size, login),
+            Err(e) => warn!("Could not download the avatar of {}: {}", login, e),
+        }
+    }
+}
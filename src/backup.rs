@@ -0,0 +1,223 @@
+use crate::crypt;
+use crate::gist::GistError;
+use crate::log::{info, warn};
+use crate::manifest::Manifest;
+use crate::store::{self, sha256_hex};
+use age::x25519::Recipient;
+use chrono::{DateTime, Datelike, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+// Default directory inside the archive holding the snapshots
+const SNAPSHOTS_DIR: &str = ".snapshots";
+// Directory inside the snapshots directory holding file contents by SHA-256
+const OBJECTS_DIR: &str = "objects";
+
+/// The state of an archive at a point in time. File contents live in the shared object
+/// directory, so a snapshot only adds the files that changed since the previous ones.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub created_at: DateTime<Utc>,
+    pub manifest: Manifest,
+    /// SHA-256 of every file, keyed by `<gist id>/<filename>`
+    pub files: BTreeMap<String, String>,
+}
+
+/// How many snapshots to keep when pruning. The newest snapshot is always kept.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Retention {
+    pub keep_daily: Option<usize>,
+    pub keep_weekly: Option<usize>,
+}
+
+impl Retention {
+    fn is_set(&self) -> bool {
+        self.keep_daily.is_some() || self.keep_weekly.is_some()
+    }
+}
+
+/// Returns the snapshots directory, `.snapshots` inside the archive unless given.
+pub fn snapshots_dir(folder: &Path, snapshots: Option<&str>) -> PathBuf {
+    snapshots.map_or_else(|| folder.join(SNAPSHOTS_DIR), PathBuf::from)
+}
+
+fn object_path(snapshots: &Path, digest: &str) -> PathBuf {
+    snapshots.join(OBJECTS_DIR).join(&digest[..2]).join(digest)
+}
+
+fn snapshot_name(created_at: &DateTime<Utc>) -> String {
+    format!("{}.json", created_at.format("%Y%m%dT%H%M%SZ"))
+}
+
+/// Loads all snapshots, oldest first, together with the path of their file.
+pub fn list_snapshots(snapshots: &Path) -> Result<Vec<(PathBuf, Snapshot)>, GistError> {
+    let mut result = Vec::new();
+    if !snapshots.exists() {
+        return Ok(result);
+    }
+    for dir_entry in std::fs::read_dir(snapshots)? {
+        let path = dir_entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            let text = std::fs::read_to_string(&path)?;
+            let snapshot: Snapshot = serde_json::from_str(&text).map_err(GistError::Manifest)?;
+            result.push((path, snapshot));
+        }
+    }
+    result.sort_by_key(|(_, snapshot)| snapshot.created_at);
+    Ok(result)
+}
+
+/// Reads the content of a file from the snapshot object directory, decrypting it if needed.
+pub fn read_object(snapshots: &Path, digest: &str) -> std::io::Result<Vec<u8>> {
+    let content = std::fs::read(object_path(snapshots, digest))?;
+    if crypt::is_encrypted(&content) {
+        return crypt::decrypt(&content);
+    }
+    Ok(content)
+}
+
+/// Records the current state of the archive as a new snapshot, storing only the file
+/// contents that aren't in the object directory yet, then applies the retention policy.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `snapshots` - Directory holding the snapshots
+/// * `retention` - Which older snapshots to keep
+/// * `encrypt` - Encrypt new objects to this recipient, objects keep the digest of the plaintext
+pub fn backup(
+    folder: &Path,
+    snapshots: &Path,
+    retention: Retention,
+    encrypt: Option<&Recipient>,
+) -> Result<(), GistError> {
+    let manifest = Manifest::load(folder)?;
+    let mut files = BTreeMap::new();
+    let mut added = 0;
+    std::fs::create_dir_all(snapshots)?;
+
+    for entry in manifest.gists.values() {
+        for file in &entry.files {
+            let content = match store::read_file(folder, &entry.id, &file.filename) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("Skipping {}/{}: {}", entry.id, file.filename, e);
+                    continue;
+                }
+            };
+            let digest = sha256_hex(&content);
+            let object = object_path(snapshots, &digest);
+            if !object.exists() {
+                std::fs::create_dir_all(object.parent().expect("objects have a parent"))?;
+                match encrypt {
+                    Some(recipient) => {
+                        std::fs::write(&object, crypt::encrypt(recipient, &content)?)?
+                    }
+                    None => std::fs::write(&object, &content)?,
+                }
+                added += 1;
+            }
+            files.insert(format!("{}/{}", entry.id, file.filename), digest);
+        }
+    }
+
+    let snapshot = Snapshot {
+        created_at: Utc::now(),
+        manifest,
+        files,
+    };
+    let path = snapshots.join(snapshot_name(&snapshot.created_at));
+    let text = serde_json::to_string_pretty(&snapshot).map_err(GistError::Manifest)?;
+    std::fs::write(&path, text)?;
+    info!(
+        "Created snapshot {} with {} files ({} new)",
+        path.display(),
+        snapshot.files.len(),
+        added
+    );
+
+    if retention.is_set() {
+        prune(snapshots, retention)?;
+    }
+
+    Ok(())
+}
+
+/// Marks the newest snapshot of each of the last `count` periods that have snapshots.
+fn keep_newest_per_period(
+    created: &[DateTime<Utc>],
+    count: usize,
+    period: impl Fn(&DateTime<Utc>) -> (i32, u32),
+    keep: &mut HashSet<usize>,
+) {
+    let mut seen = Vec::new();
+    for (i, created_at) in created.iter().enumerate().rev() {
+        let key = period(created_at);
+        if seen.contains(&key) {
+            continue;
+        }
+        if seen.len() == count {
+            break;
+        }
+        seen.push(key);
+        keep.insert(i);
+    }
+}
+
+/// Returns the indices of the snapshots to keep: the newest snapshot of each of the last
+/// `keep_daily` days and `keep_weekly` ISO weeks that have snapshots, plus the newest overall.
+fn snapshots_to_keep(created: &[DateTime<Utc>], retention: Retention) -> HashSet<usize> {
+    let mut keep = HashSet::new();
+    if let Some(last) = created.len().checked_sub(1) {
+        keep.insert(last);
+    }
+    if let Some(count) = retention.keep_daily {
+        keep_newest_per_period(created, count, |d| (d.year(), d.ordinal()), &mut keep);
+    }
+    if let Some(count) = retention.keep_weekly {
+        let iso_week = |d: &DateTime<Utc>| (d.iso_week().year(), d.iso_week().week());
+        keep_newest_per_period(created, count, iso_week, &mut keep);
+    }
+    keep
+}
+
+/// Deletes the snapshots not covered by the retention policy and the objects only they used.
+pub fn prune(snapshots: &Path, retention: Retention) -> Result<(), GistError> {
+    let all = list_snapshots(snapshots)?;
+    let created: Vec<DateTime<Utc>> = all.iter().map(|(_, s)| s.created_at).collect();
+    let keep = snapshots_to_keep(&created, retention);
+
+    let mut referenced: HashSet<String> = HashSet::new();
+    let mut removed = 0;
+    for (i, (path, snapshot)) in all.into_iter().enumerate() {
+        if keep.contains(&i) {
+            referenced.extend(snapshot.files.into_values());
+        } else {
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+
+    let mut removed_objects = 0;
+    let objects = snapshots.join(OBJECTS_DIR);
+    if objects.exists() {
+        for prefix in std::fs::read_dir(&objects)? {
+            for object in std::fs::read_dir(prefix?.path())? {
+                let object = object?;
+                let name = object.file_name().to_string_lossy().into_owned();
+                if !referenced.contains(&name) {
+                    std::fs::remove_file(object.path())?;
+                    removed_objects += 1;
+                }
+            }
+        }
+    }
+
+    info!(
+        "Pruned {} snapshots and {} unreferenced objects, {} snapshots kept",
+        removed,
+        removed_objects,
+        keep.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use crate::gist::GistError;
+use crate::log::{info, warn};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Returns the command opening URLs on this system, `$BROWSER` winning when set.
+fn opener() -> (String, Vec<String>) {
+    if let Ok(browser) = std::env::var("BROWSER") {
+        if !browser.is_empty() {
+            return (browser, Vec::new());
+        }
+    }
+    system_opener()
+}
+
+/// Returns the command opening files and URLs with the program the system associates to them.
+fn system_opener() -> (String, Vec<String>) {
+    if cfg!(target_os = "macos") {
+        ("open".to_string(), Vec::new())
+    } else if cfg!(windows) {
+        // The empty argument is the window title `start` expects before the URL
+        let args = ["/C", "start", ""].map(String::from).to_vec();
+        ("cmd".to_string(), args)
+    } else {
+        ("xdg-open".to_string(), Vec::new())
+    }
+}
+
+/// Opens a URL in the default browser without waiting for it to be closed.
+pub fn open_url(url: &str) -> Result<(), GistError> {
+    let (program, args) = opener();
+    info!("Opening {}", url);
+    Command::new(&program)
+        .args(args)
+        .arg(url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| GistError::NotFound(format!("browser opener {}: {}", program, e)))?;
+    Ok(())
+}
+
+/// Opens a folder in the file manager, or a file in the program the system associates to
+/// it, without waiting. `with` is a shell command run instead with the path as its last
+/// argument, in the terminal and waited for so editors like vim work too.
+pub fn open_path(path: &Path, with: Option<&str>) -> Result<(), GistError> {
+    info!("Opening {}", path.display());
+    let Some(with) = with else {
+        let (program, args) = system_opener();
+        Command::new(&program)
+            .args(args)
+            .arg(path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| GistError::NotFound(format!("opener {}: {}", program, e)))?;
+        return Ok(());
+    };
+    // The path is passed as `$1`, so it needs no quoting
+    let status = Command::new("sh")
+        .args(["-c", &format!("{} \"$1\"", with), "sh"])
+        .arg(path)
+        .status()
+        .map_err(|e| GistError::NotFound(format!("opener {}: {}", with, e)))?;
+    if !status.success() {
+        warn!("`{}` exited with {}", with, status);
+    }
+    Ok(())
+}
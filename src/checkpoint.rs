@@ -0,0 +1,98 @@
+use crate::gist::{GistError, GistPages, Gists};
+use crate::log::{debug, warn};
+use crate::paths::portable_name;
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Directory inside the archive keeping the checkpoints of interrupted listings, one file
+// per listing
+const CHECKPOINTS_DIR: &str = ".checkpoints";
+// Checkpoints older than this are ignored, the pages of a listing shift as gists are
+// created and deleted
+const MAX_AGE: TimeDelta = TimeDelta::hours(24);
+
+/// Where an interrupted listing stopped, so the next download continues from there instead
+/// of listing the earlier pages again.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// API path of the listing, e.g. `users/{user}/gists`
+    path: String,
+    /// `since` and page size of the listing, which must match for it to be continued
+    since: Option<String>,
+    per_page: u32,
+    /// Page the listing continues from
+    pub next_page: u32,
+    /// Ids of the gists on the earlier pages, for `--limit` and `--prune`
+    pub listed: Vec<String>,
+    /// Gists of the earlier pages that failed to download, tried again first
+    pub pending: Gists,
+    saved_at: DateTime<Utc>,
+}
+
+fn checkpoint_path(folder: &Path, pages: &GistPages) -> PathBuf {
+    let name = format!("{}.json", pages.path().replace('/', "-"));
+    folder
+        .join(CHECKPOINTS_DIR)
+        .join(portable_name(&name).as_ref())
+}
+
+/// Returns the checkpoint of the listing saved by an earlier download into `folder`, `None`
+/// when there is none, it was saved for a different `since` or page size, or is too old.
+pub fn load(folder: &Path, pages: &GistPages) -> Option<Checkpoint> {
+    let path = checkpoint_path(folder, pages);
+    let text = std::fs::read_to_string(&path).ok()?;
+    let checkpoint: Checkpoint = match serde_json::from_str(&text) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            warn!("Ignoring the checkpoint {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let matches = checkpoint.path == pages.path()
+        && checkpoint.since.as_deref() == pages.since_param()
+        && checkpoint.per_page == pages.per_page();
+    if !matches || Utc::now() - checkpoint.saved_at > MAX_AGE {
+        debug!("Ignoring the checkpoint {}, it is outdated", path.display());
+        return None;
+    }
+    Some(checkpoint)
+}
+
+/// Saves where the listing stopped: the page it continues from, the gists listed so far
+/// and those that failed to download. The previous checkpoint is replaced atomically.
+pub fn save(
+    folder: &Path,
+    pages: &GistPages,
+    listed: Vec<String>,
+    pending: Gists,
+) -> Result<(), GistError> {
+    let checkpoint = Checkpoint {
+        path: pages.path().to_string(),
+        since: pages.since_param().map(str::to_string),
+        per_page: pages.per_page(),
+        next_page: pages.next_page_number(),
+        listed,
+        pending,
+        saved_at: Utc::now(),
+    };
+    let path = checkpoint_path(folder, pages);
+    std::fs::create_dir_all(folder.join(CHECKPOINTS_DIR))?;
+    let tmp_path = path.with_extension("json.tmp");
+    let json = serde_json::to_string(&checkpoint).map_err(std::io::Error::other)?;
+    std::fs::write(&tmp_path, json)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Removes the checkpoint of a listing once it was listed to the end.
+pub fn remove(folder: &Path, pages: &GistPages) -> Result<(), GistError> {
+    match std::fs::remove_file(checkpoint_path(folder, pages)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => {
+            // Only removed once no other listing has a checkpoint left
+            let _ = std::fs::remove_dir(folder.join(CHECKPOINTS_DIR));
+            Ok(())
+        }
+    }
+}
@@ -1,4 +1,15 @@
-use clap::{Parser, Subcommand};
+use crate::autotune::Concurrency;
+use crate::conflict::Resolution;
+use crate::crypt::parse_recipient;
+use crate::secrets::Severity;
+use crate::snippets::Editor;
+use crate::store::{Eol, Store};
+use crate::table::{Column, GroupBy};
+use age::x25519::Recipient;
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(name = "local-gist")]
@@ -6,14 +17,272 @@ use clap::{Parser, Subcommand};
 #[command(version = "1.0")]
 #[command(about = "Downloads GitHub Gists", long_about = None)]
 pub struct Cli {
+    /// age identity file used to decrypt encrypted gists and snapshots
+    #[arg(long, global = true)]
+    pub identity: Option<String>,
+
+    /// Write through symlinks inside the archive folder, e.g. gist folders linked to
+    /// another drive, instead of refusing writes that would leave the folder
+    #[arg(long, global = true)]
+    pub follow_symlinks: bool,
+
+    /// Log download rates, runtime load and the rate limit every SECONDS (5 by default)
+    #[arg(long, global = true, value_name = "SECONDS", num_args = 0..=1, default_missing_value = "5")]
+    pub metrics: Option<u64>,
+
+    /// Use the defaults of this profile of the config file on top of `[default]`
+    #[arg(long, global = true, env = "LOCAL_GIST_PROFILE")]
+    pub profile: Option<String>,
+
+    /// Authenticate with the token of this account of the config file, and use its
+    /// defaults on top of those of the profile
+    #[arg(long, global = true, env = "LOCAL_GIST_ACCOUNT")]
+    pub account: Option<String>,
+
+    /// Don't ask git's credential helpers for a GitHub token when no other is configured
+    #[arg(long, global = true)]
+    pub no_git_credential: bool,
+
+    /// Fail when the GitHub rate limit is exhausted instead of waiting for it to reset
+    #[arg(long, global = true)]
+    pub no_wait: bool,
+
+    /// Send at most N requests to GitHub (API and file contents), then stop cleanly:
+    /// downloads save what they got and continue from there on the next run
+    #[arg(long, global = true, value_name = "N")]
+    pub max_requests: Option<u64>,
+
+    /// User agent of the requests to GitHub and the other services, e.g. to tell the jobs
+    /// sharing a token apart in the audit log
+    #[arg(long, global = true, env = "LOCAL_GIST_USER_AGENT")]
+    pub user_agent: Option<String>,
+
+    /// Sign the manifest with this minisign secret key whenever a command writes it, as
+    /// manifest.json.minisig, so copies of the archive can be checked with `verify` (an
+    /// encrypted key is decrypted with LOCAL_GIST_SIGNING_PASSWORD). A signed manifest is
+    /// only changed when the key is given
+    #[arg(long, global = true, value_name = "SECRET_KEY_FILE")]
+    pub sign_manifest: Option<PathBuf>,
+
+    /// How results are reported: log lines, a JSON document or one JSON object per line,
+    /// both on stdout
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+
+    /// Syntax highlighting theme of `view`, `tui` and `serve`: a theme bundled with syntect
+    /// (e.g. 'Solarized (dark)') or a .tmTheme file. By default terminals get a dark or light
+    /// theme after their background, told by COLORFGBG
+    #[arg(long, global = true, value_name = "NAME_OR_FILE")]
+    pub theme: Option<String>,
+
+    /// JMESPath expression applied to the results, e.g. '[].{id: id, files: length(files)}',
+    /// whose outcome is written as JSON instead. Not `--query`, which `find` takes its
+    /// search text with
+    #[arg(long = "select", global = true, value_name = "EXPRESSION")]
+    pub output_query: Option<String>,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+// Parsed once at startup, the size of the biggest variant doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Commands {
     /// Download gists for a specific user
     Download {
+        /// GitHub username
+        #[arg(short, long, required_unless_present_any = ["following_of", "org"])]
+        username: Option<String>,
+
+        /// Download the gists of every account this user follows, each into a subfolder
+        /// named after the account
+        #[arg(long, conflicts_with_all = ["username", "target"])]
+        following_of: Option<String>,
+
+        /// Download the gists of every member of this organization, each into a subfolder
+        /// named after the member
+        #[arg(long, conflicts_with_all = ["username", "following_of", "target"])]
+        org: Option<String>,
+
+        /// With --following-of or --org, download the gists of every account straight into
+        /// the folder, sharing one manifest, instead of a subfolder per account
+        #[arg(long, conflicts_with_all = ["username", "target"])]
+        no_owner_dirs: bool,
+
+        /// Directory to save gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Number of concurrency downloads, or `auto` to adjust it to how the server copes
+        #[arg(short, long, default_value = "4")]
+        concurrency: Concurrency,
+
+        /// Maximum number of gists to download
+        #[arg(short, long)]
+        limit: Option<u32>,
+
+        /// Number of gists requested per listing page, at most 100
+        #[arg(long, default_value_t = 100)]
+        per_page: u32,
+
+        /// How file contents are stored in the archive, plain files by default. Like
+        /// --encrypt, --compress-files, --eol and --keep-versions, it is recorded in the
+        /// manifest and used by later downloads, `sync` and `materialize` unless given again
+        #[arg(long, value_enum)]
+        store: Option<Store>,
+
+        /// Write text files of at least SIZE (1M by default, K, M and G suffixes) gzip-compressed
+        /// as <name>.gz next to the others, read back transparently by cat, view and verify
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = parse_size,
+            num_args = 0..=1,
+            default_missing_value = "1M",
+            conflicts_with_all = ["store", "target"]
+        )]
+        compress_files: Option<u64>,
+
+        /// Convert the line endings of text files to these when writing them, e.g. so
+        /// scripts with CRLF endings run from the archive on Linux
+        #[arg(long, value_enum, conflicts_with = "target")]
+        eol: Option<Eol>,
+
+        /// Write the files of secret gists age-encrypted to this recipient (age1...)
+        #[arg(long, value_parser = parse_recipient)]
+        encrypt: Option<Recipient>,
+
+        /// Mirror the gists into remote storage instead of the folder
+        /// (s3://bucket/prefix, webdav://host/path or sftp://user@host/path)
+        #[arg(long, conflicts_with = "store")]
+        target: Option<String>,
+
+        /// Commit the changes of the folder to a git repository after downloading
+        #[arg(long, conflicts_with = "target")]
+        git_commit: bool,
+
+        /// Shell command run after each gist is downloaded, `{gist_id}` and `{path}` are
+        /// replaced and the metadata is passed in GIST_* environment variables
+        #[arg(long, conflicts_with = "target")]
+        post_hook: Option<String>,
+
+        /// Shell command deciding which gists to download, it gets the metadata of each
+        /// gist as JSON on stdin and skips the gist by exiting non-zero
+        #[arg(long)]
+        filter_hook: Option<String>,
+
+        /// Shell command run once the download is complete, `{path}` is replaced and the
+        /// counts of changed gists are passed in LOCAL_GIST_* environment variables
+        #[arg(long, conflicts_with = "target")]
+        done_hook: Option<String>,
+
+        /// Pick the gists to download from the listing with a fuzzy finder
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Leave images and other binary files out of the archive
+        #[arg(long)]
+        skip_binary: bool,
+
+        /// Make files matching this glob executable, besides those starting with a shebang
+        /// (can be repeated)
+        #[arg(long, conflicts_with = "target")]
+        chmod_exec: Vec<String>,
+
+        /// Write a README.md with the description, dates, link and files into each gist folder
+        #[arg(long, conflicts_with = "target")]
+        readme: bool,
+
+        /// Only download gists updated after this date (YYYY-MM-DD or RFC 3339), asked to
+        /// the API so older gists aren't even listed
+        #[arg(long, value_parser = parse_since)]
+        updated_since: Option<DateTime<Utc>>,
+
+        /// Cap on the file content held in memory by concurrent downloads and uploads to
+        /// --target, e.g. 64M, so big accounts fit small containers (K, M and G suffixes, in
+        /// bytes otherwise)
+        #[arg(long, value_parser = parse_size)]
+        max_memory: Option<u64>,
+
+        /// Move the gists no longer on GitHub to the trash and out of the manifest, only
+        /// possible when the whole listing is downloaded
+        #[arg(long, conflicts_with_all = [
+            "target", "limit", "updated_since", "interactive", "filter_hook", "language", "filename"
+        ])]
+        prune: bool,
+
+        /// Fetch every file again, even those unchanged since the last download
+        #[arg(long, conflicts_with = "target")]
+        force: bool,
+
+        /// Keep the earlier versions of files changed on GitHub under
+        /// .versions/<gist id>/<updated_at>, at most this many versions per gist
+        #[arg(long, value_name = "N", conflicts_with = "target")]
+        keep_versions: Option<usize>,
+
+        /// Save the comments of each gist into its folder, as comments.json and rendered as
+        /// comments.md and comments.html threads
+        #[arg(long, conflicts_with = "target")]
+        comments: bool,
+
+        /// Cache the avatars of the owners (and, with --comments, of the commenters) under
+        /// .avatars, so the pages of `serve` and comments.html work offline
+        #[arg(long, conflicts_with = "target")]
+        avatars: bool,
+
+        /// Only record the metadata of the gists (manifest, index, comments) without their
+        /// files, which `materialize` downloads later for the gists wanted. Gists whose files
+        /// were downloaded already are kept up to date
+        #[arg(long, conflicts_with = "target")]
+        sparse: bool,
+
+        /// What to do with files edited in the archive that the new version of their gist
+        /// would replace, asked for each gist in a terminal and skipping it otherwise
+        #[arg(long, value_enum)]
+        resolve: Option<Resolution>,
+
+        /// Time the run may take, e.g. `25m`: new downloads stop being scheduled shortly
+        /// before it ends and the rest is left for the next run, which continues from there
+        #[arg(long, value_parser = parse_age, conflicts_with = "target")]
+        max_duration: Option<Duration>,
+
+        /// Open the folder in the file manager once the download is complete, or the gist
+        /// (its file when it has only one) when the archive holds a single gist
+        #[arg(long, conflicts_with = "target")]
+        open: bool,
+
+        /// Open it with this command instead, e.g. `code` or `vim`, the path is appended
+        #[arg(long, value_name = "CMD", conflicts_with = "target")]
+        open_with: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Continuously archive newly published public gists of everyone matching the filters
+    Firehose {
+        /// Directory to save gists
+        #[arg(short, long, default_value = "firehose")]
+        folder: String,
+
+        /// Number of concurrency downloads, or `auto` to adjust it to how the server copes
+        #[arg(short, long, default_value = "4")]
+        concurrency: Concurrency,
+
+        /// Seconds to wait between two polls of the feed
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+
+        /// Poll the feed once and exit, e.g. when run from cron
+        #[arg(long)]
+        once: bool,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Download the gists of a user updated since the previous sync, keeping the time of
+    /// the last successful sync in the folder
+    Sync {
         /// GitHub username
         #[arg(short, long)]
         username: String,
@@ -22,22 +291,775 @@ pub enum Commands {
         #[arg(short, long, default_value = "gists")]
         folder: String,
 
+        /// Number of concurrency downloads, or `auto` to adjust it to how the server copes
+        #[arg(short, long, default_value = "4")]
+        concurrency: Concurrency,
+
+        /// Seconds to wait between two syncs
+        #[arg(long, default_value_t = 3600)]
+        interval: u64,
+
+        /// Sync once and exit, e.g. when run from cron
+        #[arg(long)]
+        once: bool,
+
+        /// What to do with files edited in the archive that the new version of their gist
+        /// would replace, asked for each gist in a terminal and skipping it otherwise
+        #[arg(long, value_enum)]
+        resolve: Option<Resolution>,
+
+        /// Write text files of at least SIZE (1M by default, K, M and G suffixes) gzip-compressed
+        /// as <name>.gz next to the others, read back transparently by cat, view and verify
+        #[arg(
+            long,
+            value_name = "SIZE",
+            value_parser = parse_size,
+            num_args = 0..=1,
+            default_missing_value = "1M",
+            conflicts_with = "target"
+        )]
+        compress_files: Option<u64>,
+
+        /// Convert the line endings of text files to these when writing them, e.g. so
+        /// scripts with CRLF endings run from the archive on Linux
+        #[arg(long, value_enum, conflicts_with = "target")]
+        eol: Option<Eol>,
+
+        /// Mirror the gists into remote storage instead of the folder
+        /// (s3://bucket/prefix, webdav://host/path or sftp://user@host/path)
+        #[arg(long)]
+        target: Option<String>,
+
+        /// Time the run may take, e.g. `25m`: new downloads stop being scheduled shortly
+        /// before it ends and the rest is left for the next run, which continues from there
+        #[arg(long, value_parser = parse_age, conflicts_with = "target")]
+        max_duration: Option<Duration>,
+
+        /// Commit the changes of the folder to a git repository after each sync
+        #[arg(long, conflicts_with = "target")]
+        git_commit: bool,
+
+        /// Shell command run after each gist is downloaded, `{gist_id}` and `{path}` are
+        /// replaced and the metadata is passed in GIST_* environment variables
+        #[arg(long, conflicts_with = "target")]
+        post_hook: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Download the files of gists archived with `download --sparse`
+    Materialize {
+        /// IDs of the gists; with none, every sparse gist matching the filters
+        ids: Vec<String>,
+
+        /// Directory the gists are saved in
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
         /// Number of concurrency downloads
-        #[arg(short, long, default_value_t = 4)]
+        #[arg(short, long, default_value = "4")]
         concurrency: usize,
 
-        /// Maximum number of gists to download
-        #[arg(short, long)]
-        limit: Option<u32>,
+        /// Open the folder in the file manager once the files are downloaded, or the gist
+        /// (its file when it has only one) when a single gist was materialized
+        #[arg(long)]
+        open: bool,
+
+        /// Open it with this command instead, e.g. `code` or `vim`, the path is appended
+        #[arg(long, value_name = "CMD")]
+        open_with: Option<String>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Update the descriptions, timestamps, comment counts and stars of the archived gists
+    /// from GitHub, without downloading their files
+    Refresh {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
     },
     /// List gists for a specific user
     List {
+        /// GitHub username, several can be given (comma-separated or repeated)
+        #[arg(
+            short,
+            long,
+            value_delimiter = ',',
+            required_unless_present = "starred"
+        )]
+        username: Vec<String>,
+
+        /// List the gists starred by the owner of the token
+        #[arg(long, conflicts_with = "web")]
+        starred: bool,
+
+        /// Group the gists by owner, in JSON as an object keyed by owner
+        #[arg(long, value_enum)]
+        group_by: Option<GroupBy>,
+
+        /// Maximum number of gists to list
+        #[arg(short, long)]
+        limit: Option<u32>,
+
+        /// Number of gists requested per listing page, at most 100
+        #[arg(long, default_value_t = 100)]
+        per_page: u32,
+
+        /// Open the gists page of the user in the browser instead
+        #[arg(short, long)]
+        web: bool,
+
+        /// Show a table with these columns and a line of totals instead of one line per
+        /// gist, e.g. id,files,size,updated
+        #[arg(long, value_enum, value_delimiter = ',')]
+        columns: Vec<Column>,
+
+        /// Link the descriptions of the gists archived in this folder to their local copy,
+        /// in terminals showing hyperlinks
+        #[arg(long)]
+        folder: Option<String>,
+    },
+    /// Find gists of a user by description or filename without downloading them
+    Find {
         /// GitHub username
         #[arg(short, long)]
         username: String,
 
-        /// Maximum number of gists to list
+        /// Text to look for in descriptions and filenames
+        #[arg(short, long)]
+        query: String,
+
+        /// Maximum number of matches to show
         #[arg(short, long)]
         limit: Option<u32>,
     },
+    /// Show counts, sizes and a language breakdown of a user's gists
+    Stats {
+        /// GitHub username
+        #[arg(short, long, required_unless_present = "local")]
+        username: Option<String>,
+
+        /// Compute the stats from the local archive instead of the API
+        #[arg(long)]
+        local: bool,
+
+        /// Also list the N largest gists by aggregate file size
+        #[arg(long, default_value_t = 0)]
+        top_size: usize,
+
+        /// Also list the N most recently updated gists
+        #[arg(long, default_value_t = 0)]
+        top_recent: usize,
+
+        /// Directory containing downloaded gists, used with --local
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+    },
+    /// Search downloaded gists by description, filename and content
+    Search {
+        /// Text to search for
+        query: String,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Maximum number of results to show
+        #[arg(short, long, default_value_t = 20)]
+        limit: u32,
+
+        /// Rebuild the search index from the manifest before searching
+        #[arg(long)]
+        reindex: bool,
+
+        /// Open the best matching gist in the browser
+        #[arg(short, long)]
+        web: bool,
+    },
+    /// Show a downloaded gist with syntax highlighting, line numbers and its metadata
+    View {
+        /// Id, id prefix, part of the description or search terms of the gist
+        gist: String,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Write to stdout instead of $PAGER when in a terminal
+        #[arg(long)]
+        no_pager: bool,
+    },
+    /// Open a downloaded gist in the browser
+    Open {
+        /// Id, id prefix, part of the description or search terms of the gist
+        gist: String,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+    },
+    /// Search downloaded files for a regular expression
+    Grep {
+        /// Regular expression matched against each line
+        pattern: String,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        /// Match case-insensitively
+        #[arg(short, long)]
+        ignore_case: bool,
+    },
+    /// Report downloaded gists containing likely credentials
+    ScanSecrets {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Only report findings of at least this severity
+        #[arg(long, value_enum, default_value_t = Severity::Low)]
+        min_severity: Severity,
+    },
+    /// Materialize the files of a gist kept in the content store or gzip-compressed as plain
+    /// files
+    Checkout {
+        /// Id of the gist
+        id: String,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+    },
+    /// Print the content of a gist file, from the archive when the gist was downloaded
+    /// (decrypting it if needed) and from GitHub otherwise
+    Cat {
+        /// Id of the gist
+        id: String,
+
+        /// Name of the file, needed when the gist has several files
+        filename: Option<String>,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+    },
+    /// Put the content of a gist file on the system clipboard
+    Copy {
+        /// Id of the gist
+        id: String,
+
+        /// Name of the file, asked for in a terminal when the gist has several files
+        filename: Option<String>,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+    },
+    /// Replace the description of one of your gists on GitHub, and in the archive when the
+    /// gist was downloaded
+    Describe {
+        /// Id of the gist
+        id: String,
+
+        /// New description
+        #[arg(required_unless_present = "from_file", conflicts_with = "from_file")]
+        description: Option<String>,
+
+        /// Read the description from this file, `-` for stdin
+        #[arg(long, value_name = "FILE")]
+        from_file: Option<PathBuf>,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+    },
+    /// Create an incremental snapshot of the archive and prune old snapshots
+    Backup {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Directory holding the snapshots [default: <folder>/.snapshots]
+        #[arg(long)]
+        snapshots: Option<String>,
+
+        /// Keep the newest snapshot of each of the last N days
+        #[arg(long)]
+        keep_daily: Option<usize>,
+
+        /// Keep the newest snapshot of each of the last N weeks
+        #[arg(long)]
+        keep_weekly: Option<usize>,
+
+        /// Write new snapshot objects age-encrypted to this recipient (age1...)
+        #[arg(long, value_parser = parse_recipient)]
+        encrypt: Option<Recipient>,
+    },
+    /// Restore the archive from an export or a snapshot, validating checksums
+    Restore {
+        /// Export (.tar.gz or .zip) to restore from
+        #[arg(
+            long,
+            required_unless_present = "snapshot",
+            conflicts_with = "snapshot"
+        )]
+        from: Option<String>,
+
+        /// Restore from the snapshots created by the backup command
+        #[arg(long)]
+        snapshot: bool,
+
+        /// Use the newest snapshot taken at or before this date (YYYY-MM-DD or RFC 3339)
+        #[arg(long, requires = "snapshot", value_parser = parse_date)]
+        at: Option<DateTime<Utc>>,
+
+        /// Directory holding the snapshots [default: <folder>/.snapshots]
+        #[arg(long, requires = "snapshot")]
+        snapshots: Option<String>,
+
+        /// Directory to restore the gists into
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+    },
+    /// Export the local archive to another format
+    Export {
+        #[command(subcommand)]
+        format: ExportFormat,
+    },
+    /// Import snippets from another service into the archive
+    Import {
+        #[command(subcommand)]
+        service: ImportService,
+    },
+    /// Mirror the archived gists to another service
+    Mirror {
+        #[command(subcommand)]
+        service: MirrorService,
+    },
+    /// Browse downloaded gists interactively in the terminal
+    Tui {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// GitHub token with the gist scope, needed to star and delete gists (the token
+        /// of the account, or GITHUB_TOKEN, by default)
+        #[arg(long)]
+        github_token: Option<String>,
+    },
+    /// Serve a local web UI for browsing downloaded gists
+    Serve {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Port to listen on
+        #[arg(short, long, default_value_t = 8080)]
+        port: u16,
+    },
+    /// Remove objects no gist uses anymore from the content store, expire cached listing
+    /// pages and compact the index
+    Gc {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Remove the cached listing pages unused for longer than this, e.g. 30d or 12h
+        #[arg(long, value_parser = parse_age, default_value = "30d")]
+        cache_max_age: Duration,
+    },
+    /// Check that every file of the manifest is in the archive and matches its checksum
+    Verify {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Also check the signature of the manifest written by `--sign-manifest`, against
+        /// this minisign public key
+        #[arg(long, value_name = "PUBLIC_KEY_FILE")]
+        public_key: Option<PathBuf>,
+    },
+    /// Manage the trash holding the files replaced and the gists pruned by downloads
+    Trash {
+        #[command(subcommand)]
+        action: TrashAction,
+    },
+    /// Get, set and list the settings of the config file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Update local-gist to the latest GitHub release, checking its SHA-256
+    SelfUpdate {
+        /// Only report whether a newer release is available
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum TrashAction {
+    /// Delete the content of the trash for good
+    Empty {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Only delete what was moved to the trash longer ago than this, e.g. 30d or 12h
+        #[arg(long, value_parser = parse_age)]
+        older_than: Option<Duration>,
+    },
+}
+
+/// Settings are dotted keys: `default.<option>` replaces the default of that option in
+/// every command, `profile.<name>.<option>` does so when running with `--profile <name>`.
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Print the value of a key
+    Get {
+        /// Dotted key, e.g. default.folder
+        key: String,
+    },
+    /// Set the value of a key
+    Set {
+        /// Dotted key, e.g. default.folder or profile.work.folder
+        key: String,
+
+        /// Value, numbers and true/false are stored as such
+        value: String,
+    },
+    /// Remove a key
+    Unset {
+        /// Dotted key
+        key: String,
+    },
+    /// Print every key and its value
+    List,
+    /// Print the path of the config file
+    Path,
+}
+
+#[derive(Subcommand)]
+pub enum ImportService {
+    /// Import the GitLab snippets of a user
+    Gitlab {
+        /// GitLab username, the token must belong to this user
+        #[arg(short, long)]
+        user: String,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// GitLab personal access token with the read_api scope
+        #[arg(long, env = "GITLAB_TOKEN", hide_env_values = true)]
+        gitlab_token: String,
+
+        /// Base URL of the GitLab instance
+        #[arg(long, default_value = "https://gitlab.com")]
+        gitlab_url: String,
+
+        /// Also republish every new snippet as a GitHub gist
+        #[arg(long)]
+        republish: bool,
+
+        /// GitHub token with the gist scope, needed by --republish (the token of the
+        /// account, or GITHUB_TOKEN, by default)
+        #[arg(long)]
+        github_token: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum MirrorService {
+    /// Create or update a GitLab snippet for every gist
+    Gitlab {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// GitLab personal access token with the api scope
+        #[arg(long, env = "GITLAB_TOKEN", hide_env_values = true)]
+        gitlab_token: String,
+
+        /// Base URL of the GitLab instance
+        #[arg(long, default_value = "https://gitlab.com")]
+        gitlab_url: String,
+    },
+    /// Create or update a Gitea repository for every gist
+    Gitea {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Gitea access token allowed to write repositories
+        #[arg(long, env = "GITEA_TOKEN", hide_env_values = true)]
+        gitea_token: String,
+
+        /// Base URL of the Gitea instance
+        #[arg(long)]
+        gitea_url: String,
+    },
+    /// Push every gist to an opengist instance
+    Opengist {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Base URL of the opengist instance
+        #[arg(long)]
+        opengist_url: String,
+
+        /// opengist account the gists are created for
+        #[arg(long)]
+        opengist_user: String,
+
+        /// Password of the opengist account
+        #[arg(long, env = "OPENGIST_PASSWORD", hide_env_values = true)]
+        opengist_password: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ExportFormat {
+    /// Package the archive and its manifest into a .tar.gz file
+    Tar {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Path of the .tar.gz file to create
+        #[arg(short, long)]
+        out: String,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Package the archive and its manifest into a .zip file
+    Zip {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Path of the .zip file to create, or a directory with --per-gist
+        #[arg(short, long)]
+        out: String,
+
+        /// Create one zip per gist instead of a single zip
+        #[arg(long)]
+        per_gist: bool,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Write the catalog of the archive (owners, languages, gists and files) into a
+    /// standalone SQLite database for ad-hoc queries
+    Sqlite {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Path of the database to create, replaced when it exists
+        #[arg(short, long)]
+        out: String,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Write the metadata of the gists and their files as Parquet (gists.parquet and
+    /// files.parquet), for DuckDB, Polars and other analytics tools
+    Parquet {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Directory to write the Parquet files into
+        #[arg(short, long)]
+        out: String,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Write every gist as a Markdown note of an Obsidian vault, with its metadata as YAML
+    /// front matter and its files as code blocks
+    Obsidian {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Directory of the vault, created when missing
+        #[arg(short, long)]
+        out: String,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Convert the gists made of a single file into snippets of VS Code or the JetBrains
+    /// IDEs, named after their description
+    #[command(visible_alias = "vscode-snippets")]
+    Snippets {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Path of the snippet file to create, e.g. gists.code-snippets or Gists.xml
+        #[arg(short, long)]
+        out: String,
+
+        /// Editor whose snippet format is written
+        #[arg(long, value_enum, default_value_t = Editor::Vscode)]
+        editor: Editor,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Write a Hugo page bundle per gist into the content tree of a static site
+    Hugo {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Content directory to write the bundles into, e.g. content/snippets
+        #[arg(short, long)]
+        out: String,
+
+        /// Also publish the secret gists, left out by default
+        #[arg(long)]
+        include_secret: bool,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Pass the gists to an exporter plugin, a local-gist-export-<name> executable on PATH
+    Plugin {
+        /// Name of the plugin
+        name: String,
+
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+
+        /// Arguments passed on to the plugin
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// List the exporter plugins found on PATH
+    Plugins,
+    /// Create a git repository with a folder per gist and the full history of every gist
+    Repo {
+        /// Directory containing downloaded gists
+        #[arg(short, long, default_value = "gists")]
+        folder: String,
+
+        /// Directory of the repository to create
+        #[arg(short, long)]
+        out: String,
+
+        /// Fetch the gists over SSH (git@gist.github.com:<id>.git) with the keys of
+        /// ssh-agent, instead of over HTTPS
+        #[arg(long)]
+        ssh: bool,
+
+        /// Private key to fetch over SSH with, instead of the keys of ssh-agent (implies
+        /// --ssh)
+        #[arg(long, value_name = "KEY_FILE")]
+        ssh_key: Option<PathBuf>,
+
+        /// Only replay the last N revisions of each gist (a shallow fetch), 1 for just the
+        /// latest content
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
+
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+}
+
+/// Selects gists by the languages and names of their files
+#[derive(Args, Debug)]
+pub struct FilterArgs {
+    /// Only include files in this language (can be repeated)
+    #[arg(long)]
+    pub language: Vec<String>,
+
+    /// Only include files whose name matches this glob, e.g. '*.sh'
+    #[arg(long)]
+    pub filename: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    Json,
+    Ndjson,
+}
+
+/// Parses a size in bytes with an optional K, M or G suffix (powers of 1024).
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let upper = s.to_ascii_uppercase();
+    let digits = upper.trim_end_matches(['I', 'B']);
+    let (number, unit) = match digits.char_indices().last() {
+        Some((i, 'K')) => (&digits[..i], 1 << 10),
+        Some((i, 'M')) => (&digits[..i], 1 << 20),
+        Some((i, 'G')) => (&digits[..i], 1 << 30),
+        _ => (digits, 1),
+    };
+    let number: u64 = number
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected a size like 512K, 64M or 1G, got {}", s))?;
+    number
+        .checked_mul(unit)
+        .ok_or_else(|| format!("{} is too large a size", s))
+}
+
+/// Parses an age made of a number and a unit: s, m, h, d or w.
+fn parse_age(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let unit = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        Some('w') => 7 * 24 * 60 * 60,
+        _ => return Err(format!("expected an age like 30d, 12h or 2w, got {}", s)),
+    };
+    let number: u64 = s[..s.len() - 1]
+        .trim()
+        .parse()
+        .map_err(|_| format!("expected an age like 30d, 12h or 2w, got {}", s))?;
+    number
+        .checked_mul(unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{} is too long an age", s))
+}
+
+/// Parses an RFC 3339 timestamp, or a date meaning the start of that day in UTC.
+fn parse_since(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Ok(date.and_hms_opt(0, 0, 0).expect("valid time").and_utc());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|d| d.with_timezone(&Utc))
+        .map_err(|e| format!("expected YYYY-MM-DD or an RFC 3339 timestamp: {}", e))
+}
+
+/// Parses an RFC 3339 timestamp, or a date meaning the end of that day in UTC.
+fn parse_date(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let end_of_day = date.and_hms_opt(23, 59, 59).expect("valid time");
+        return Ok(end_of_day.and_utc());
+    }
+    DateTime::parse_from_rfc3339(s)
+        .map(|d| d.with_timezone(&Utc))
+        .map_err(|e| format!("expected YYYY-MM-DD or an RFC 3339 timestamp: {}", e))
 }
@@ -0,0 +1,28 @@
+use crate::gist::GistError;
+use crate::log::info;
+use arboard::Clipboard;
+
+/// Puts text on the system clipboard.
+///
+/// On Linux the clipboard content is owned by the process that set it, so this waits
+/// until another copy replaces it (clipboard managers take it over right away).
+pub fn copy(text: &str) -> Result<(), GistError> {
+    let mut clipboard = Clipboard::new()?;
+
+    #[cfg(target_os = "linux")]
+    {
+        use arboard::SetExtLinux;
+        info!(
+            "Copied {} bytes, keeping them until the clipboard is replaced",
+            text.len()
+        );
+        clipboard.set().wait().text(text)?;
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        clipboard.set_text(text)?;
+        info!("Copied {} bytes to the clipboard", text.len());
+    }
+
+    Ok(())
+}
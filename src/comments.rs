@@ -0,0 +1,187 @@
+use crate::avatars::{self, AVATARS_DIR};
+use crate::gist::{self, Comment, Gist};
+use crate::highlight::escape_html;
+use crate::log::{debug, warn};
+use crate::paths::long_path;
+use crate::store::Folder;
+use reqwest::Client;
+use std::fmt::Write as _;
+use std::io;
+use std::path::Path;
+
+// Names of the files written into each gist folder: the comments as GitHub returned them,
+// and the thread rendered for reading
+const COMMENTS_JSON: &str = "comments.json";
+const COMMENTS_MD: &str = "comments.md";
+const COMMENTS_HTML: &str = "comments.html";
+
+fn author(comment: &Comment) -> &str {
+    comment
+        .user
+        .as_ref()
+        .map_or("ghost", |user| user.login.as_str())
+}
+
+fn title(gist: &Gist) -> &str {
+    gist.description
+        .as_deref()
+        .filter(|description| !description.trim().is_empty())
+        .unwrap_or(&gist.id)
+}
+
+/// Returns the comments saved for a gist, `None` when they weren't downloaded.
+pub fn load(folder: &Path, id: &str) -> Option<Vec<Comment>> {
+    let json = std::fs::read(long_path(folder.join(id).join(COMMENTS_JSON))).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// Renders the thread as Markdown: each comment under a heading with its author and date.
+fn render_markdown(gist: &Gist, comments: &[Comment]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# Comments on {}\n", title(gist).replace('\n', " "));
+    let _ = writeln!(out, "<{}>\n", gist.html_url);
+    for comment in comments {
+        let _ = writeln!(out, "## {} on {}\n", author(comment), comment.created_at);
+        let _ = writeln!(out, "{}\n", comment.body.trim_end());
+    }
+    out
+}
+
+/// Renders the thread as an HTML fragment, with the bodies as rendered by GitHub, or as
+/// preformatted text when the HTML wasn't returned. `avatar` gives the address of the
+/// avatar of a login, when one is cached.
+pub fn render_thread(comments: &[Comment], avatar: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::new();
+    for comment in comments {
+        let body = match &comment.body_html {
+            Some(html) => html.clone(),
+            None => format!("<pre>{}</pre>", escape_html(&comment.body)),
+        };
+        let image = avatar(author(comment)).map_or(String::new(), |src| {
+            format!(
+                r#"<img class="avatar" src="{}" alt="" width="20" height="20"> "#,
+                escape_html(&src)
+            )
+        });
+        let _ = writeln!(
+            out,
+            r#"<div class="comment"><p class="meta">{}{} on {}</p>{}</div>"#,
+            image,
+            escape_html(author(comment)),
+            escape_html(&comment.created_at),
+            body
+        );
+    }
+    out
+}
+
+/// Renders the thread as a standalone HTML page, showing the avatars cached in `folder`
+/// from where the page is written, so it works offline.
+fn render_html(folder: &Path, gist: &Gist, comments: &[Comment]) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Comments on {0}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; }}
+.comment {{ border-top: 1px solid #ddd; padding: 0.4rem 0; }}
+.meta {{ color: #666; font-size: 0.9rem; }}
+.avatar {{ vertical-align: middle; border-radius: 50%; }}
+</style>
+</head>
+<body>
+<h1>Comments on {0}</h1>
+<p><a href="{1}">{1}</a></p>
+{2}</body>
+</html>
+"#,
+        escape_html(title(gist)),
+        escape_html(&gist.html_url),
+        render_thread(comments, |login| {
+            avatars::cached(folder, login, avatars::SMALL)
+                .map(|name| format!("../{}/{}", AVATARS_DIR, name))
+        })
+    )
+}
+
+/// Writes the comments of a gist into its folder, as JSON and rendered as Markdown and
+/// HTML threads. A file the gist has of the same name is left alone.
+pub fn write_comments(archive: &Folder, gist: &Gist, comments: &[Comment]) -> io::Result<()> {
+    let folder = archive.root;
+    let json = serde_json::to_string_pretty(comments).map_err(io::Error::other)?;
+    let rendered = [
+        (COMMENTS_JSON, json),
+        (COMMENTS_MD, render_markdown(gist, comments)),
+        (COMMENTS_HTML, render_html(folder, gist, comments)),
+    ];
+    for (name, content) in rendered {
+        if gist
+            .files
+            .keys()
+            .any(|filename| filename.eq_ignore_ascii_case(name))
+        {
+            debug!("Gist {} has its own {}, not writing one", gist.id, name);
+            continue;
+        }
+        let path = long_path(folder.join(&gist.id).join(name));
+        archive.check_inside(&path)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, content)?;
+    }
+    Ok(())
+}
+
+/// Downloads the avatars of the authors of comments into the archive.
+async fn download_avatars(
+    client: &Client,
+    archive: &Folder<'_>,
+    comments: &[Comment],
+    force: bool,
+) {
+    for user in comments.iter().filter_map(|comment| comment.user.as_ref()) {
+        if let Some(avatar_url) = &user.avatar_url {
+            avatars::download(client, archive, &user.login, avatar_url, force).await;
+        }
+    }
+}
+
+/// Downloads the comments of a gist into its folder, unless as many as the gist has were
+/// saved already and `force` isn't set. With `with_avatars`, the avatars of their authors
+/// are cached too and shown by comments.html. Failures are only logged, the gist itself is
+/// fine.
+pub async fn download(
+    client: &Client,
+    archive: &Folder<'_>,
+    gist: &Gist,
+    force: bool,
+    with_avatars: bool,
+) {
+    let saved = load(archive.root, &gist.id);
+    if !force && saved.as_ref().map(Vec::len) == Some(gist.comments as usize) {
+        debug!("The comments of gist {} are up to date", gist.id);
+        // Renders the thread again in case avatars were missing when it was written
+        if let Some(saved) = saved.filter(|_| with_avatars) {
+            download_avatars(client, archive, &saved, force).await;
+            if let Err(e) = write_comments(archive, gist, &saved) {
+                warn!("Could not write the comments of gist {}: {}", gist.id, e);
+            }
+        }
+        return;
+    }
+    let written = match gist::list_comments(client, &gist.id).await {
+        Ok(comments) => {
+            if with_avatars {
+                download_avatars(client, archive, &comments, force).await;
+            }
+            write_comments(archive, gist, &comments).map_err(Into::into)
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = written {
+        warn!("Could not download the comments of gist {}: {}", gist.id, e);
+    }
+}
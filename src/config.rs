@@ -0,0 +1,330 @@
+use crate::auth::{self, Credentials, GitHubApp};
+use crate::cli::Cli;
+use crate::gist::GistError;
+use clap::builder::Resettable;
+use clap::{Command, CommandFactory, FromArgMatches, Id};
+use std::path::PathBuf;
+use toml_edit::{DocumentMut, Item, Table, Value};
+
+// Table holding the defaults applied to every run
+const DEFAULT_TABLE: &str = "default";
+// Table holding a table of defaults per profile
+const PROFILE_TABLE: &str = "profile";
+// Table holding a table per account, with its token and defaults
+const ACCOUNT_TABLE: &str = "account";
+// Key of the token in the table of an account
+const TOKEN_KEY: &str = "token";
+// Keys of the GitHub App an account can authenticate as instead of a token
+const APP_ID_KEY: &str = "app_id";
+const PRIVATE_KEY_KEY: &str = "private_key";
+const INSTALLATION_ID_KEY: &str = "installation_id";
+// Keys of an account that are credentials rather than option defaults
+const CREDENTIAL_KEYS: [&str; 4] = [TOKEN_KEY, APP_ID_KEY, PRIVATE_KEY_KEY, INSTALLATION_ID_KEY];
+
+/// Returns where the config file is: `$LOCAL_GIST_CONFIG`, or `local-gist/config.toml`
+/// in `$XDG_CONFIG_HOME` or `~/.config`.
+pub fn config_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("LOCAL_GIST_CONFIG") {
+        return PathBuf::from(path);
+    }
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_default();
+    base.join("local-gist").join("config.toml")
+}
+
+/// The TOML config file, kept as a document so editing it preserves comments and layout.
+///
+/// `[default]` holds values used in place of the built-in defaults of command options,
+/// keyed by option name (e.g. `folder = "~/gists"`), and `[profile.<name>]` holds values
+/// overriding them when running with `--profile <name>`. `[account.<name>]` holds the
+/// `token` GitHub requests use with `--account <name>`, or the `app_id`, `private_key` and
+/// `installation_id` of a GitHub App, next to defaults like a profile.
+pub struct Config {
+    path: PathBuf,
+    document: DocumentMut,
+}
+
+impl Config {
+    /// Loads the config file, empty when there is none yet.
+    pub fn load() -> Result<Self, GistError> {
+        let path = config_path();
+        let document = match std::fs::read_to_string(&path) {
+            Ok(text) => text
+                .parse::<DocumentMut>()
+                .map_err(|e| GistError::Config(format!("{}: {}", path.display(), e)))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => DocumentMut::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Config { path, document })
+    }
+
+    pub fn save(&self) -> Result<(), GistError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, self.document.to_string())?;
+        // Only readable by its owner, since it may hold account tokens
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&self.path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the value of a dotted key like `default.folder` or `profile.work.folder`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        let mut item = self.document.as_item();
+        for part in key.split('.') {
+            item = item.get(part)?;
+        }
+        item.as_value().map(display_value)
+    }
+
+    /// Sets a dotted key, creating the tables on the way. Numbers and booleans are stored
+    /// as such, anything else as a string.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), GistError> {
+        let (tables, name) = split_key(key)?;
+        let mut table = self.document.as_table_mut();
+        for part in tables {
+            let item = table
+                .entry(part)
+                .or_insert_with(|| Item::Table(implicit_table()));
+            table = item
+                .as_table_mut()
+                .ok_or_else(|| GistError::Config(format!("{} is not a table", part)))?;
+        }
+        table.insert(name, Item::Value(parse_value(value)));
+        Ok(())
+    }
+
+    /// Removes a dotted key, returning whether it was set.
+    pub fn unset(&mut self, key: &str) -> Result<bool, GistError> {
+        let (tables, name) = split_key(key)?;
+        let mut table = self.document.as_table_mut();
+        for part in tables {
+            match table.get_mut(part).and_then(Item::as_table_mut) {
+                Some(inner) => table = inner,
+                None => return Ok(false),
+            }
+        }
+        Ok(table.remove(name).is_some())
+    }
+
+    /// Returns every key set, dotted, with its value. Account tokens are hidden, `get`
+    /// prints them.
+    pub fn list(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        collect(self.document.as_table(), "", &mut entries);
+        for (key, value) in &mut entries {
+            if key.starts_with(ACCOUNT_TABLE) && key.ends_with(&format!(".{}", TOKEN_KEY)) {
+                *value = "<hidden>".to_string();
+            }
+        }
+        entries
+    }
+
+    fn named_table(&self, table: &str, name: &str) -> Result<&Table, GistError> {
+        self.document
+            .get(table)
+            .and_then(|tables| tables.get(name))
+            .and_then(Item::as_table)
+            .ok_or_else(|| GistError::Config(format!("no {} named {}", table, name)))
+    }
+
+    /// Returns the credentials of an account: its token, or else its GitHub App.
+    fn account_credentials(&self, account: &str) -> Result<Credentials, GistError> {
+        let table = self.named_table(ACCOUNT_TABLE, account)?;
+        let value = |key: &str| {
+            table
+                .get(key)
+                .and_then(Item::as_value)
+                .map(display_value)
+                .filter(|value| !value.is_empty())
+        };
+        if let Some(token) = value(TOKEN_KEY) {
+            return Ok(Credentials::Token(token));
+        }
+        let (Some(app_id), Some(private_key), Some(installation_id)) = (
+            value(APP_ID_KEY),
+            value(PRIVATE_KEY_KEY),
+            value(INSTALLATION_ID_KEY),
+        ) else {
+            return Err(GistError::Config(format!(
+                "account {} needs a {}, or an {}, {} and {}",
+                account, TOKEN_KEY, APP_ID_KEY, PRIVATE_KEY_KEY, INSTALLATION_ID_KEY
+            )));
+        };
+        let installation_id = installation_id.parse().map_err(|_| {
+            GistError::Config(format!(
+                "{} of {} isn't a number",
+                INSTALLATION_ID_KEY, account
+            ))
+        })?;
+        let app = GitHubApp::new(&app_id, &expand_home(private_key), installation_id)?;
+        Ok(Credentials::App(app))
+    }
+
+    /// Returns the option defaults of a run: `[default]` overlaid with the profile's, then
+    /// with the account's.
+    fn defaults(
+        &self,
+        profile: Option<&str>,
+        account: Option<&str>,
+    ) -> Result<Vec<(String, String)>, GistError> {
+        let mut defaults: Vec<(String, String)> = Vec::new();
+        let mut overlay = |table: &Table| {
+            for (name, item) in table.iter() {
+                if CREDENTIAL_KEYS.contains(&name) {
+                    continue;
+                }
+                if let Some(value) = item.as_value() {
+                    defaults.retain(|(key, _)| key != name);
+                    defaults.push((name.to_string(), expand_home(display_value(value))));
+                }
+            }
+        };
+        if let Some(table) = self.document.get(DEFAULT_TABLE).and_then(Item::as_table) {
+            overlay(table);
+        }
+        if let Some(profile) = profile {
+            overlay(self.named_table(PROFILE_TABLE, profile)?);
+        }
+        if let Some(account) = account {
+            overlay(self.named_table(ACCOUNT_TABLE, account)?);
+        }
+        Ok(defaults)
+    }
+}
+
+fn implicit_table() -> Table {
+    let mut table = Table::new();
+    table.set_implicit(true);
+    table
+}
+
+fn split_key(key: &str) -> Result<(Vec<&str>, &str), GistError> {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let name = parts.pop().unwrap_or_default();
+    if name.is_empty() || parts.iter().any(|part| part.is_empty()) {
+        return Err(GistError::Config(format!("invalid key {:?}", key)));
+    }
+    Ok((parts, name))
+}
+
+fn parse_value(value: &str) -> Value {
+    if let Ok(number) = value.parse::<i64>() {
+        return number.into();
+    }
+    match value {
+        "true" => true.into(),
+        "false" => false.into(),
+        _ => value.into(),
+    }
+}
+
+/// Shows a value the way it would be typed on the command line, strings unquoted.
+fn display_value(value: &Value) -> String {
+    match value.as_str() {
+        Some(text) => text.to_string(),
+        None => value.to_string().trim().to_string(),
+    }
+}
+
+/// Expands a leading `~/`, which the shell doesn't do for values read from the file.
+fn expand_home(value: String) -> String {
+    match (value.strip_prefix("~/"), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home.trim_end_matches('/'), rest),
+        _ => value,
+    }
+}
+
+fn collect(table: &Table, prefix: &str, entries: &mut Vec<(String, String)>) {
+    for (name, item) in table.iter() {
+        let key = format!("{}{}", prefix, name);
+        match item {
+            Item::Value(value) => entries.push((key, display_value(value))),
+            Item::Table(inner) => collect(inner, &format!("{}.", key), entries),
+            _ => {}
+        }
+    }
+}
+
+/// Returns the value of a global option like `--profile`, or else of its environment
+/// variable, looked up before parsing since it decides the defaults the arguments are
+/// parsed with.
+fn requested(args: &[String], option: &str, env: &str) -> Option<String> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == option {
+            return args.next().cloned();
+        }
+        if let Some(value) = arg
+            .strip_prefix(option)
+            .and_then(|rest| rest.strip_prefix('='))
+        {
+            return Some(value.to_string());
+        }
+    }
+    std::env::var(env).ok().filter(|value| !value.is_empty())
+}
+
+/// Replaces the default value of every option named `name`, in every subcommand.
+fn apply_default(command: Command, name: &str, value: &str) -> Command {
+    let has_arg = command
+        .get_arguments()
+        .any(|arg| arg.get_id() == name && !arg.is_positional());
+    let command = match has_arg {
+        // A required option is satisfied by its default, which clap doesn't do by itself
+        true => command.mut_arg(name, |arg| {
+            arg.default_value(value.to_string())
+                .required(false)
+                .required_unless_present(Resettable::<Id>::Reset)
+        }),
+        false => command,
+    };
+    let subcommands: Vec<String> = command
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .collect();
+    subcommands.iter().fold(command, |command, sub| {
+        command.mut_subcommand(sub, |sub| apply_default(sub, name, value))
+    })
+}
+
+/// Parses the command line with the defaults of the config file and profile in place of
+/// the built-in ones. Exits with the usage on invalid arguments, like `Cli::parse`.
+pub fn parse_cli() -> Result<Cli, GistError> {
+    let args: Vec<String> = std::env::args().collect();
+    let config = Config::load()?;
+    let profile = requested(&args, "--profile", "LOCAL_GIST_PROFILE");
+    let account = requested(&args, "--account", "LOCAL_GIST_ACCOUNT");
+    match &account {
+        Some(account) => auth::use_credentials(config.account_credentials(account)?),
+        None => {
+            if let Some(app) = auth::app_from_env()? {
+                auth::use_credentials(Credentials::App(app));
+            }
+        }
+    }
+    let mut command = Cli::command();
+    for (name, value) in config.defaults(profile.as_deref(), account.as_deref())? {
+        command = apply_default(command, &name.replace('-', "_"), &value);
+    }
+    let matches = command
+        .try_get_matches_from(args)
+        .unwrap_or_else(|e| exit_usage(e));
+    Ok(Cli::from_arg_matches(&matches).unwrap_or_else(|e| exit_usage(e)))
+}
+
+/// Prints the usage error, or the help and version asked for, and exits. Invalid arguments
+/// exit with 1 like other failures rather than clap's 2, which means a partial failure here.
+fn exit_usage(error: clap::Error) -> ! {
+    let _ = error.print();
+    std::process::exit(if error.use_stderr() { 1 } else { 0 })
+}
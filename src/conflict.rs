@@ -0,0 +1,136 @@
+use crate::gist::{self, Gist, GistError};
+use crate::log::warn;
+use crate::manifest::ManifestEntry;
+use crate::paths;
+use crate::progress;
+use crate::store;
+use clap::ValueEnum;
+use reqwest::Client;
+use similar::TextDiff;
+use std::collections::HashSet;
+use std::io::IsTerminal;
+use std::path::Path;
+
+/// What to do with a gist whose files were edited in the archive when a download would
+/// replace them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Resolution {
+    /// Keep the edited files, the other files of the gist are updated
+    Local,
+    /// Replace the edited files with the version on GitHub, moving them to the trash
+    Remote,
+    /// Leave the gist as it is until the next download
+    Skip,
+}
+
+/// Returns the files of a gist edited in the archive since they were downloaded, their
+/// content no longer matching the checksum of the manifest, that a download would write
+/// again: those not in `unchanged`.
+pub fn edited_files(
+    folder: &Path,
+    entry: &ManifestEntry,
+    unchanged: &HashSet<String>,
+) -> Vec<String> {
+    entry
+        .files
+        .iter()
+        .filter(|file| !unchanged.contains(&file.filename))
+        .filter(|file| {
+            let Some(sha256) = &file.sha256 else {
+                return false;
+            };
+            store::read_file(folder, &entry.id, &file.filename)
+                .is_ok_and(|content| store::sha256_hex(&content) != *sha256)
+        })
+        .map(|file| file.filename.clone())
+        .collect()
+}
+
+/// Prints the differences between the archived and the GitHub version of the files.
+async fn show_diff(
+    client: &Client,
+    folder: &Path,
+    gist: &Gist,
+    filenames: &[String],
+) -> Result<(), GistError> {
+    for filename in filenames {
+        let local = store::read_file(folder, &gist.id, filename)?;
+        let remote = match gist
+            .files
+            .iter()
+            .find(|(name, _)| paths::same_name(name, filename))
+        {
+            Some((_, file)) => gist::fetch_file(client, file).await?,
+            None => Vec::new(),
+        };
+        let (local, remote) = (
+            String::from_utf8_lossy(&local),
+            String::from_utf8_lossy(&remote),
+        );
+        let diff = TextDiff::from_lines(local.as_ref(), remote.as_ref());
+        let local_name = format!("archive/{}/{}", gist.id, filename);
+        let remote_name = format!("github/{}/{}", gist.id, filename);
+        eprint!("{}", diff.unified_diff().header(&local_name, &remote_name));
+    }
+    Ok(())
+}
+
+/// Asks on the terminal what to do with the edited files of a gist, showing their
+/// differences with GitHub as many times as asked.
+async fn prompt(
+    client: &Client,
+    folder: &Path,
+    gist: &Gist,
+    edited: &[String],
+) -> Result<Resolution, GistError> {
+    eprintln!(
+        "Gist {} changed on GitHub and these files were edited in the archive: {}",
+        gist.id,
+        edited.join(", ")
+    );
+    loop {
+        eprint!("Keep [l]ocal, keep [r]emote, view [d]iff or [s]kip? ");
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            return Ok(Resolution::Skip);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "l" | "local" => return Ok(Resolution::Local),
+            "r" | "remote" => return Ok(Resolution::Remote),
+            "s" | "skip" => return Ok(Resolution::Skip),
+            "d" | "diff" => show_diff(client, folder, gist, edited).await?,
+            _ => continue,
+        }
+    }
+}
+
+/// Decides what to do with the files of a gist edited in the archive: `resolution` when
+/// given, else the answer of the user when stdin is a terminal. Without either the gist is
+/// skipped, edits are never replaced silently.
+pub async fn resolve(
+    client: &Client,
+    folder: &Path,
+    gist: &Gist,
+    edited: &[String],
+    resolution: Option<Resolution>,
+) -> Resolution {
+    let resolution = match resolution {
+        Some(resolution) => resolution,
+        None if std::io::stdin().is_terminal() => {
+            let answer = progress::without_bars(prompt(client, folder, gist, edited)).await;
+            answer.unwrap_or_else(|e| {
+                warn!("Could not ask about gist {}: {}", gist.id, e);
+                Resolution::Skip
+            })
+        }
+        None => Resolution::Skip,
+    };
+    if resolution == Resolution::Skip {
+        warn!(
+            "Skipping gist {}, its files {} were edited in the archive (see --resolve)",
+            gist.id,
+            edited.join(", ")
+        );
+    }
+    resolution
+}
@@ -0,0 +1,74 @@
+use age::x25519::Recipient;
+use age::Identity;
+use std::io::{self, Read, Write};
+use std::sync::OnceLock;
+
+/// Extension of age-encrypted files in the archive
+pub const AGE_EXTENSION: &str = "age";
+// Every binary age file starts with this header line
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1";
+
+// Identities used to decrypt, loaded once from the file given with `--identity`
+static IDENTITIES: OnceLock<Vec<Box<dyn Identity + Send + Sync>>> = OnceLock::new();
+
+/// Returns true when the content is an age-encrypted file.
+pub fn is_encrypted(content: &[u8]) -> bool {
+    content.starts_with(AGE_MAGIC)
+}
+
+/// Parses an `age1...` X25519 recipient, for use as a clap value parser.
+pub fn parse_recipient(s: &str) -> Result<Recipient, String> {
+    s.parse::<Recipient>()
+        .map_err(|e| format!("invalid age recipient: {}", e))
+}
+
+/// Encrypts the content to the recipient.
+pub fn encrypt(recipient: &Recipient, content: &[u8]) -> io::Result<Vec<u8>> {
+    age::encrypt(recipient, content).map_err(io::Error::other)
+}
+
+/// Encrypts what `content` reads to the recipient, writing the age file to `output`.
+pub fn encrypt_to(
+    recipient: &Recipient,
+    content: &mut dyn Read,
+    output: impl Write,
+) -> io::Result<()> {
+    let recipients = std::iter::once(recipient as &dyn age::Recipient);
+    let encryptor = age::Encryptor::with_recipients(recipients).map_err(io::Error::other)?;
+    let mut writer = encryptor.wrap_output(output)?;
+    io::copy(content, &mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Loads the identities of an age identity file, making them available to `decrypt`.
+pub fn load_identities(path: &str) -> io::Result<()> {
+    let identities = age::IdentityFile::from_file(path.to_string())?
+        .into_identities()
+        .map_err(io::Error::other)?;
+    IDENTITIES
+        .set(identities)
+        .map_err(|_| io::Error::other("identities are already loaded"))
+}
+
+/// Decrypts age-encrypted content with the loaded identities.
+pub fn decrypt(content: &[u8]) -> io::Result<Vec<u8>> {
+    let mut plaintext = Vec::new();
+    decrypt_reader(content)?.read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+/// Returns a reader of the plaintext of the age file read from `input`, decrypted with the
+/// loaded identities as it is read.
+pub fn decrypt_reader<R: Read>(input: R) -> io::Result<impl Read> {
+    let Some(identities) = IDENTITIES.get() else {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "content is age-encrypted, pass --identity to decrypt it",
+        ));
+    };
+    let decryptor = age::Decryptor::new(input).map_err(io::Error::other)?;
+    decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref() as &dyn Identity))
+        .map_err(io::Error::other)
+}
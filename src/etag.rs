@@ -0,0 +1,86 @@
+use crate::log::debug;
+use crate::spool;
+use crate::store::sha256_hex;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// A listing page as GitHub last sent it, sent again with `If-None-Match` so an unchanged
+/// page comes back as a `304 Not Modified`, which doesn't count against the rate limit.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedPage {
+    pub url: String,
+    pub etag: String,
+    /// `Link` header of the page, which a `304` doesn't always repeat
+    pub link: Option<String>,
+    pub body: String,
+}
+
+/// Directory of the cached pages: `$XDG_CACHE_HOME/local-gist/etags`, or
+/// `~/.cache/local-gist/etags`.
+pub fn cache_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        .unwrap_or_default();
+    base.join("local-gist").join("etags")
+}
+
+/// Path of the cached page of `url`. Pages are cached per token, since a token sees secret
+/// gists a listing without one leaves out.
+fn entry_path(url: &str, token: Option<&str>) -> PathBuf {
+    let token = token.map(|token| sha256_hex(token.as_bytes()));
+    let key = sha256_hex(format!("{}\n{}", token.unwrap_or_default(), url).as_bytes());
+    cache_dir().join(format!("{}.json", key))
+}
+
+/// Returns the cached page of `url`, marking it as used so `gc` keeps it.
+pub fn lookup(url: &str, token: Option<&str>) -> Option<CachedPage> {
+    let path = entry_path(url, token);
+    let page: CachedPage = serde_json::from_slice(&std::fs::read(&path).ok()?).ok()?;
+    if let Ok(file) = File::options().append(true).open(&path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+    (page.url == url).then_some(page)
+}
+
+/// Caches a page GitHub sent with an ETag. A cache that can't be written only costs the
+/// next run a full response, so failures are only logged.
+pub fn save(page: &CachedPage, token: Option<&str>) {
+    let path = entry_path(&page.url, token);
+    let written = std::fs::create_dir_all(cache_dir()).and_then(|_| {
+        let tmp = path.with_extension(format!("{}.tmp", spool::unique_name()));
+        std::fs::write(&tmp, serde_json::to_vec(page)?)?;
+        std::fs::rename(&tmp, &path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&tmp);
+        })
+    });
+    if let Err(e) = written {
+        debug!("Could not cache {}: {}", page.url, e);
+    }
+}
+
+/// Deletes the cached pages unused for longer than `max_age`, and the temporary files of
+/// interrupted writes, returning how many and their size.
+pub fn expire(max_age: Duration) -> io::Result<(usize, u64)> {
+    let dir = cache_dir();
+    let (mut removed, mut bytes) = (0, 0);
+    if !dir.exists() {
+        return Ok((removed, bytes));
+    }
+    for entry in std::fs::read_dir(&dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let age = metadata.modified()?.elapsed().unwrap_or_default();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if age > max_age || name.ends_with(".tmp") {
+            debug!("Removing cached page {}", name);
+            std::fs::remove_file(entry.path())?;
+            removed += 1;
+            bytes += metadata.len();
+        }
+    }
+    Ok((removed, bytes))
+}
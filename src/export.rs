@@ -0,0 +1,704 @@
+use crate::filter::GistFilter;
+use crate::gist::GistError;
+use crate::git::{self, GistRemote};
+use crate::log::{debug, info, warn};
+use crate::manifest::{Manifest, ManifestEntry, ManifestFile, MANIFEST_FILE};
+use crate::store;
+use chrono::{DateTime, FixedOffset, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use parquet::basic::ZstdLevel;
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use rusqlite::params;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+/// Returns a manifest holding only the gists selected by the filter.
+fn filtered_manifest(folder: &Path, filter: &GistFilter) -> Result<Manifest, GistError> {
+    let mut manifest = Manifest::load(folder)?;
+    manifest.gists.retain(|_, entry| filter.matches_gist(entry));
+    Ok(manifest)
+}
+
+// Permissions of exported files without a recorded mode
+const DEFAULT_MODE: u32 = 0o644;
+
+/// Returns when a gist last changed, the time its files get in exports so exporting the
+/// same archive twice gives identical files. The Unix epoch when unknown.
+fn gist_time(entry: &ManifestEntry) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(&entry.updated_at)
+        .or_else(|_| DateTime::parse_from_rfc3339(&entry.created_at))
+        .map_or(DateTime::UNIX_EPOCH, |time| time.to_utc())
+}
+
+/// Returns the time of the manifest in exports, that of the newest gist it lists.
+fn manifest_time(manifest: &Manifest) -> DateTime<Utc> {
+    manifest
+        .gists
+        .values()
+        .map(gist_time)
+        .max()
+        .unwrap_or(DateTime::UNIX_EPOCH)
+}
+
+fn file_mode(file: &ManifestFile) -> u32 {
+    file.mode
+        .as_deref()
+        .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+        .unwrap_or(DEFAULT_MODE)
+}
+
+/// Header of a tar entry, with nothing taken from the system exporting it.
+fn tar_header(size: usize, mtime: DateTime<Utc>, mode: u32) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size as u64);
+    header.set_mode(mode);
+    header.set_mtime(mtime.timestamp().max(0) as u64);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_cksum();
+    header
+}
+
+/// Packages the archive into a gzipped tarball holding the manifest followed by every
+/// gist folder, with gists and files in sorted order.
+///
+/// The export is reproducible: entries get the update time of their gist instead of the
+/// time of the export, so the same archive always gives the same bytes.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `out` - Path of the .tar.gz file to create
+/// * `filter` - Selects the gists to export
+///
+/// Returns the number of exported gists.
+pub fn export_tar(folder: &str, out: &str, filter: &GistFilter) -> Result<usize, GistError> {
+    let folder = Path::new(folder);
+    let manifest = filtered_manifest(folder, filter)?;
+
+    let encoder = GzEncoder::new(File::create(out)?, Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+
+    let manifest_json = manifest.to_json()?;
+    builder.append_data(
+        &mut tar_header(manifest_json.len(), manifest_time(&manifest), DEFAULT_MODE),
+        MANIFEST_FILE,
+        manifest_json.as_bytes(),
+    )?;
+
+    for entry in manifest.gists.values() {
+        for file in &entry.files {
+            let content = match store::read_file(folder, &entry.id, &file.filename) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("Skipping {}/{}: {}", entry.id, file.filename, e);
+                    continue;
+                }
+            };
+            builder.append_data(
+                &mut tar_header(content.len(), gist_time(entry), file_mode(file)),
+                Path::new(&entry.id).join(&file.filename),
+                content.as_slice(),
+            )?;
+        }
+    }
+
+    builder.into_inner()?.finish()?;
+    info!("Exported {} gists to {}", manifest.gists.len(), out);
+
+    Ok(manifest.gists.len())
+}
+
+fn zip_options(mtime: DateTime<Utc>, mode: u32) -> SimpleFileOptions {
+    // Zip times can't go before 1980 and are local times without zone, UTC is used
+    let mtime = zip::DateTime::try_from(mtime.naive_utc()).unwrap_or_default();
+    SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(mtime)
+        .unix_permissions(mode)
+}
+
+/// Adds the files of a gist to a zip, under `prefix` when given.
+fn zip_gist(
+    zip: &mut ZipWriter<File>,
+    folder: &Path,
+    entry: &ManifestEntry,
+    prefix: Option<&str>,
+) -> Result<(), GistError> {
+    for file in &entry.files {
+        let content = match store::read_file(folder, &entry.id, &file.filename) {
+            Ok(content) => content,
+            Err(e) => {
+                warn!("Skipping {}/{}: {}", entry.id, file.filename, e);
+                continue;
+            }
+        };
+        let name = match prefix {
+            Some(prefix) => format!("{}/{}", prefix, file.filename),
+            None => file.filename.clone(),
+        };
+        zip.start_file(name, zip_options(gist_time(entry), file_mode(file)))?;
+        zip.write_all(&content)?;
+    }
+    Ok(())
+}
+
+/// Packages the archive into zip files, either a single zip holding the manifest and every
+/// gist folder, or one zip per gist holding just its files. Like tarballs, zips are
+/// reproducible.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `out` - Path of the .zip file to create, or a directory with `per_gist`
+/// * `per_gist` - Create `<out>/<gist id>.zip` for every gist
+/// * `filter` - Selects the gists to export
+///
+/// Returns the number of exported gists.
+pub fn export_zip(
+    folder: &str,
+    out: &str,
+    per_gist: bool,
+    filter: &GistFilter,
+) -> Result<usize, GistError> {
+    let folder = Path::new(folder);
+    let manifest = filtered_manifest(folder, filter)?;
+
+    if per_gist {
+        std::fs::create_dir_all(out)?;
+        for entry in manifest.gists.values() {
+            let path = Path::new(out).join(format!("{}.zip", entry.id));
+            let mut zip = ZipWriter::new(File::create(path)?);
+            zip_gist(&mut zip, folder, entry, None)?;
+            zip.finish()?;
+        }
+    } else {
+        let mut zip = ZipWriter::new(File::create(out)?);
+        let options = zip_options(manifest_time(&manifest), DEFAULT_MODE);
+        zip.start_file(MANIFEST_FILE, options)?;
+        zip.write_all(manifest.to_json()?.as_bytes())?;
+        for entry in manifest.gists.values() {
+            zip_gist(&mut zip, folder, entry, Some(&entry.id))?;
+        }
+        zip.finish()?;
+    }
+    info!("Exported {} gists to {}", manifest.gists.len(), out);
+
+    Ok(manifest.gists.len())
+}
+
+// Schema of the SQLite export, its version kept in `PRAGMA user_version`. Times are RFC 3339
+// strings, flags are 0 or 1
+const SQLITE_SCHEMA_VERSION: u32 = 1;
+const SQLITE_SCHEMA: &str = "
+-- Accounts owning gists
+CREATE TABLE owners (
+    login TEXT PRIMARY KEY,
+    gists INTEGER NOT NULL
+);
+-- Languages of the files, as detected by GitHub
+CREATE TABLE languages (
+    name TEXT PRIMARY KEY,
+    files INTEGER NOT NULL,
+    bytes INTEGER NOT NULL
+);
+CREATE TABLE gists (
+    id TEXT PRIMARY KEY,
+    owner TEXT NOT NULL REFERENCES owners (login),
+    description TEXT,
+    html_url TEXT NOT NULL,
+    public INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    comments INTEGER NOT NULL,
+    starred INTEGER NOT NULL
+);
+-- Files of the gists, with the content of text files
+CREATE TABLE files (
+    gist_id TEXT NOT NULL REFERENCES gists (id),
+    filename TEXT NOT NULL,
+    language TEXT REFERENCES languages (name),
+    size INTEGER NOT NULL,
+    binary INTEGER NOT NULL,
+    license TEXT,
+    sha256 TEXT,
+    content TEXT,
+    PRIMARY KEY (gist_id, filename)
+);
+CREATE INDEX gists_owner ON gists (owner);
+CREATE INDEX files_language ON files (language);
+";
+
+/// Writes the catalog of the archive into a new SQLite database with a documented schema
+/// (owners, languages, gists and files), for ad-hoc queries in any SQLite client. It
+/// doesn't depend on the manifest format or the search index, which may change.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `out` - Path of the database to create, replaced when it exists
+/// * `filter` - Selects the gists to export
+///
+/// Returns the number of exported gists.
+pub fn export_sqlite(folder: &str, out: &str, filter: &GistFilter) -> Result<usize, GistError> {
+    let folder = Path::new(folder);
+    let manifest = filtered_manifest(folder, filter)?;
+
+    if Path::new(out).exists() {
+        std::fs::remove_file(out)?;
+    }
+    let mut conn = rusqlite::Connection::open(out)?;
+    conn.pragma_update(None, "user_version", SQLITE_SCHEMA_VERSION)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(SQLITE_SCHEMA)?;
+
+    let mut owners: BTreeMap<&str, i64> = BTreeMap::new();
+    let mut languages: BTreeMap<&str, (i64, i64)> = BTreeMap::new();
+    for entry in manifest.gists.values() {
+        *owners.entry(&entry.owner).or_default() += 1;
+        for file in &entry.files {
+            if let Some(language) = &file.language {
+                let (files, bytes) = languages.entry(language).or_default();
+                *files += 1;
+                *bytes += i64::from(file.size);
+            }
+        }
+    }
+    for (login, gists) in &owners {
+        tx.execute(
+            "INSERT INTO owners (login, gists) VALUES (?1, ?2)",
+            params![login, gists],
+        )?;
+    }
+    for (name, (files, bytes)) in &languages {
+        tx.execute(
+            "INSERT INTO languages (name, files, bytes) VALUES (?1, ?2, ?3)",
+            params![name, files, bytes],
+        )?;
+    }
+
+    for entry in manifest.gists.values() {
+        tx.execute(
+            "INSERT INTO gists (id, owner, description, html_url, public, created_at, updated_at, comments, starred)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                entry.id,
+                entry.owner,
+                entry.description,
+                entry.html_url,
+                entry.public,
+                entry.created_at,
+                entry.updated_at,
+                entry.comments,
+                entry.starred
+            ],
+        )?;
+        for file in &entry.files {
+            // Binary files and files missing from the archive have no content
+            let content = match store::read_file(folder, &entry.id, &file.filename) {
+                Ok(_) if file.binary => None,
+                Ok(content) => Some(String::from_utf8_lossy(&content).into_owned()),
+                Err(e) => {
+                    debug!(
+                        "Exporting {}/{} without content: {}",
+                        entry.id, file.filename, e
+                    );
+                    None
+                }
+            };
+            tx.execute(
+                "INSERT INTO files (gist_id, filename, language, size, binary, license, sha256, content)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    entry.id,
+                    file.filename,
+                    file.language,
+                    file.size,
+                    file.binary,
+                    file.license,
+                    file.sha256,
+                    content
+                ],
+            )?;
+        }
+    }
+    tx.commit()?;
+    info!("Exported {} gists to {}", manifest.gists.len(), out);
+
+    Ok(manifest.gists.len())
+}
+
+// Schemas of the Parquet exports, a row per gist and a row per file. Times are UTC
+// timestamps in milliseconds, null when the manifest has none
+const GISTS_PARQUET_SCHEMA: &str = "
+message gist {
+    REQUIRED BYTE_ARRAY id (STRING);
+    REQUIRED BYTE_ARRAY owner (STRING);
+    OPTIONAL BYTE_ARRAY description (STRING);
+    REQUIRED BYTE_ARRAY html_url (STRING);
+    REQUIRED BOOLEAN public;
+    OPTIONAL INT64 created_at (TIMESTAMP(MILLIS, true));
+    OPTIONAL INT64 updated_at (TIMESTAMP(MILLIS, true));
+    REQUIRED INT32 comments;
+    REQUIRED BOOLEAN starred;
+    REQUIRED INT32 files;
+    REQUIRED INT64 size;
+}
+";
+const FILES_PARQUET_SCHEMA: &str = "
+message file {
+    REQUIRED BYTE_ARRAY gist_id (STRING);
+    REQUIRED BYTE_ARRAY owner (STRING);
+    REQUIRED BYTE_ARRAY filename (STRING);
+    OPTIONAL BYTE_ARRAY language (STRING);
+    REQUIRED INT64 size;
+    REQUIRED BOOLEAN binary;
+    OPTIONAL BYTE_ARRAY license (STRING);
+    OPTIONAL BYTE_ARRAY sha256 (STRING);
+    OPTIONAL INT64 gist_updated_at (TIMESTAMP(MILLIS, true));
+}
+";
+
+/// The values of a column of a Parquet export, in the order of its schema. `None` is null.
+enum ParquetColumn {
+    Text(Vec<Option<String>>),
+    Bool(Vec<bool>),
+    Int32(Vec<i32>),
+    Int64(Vec<Option<i64>>),
+}
+
+fn timestamp_millis(time: &str) -> Option<i64> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|time| time.timestamp_millis())
+}
+
+/// Definition levels of an optional column, 1 for a value and 0 for null. Ignored by
+/// required columns.
+fn definition_levels<T>(values: &[Option<T>]) -> Vec<i16> {
+    values.iter().map(|value| value.is_some() as i16).collect()
+}
+
+/// Writes the columns into a zstd-compressed Parquet file with the given schema, as a
+/// single row group.
+fn write_parquet(path: &Path, schema: &str, columns: Vec<ParquetColumn>) -> Result<(), GistError> {
+    let schema = Arc::new(parse_message_type(schema)?);
+    let properties = WriterProperties::builder()
+        .set_compression(parquet::basic::Compression::ZSTD(ZstdLevel::default()))
+        .build();
+    let mut writer = SerializedFileWriter::new(File::create(path)?, schema, Arc::new(properties))?;
+    let mut row_group = writer.next_row_group()?;
+    for column in columns {
+        let Some(mut column_writer) = row_group.next_column()? else {
+            break;
+        };
+        match column {
+            ParquetColumn::Text(values) => {
+                let levels = definition_levels(&values);
+                let values: Vec<ByteArray> = values
+                    .into_iter()
+                    .flatten()
+                    .map(|value| ByteArray::from(value.into_bytes()))
+                    .collect();
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+            ParquetColumn::Bool(values) => {
+                column_writer
+                    .typed::<BoolType>()
+                    .write_batch(&values, None, None)?;
+            }
+            ParquetColumn::Int32(values) => {
+                column_writer
+                    .typed::<Int32Type>()
+                    .write_batch(&values, None, None)?;
+            }
+            ParquetColumn::Int64(values) => {
+                let levels = definition_levels(&values);
+                let values: Vec<i64> = values.into_iter().flatten().collect();
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, Some(&levels), None)?;
+            }
+        }
+        column_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Writes the metadata of the gists and their files as Parquet, `gists.parquet` and
+/// `files.parquet` in the output directory, for loading into DuckDB, Polars and other
+/// analytics tools. Contents aren't exported.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `out` - Directory the Parquet files are written into
+/// * `filter` - Selects the gists to export
+///
+/// Returns the number of exported gists.
+pub fn export_parquet(folder: &str, out: &str, filter: &GistFilter) -> Result<usize, GistError> {
+    let manifest = filtered_manifest(Path::new(folder), filter)?;
+    let gists: Vec<&ManifestEntry> = manifest.gists.values().collect();
+    let files: Vec<(&ManifestEntry, &ManifestFile)> = gists
+        .iter()
+        .flat_map(|entry| entry.files.iter().map(move |file| (*entry, file)))
+        .collect();
+    let text = |value: &str| Some(value.to_string());
+
+    std::fs::create_dir_all(out)?;
+    write_parquet(
+        &Path::new(out).join("gists.parquet"),
+        GISTS_PARQUET_SCHEMA,
+        vec![
+            ParquetColumn::Text(gists.iter().map(|entry| text(&entry.id)).collect()),
+            ParquetColumn::Text(gists.iter().map(|entry| text(&entry.owner)).collect()),
+            ParquetColumn::Text(
+                gists
+                    .iter()
+                    .map(|entry| entry.description.clone())
+                    .collect(),
+            ),
+            ParquetColumn::Text(gists.iter().map(|entry| text(&entry.html_url)).collect()),
+            ParquetColumn::Bool(gists.iter().map(|entry| entry.public).collect()),
+            ParquetColumn::Int64(
+                gists
+                    .iter()
+                    .map(|entry| timestamp_millis(&entry.created_at))
+                    .collect(),
+            ),
+            ParquetColumn::Int64(
+                gists
+                    .iter()
+                    .map(|entry| timestamp_millis(&entry.updated_at))
+                    .collect(),
+            ),
+            ParquetColumn::Int32(gists.iter().map(|entry| entry.comments as i32).collect()),
+            ParquetColumn::Bool(gists.iter().map(|entry| entry.starred).collect()),
+            ParquetColumn::Int32(gists.iter().map(|entry| entry.files.len() as i32).collect()),
+            ParquetColumn::Int64(
+                gists
+                    .iter()
+                    .map(|entry| Some(entry.files.iter().map(|file| i64::from(file.size)).sum()))
+                    .collect(),
+            ),
+        ],
+    )?;
+    write_parquet(
+        &Path::new(out).join("files.parquet"),
+        FILES_PARQUET_SCHEMA,
+        vec![
+            ParquetColumn::Text(files.iter().map(|(entry, _)| text(&entry.id)).collect()),
+            ParquetColumn::Text(files.iter().map(|(entry, _)| text(&entry.owner)).collect()),
+            ParquetColumn::Text(files.iter().map(|(_, file)| text(&file.filename)).collect()),
+            ParquetColumn::Text(
+                files
+                    .iter()
+                    .map(|(_, file)| file.language.clone())
+                    .collect(),
+            ),
+            ParquetColumn::Int64(
+                files
+                    .iter()
+                    .map(|(_, file)| Some(i64::from(file.size)))
+                    .collect(),
+            ),
+            ParquetColumn::Bool(files.iter().map(|(_, file)| file.binary).collect()),
+            ParquetColumn::Text(files.iter().map(|(_, file)| file.license.clone()).collect()),
+            ParquetColumn::Text(files.iter().map(|(_, file)| file.sha256.clone()).collect()),
+            ParquetColumn::Int64(
+                files
+                    .iter()
+                    .map(|(entry, _)| timestamp_millis(&entry.updated_at))
+                    .collect(),
+            ),
+        ],
+    )?;
+    info!(
+        "Exported {} gists and {} files to {}",
+        gists.len(),
+        files.len(),
+        out
+    );
+
+    Ok(gists.len())
+}
+
+/// A revision of a gist, replayed as a commit of the monorepo.
+struct GistCommit {
+    gist_id: String,
+    sha: String,
+    author_name: String,
+    author_email: String,
+    date: DateTime<FixedOffset>,
+}
+
+/// Fetches the history of a gist into the repository, only its last `depth` revisions when
+/// given, returning its revisions oldest first.
+fn fetch_history(
+    repo: &Path,
+    id: &str,
+    remote: &GistRemote,
+    depth: Option<u32>,
+) -> Result<Vec<GistCommit>, GistError> {
+    let refname = format!("refs/gists/{}", id);
+    let refspec = format!("+HEAD:{}", refname);
+    let depth = depth.map(|depth| format!("--depth={}", depth));
+    let url = remote.url(id);
+    let env = remote.env();
+    let env: Vec<(&str, &str)> = env
+        .iter()
+        .map(|(name, value)| (*name, value.as_str()))
+        .collect();
+    let mut args = vec!["fetch", "-q", "--no-tags"];
+    args.extend(depth.as_deref());
+    args.extend([url.as_str(), refspec.as_str()]);
+    git::run(repo, &args, &env)?;
+    let log = git::run(
+        repo,
+        &[
+            "log",
+            "--reverse",
+            "--format=%H%x00%an%x00%ae%x00%aI",
+            &refname,
+        ],
+        &[],
+    )?;
+
+    let mut commits = Vec::new();
+    for line in String::from_utf8_lossy(&log.stdout).lines() {
+        let fields: Vec<&str> = line.split('\0').collect();
+        let [sha, author_name, author_email, date] = fields[..] else {
+            continue;
+        };
+        let Ok(date) = DateTime::parse_from_rfc3339(date) else {
+            warn!(
+                "Skipping commit {} of gist {}: invalid date {}",
+                sha, id, date
+            );
+            continue;
+        };
+        commits.push(GistCommit {
+            gist_id: id.to_string(),
+            sha: sha.to_string(),
+            author_name: author_name.to_string(),
+            author_email: author_email.to_string(),
+            date,
+        });
+    }
+    Ok(commits)
+}
+
+/// Creates a git repository holding a folder per gist, with the full revision history of
+/// every gist replayed as commits in chronological order, keeping the original authors
+/// and dates.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `out` - Directory of the repository to create, must not exist or be empty
+/// * `filter` - Selects the gists to export
+/// * `remote` - How the gist repositories are fetched
+/// * `depth` - Revisions replayed per gist, the newest ones, all of them when `None`
+///
+/// Returns the number of exported gists.
+pub fn export_repo(
+    folder: &str,
+    out: &str,
+    filter: &GistFilter,
+    remote: &GistRemote,
+    depth: Option<u32>,
+) -> Result<usize, GistError> {
+    let folder = Path::new(folder);
+    let manifest = filtered_manifest(folder, filter)?;
+    let repo = Path::new(out);
+    if repo.exists() && std::fs::read_dir(repo)?.next().is_some() {
+        return Err(GistError::Io(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} is not empty", out),
+        )));
+    }
+    std::fs::create_dir_all(repo)?;
+    git::run(repo, &["init", "-q"], &[])?;
+
+    let mut commits = Vec::new();
+    let mut exported = 0;
+    for entry in manifest.gists.values() {
+        match fetch_history(repo, &entry.id, remote, depth) {
+            Ok(history) => {
+                commits.extend(history);
+                exported += 1;
+            }
+            Err(e) => warn!("Skipping gist {}: {}", entry.id, e),
+        }
+    }
+    // The sort is stable, so revisions sharing a date stay in the order of their gist
+    commits.sort_by_key(|c| c.date);
+
+    let mut added = HashSet::new();
+    for commit in &commits {
+        let id = commit.gist_id.as_str();
+        git::run(
+            repo,
+            &["rm", "-r", "-q", "--cached", "--ignore-unmatch", "--", id],
+            &[],
+        )?;
+        git::run(
+            repo,
+            &["read-tree", &format!("--prefix={}/", id), &commit.sha],
+            &[],
+        )?;
+        let message = match manifest.gists[id].description.as_deref() {
+            _ if !added.insert(id) => format!("Update gist {}", id),
+            Some(description) if !description.is_empty() => {
+                format!("Add gist {}: {}", id, description)
+            }
+            _ => format!("Add gist {}", id),
+        };
+        let date = commit.date.to_rfc3339();
+        git::run(
+            repo,
+            &[
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                message.trim_end_matches([':', ' ']),
+            ],
+            &[
+                ("GIT_AUTHOR_NAME", &commit.author_name),
+                ("GIT_AUTHOR_EMAIL", &commit.author_email),
+                ("GIT_AUTHOR_DATE", &date),
+                ("GIT_COMMITTER_DATE", &date),
+            ],
+        )?;
+    }
+
+    // Materialize the final tree and drop the fetched gist histories
+    git::run(repo, &["reset", "-q", "--hard"], &[])?;
+    for entry in manifest.gists.values() {
+        let refname = format!("refs/gists/{}", entry.id);
+        git::run(repo, &["update-ref", "-d", &refname], &[])?;
+    }
+    // The commits cut off by --depth belong to the dropped histories only, so the
+    // repository is complete and isn't left marked as shallow
+    let shallow = repo.join(".git").join("shallow");
+    if shallow.exists() {
+        std::fs::remove_file(shallow)?;
+    }
+
+    info!(
+        "Exported {} gists with {} commits to {}",
+        exported,
+        commits.len(),
+        out
+    );
+    Ok(exported)
+}
@@ -0,0 +1,186 @@
+use crate::filter::GistFilter;
+use crate::gist::GistError;
+use crate::log::{info, warn};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::store;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+// Executables on PATH with this prefix are exporter plugins, named by the rest of their name
+const PLUGIN_PREFIX: &str = "local-gist-export-";
+// Argument asking a plugin to print its manifest instead of exporting
+const DESCRIBE_ARG: &str = "--describe";
+// Version of the messages sent to plugins, part of the `start` message
+const PROTOCOL_VERSION: u32 = 1;
+
+/// A destination gists are exported to one at a time.
+pub trait Exporter {
+    /// Exports a gist with the content of its files.
+    fn export(
+        &mut self,
+        entry: &ManifestEntry,
+        files: &[(String, Vec<u8>)],
+    ) -> Result<(), GistError>;
+
+    /// Completes the export once every gist was passed to `export`.
+    fn finish(&mut self) -> Result<(), GistError>;
+}
+
+/// What a plugin prints when run with `--describe`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+/// An exporter implemented by an external process.
+///
+/// The plugin is started with the extra arguments given by the user and reads JSON
+/// messages from stdin, one per line: `{"type": "start", "protocol": 1}`, then for every
+/// gist `{"type": "gist", "gist": <manifest entry>, "files": [...]}` where each file has a
+/// `filename` and either a UTF-8 `content` or a `content_base64`, and finally
+/// `{"type": "end"}`. The export fails when the plugin exits non-zero.
+pub struct PluginExporter {
+    name: String,
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+/// Returns the executables of exporter plugins found on PATH, without duplicates.
+fn plugin_executables() -> Vec<(String, PathBuf)> {
+    let mut plugins: Vec<(String, PathBuf)> = Vec::new();
+    let Some(path) = std::env::var_os("PATH") else {
+        return plugins;
+    };
+    for dir in std::env::split_paths(&path) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let Some(name) = file_name.strip_prefix(PLUGIN_PREFIX) else {
+                continue;
+            };
+            // Earlier PATH entries win, like for any other command
+            if !plugins.iter().any(|(existing, _)| existing == name) {
+                plugins.push((name.to_string(), entry.path()));
+            }
+        }
+    }
+    plugins.sort();
+    plugins
+}
+
+/// Finds the exporter plugins on PATH and asks each one for its manifest.
+pub fn discover_plugins() -> Vec<PluginManifest> {
+    plugin_executables()
+        .into_iter()
+        .map(|(name, path)| {
+            let described = Command::new(&path)
+                .arg(DESCRIBE_ARG)
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .and_then(|output| serde_json::from_slice::<PluginManifest>(&output.stdout).ok());
+            described.unwrap_or_else(|| {
+                warn!("{} didn't describe itself", path.display());
+                PluginManifest {
+                    name,
+                    ..Default::default()
+                }
+            })
+        })
+        .collect()
+}
+
+impl PluginExporter {
+    /// Starts the plugin `local-gist-export-<name>` found on PATH.
+    pub fn start(name: &str, args: &[String]) -> Result<Self, GistError> {
+        let Some((_, path)) = plugin_executables().into_iter().find(|(n, _)| n == name) else {
+            return Err(GistError::NotFound(format!(
+                "exporter plugin {}{} on PATH",
+                PLUGIN_PREFIX, name
+            )));
+        };
+        let mut child = Command::new(path)
+            .args(args)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take();
+        let mut exporter = PluginExporter {
+            name: name.to_string(),
+            child,
+            stdin,
+        };
+        exporter.send(&json!({"type": "start", "protocol": PROTOCOL_VERSION}))?;
+        Ok(exporter)
+    }
+
+    fn send(&mut self, message: &serde_json::Value) -> Result<(), GistError> {
+        let stdin = self.stdin.as_mut().expect("stdin is open until finish");
+        serde_json::to_writer(&mut *stdin, message).map_err(GistError::Manifest)?;
+        stdin.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+impl Exporter for PluginExporter {
+    fn export(
+        &mut self,
+        entry: &ManifestEntry,
+        files: &[(String, Vec<u8>)],
+    ) -> Result<(), GistError> {
+        let files: Vec<serde_json::Value> = files
+            .iter()
+            .map(|(filename, content)| match std::str::from_utf8(content) {
+                Ok(text) => json!({"filename": filename, "content": text}),
+                Err(_) => json!({"filename": filename, "content_base64": BASE64.encode(content)}),
+            })
+            .collect();
+        self.send(&json!({"type": "gist", "gist": entry, "files": files}))
+    }
+
+    fn finish(&mut self) -> Result<(), GistError> {
+        self.send(&json!({"type": "end"}))?;
+        // Closing stdin tells the plugin there is nothing more to read
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(GistError::Target(format!(
+                "exporter plugin {} failed with {}",
+                self.name, status
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Passes every gist of the archive selected by the filter to an exporter.
+///
+/// Returns the number of exported gists.
+pub fn export_with(
+    folder: &str,
+    filter: &GistFilter,
+    exporter: &mut dyn Exporter,
+) -> Result<usize, GistError> {
+    let folder = Path::new(folder);
+    let mut manifest = Manifest::load(folder)?;
+    manifest.gists.retain(|_, entry| filter.matches_gist(entry));
+
+    for entry in manifest.gists.values() {
+        let files = store::read_gist_files(folder, entry)?;
+        exporter.export(entry, &files)?;
+    }
+    exporter.finish()?;
+    info!("Exported {} gists", manifest.gists.len());
+
+    Ok(manifest.gists.len())
+}
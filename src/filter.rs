@@ -0,0 +1,52 @@
+use crate::gist::GistError;
+use crate::manifest::{ManifestEntry, ManifestFile};
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+
+/// Selects files by language and filename pattern; a gist matches when any of its files do.
+#[derive(Debug, Default, Clone)]
+pub struct GistFilter {
+    languages: Vec<String>,
+    filename: Option<GlobMatcher>,
+}
+
+impl GistFilter {
+    /// # Arguments
+    /// * `languages` - Languages to keep (case-insensitive), all languages when empty
+    /// * `filename` - Optional glob the filename has to match, e.g. `*.sh`
+    pub fn new(languages: &[String], filename: Option<&str>) -> Result<Self, GistError> {
+        let filename = match filename {
+            Some(pattern) => Some(Glob::new(pattern)?.compile_matcher()),
+            None => None,
+        };
+        Ok(GistFilter {
+            languages: languages.iter().map(|l| l.to_lowercase()).collect(),
+            filename,
+        })
+    }
+
+    pub fn matches_file(&self, file: &ManifestFile) -> bool {
+        let language_matches = self.languages.is_empty()
+            || file
+                .language
+                .as_deref()
+                .is_some_and(|l| self.languages.contains(&l.to_lowercase()));
+        let filename_matches = self
+            .filename
+            .as_ref()
+            .is_none_or(|glob| glob.is_match(&file.filename));
+        language_matches && filename_matches
+    }
+
+    pub fn matches_gist(&self, entry: &ManifestEntry) -> bool {
+        entry.files.iter().any(|f| self.matches_file(f))
+    }
+}
+
+/// Compiles filename globs into a set matching any of them, empty when there are none.
+pub fn glob_set(patterns: &[String]) -> Result<GlobSet, GistError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    Ok(builder.build()?)
+}
@@ -0,0 +1,74 @@
+use crate::etag;
+use crate::gist::GistError;
+use crate::index::Index;
+use crate::manifest::Manifest;
+use crate::spool;
+use crate::stats::format_bytes;
+use crate::store;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+/// What a garbage collection of the archive reclaimed.
+#[derive(Debug, Default, Serialize)]
+pub struct GcReport {
+    /// Objects of the deduplicated store no gist used anymore
+    pub objects: usize,
+    pub object_bytes: u64,
+    /// Files left in the spool by interrupted downloads
+    pub partial: usize,
+    pub partial_bytes: u64,
+    /// Cached listing pages unused for longer than the maximum age
+    pub cached_pages: usize,
+    pub cached_bytes: u64,
+    /// Space freed by compacting the search index
+    pub index_bytes: u64,
+}
+
+impl GcReport {
+    pub fn print(&self) {
+        println!(
+            "Removed {} orphaned objects ({}), {} partial downloads ({}) and {} stale cached pages ({}), compacted the index ({}), {} reclaimed in total",
+            self.objects,
+            format_bytes(self.object_bytes),
+            self.partial,
+            format_bytes(self.partial_bytes),
+            self.cached_pages,
+            format_bytes(self.cached_bytes),
+            format_bytes(self.index_bytes),
+            format_bytes(
+                self.object_bytes + self.partial_bytes + self.cached_bytes + self.index_bytes
+            )
+        );
+    }
+}
+
+/// Drops the objects of the deduplicated store that no gist of the manifest uses, the
+/// partial downloads of interrupted runs and the cached listing pages unused for longer
+/// than `cache_max_age`, and compacts the search index, so long-lived archives don't keep growing with junk.
+pub fn gc(folder: &Path, cache_max_age: Duration) -> Result<GcReport, GistError> {
+    let manifest = Manifest::load(folder)?;
+    let referenced: HashSet<String> = manifest
+        .gists
+        .values()
+        .flat_map(|entry| &entry.files)
+        .filter_map(|file| file.sha256.clone())
+        .collect();
+    let (objects, object_bytes) = store::remove_orphaned_objects(folder, &referenced)?;
+    let (partial, partial_bytes) = spool::remove_leftovers(folder)?;
+    let (cached_pages, cached_bytes) = etag::expire(cache_max_age)?;
+
+    let mut index = Index::open(folder)?;
+    let index_bytes = index.compact(folder)?;
+
+    Ok(GcReport {
+        objects,
+        object_bytes,
+        partial,
+        partial_bytes,
+        cached_pages,
+        cached_bytes,
+        index_bytes,
+    })
+}
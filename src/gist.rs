@@ -1,26 +1,234 @@
-use reqwest::header::HeaderMap;
-use reqwest::{Client, Error as ReqwestError};
+use crate::auth::{self, github_token};
+use crate::etag::{self, CachedPage};
+use crate::log::{debug, info, warn, Instrument};
+use crate::metrics;
+use crate::spool::{Spool, Spooled};
+use crate::store::{self, Eol, Store};
+use age::x25519::Recipient;
+use chrono::{DateTime, SecondsFormat, Utc};
+use globset::GlobSet;
+use reqwest::header::{HeaderMap, HeaderValue, ETAG, IF_NONE_MATCH, LINK};
+use reqwest::{Client, Error as ReqwestError, Response, StatusCode};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::io::Error as IoError;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use thiserror::Error;
-use tokio::time::sleep;
-use tracing::{debug, info, instrument};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, sleep_until, Instant};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Error, Debug)]
 pub enum GistError {
     #[error("HTTP request failed: {0}")]
-    RequestError(#[from] ReqwestError),
+    Request(#[from] ReqwestError),
     #[error("IO operation failed: {0}")]
-    IoError(#[from] IoError),
+    Io(#[from] IoError),
     #[error("JSON parsing failed: {0}\nResponse text: {1}")]
-    JsonError(serde_json::Error, String),
+    Json(serde_json::Error, String),
+    #[error("Manifest is invalid: {0}")]
+    Manifest(serde_json::Error),
+    #[error("Search index operation failed: {0}")]
+    Index(#[from] rusqlite::Error),
+    #[error("Invalid filename pattern: {0}")]
+    Pattern(#[from] globset::Error),
+    #[error("Invalid regular expression: {0}")]
+    Regex(#[from] regex::Error),
+    #[error("Zip operation failed: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("Parquet operation failed: {0}")]
+    Parquet(#[from] parquet::errors::ParquetError),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Clipboard operation failed: {0}")]
+    Clipboard(#[from] arboard::Error),
+    #[error("Ambiguous gist: {0}")]
+    Ambiguous(String),
+    #[error("Storage target error: {0}")]
+    Target(String),
+    #[error("Git command failed: {0}")]
+    Git(String),
+    #[error("Checksum mismatch: {0}")]
+    Checksum(String),
+    #[error("Signature check failed: {0}")]
+    Signature(String),
+    #[error("Authentication failed: {0}")]
+    Auth(String),
+    #[error("Config error: {0}")]
+    Config(String),
+    #[error("Invalid query: {0}")]
+    Query(#[from] jmespath::JmespathError),
+    #[error("Rate limit exhausted: {0}")]
+    RateLimited(String),
+    #[error("Secondary rate limit hit: {0}")]
+    SecondaryRateLimited(String),
+    #[error("Partial failure: {0}")]
+    Partial(String),
+    #[error("Request budget used up: all {0} requests allowed by --max-requests were sent")]
+    Budget(u64),
+    #[error("Time limit of {0}s set by --max-duration reached before the end of the run")]
+    Deadline(u64),
+    #[error("Interrupted before the end of the run")]
+    Cancelled,
+    #[cfg(feature = "otel")]
+    #[error("Trace exporter could not be set up: {0}")]
+    Telemetry(#[from] opentelemetry_otlp::ExporterBuildError),
 }
 
 // GitHub API base URL
 const GITHUB_API_URL: &str = "https://api.github.com";
+// User agent sent when `--user-agent` isn't given
+const DEFAULT_USER_AGENT: &str = "RustRequestClient";
+// Largest page size the listing API accepts
+pub const MAX_PER_PAGE: u32 = 100;
+// Number of leading bytes looked at for NUL bytes when detecting binary content
+pub const BINARY_SNIFF_LEN: usize = 8000;
+// Maximum number of listing pages fetched at the same time
+const PAGE_CONCURRENCY: usize = 8;
+// Attempts at a request failing with a transient error, and the delay before the first
+// retry, doubled after each one
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+// How long requests pause after a secondary rate limit when GitHub doesn't say, the
+// minute its documentation asks for
+const SECONDARY_PAUSE: Duration = Duration::from_secs(60);
+// Messages GitHub refuses requests with when they come too fast, rather than too many
+const SECONDARY_LIMIT_MESSAGES: [&str; 2] = ["secondary rate limit", "abuse detection"];
+
+// Whether requests wait for an exhausted rate limit to reset rather than failing
+static WAIT_FOR_RESET: AtomicBool = AtomicBool::new(true);
+// Until when every request waits after a secondary rate limit, shared by all tasks
+static PAUSED_UNTIL: Mutex<Option<Instant>> = Mutex::new(None);
+// Requests sent to GitHub during the run, and how many `--max-requests` allows
+static REQUESTS_SENT: AtomicU64 = AtomicU64::new(0);
+static MAX_REQUESTS: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// Whether a failed request is worth sending again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// The request would fail the same way, e.g. on 401 or 404
+    Never,
+    /// Server errors and network failures that usually go away, retried with backoff
+    Backoff,
+    /// The rate limit is exhausted, retried once it resets
+    WaitForReset,
+    /// Requests came too fast, retried once the pause they caused is over
+    AfterPause,
+}
+
+impl GistError {
+    /// Classifies the error by whether retrying the request can help.
+    pub fn retry(&self) -> Retry {
+        match self {
+            GistError::RateLimited(_) => Retry::WaitForReset,
+            GistError::SecondaryRateLimited(_) => Retry::AfterPause,
+            GistError::Request(e) => match e.status() {
+                Some(status) if status.is_server_error() => match status {
+                    StatusCode::NOT_IMPLEMENTED | StatusCode::HTTP_VERSION_NOT_SUPPORTED => {
+                        Retry::Never
+                    }
+                    _ => Retry::Backoff,
+                },
+                Some(StatusCode::REQUEST_TIMEOUT) => Retry::Backoff,
+                Some(_) => Retry::Never,
+                // Failing to decode a body that arrived won't change on retry, failing to
+                // connect or to receive it may
+                None if e.is_decode() || e.is_builder() => Retry::Never,
+                None => Retry::Backoff,
+            },
+            GistError::Io(e) => match e.kind() {
+                std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::UnexpectedEof
+                | std::io::ErrorKind::Interrupted => Retry::Backoff,
+                _ => Retry::Never,
+            },
+            _ => Retry::Never,
+        }
+    }
+}
+
+/// Sends a request with `send`, again when it fails with a transient error: server errors
+/// and network failures are retried with backoff and secondary rate limits after their
+/// pause, up to `ATTEMPTS` in all, and an exhausted rate limit is waited out once. Other
+/// errors are returned at once.
+async fn with_retry<T, F, Fut>(client: &Client, what: &str, send: F) -> Result<T, GistError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, GistError>>,
+{
+    with_retry_until(client, what, &CancellationToken::new(), send).await
+}
+
+/// Like `with_retry`, failing with `GistError::Cancelled` as soon as `cancel` is cancelled,
+/// whether the request is on its way or waiting to be retried.
+async fn with_retry_until<T, F, Fut>(
+    client: &Client,
+    what: &str,
+    cancel: &CancellationToken,
+    mut send: F,
+) -> Result<T, GistError>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<T, GistError>>,
+{
+    let mut delay = RETRY_DELAY;
+    let mut attempt = 1;
+    let mut waited = false;
+    loop {
+        unless_cancelled(cancel, wait_for_pause()).await?;
+        count_request()?;
+        let error = match unless_cancelled(cancel, send()).await? {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        match error.retry() {
+            Retry::Backoff if attempt < ATTEMPTS => {
+                debug!(
+                    "{} failed (attempt {}/{}), retrying in {:?}: {}",
+                    what, attempt, ATTEMPTS, delay, error
+                );
+                unless_cancelled(cancel, sleep(delay)).await?;
+                delay *= 2;
+                attempt += 1;
+            }
+            Retry::AfterPause if attempt < ATTEMPTS => {
+                info!(
+                    "{} hit the secondary rate limit, retrying after the pause",
+                    what
+                );
+                attempt += 1;
+            }
+            Retry::WaitForReset if !waited => {
+                info!("{} hit the rate limit, retrying once it resets", what);
+                wait_for_rate_limit(client, cancel).await?;
+                waited = true;
+            }
+            _ => return Err(error),
+        }
+    }
+}
+
+/// Runs `future` to its end, or fails with `GistError::Cancelled` as soon as `cancel` is
+/// cancelled, dropping it.
+async fn unless_cancelled<T>(
+    cancel: &CancellationToken,
+    future: impl std::future::Future<Output = T>,
+) -> Result<T, GistError> {
+    tokio::select! {
+        output = future => Ok(output),
+        _ = cancel.cancelled() => Err(GistError::Cancelled),
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct GistFile {
@@ -82,7 +290,7 @@ pub struct Gist {
 impl fmt::Display for Gist {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let s = match &self.description {
-            Some(d) => &d,
+            Some(d) => d,
             None => "<no description>",
         };
         write!(
@@ -108,12 +316,16 @@ fn has_next_page(headers: &HeaderMap) -> bool {
         .unwrap_or(false)
 }
 
-#[instrument]
-fn get_url(username: &str, per_page: u32, page: u32) -> String {
-    return format!(
-        "{}/users/{}/gists?per_page={}&page={}",
-        GITHUB_API_URL, username, per_page, page
+#[cfg_attr(feature = "tracing", tracing::instrument)]
+fn get_url(path: &str, per_page: u32, page: u32, since: Option<&str>) -> String {
+    let url = format!(
+        "{}/{}?per_page={}&page={}",
+        GITHUB_API_URL, path, per_page, page
     );
+    match since {
+        Some(since) => format!("{}&since={}", url, since),
+        None => url,
+    }
 }
 
 fn get_rate_limit(headers: &HeaderMap) -> Option<&str> {
@@ -128,56 +340,452 @@ fn get_rate_limit(headers: &HeaderMap) -> Option<&str> {
         "rate_limit: {:?} rate_remaining: {:?}",
         rate_limit, rate_remaining
     );
+    metrics::record_rate_limit(rate_remaining);
 
     rate_remaining
 }
 
-fn should_continue(remaining: Option<&str>) -> bool {
-    remaining
-        .and_then(|r| r.parse::<u32>().ok())
-        .map_or(false, |n| n > 0)
+/// Returns when the rate limit resets, in seconds since the epoch, when a response says
+/// no requests are left. Missing or malformed headers say nothing.
+fn exhausted_until(headers: &HeaderMap) -> Option<u64> {
+    let header = |name: &str| headers.get(name)?.to_str().ok()?.parse::<u64>().ok();
+    match header("x-ratelimit-remaining")? {
+        0 => header("x-ratelimit-reset"),
+        _ => None,
+    }
 }
 
-/// Lists all Gists for a given GitHub username.
-///
-/// # Arguments
-/// * `username` - GitHub username to fetch gists for
-/// * `limit` - Optional maximum number of gists to return)
-#[instrument]
-pub async fn list_gists(username: &str, limit: Option<u32>) -> Result<Gists, GistError> {
-    let client: Client = Client::builder().user_agent("RustRequestClient").build()?;
-    let mut all_gists: Vec<Gist> = Vec::new();
-    let mut page: u32 = 1;
-    let per_page: u32 = limit.unwrap_or(100);
+/// Makes requests fail when the rate limit is exhausted instead of waiting for it to
+/// reset, with `--no-wait`.
+pub fn wait_for_reset(enabled: bool) {
+    WAIT_FOR_RESET.store(enabled, Ordering::Relaxed);
+}
 
-    info!("Limit: {:?}, per page: {:?} ", limit, per_page);
+/// Sleeps until the rate limit resets at `reset`, in seconds since the epoch, or fails
+/// with `--no-wait` or once `cancel` is cancelled.
+async fn sleep_until_reset(reset: u64, cancel: &CancellationToken) -> Result<(), GistError> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // A second more, since the reset time is rounded down
+    let wait = reset.saturating_sub(now) + 1;
+    let eta = DateTime::from_timestamp((now + wait) as i64, 0)
+        .map(|eta| {
+            eta.with_timezone(&chrono::Local)
+                .format("%H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default();
+    if !WAIT_FOR_RESET.load(Ordering::Relaxed) {
+        return Err(GistError::RateLimited(format!(
+            "resets in {}s, at {}",
+            wait, eta
+        )));
+    }
+    info!(
+        "Rate limit exhausted, waiting {}s for it to reset at {}",
+        wait, eta
+    );
+    unless_cancelled(cancel, sleep(Duration::from_secs(wait))).await
+}
 
-    loop {
-        let url: String = get_url(username, per_page, page);
-        info!("Requesting URL: {}", url);
-        let response: reqwest::Response = client.get(&url).send().await?;
-        info!("Status: {}", response.status());
-        let has_next_page: bool = has_next_page(response.headers());
+/// Makes every request wait `wait` before going out, after a secondary rate limit.
+fn pause_requests(wait: Duration) {
+    let until = Instant::now() + wait;
+    let mut paused = PAUSED_UNTIL.lock().unwrap_or_else(|e| e.into_inner());
+    if paused.is_none_or(|paused| paused < until) {
+        *paused = Some(until);
+    }
+}
+
+/// Builds the client requests are sent with, to GitHub and the other services alike,
+/// identifying as `user_agent`, or `DEFAULT_USER_AGENT` when not given.
+pub fn client(user_agent: Option<&str>) -> Result<Client, GistError> {
+    Ok(Client::builder()
+        .user_agent(user_agent.unwrap_or(DEFAULT_USER_AGENT))
+        .build()?)
+}
+
+/// Caps the requests the run sends to GitHub, API and raw content alike, at `max`. Requests
+/// past it fail with `GistError::Budget`.
+pub fn limit_requests(max: u64) {
+    MAX_REQUESTS.store(max, Ordering::Relaxed);
+}
+
+/// Returns the number of requests sent to GitHub so far.
+pub fn requests_sent() -> u64 {
+    REQUESTS_SENT.load(Ordering::Relaxed)
+}
+
+/// Returns the requests `--max-requests` allows, `None` without a cap.
+pub fn max_requests() -> Option<u64> {
+    Some(MAX_REQUESTS.load(Ordering::Relaxed)).filter(|&max| max != u64::MAX)
+}
+
+/// Whether the requests allowed by `--max-requests` were all sent.
+pub fn budget_spent() -> bool {
+    max_requests().is_some_and(|max| requests_sent() >= max)
+}
+
+/// Counts a request about to be sent to GitHub, failing instead once the budget is spent.
+fn count_request() -> Result<(), GistError> {
+    let max = MAX_REQUESTS.load(Ordering::Relaxed);
+    REQUESTS_SENT
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |sent| {
+            (sent < max).then_some(sent + 1)
+        })
+        .map(|_| ())
+        .map_err(|_| GistError::Budget(max))
+}
+
+/// Waits for the pause after a secondary rate limit to be over, if there is one.
+async fn wait_for_pause() {
+    let paused = *PAUSED_UNTIL.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(until) = paused.filter(|until| *until > Instant::now()) {
+        sleep_until(until).await;
+    }
+}
+
+/// Fails on error statuses of the API, telling an exhausted rate limit (403 or 429 with no
+/// requests remaining) and the secondary rate limit GitHub enforces on requests coming too
+/// fast (403 or 429 with `Retry-After` or its message) apart from other refusals. The
+/// secondary rate limit pauses every request for the time GitHub asks.
+async fn check_status(response: Response) -> Result<Response, GistError> {
+    let status = response.status();
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return Ok(response.error_for_status()?);
+    }
+    let url = response.url().to_string();
+    let header = |name: &str| {
+        response
+            .headers()
+            .get(name)?
+            .to_str()
+            .ok()
+            .map(str::to_string)
+    };
+    if header("x-ratelimit-remaining").is_some_and(|remaining| remaining == "0") {
+        return Err(GistError::RateLimited(url));
+    }
+    let retry_after = header("retry-after").and_then(|seconds| seconds.parse().ok());
+    if status == StatusCode::FORBIDDEN && retry_after.is_none() {
+        // Only the message tells the secondary rate limit apart from a refused token
+        let error = response.error_for_status_ref().unwrap_err();
+        let body = response.text().await.unwrap_or_default().to_lowercase();
+        if !SECONDARY_LIMIT_MESSAGES
+            .iter()
+            .any(|message| body.contains(message))
+        {
+            return Err(error.into());
+        }
+    }
+    let wait = retry_after.map_or(SECONDARY_PAUSE, Duration::from_secs);
+    warn!(
+        "Hit GitHub's secondary rate limit, pausing every request for {}s",
+        wait.as_secs()
+    );
+    pause_requests(wait);
+    Err(GistError::SecondaryRateLimited(url))
+}
+
+/// Returns the page number of the last page of the listing from the `Link` header.
+fn last_page(headers: &HeaderMap) -> Option<u32> {
+    let link = headers.get("link")?.to_str().ok()?;
+    let last = link
+        .split(',')
+        .find(|part| part.contains(r#"rel="last""#))?;
+    let url = last.split(['<', '>']).nth(1)?;
+    let url = reqwest::Url::parse(url).ok()?;
+    let page = url
+        .query_pairs()
+        .find(|(name, _)| name == "page")?
+        .1
+        .parse()
+        .ok()?;
+    Some(page)
+}
+
+/// Fetches a listing of gists one page at a time, so callers can process them
+/// while the listing is still in progress. With a token, the pages after the first are
+/// fetched concurrently ahead of the caller, still returned in page order.
+#[derive(Debug)]
+pub struct GistPages {
+    request: PageRequest,
+    // Whether requests are authenticated, which allows fetching pages concurrently
+    authenticated: bool,
+    page: u32,
+    done: bool,
+    // Known once a page with a `last` link was fetched, when there is more than one page
+    last_page: Option<u32>,
+    // Last page the caller needs, from the limit on the number of gists
+    needed: Option<u32>,
+    // Pages being fetched ahead of `page`, in page order
+    ahead: VecDeque<JoinHandle<Result<(Gists, HeaderMap), GistError>>>,
+}
+
+/// The request for a page of a listing, cloned into the tasks fetching pages ahead.
+#[derive(Debug, Clone)]
+struct PageRequest {
+    client: Client,
+    // API path of the listing, e.g. `users/{user}/gists`
+    path: String,
+    // Only gists updated after this timestamp are listed, when set
+    since: Option<String>,
+    per_page: u32,
+    // Stops the listing, even while a page is on its way or waits for the rate limit
+    cancel: CancellationToken,
+}
+
+impl GistPages {
+    /// Starts a listing of the gists of a user with pages of `per_page` gists, clamped to
+    /// what the API accepts.
+    pub fn new(client: &Client, username: &str, per_page: u32) -> Self {
+        Self::listing(client, format!("users/{}/gists", username), per_page)
+    }
+
+    /// Only lists the gists updated after `since`, letting the API leave out the others
+    /// instead of paging through them.
+    pub fn since(mut self, since: Option<DateTime<Utc>>) -> Self {
+        self.request.since = since.map(|since| since.to_rfc3339_opts(SecondsFormat::Secs, true));
+        self
+    }
+
+    /// Stops the listing once `cancel` is cancelled, `next_page` then failing with
+    /// `GistError::Cancelled`.
+    pub fn cancel_on(mut self, cancel: &CancellationToken) -> Self {
+        self.request.cancel = cancel.clone();
+        self
+    }
+
+    /// Only fetches the pages holding the first `limit` gists, so no page is fetched ahead
+    /// for nothing.
+    pub fn limit(mut self, limit: Option<u32>) -> Self {
+        self.needed = limit.map(|limit| limit.div_ceil(self.request.per_page).max(1));
+        self
+    }
+
+    /// Starts a listing of the gists the authenticated user starred.
+    pub fn starred(client: &Client, per_page: u32) -> Self {
+        Self::listing(client, "gists/starred".to_string(), per_page)
+    }
+
+    /// Starts a listing of the public gists of everyone, newest first.
+    pub fn public(client: &Client, per_page: u32) -> Self {
+        Self::listing(client, "gists/public".to_string(), per_page)
+    }
+
+    /// API path of the listing, e.g. `users/{user}/gists`.
+    pub fn path(&self) -> &str {
+        &self.request.path
+    }
+
+    /// `since` parameter of the listing requests, when set.
+    pub fn since_param(&self) -> Option<&str> {
+        self.request.since.as_deref()
+    }
+
+    pub fn per_page(&self) -> u32 {
+        self.request.per_page
+    }
+
+    /// Page `next_page` returns next.
+    pub fn next_page_number(&self) -> u32 {
+        self.page
+    }
+
+    /// Whether an interrupted listing can be continued later from where it stopped, which
+    /// the listing of public gists can't: its pages shift every second.
+    pub fn resumable(&self) -> bool {
+        self.request.path != "gists/public"
+    }
+
+    /// Continues the listing from `page`, the earlier pages having been listed by an
+    /// earlier run.
+    pub fn resume_at(&mut self, page: u32) {
+        self.page = page.max(1);
+    }
+
+    fn listing(client: &Client, path: String, per_page: u32) -> Self {
+        GistPages {
+            request: PageRequest {
+                client: client.clone(),
+                path,
+                since: None,
+                per_page: per_page.clamp(1, MAX_PER_PAGE),
+                cancel: CancellationToken::new(),
+            },
+            authenticated: auth::has_token(),
+            page: 1,
+            done: false,
+            last_page: None,
+            needed: None,
+            ahead: VecDeque::new(),
+        }
+    }
+
+    /// Returns the next page of gists, or `None` once the last page has been returned.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), fields(path = %self.request.path, page = self.page)))]
+    pub async fn next_page(&mut self) -> Result<Option<Gists>, GistError> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let (gists, headers) = match self.ahead.pop_front() {
+            Some(fetching) => unless_cancelled(&self.request.cancel, fetching)
+                .await?
+                .map_err(|e| GistError::Io(IoError::other(e)))??,
+            None => self.request.fetch_with_retry(self.page).await?,
+        };
+        let has_next_page: bool = has_next_page(&headers);
         if has_next_page {
             info!("Wait, there is more!")
         } else {
             info!("There are no more gists")
         }
-        let rate_remaining = get_rate_limit(response.headers());
-        match should_continue(rate_remaining) {
-            true => debug!("We can continue, there is rate limit left to use"),
-            false => {
-                info!("We need to slow down");
-                sleep(Duration::from_millis(3000)).await;
+        if self.last_page.is_none() {
+            self.last_page = last_page(&headers);
+        }
+        self.done = !has_next_page || self.needed.is_some_and(|needed| self.page >= needed);
+        self.page += 1;
+        self.fetch_ahead();
+        Ok(Some(gists))
+    }
+
+    /// Keeps up to `PAGE_CONCURRENCY` of the next pages fetching while the caller handles
+    /// the current one, when a token lets the rate limit allow it and the last page is known.
+    fn fetch_ahead(&mut self) {
+        if !self.authenticated || self.done {
+            return;
+        }
+        let Some(last) = self.last_page else {
+            return;
+        };
+        let last = self.needed.map_or(last, |needed| needed.min(last));
+        while self.ahead.len() < PAGE_CONCURRENCY {
+            let page = self.page + self.ahead.len() as u32;
+            if page > last {
+                break;
+            }
+            let request = self.request.clone();
+            self.ahead.push_back(tokio::spawn(
+                async move { request.fetch_with_retry(page).await }.in_current_span(),
+            ));
+        }
+    }
+
+    /// Fetches the whole listing, or its first `limit` gists.
+    pub async fn collect(self, limit: Option<u32>) -> Result<Gists, GistError> {
+        let mut pages = self.limit(limit);
+        let mut all_gists: Vec<Gist> = Vec::new();
+
+        info!("Limit: {:?}, per page: {:?} ", limit, pages.per_page());
+
+        while let Some(mut gists) = pages.next_page().await? {
+            all_gists.append(&mut gists);
+
+            if let Some(limit) = limit {
+                if all_gists.len() >= limit as usize {
+                    all_gists.truncate(limit as usize);
+                    break;
+                }
+            }
+        }
+
+        Ok(all_gists)
+    }
+}
+
+impl Clone for GistPages {
+    /// Clones the listing from the same page, without the pages fetched ahead.
+    fn clone(&self) -> Self {
+        GistPages {
+            request: self.request.clone(),
+            authenticated: self.authenticated,
+            page: self.page,
+            done: self.done,
+            last_page: self.last_page,
+            needed: self.needed,
+            ahead: VecDeque::new(),
+        }
+    }
+}
+
+impl Drop for GistPages {
+    fn drop(&mut self) {
+        // Pages fetched ahead that nobody will ask for anymore
+        for fetching in &self.ahead {
+            fetching.abort();
+        }
+    }
+}
+
+impl PageRequest {
+    /// Fetches a page, again when it fails with a transient error.
+    async fn fetch_with_retry(&self, page: u32) -> Result<(Gists, HeaderMap), GistError> {
+        let what = format!("Page {} of {}", page, self.path);
+        with_retry_until(&self.client, &what, &self.cancel, || self.fetch(page)).await
+    }
+
+    /// Fetches a single page of the listing, returning its gists and headers.
+    #[cfg_attr(feature = "tracing", tracing::instrument(skip(self), fields(path = %self.path)))]
+    async fn fetch(&self, page: u32) -> Result<(Gists, HeaderMap), GistError> {
+        let url: String = get_url(&self.path, self.per_page, page, self.since.as_deref());
+        info!("Requesting URL: {}", url);
+        let token = github_token(&self.client).await?;
+        let cached = etag::lookup(&url, token.as_deref());
+        let mut request = self.client.get(&url);
+        if let Some(token) = &token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(cached) = &cached {
+            request = request.header(IF_NONE_MATCH, &cached.etag);
+        }
+        let response: reqwest::Response = request.send().await?;
+        info!("Status: {}", response.status());
+        let mut headers = response.headers().clone();
+        get_rate_limit(&headers);
+        let text: String = match cached {
+            Some(cached) if response.status() == StatusCode::NOT_MODIFIED => {
+                debug!("Page {} is unchanged since it was cached", page);
+                if let Some(link) = cached
+                    .link
+                    .and_then(|link| HeaderValue::from_str(&link).ok())
+                {
+                    headers.insert(LINK, link);
+                }
+                cached.body
+            }
+            _ => {
+                let text = check_status(response).await?.text().await?;
+                if let Some(etag) = headers.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+                    let page = CachedPage {
+                        url: url.clone(),
+                        etag: etag.to_string(),
+                        link: headers
+                            .get(LINK)
+                            .and_then(|link| link.to_str().ok())
+                            .map(str::to_string),
+                        body: text,
+                    };
+                    etag::save(&page, token.as_deref());
+                    page.body
+                } else {
+                    text
+                }
             }
         };
 
-        let text: String = response.text().await?;
+        // The next page would be refused until the rate limit resets, so it waits for the
+        // reset now, or leaves it to that request to fail with `--no-wait`
+        if let Some(reset) = exhausted_until(&headers) {
+            if has_next_page(&headers) && WAIT_FOR_RESET.load(Ordering::Relaxed) {
+                sleep_until_reset(reset, &self.cancel).await?;
+            }
+        }
 
         match serde_json::from_str::<Vec<Gist>>(&text) {
-            Ok(mut gists) => {
-                all_gists.append(&mut gists);
-            }
+            Ok(gists) => Ok((gists, headers)),
             Err(e) => {
                 // Print error context
                 info!("Error details: {}", e);
@@ -189,49 +797,906 @@ pub async fn list_gists(username: &str, limit: Option<u32>) -> Result<Gists, Gis
                 let context = &text[start_pos..end_pos];
                 info!("JSON context around error: {}", context);
 
-                return Err(GistError::JsonError(e, text));
+                Err(GistError::Json(e, text))
             }
         }
+    }
+}
 
-        if let Some(limit) = limit {
-            if all_gists.len() >= limit as usize {
-                all_gists.truncate(limit as usize);
-                break;
+/// Lists all Gists for a given GitHub username.
+///
+/// # Arguments
+/// * `username` - GitHub username to fetch gists for
+/// * `limit` - Optional maximum number of gists to return, across pages
+/// * `per_page` - Number of gists requested per page, at most 100
+/// * `cancel` - Stops the listing, which then fails with `GistError::Cancelled`
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(cancel)))]
+pub async fn list_gists(
+    client: &Client,
+    username: &str,
+    limit: Option<u32>,
+    per_page: u32,
+    cancel: &CancellationToken,
+) -> Result<Gists, GistError> {
+    GistPages::new(client, username, per_page)
+        .cancel_on(cancel)
+        .collect(limit)
+        .await
+}
+
+/// Streams the listing of a user and returns the gists whose description or filenames
+/// contain the query (case-insensitive), without downloading any content.
+///
+/// # Arguments
+/// * `username` - GitHub username to fetch gists for
+/// * `query` - Text to look for
+/// * `limit` - Optional maximum number of matches to return
+/// * `on_match` - Called with each match as soon as its page arrives
+#[cfg_attr(feature = "tracing", tracing::instrument(skip(on_match)))]
+pub async fn find_gists(
+    client: &Client,
+    username: &str,
+    query: &str,
+    limit: Option<u32>,
+    mut on_match: impl FnMut(&Gist) -> Result<(), GistError>,
+) -> Result<Gists, GistError> {
+    let query = query.to_lowercase();
+    let mut matches: Vec<Gist> = Vec::new();
+    let mut pages = GistPages::new(client, username, MAX_PER_PAGE);
+
+    while let Some(gists) = pages.next_page().await? {
+        for gist in gists {
+            let description_matches = gist
+                .description
+                .as_deref()
+                .is_some_and(|d| d.to_lowercase().contains(&query));
+            let filename_matches = gist.files.keys().any(|f| f.to_lowercase().contains(&query));
+            if description_matches || filename_matches {
+                on_match(&gist)?;
+                matches.push(gist);
+                if limit.is_some_and(|limit| matches.len() >= limit as usize) {
+                    return Ok(matches);
+                }
+            }
+        }
+    }
+
+    Ok(matches)
+}
+/// Creates a gist for the authenticated user.
+///
+/// # Arguments
+/// * `token` - GitHub token allowed to create gists
+/// * `description` - Description of the gist
+/// * `public` - Whether the gist is public or secret
+/// * `files` - Filename and content of every file
+pub async fn create_gist(
+    client: &Client,
+    token: &str,
+    description: Option<&str>,
+    public: bool,
+    files: &[(String, String)],
+) -> Result<Gist, GistError> {
+    count_request()?;
+    let files: serde_json::Map<String, serde_json::Value> = files
+        .iter()
+        .map(|(filename, content)| (filename.clone(), serde_json::json!({ "content": content })))
+        .collect();
+    let body = serde_json::json!({
+        "description": description,
+        "public": public,
+        "files": files,
+    });
+
+    let response = client
+        .post(format!("{}/gists", GITHUB_API_URL))
+        .bearer_auth(token)
+        .json(&body)
+        .send()
+        .await?;
+    let text = check_status(response).await?.text().await?;
+    serde_json::from_str(&text).map_err(|e| GistError::Json(e, text))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Account {
+    pub login: String,
+    #[serde(default)]
+    pub avatar_url: Option<String>,
+}
+
+/// A comment on a gist, with its Markdown body and the HTML GitHub renders it to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Comment {
+    pub id: u64,
+    /// `None` when the account of the author was deleted
+    pub user: Option<Account>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub body: String,
+    #[serde(default)]
+    pub body_html: Option<String>,
+}
+
+/// Returns the logins of a paginated list of accounts, going through every page.
+///
+/// # Arguments
+/// * `path` - API path of the list, e.g. `users/{user}/following`
+/// * `owner` - What the list belongs to, for the error when it doesn't exist
+async fn list_accounts(client: &Client, path: &str, owner: &str) -> Result<Vec<String>, GistError> {
+    let mut logins = Vec::new();
+    for page in 1.. {
+        let url = format!(
+            "{}/{}?per_page={}&page={}",
+            GITHUB_API_URL, path, MAX_PER_PAGE, page
+        );
+        info!("Requesting URL: {}", url);
+        let (text, has_next_page) = with_retry(client, &url, || async {
+            let mut request = client.get(&url);
+            if let Some(token) = github_token(client).await? {
+                request = request.bearer_auth(token);
             }
+            let response = request.send().await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Err(GistError::NotFound(owner.to_string()));
+            }
+            let response = check_status(response).await?;
+            let has_next_page = has_next_page(response.headers());
+            Ok((response.text().await?, has_next_page))
+        })
+        .await?;
+        let accounts: Vec<Account> =
+            serde_json::from_str(&text).map_err(|e| GistError::Json(e, text))?;
+        logins.extend(accounts.into_iter().map(|account| account.login));
+        if !has_next_page {
+            break;
         }
+    }
+    Ok(logins)
+}
 
+/// Returns the logins of the accounts a user follows.
+pub async fn list_following(client: &Client, username: &str) -> Result<Vec<String>, GistError> {
+    let path = format!("users/{}/following", username);
+    list_accounts(client, &path, &format!("user {}", username)).await
+}
+
+/// Returns the logins of the members of an organization. Only public members are listed
+/// unless the token belongs to a member of the organization.
+pub async fn list_org_members(client: &Client, org: &str) -> Result<Vec<String>, GistError> {
+    let path = format!("orgs/{}/members", org);
+    list_accounts(client, &path, &format!("organization {}", org)).await
+}
+
+/// Returns the comments of a gist, oldest first, going through every page.
+pub async fn list_comments(client: &Client, id: &str) -> Result<Vec<Comment>, GistError> {
+    let mut comments = Vec::new();
+    for page in 1.. {
+        let url = format!(
+            "{}/gists/{}/comments?per_page={}&page={}",
+            GITHUB_API_URL, id, MAX_PER_PAGE, page
+        );
+        info!("Requesting URL: {}", url);
+        let (text, has_next_page) = with_retry(client, &url, || async {
+            // Asks for the rendered HTML of the bodies next to their Markdown
+            let mut request = client
+                .get(&url)
+                .header(reqwest::header::ACCEPT, "application/vnd.github.full+json");
+            if let Some(token) = github_token(client).await? {
+                request = request.bearer_auth(token);
+            }
+            let response = request.send().await?;
+            if response.status() == StatusCode::NOT_FOUND {
+                return Err(GistError::NotFound(format!("gist {}", id)));
+            }
+            let response = check_status(response).await?;
+            let has_next_page = has_next_page(response.headers());
+            Ok((response.text().await?, has_next_page))
+        })
+        .await?;
+        let mut page: Vec<Comment> =
+            serde_json::from_str(&text).map_err(|e| GistError::Json(e, text))?;
+        comments.append(&mut page);
         if !has_next_page {
             break;
         }
+    }
+    Ok(comments)
+}
+
+/// Fetches the metadata of a single gist by id, authenticated like the listings so secret
+/// gists are found and the requests count against the higher rate limit.
+pub async fn get_gist(client: &Client, id: &str) -> Result<Gist, GistError> {
+    let url = format!("{}/gists/{}", GITHUB_API_URL, id);
+    let text = with_retry(client, &url, || async {
+        let mut request = client.get(&url);
+        if let Some(token) = github_token(client).await? {
+            request = request.bearer_auth(token);
+        }
+        let response = request.send().await?;
+        get_rate_limit(response.headers());
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(GistError::NotFound(format!("gist {}", id)));
+        }
+        Ok(check_status(response).await?.text().await?)
+    })
+    .await?;
+    serde_json::from_str(&text).map_err(|e| GistError::Json(e, text))
+}
+
+/// A published release of a GitHub repository.
+#[derive(Debug, Deserialize)]
+pub struct Release {
+    pub tag_name: String,
+    pub assets: Vec<ReleaseAsset>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReleaseAsset {
+    pub name: String,
+    pub browser_download_url: String,
+}
+
+/// Returns the OAuth scopes of a classic token, from the `x-oauth-scopes` header of a
+/// request that doesn't count against the rate limit. `None` for tokens without scopes,
+/// like fine-grained ones.
+pub async fn token_scopes(client: &Client, token: &str) -> Result<Option<Vec<String>>, GistError> {
+    count_request()?;
+    let response = client
+        .get(format!("{}/rate_limit", GITHUB_API_URL))
+        .bearer_auth(token)
+        .send()
+        .await?;
+    let response = check_status(response).await?;
+    let Some(scopes) = response.headers().get("x-oauth-scopes") else {
+        return Ok(None);
+    };
+    let scopes = scopes.to_str().unwrap_or_default();
+    Ok(Some(
+        scopes
+            .split(',')
+            .map(str::trim)
+            .filter(|scope| !scope.is_empty())
+            .map(str::to_string)
+            .collect(),
+    ))
+}
+
+/// Whether the token may read the gists of its user, probed by listing one of them.
+pub async fn can_read_own_gists(client: &Client, token: &str) -> Result<bool, GistError> {
+    count_request()?;
+    let response = client
+        .get(format!("{}/gists?per_page=1", GITHUB_API_URL))
+        .bearer_auth(token)
+        .send()
+        .await?;
+    match response.status() {
+        StatusCode::FORBIDDEN | StatusCode::NOT_FOUND => Ok(false),
+        _ => check_status(response).await.map(|_| true),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct InstallationToken {
+    token: String,
+    expires_at: DateTime<Utc>,
+}
+
+/// Exchanges the JWT of a GitHub App for a token of one of its installations, returning
+/// the token and when it expires.
+pub async fn create_installation_token(
+    client: &Client,
+    jwt: &str,
+    installation_id: u64,
+) -> Result<(String, DateTime<Utc>), GistError> {
+    count_request()?;
+    let url = format!(
+        "{}/app/installations/{}/access_tokens",
+        GITHUB_API_URL, installation_id
+    );
+    let response = client.post(url).bearer_auth(jwt).send().await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(GistError::NotFound(format!(
+            "installation {}",
+            installation_id
+        )));
+    }
+    let text = check_status(response).await?.text().await?;
+    let installation: InstallationToken =
+        serde_json::from_str(&text).map_err(|e| GistError::Json(e, text))?;
+    Ok((installation.token, installation.expires_at))
+}
+
+/// Fetches the latest release of a repository (`owner/name`).
+pub async fn latest_release(client: &Client, repo: &str) -> Result<Release, GistError> {
+    count_request()?;
+    let response = client
+        .get(format!("{}/repos/{}/releases/latest", GITHUB_API_URL, repo))
+        .send()
+        .await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(GistError::NotFound(format!("release of {}", repo)));
+    }
+    let text = check_status(response).await?.text().await?;
+    serde_json::from_str(&text).map_err(|e| GistError::Json(e, text))
+}
+
+/// Downloads a release asset.
+pub async fn fetch_asset(client: &Client, asset: &ReleaseAsset) -> Result<Vec<u8>, GistError> {
+    count_request()?;
+    let response = client
+        .get(&asset.browser_download_url)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(Vec::from(response.bytes().await?))
+}
 
-        page += 1;
+#[derive(Debug, Deserialize)]
+struct RateLimit {
+    remaining: u32,
+    reset: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimitResources {
+    core: RateLimit,
+}
+
+#[derive(Debug, Deserialize)]
+struct RateLimitStatus {
+    resources: RateLimitResources,
+}
+
+/// Waits for the rate limit to reset when no requests are left, so retries don't fail
+/// for the same reason, or fails with `--no-wait` or once `cancel` is cancelled. Checking
+/// the rate limit doesn't count against it.
+pub async fn wait_for_rate_limit(
+    client: &Client,
+    cancel: &CancellationToken,
+) -> Result<(), GistError> {
+    count_request()?;
+    let mut request = client.get(format!("{}/rate_limit", GITHUB_API_URL));
+    if let Some(token) = github_token(client).await? {
+        request = request.bearer_auth(token);
+    }
+    let text = check_status(request.send().await?).await?.text().await?;
+    let status: RateLimitStatus =
+        serde_json::from_str(&text).map_err(|e| GistError::Json(e, text))?;
+    let core = status.resources.core;
+    if core.remaining == 0 {
+        sleep_until_reset(core.reset, cancel).await?;
     }
+    Ok(())
+}
 
-    Ok(all_gists)
+/// Replaces the description of a gist of the authenticated user on GitHub, returning the
+/// updated gist.
+pub async fn update_description(
+    client: &Client,
+    token: &str,
+    id: &str,
+    description: &str,
+) -> Result<Gist, GistError> {
+    count_request()?;
+    let response = client
+        .patch(format!("{}/gists/{}", GITHUB_API_URL, id))
+        .bearer_auth(token)
+        .json(&serde_json::json!({ "description": description }))
+        .send()
+        .await?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Err(GistError::NotFound(format!("gist {}", id)));
+    }
+    let text = check_status(response).await?.text().await?;
+    serde_json::from_str(&text).map_err(|e| GistError::Json(e, text))
 }
-/// Downloads a single gist to a specified path
+
+/// Stars a gist for the authenticated user.
+pub async fn star_gist(client: &Client, token: &str, id: &str) -> Result<(), GistError> {
+    count_request()?;
+    let response = client
+        .put(format!("{}/gists/{}/star", GITHUB_API_URL, id))
+        .bearer_auth(token)
+        .header(reqwest::header::CONTENT_LENGTH, 0)
+        .send()
+        .await?;
+    check_status(response).await?;
+    Ok(())
+}
+
+/// Deletes a gist of the authenticated user on GitHub.
+pub async fn delete_gist(client: &Client, token: &str, id: &str) -> Result<(), GistError> {
+    count_request()?;
+    let response = client
+        .delete(format!("{}/gists/{}", GITHUB_API_URL, id))
+        .bearer_auth(token)
+        .send()
+        .await?;
+    check_status(response).await?;
+    Ok(())
+}
+
+/// Fetches the raw content of a single file of a gist.
+pub async fn fetch_file(client: &Client, file: &GistFile) -> Result<Vec<u8>, GistError> {
+    with_retry(client, &file.raw_url, || async {
+        let response = client.get(&file.raw_url).send().await?.error_for_status()?;
+        Ok(response.bytes().await?.to_vec())
+    })
+    .await
+}
+
+/// Fetches an avatar image at `size` pixels, with its content type.
+pub async fn fetch_avatar(
+    client: &Client,
+    avatar_url: &str,
+    size: u32,
+) -> Result<(Vec<u8>, Option<String>), GistError> {
+    let separator = if avatar_url.contains('?') { '&' } else { '?' };
+    let url = format!("{}{}s={}", avatar_url, separator, size);
+    with_retry(client, &url, || async {
+        let response = client.get(&url).send().await?.error_for_status()?;
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        Ok((response.bytes().await?.to_vec(), content_type))
+    })
+    .await
+}
+
+/// Tells whether downloaded content is binary, from its declared content type or, when
+/// that says nothing, from NUL bytes or invalid UTF-8 in the content itself.
+pub fn is_binary(content_type: Option<&str>, content: &[u8]) -> bool {
+    declared_binary(content_type) || sniffs_binary(content) || std::str::from_utf8(content).is_err()
+}
+
+/// Tells whether a content type is that of binary content.
+pub fn declared_binary(content_type: Option<&str>) -> bool {
+    content_type.is_some_and(|content_type| {
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        let text = mime.starts_with("text/")
+            || ["json", "xml", "javascript", "x-sh", "x-yaml", "toml"]
+                .iter()
+                .any(|kind| mime.ends_with(kind));
+        !mime.is_empty() && !text
+    })
+}
+
+/// Tells whether the start of some content has NUL bytes. Like git, only the first
+/// `BINARY_SNIFF_LEN` bytes are sniffed.
+pub fn sniffs_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_SNIFF_LEN)].contains(&0)
+}
+
+/// File content that may be held in memory at once, shared by all downloads or uploads of
+/// a run, set by `--max-memory`.
+#[derive(Clone)]
+pub struct MemoryBudget {
+    // One permit per KiB
+    semaphore: Arc<Semaphore>,
+    kib: u32,
+}
+
+impl MemoryBudget {
+    pub fn new(bytes: u64) -> Self {
+        let kib = (bytes / 1024).clamp(1, u32::MAX as u64) as u32;
+        MemoryBudget {
+            semaphore: Arc::new(Semaphore::new(kib as usize)),
+            kib,
+        }
+    }
+
+    /// Waits for `bytes` to fit in the budget, holding them until the permit is dropped.
+    /// More than the whole budget waits for all of it to be free.
+    pub async fn reserve(&self, bytes: u64) -> Option<OwnedSemaphorePermit> {
+        let kib = bytes.div_ceil(1024).clamp(1, self.kib as u64) as u32;
+        self.semaphore.clone().acquire_many_owned(kib).await.ok()
+    }
+}
+
+/// A file of a gist as fetched from GitHub.
+pub struct FetchedFile {
+    pub content: Vec<u8>,
+    pub binary: bool,
+}
+
+/// Returns the content type a response declares for its body.
+fn content_type(response: &reqwest::Response) -> Option<String> {
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Fetches the content of a gist file into memory, telling whether it is binary.
+async fn fetch_gist_file(client: &Client, file: &GistFile) -> Result<FetchedFile, GistError> {
+    let (content_type, content) = with_retry(client, &file.raw_url, || async {
+        let response = client.get(&file.raw_url).send().await?.error_for_status()?;
+        let content_type = content_type(&response);
+        // Takes over the buffer of the body instead of copying it, one copy per file is enough
+        Ok((content_type, Vec::from(response.bytes().await?)))
+    })
+    .await?;
+    metrics::record_file(content.len());
+    let binary = is_binary(content_type.as_deref(), &content);
+    Ok(FetchedFile { content, binary })
+}
+
+/// Fetches the content of every file of a gist into memory, for uploading it elsewhere.
+///
+/// Returns the filename and content of each file.
+pub async fn fetch_files(
+    client: &Client,
+    gist: &Gist,
+) -> Result<Vec<(String, FetchedFile)>, GistError> {
+    let mut files = Vec::with_capacity(gist.files.len());
+    for (filename, file) in &gist.files {
+        files.push((filename.clone(), fetch_gist_file(client, file).await?));
+    }
+    Ok(files)
+}
+
+/// A file of a gist downloaded into the spool of the archive.
+struct SpooledFile {
+    content: Spooled,
+    binary: bool,
+}
+
+/// Downloads the content of a gist file chunk by chunk into the spool of the archive, so
+/// big files never sit in memory, telling whether it is binary. A failed or cancelled
+/// attempt deletes what it spooled.
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(file = %file.filename, size = file.size)))]
+async fn spool_gist_file(
+    client: &Client,
+    file: &GistFile,
+    folder: &Path,
+    cancel: &CancellationToken,
+) -> Result<SpooledFile, GistError> {
+    let (content_type, content) = with_retry_until(client, &file.raw_url, cancel, || async {
+        let mut response = client.get(&file.raw_url).send().await?.error_for_status()?;
+        let content_type = content_type(&response);
+        let mut spool = Spool::create(folder)?;
+        while let Some(chunk) = unless_cancelled(cancel, response.chunk()).await?? {
+            spool.write(&chunk)?;
+        }
+        Ok((content_type, spool.finish()?))
+    })
+    .await?;
+    metrics::record_file(content.len as usize);
+    let binary = content.is_binary(content_type.as_deref());
+    Ok(SpooledFile { content, binary })
+}
+
+/// How the files of a downloaded gist are written into the archive.
+#[derive(Clone, Default)]
+pub struct WriteOptions {
+    /// How the file contents are kept in the archive
+    pub store: Store,
+    /// Write the files of secret gists age-encrypted to this recipient
+    pub encrypt: Option<Recipient>,
+    /// Leave binary files out of the archive
+    pub skip_binary: bool,
+    /// Files to make executable besides those starting with a shebang
+    pub chmod_exec: GlobSet,
+    /// Size from which plain text files are written gzip-compressed, never when not set
+    pub compress_from: Option<u64>,
+    /// Line endings given to text files, `Lf` or `Crlf`, as they are on GitHub when not set
+    pub eol: Option<Eol>,
+    /// Write through symlinks inside the archive folder instead of refusing writes that
+    /// would leave it
+    pub follow_symlinks: bool,
+}
+
+/// What the archive holds of a gist from an earlier download.
+#[derive(Debug, Default)]
+pub struct LocalCopy {
+    /// Files already in the archive as they are on GitHub, left alone
+    pub unchanged: HashSet<String>,
+    /// `updated_at` of the earlier download, to keep the files it replaces under, when
+    /// earlier versions are kept
+    pub keep_as: Option<String>,
+    /// Files edited in the archive, kept instead of the version on GitHub
+    pub edited: HashSet<String>,
+    /// Only the metadata of the gist is recorded, its files aren't downloaded
+    pub sparse: bool,
+}
+
+/// What happened to the files of a downloaded gist.
+#[derive(Debug, Default)]
+pub struct DownloadOutcome {
+    /// Files that failed to download, with their error
+    pub failed: BTreeMap<String, String>,
+    /// Files detected as binary
+    pub binary: Vec<String>,
+    /// Binary files left out of the archive
+    pub skipped: Vec<String>,
+    /// Files made executable
+    pub executable: Vec<String>,
+    /// Files written gzip-compressed by `--compress-files`
+    pub gzipped: Vec<String>,
+    /// Text files written with the line endings of `--eol`
+    pub converted: Vec<String>,
+    /// Line endings the converted files were written with
+    pub eol: Option<Eol>,
+    /// Files not fetched again, since they are unchanged since the last download
+    pub unchanged: Vec<String>,
+    /// Files edited in the archive and kept, not fetched either
+    pub kept: Vec<String>,
+}
+
+/// Downloads a single gist to a specified path. A file failing doesn't stop the others
+/// from being downloaded.
 ///
 /// # Arguments
 /// * `gist` - The Gist to download
 /// * `output_path` - Directory where the gist should be saved
-pub async fn download_gist(gist: &Gist, output_path: &str) -> Result<(), GistError> {
-    let client = Client::builder().user_agent("RustRequestClient").build()?;
-
-    // Create the parent directory if it doesn't exist
-    let base_dir = format!("{}/{}", output_path, gist.id);
-    std::fs::create_dir_all(&base_dir)?;
+/// * `write` - How the files are written into the archive
+/// * `local` - What the archive holds of the gist already
+/// * `cancel` - Stops the download: the file being fetched is discarded and the files not
+///   written yet fail with `GistError::Cancelled`, those written are kept
+///
+/// Fails when no file could be downloaded at all.
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(id = %gist.id)))]
+pub async fn download_gist(
+    client: &Client,
+    gist: &Gist,
+    output_path: &str,
+    write: &WriteOptions,
+    local: &LocalCopy,
+    cancel: &CancellationToken,
+) -> Result<DownloadOutcome, GistError> {
+    let mut outcome = DownloadOutcome {
+        eol: write.eol,
+        ..DownloadOutcome::default()
+    };
+    let mut first_error: Option<GistError> = None;
+    outcome.unchanged = gist
+        .files
+        .keys()
+        .filter(|filename| local.unchanged.contains(*filename))
+        .cloned()
+        .collect();
+    outcome.kept = gist
+        .files
+        .keys()
+        .filter(|filename| local.edited.contains(*filename))
+        .cloned()
+        .collect();
+    let not_fetched: HashSet<String> = local.unchanged.union(&local.edited).cloned().collect();
 
-    // Download each file in the gist
+    let folder = Path::new(output_path);
+    let archive = store::Folder::new(folder, write.follow_symlinks);
+    // One file at a time, so a gist holds no more than a chunk of its content in memory
     for (filename, file) in &gist.files {
-        // Get the file content
-        let response = client.get(&file.raw_url).send().await?.text().await?;
+        if not_fetched.contains(filename) {
+            continue;
+        }
+        let filename = filename.clone();
+        let fetched = match spool_gist_file(client, file, folder, cancel).await {
+            Ok(fetched) if fetched.binary && write.skip_binary => {
+                debug!("Skipping binary file {}/{}", gist.id, filename);
+                outcome.skipped.push(filename);
+                continue;
+            }
+            fetched => fetched,
+        };
+        // Write the content to the archive, creating the gist directory if needed
+        let written = fetched.and_then(|mut fetched| {
+            if let Some(eol) = write.eol.filter(|_| !fetched.binary) {
+                fetched.content = fetched.content.convert_eol(folder, eol)?;
+            }
+            let content = &fetched.content;
+            if let Some(version) = &local.keep_as {
+                let current = store::digest_file(folder, &gist.id, &filename);
+                if !current.is_ok_and(|current| current == content.sha256) {
+                    store::keep_version(&archive, &gist.id, &filename, version)?;
+                }
+            }
+            let gzips = write.store == Store::Plain
+                && !fetched.binary
+                && write
+                    .compress_from
+                    .is_some_and(|min_size| content.len >= min_size);
+            match &write.encrypt {
+                Some(recipient) if !gist.public => {
+                    store::write_encrypted(&archive, &gist.id, &filename, content, recipient)?
+                }
+                _ => {
+                    if gzips {
+                        store::write_gzipped(&archive, &gist.id, &filename, content)?;
+                        outcome.gzipped.push(filename.clone());
+                    } else {
+                        store::write_spooled(&archive, &gist.id, &filename, content, write.store)?;
+                    }
+                    // Scripts start with a shebang
+                    if content.starts_with(b"#!") || write.chmod_exec.is_match(&filename) {
+                        let mode = store::EXECUTABLE_MODE;
+                        store::set_mode(&archive, &gist.id, &filename, mode)?;
+                        outcome.executable.push(filename.clone());
+                    }
+                }
+            }
+            Ok(fetched.binary)
+        });
+        match written {
+            Ok(true) => outcome.binary.push(filename),
+            Ok(false) if write.eol.is_some() => outcome.converted.push(filename),
+            Ok(false) => {}
+            Err(e) => {
+                debug!("Failed to download {}/{}: {}", gist.id, filename, e);
+                outcome.failed.insert(filename, e.to_string());
+                first_error.get_or_insert(e);
+            }
+        }
+    }
 
-        // Create the full path for the file
-        let file_path = format!("{}/{}", base_dir, filename);
+    match first_error {
+        Some(e)
+            if outcome.failed.len()
+                + outcome.skipped.len()
+                + outcome.unchanged.len()
+                + outcome.kept.len()
+                == gist.files.len() =>
+        {
+            Err(e)
+        }
+        _ => Ok(outcome),
+    }
+}
 
-        // Write the content to a file
-        std::fs::write(file_path, response)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const OK: &str = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok";
+    const UNAVAILABLE: &str =
+        "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const NOT_IMPLEMENTED: &str =
+        "HTTP/1.1 501 Not Implemented\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const NOT_FOUND: &str =
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const UNAUTHORIZED: &str =
+        "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const RATE_LIMITED: &str = "HTTP/1.1 403 Forbidden\r\nx-ratelimit-remaining: 0\r\n\
+        x-ratelimit-reset: 4102444800\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const REFUSED_TOKEN: &str = "HTTP/1.1 403 Forbidden\r\nx-ratelimit-remaining: 4999\r\n\
+        Content-Length: 9\r\nConnection: close\r\n\r\nforbidden";
+    // A pause of no time, so the other tests aren't paused along with it
+    const TOO_MANY_REQUESTS: &str = "HTTP/1.1 429 Too Many Requests\r\nretry-after: 0\r\n\
+        Content-Length: 0\r\nConnection: close\r\n\r\n";
+    // Accepts the connection and never answers
+    const SILENT: &str = "";
+
+    /// Serves the canned responses in turn, the last one to every request after them, and
+    /// counts the requests.
+    async fn serve(responses: &[&'static str]) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counted = Arc::clone(&hits);
+        let responses = responses.to_vec();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let hit = counted.fetch_add(1, Ordering::SeqCst);
+                let response = responses[hit.min(responses.len() - 1)];
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match socket.read(&mut buffer).await {
+                        Ok(0) | Err(_) => break,
+                        Ok(read) => request.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                if response == SILENT {
+                    tokio::spawn(async move {
+                        sleep(Duration::from_secs(60)).await;
+                        drop(socket);
+                    });
+                    continue;
+                }
+                let _ = socket.write_all(response.as_bytes()).await;
+                let _ = socket.shutdown().await;
+            }
+        });
+        (url, hits)
     }
 
-    Ok(())
+    /// Gets `url` the way the API requests are sent, retrying like them.
+    async fn get(client: &Client, url: &str) -> Result<String, GistError> {
+        with_retry(client, url, || async {
+            Ok(check_status(client.get(url).send().await?)
+                .await?
+                .text()
+                .await?)
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_reset_when_the_rate_limit_is_exhausted() {
+        let (url, _) = serve(&[RATE_LIMITED]).await;
+        let response = reqwest::get(&url).await.unwrap();
+        let error = check_status(response).await.unwrap_err();
+        assert!(matches!(error, GistError::RateLimited(_)), "{}", error);
+        assert_eq!(error.retry(), Retry::WaitForReset);
+    }
+
+    #[tokio::test]
+    async fn retries_after_the_pause_on_too_many_requests() {
+        let (url, _) = serve(&[TOO_MANY_REQUESTS]).await;
+        let response = reqwest::get(&url).await.unwrap();
+        let error = check_status(response).await.unwrap_err();
+        assert!(
+            matches!(error, GistError::SecondaryRateLimited(_)),
+            "{}",
+            error
+        );
+        assert_eq!(error.retry(), Retry::AfterPause);
+
+        let (url, hits) = serve(&[TOO_MANY_REQUESTS, OK]).await;
+        assert_eq!(get(&Client::new(), &url).await.unwrap(), "ok");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_with_backoff() {
+        let (url, hits) = serve(&[UNAVAILABLE, UNAVAILABLE, OK]).await;
+        assert_eq!(get(&Client::new(), &url).await.unwrap(), "ok");
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_attempt() {
+        let (url, hits) = serve(&[UNAVAILABLE]).await;
+        let started = Instant::now();
+        let error = get(&Client::new(), &url).await.unwrap_err();
+        assert_eq!(error.retry(), Retry::Backoff);
+        assert_eq!(hits.load(Ordering::SeqCst), ATTEMPTS as usize);
+        // The delay doubles after each retry
+        assert!(started.elapsed() >= RETRY_DELAY + RETRY_DELAY * 2);
+    }
+
+    #[tokio::test]
+    async fn never_retries_what_would_fail_the_same_way() {
+        for response in [NOT_FOUND, UNAUTHORIZED, REFUSED_TOKEN, NOT_IMPLEMENTED] {
+            let (url, hits) = serve(&[response]).await;
+            let error = get(&Client::new(), &url).await.unwrap_err();
+            assert_eq!(error.retry(), Retry::Never, "{}", response);
+            assert_eq!(hits.load(Ordering::SeqCst), 1, "{}", response);
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_refused_connections() {
+        // A port nothing listens on anymore
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let started = Instant::now();
+        let error = get(&Client::new(), &url).await.unwrap_err();
+        assert_eq!(error.retry(), Retry::Backoff);
+        assert!(started.elapsed() >= RETRY_DELAY + RETRY_DELAY * 2);
+    }
+
+    #[tokio::test]
+    async fn retries_timeouts() {
+        let (url, hits) = serve(&[SILENT, OK]).await;
+        let client = Client::builder()
+            .timeout(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        assert_eq!(get(&client, &url).await.unwrap(), "ok");
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        let (url, hits) = serve(&[SILENT]).await;
+        let error = get(&client, &url).await.unwrap_err();
+        assert_eq!(error.retry(), Retry::Backoff);
+        assert_eq!(hits.load(Ordering::SeqCst), ATTEMPTS as usize);
+    }
 }
@@ -0,0 +1,113 @@
+use crate::gist::GistError;
+use crate::log::{debug, info};
+use crate::manifest::ChangeSummary;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+// Derived files of the archive that aren't worth tracking
+const GITIGNORE: &str = "index.db\n.partial/\n";
+// Identity of the commits made by the tool itself
+const USER_NAME: &str = "local-gist";
+const USER_EMAIL: &str = "local-gist@localhost";
+
+/// Runs git in a directory with extra environment variables, failing with its error
+/// output when it doesn't succeed.
+pub fn run(dir: &Path, args: &[&str], env: &[(&str, &str)]) -> Result<Output, GistError> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args(["-c", &format!("user.name={}", USER_NAME)])
+        .args(["-c", &format!("user.email={}", USER_EMAIL)])
+        .args(args)
+        .envs(env.iter().copied())
+        .output()?;
+    if !output.status.success() {
+        return Err(GistError::Git(format!(
+            "git {}: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output)
+}
+
+/// Asks the credential helpers configured in git (a credential manager, `gh auth
+/// setup-git`, ...) for the password of `https://github.com`, which is a token. Never
+/// prompts; `None` when git isn't installed or has nothing stored.
+pub fn credential_token() -> Option<String> {
+    let mut child = Command::new("git")
+        .args(["-c", "core.askPass=", "credential", "fill"])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GCM_INTERACTIVE", "never")
+        .env_remove("GIT_ASKPASS")
+        .env_remove("SSH_ASKPASS")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let request = "protocol=https\nhost=github.com\n\n";
+    child.stdin.take()?.write_all(request.as_bytes()).ok()?;
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        debug!("git has no credential for github.com");
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("password="))
+        .filter(|password| !password.is_empty())
+        .map(str::to_string)
+}
+
+/// How gist repositories are fetched: over HTTPS, or over SSH (the `git_push_url` of a
+/// gist) with the keys of ssh-agent or a given private key.
+#[derive(Debug, Default)]
+pub struct GistRemote {
+    pub ssh: bool,
+    pub ssh_key: Option<PathBuf>,
+}
+
+impl GistRemote {
+    /// Returns the URL git fetches a gist from.
+    pub fn url(&self, id: &str) -> String {
+        match self.ssh || self.ssh_key.is_some() {
+            true => format!("git@gist.github.com:{}.git", id),
+            false => format!("https://gist.github.com/{}.git", id),
+        }
+    }
+
+    /// Returns the environment making git use the private key, if one was given, and never
+    /// prompt.
+    pub fn env(&self) -> Vec<(&'static str, String)> {
+        let mut env = vec![("GIT_TERMINAL_PROMPT", "0".to_string())];
+        if let Some(key) = &self.ssh_key {
+            // GIT_SSH_COMMAND goes through the shell, so the path is quoted
+            let quoted = key.to_string_lossy().replace('\'', "'\\''");
+            env.push((
+                "GIT_SSH_COMMAND",
+                format!("ssh -i '{}' -o IdentitiesOnly=yes -o BatchMode=yes", quoted),
+            ));
+        }
+        env
+    }
+}
+
+/// Commits every change of the archive folder, turning it into a git repository first
+/// if needed. Returns false when there was nothing to commit.
+pub fn commit_archive(folder: &Path, changes: &ChangeSummary) -> Result<bool, GistError> {
+    if !folder.join(".git").exists() {
+        run(folder, &["init", "-q"], &[])?;
+        std::fs::write(folder.join(".gitignore"), GITIGNORE)?;
+    }
+    run(folder, &["add", "-A"], &[])?;
+    let status = run(folder, &["status", "--porcelain"], &[])?;
+    if status.stdout.is_empty() {
+        debug!("Nothing to commit in {}", folder.display());
+        return Ok(false);
+    }
+    let message = format!("sync: {}", changes);
+    run(folder, &["commit", "-q", "-m", &message], &[])?;
+    info!("Committed {}", message);
+    Ok(true)
+}
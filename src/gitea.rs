@@ -0,0 +1,224 @@
+use crate::gist::GistError;
+use crate::log::{debug, error, info};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::store;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use minisign::SecretKey;
+use reqwest::{Client, Method, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::path::Path;
+
+// Key of Gitea repositories in the mirrors of a manifest entry
+const MIRROR_KEY: &str = "gitea";
+
+#[derive(Debug, Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ContentEntry {
+    path: String,
+    sha: String,
+    #[serde(rename = "type")]
+    entry_type: String,
+}
+
+/// Client for the API of a Gitea (or Forgejo) instance, which keeps every gist in a
+/// repository of its own since Gitea has no snippets.
+pub struct Gitea {
+    client: Client,
+    api_url: String,
+    token: String,
+}
+
+/// Returns the SHA-1 git uses for a blob with this content.
+fn git_blob_sha(content: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", content.len()).as_bytes());
+    hasher.update(content);
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl Gitea {
+    /// # Arguments
+    /// * `url` - Base URL of the instance, e.g. `https://gitea.example.com`
+    /// * `token` - Access token allowed to write repositories
+    pub fn new(client: &Client, url: &str, token: &str) -> Result<Self, GistError> {
+        Ok(Gitea {
+            client: client.clone(),
+            api_url: format!("{}/api/v1", url.trim_end_matches('/')),
+            token: token.to_string(),
+        })
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client
+            .request(method, format!("{}{}", self.api_url, path))
+            .header("Authorization", format!("token {}", self.token))
+    }
+
+    /// Sends a request and parses the JSON response, `None` when the resource is missing.
+    async fn send<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<Option<T>, GistError> {
+        let response = request.send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        match status {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => serde_json::from_str(&text)
+                .map(Some)
+                .map_err(|e| GistError::Json(e, text)),
+            status => Err(GistError::Target(format!(
+                "Gitea answered {}: {}",
+                status, text
+            ))),
+        }
+    }
+
+    /// Returns the login of the user owning the token.
+    async fn login(&self) -> Result<String, GistError> {
+        let user: Option<User> = self.send(self.request(Method::GET, "/user")).await?;
+        user.map(|u| u.login)
+            .ok_or_else(|| GistError::NotFound("Gitea user of the token".to_string()))
+    }
+
+    /// Creates or updates the repository mirroring a gist, committing only the files that
+    /// changed. Returns the `owner/name` of the repository.
+    async fn mirror_gist(
+        &self,
+        owner: &str,
+        entry: &ManifestEntry,
+        files: &[(String, Vec<u8>)],
+        repository: Option<&str>,
+    ) -> Result<String, GistError> {
+        let repository = repository
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("{}/gist-{}", owner, entry.id));
+        let settings = json!({
+            "description": entry.description.as_deref().unwrap_or_default(),
+            "private": !entry.public,
+        });
+
+        let path = format!("/repos/{}", repository);
+        let existing: Option<Value> = self.send(self.request(Method::GET, &path)).await?;
+        if existing.is_some() {
+            let _: Option<Value> = self
+                .send(self.request(Method::PATCH, &path).json(&settings))
+                .await?;
+        } else {
+            let name = repository.rsplit('/').next().unwrap_or(&repository);
+            let mut body = settings.clone();
+            body["name"] = json!(name);
+            let _: Option<Value> = self
+                .send(self.request(Method::POST, "/user/repos").json(&body))
+                .await?;
+        }
+
+        // An empty repository has no contents yet
+        let contents: Vec<ContentEntry> = self
+            .send(self.request(Method::GET, &format!("{}/contents", path)))
+            .await?
+            .unwrap_or_default();
+        let existing: HashMap<&str, &str> = contents
+            .iter()
+            .filter(|c| c.entry_type == "file")
+            .map(|c| (c.path.as_str(), c.sha.as_str()))
+            .collect();
+
+        let mut operations = Vec::new();
+        for (filename, content) in files {
+            let sha = git_blob_sha(content);
+            match existing.get(filename.as_str()) {
+                Some(existing_sha) if *existing_sha == sha => {}
+                Some(existing_sha) => operations.push(json!({
+                    "operation": "update",
+                    "path": filename,
+                    "content": BASE64.encode(content),
+                    "sha": existing_sha,
+                })),
+                None => operations.push(json!({
+                    "operation": "create",
+                    "path": filename,
+                    "content": BASE64.encode(content),
+                })),
+            }
+        }
+        for (path, sha) in &existing {
+            if !files.iter().any(|(filename, _)| filename == path) {
+                operations.push(json!({"operation": "delete", "path": path, "sha": sha}));
+            }
+        }
+
+        if operations.is_empty() {
+            debug!("Repository {} is up to date", repository);
+        } else {
+            let body = json!({
+                "message": format!("Sync gist {} ({})", entry.id, entry.updated_at),
+                "files": operations,
+            });
+            let _: Option<Value> = self
+                .send(
+                    self.request(Method::POST, &format!("{}/contents", path))
+                        .json(&body),
+                )
+                .await?;
+        }
+        Ok(repository)
+    }
+}
+
+/// Creates or updates a Gitea repository for every gist of the archive, recording the
+/// repositories in the manifest so later runs update the same ones.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `gitea` - The Gitea instance to mirror to
+/// * `signing_key` - Key the manifest is signed with, when it is
+///
+/// Returns the number of mirrored gists.
+pub async fn mirror(
+    folder: &Path,
+    gitea: &Gitea,
+    signing_key: Option<&SecretKey>,
+) -> Result<usize, GistError> {
+    let mut manifest = Manifest::load(folder)?;
+    let owner = gitea.login().await?;
+    let mut mirrored = 0;
+
+    for entry in manifest.gists.values_mut() {
+        let result = match store::read_gist_files(folder, entry) {
+            Ok(files) => {
+                let repository = entry.mirrors.get(MIRROR_KEY).map(String::as_str);
+                gitea.mirror_gist(&owner, entry, &files, repository).await
+            }
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(repository) => {
+                info!("Mirrored gist {} to {}", entry.id, repository);
+                entry.mirrors.insert(MIRROR_KEY.to_string(), repository);
+                mirrored += 1;
+            }
+            Err(e) => error!("Failed to mirror gist {}: {}", entry.id, e),
+        }
+    }
+
+    manifest.save(folder, signing_key)?;
+    info!(
+        "Mirrored {} of {} gists to Gitea",
+        mirrored,
+        manifest.gists.len()
+    );
+    Ok(mirrored)
+}
@@ -0,0 +1,405 @@
+use crate::gist::{create_gist, GistError};
+use crate::highlight;
+use crate::index::Index;
+use crate::log::{debug, error, info};
+use crate::manifest::{Manifest, ManifestEntry, ManifestFile};
+use crate::store::{self, Folder, Store};
+use minisign::SecretKey;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+// Key of GitLab snippet ids in the mirrors of a manifest entry
+const MIRROR_KEY: &str = "gitlab";
+
+/// Prefix of the archive ids of imported snippets that weren't republished as gists
+pub const SNIPPET_ID_PREFIX: &str = "gitlab-";
+// Snippets requested per page when listing
+const PER_PAGE: u32 = 100;
+
+#[derive(Debug, Deserialize)]
+struct SnippetFile {
+    path: String,
+    #[serde(default)]
+    raw_url: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SnippetAuthor {
+    username: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Snippet {
+    id: u64,
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    visibility: String,
+    #[serde(default)]
+    author: SnippetAuthor,
+    #[serde(default)]
+    web_url: String,
+    #[serde(default)]
+    created_at: String,
+    #[serde(default)]
+    updated_at: String,
+    #[serde(default)]
+    files: Vec<SnippetFile>,
+}
+
+/// Client for the snippets API of a GitLab instance.
+pub struct GitLab {
+    client: Client,
+    api_url: String,
+    token: String,
+}
+
+/// Returns the snippet title of a gist, GitLab requires one where gists don't.
+fn title(entry: &ManifestEntry) -> String {
+    match entry.description.as_deref() {
+        Some(description) if !description.trim().is_empty() => description.to_string(),
+        _ => entry
+            .files
+            .first()
+            .map_or_else(|| entry.id.clone(), |f| f.filename.clone()),
+    }
+}
+
+impl GitLab {
+    /// # Arguments
+    /// * `url` - Base URL of the instance, e.g. `https://gitlab.com`
+    /// * `token` - Personal access token with the `api` scope
+    pub fn new(client: &Client, url: &str, token: &str) -> Result<Self, GistError> {
+        Ok(GitLab {
+            client: client.clone(),
+            api_url: format!("{}/api/v4", url.trim_end_matches('/')),
+            token: token.to_string(),
+        })
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        self.client
+            .request(method, format!("{}{}", self.api_url, path))
+            .header("PRIVATE-TOKEN", &self.token)
+    }
+
+    /// Sends a request and parses the JSON response, `None` when the resource is missing.
+    async fn send<T: for<'de> Deserialize<'de>>(
+        &self,
+        request: RequestBuilder,
+    ) -> Result<Option<T>, GistError> {
+        let response = request.send().await?;
+        let status = response.status();
+        let text = response.text().await?;
+        match status {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => serde_json::from_str(&text)
+                .map(Some)
+                .map_err(|e| GistError::Json(e, text)),
+            status => Err(GistError::Target(format!(
+                "GitLab answered {}: {}",
+                status, text
+            ))),
+        }
+    }
+
+    /// Lists the snippets of the authenticated user.
+    async fn list_snippets(&self) -> Result<Vec<Snippet>, GistError> {
+        let mut snippets = Vec::new();
+        for page in 1.. {
+            let path = format!("/snippets?per_page={}&page={}", PER_PAGE, page);
+            let batch: Vec<Snippet> = self
+                .send(self.request(reqwest::Method::GET, &path))
+                .await?
+                .unwrap_or_default();
+            let done = batch.len() < PER_PAGE as usize;
+            snippets.extend(batch);
+            if done {
+                break;
+            }
+        }
+        Ok(snippets)
+    }
+
+    /// Fetches the filename and content of every file of a snippet.
+    async fn snippet_files(&self, snippet: &Snippet) -> Result<Vec<(String, String)>, GistError> {
+        let mut files = Vec::with_capacity(snippet.files.len());
+        for file in &snippet.files {
+            let content = self
+                .client
+                .get(&file.raw_url)
+                .header("PRIVATE-TOKEN", &self.token)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            files.push((file.path.clone(), content));
+        }
+        Ok(files)
+    }
+
+    /// Creates or updates the snippet mirroring a gist, returning its id.
+    ///
+    /// # Arguments
+    /// * `entry` - The gist to mirror
+    /// * `files` - Filename and content of every file of the gist
+    /// * `snippet_id` - The snippet created by an earlier run, if any
+    async fn mirror_gist(
+        &self,
+        entry: &ManifestEntry,
+        files: &[(String, String)],
+        snippet_id: Option<&str>,
+    ) -> Result<u64, GistError> {
+        let visibility = if entry.public { "public" } else { "private" };
+        let existing: Option<Snippet> = match snippet_id {
+            Some(id) => {
+                self.send(self.request(reqwest::Method::GET, &format!("/snippets/{}", id)))
+                    .await?
+            }
+            None => None,
+        };
+
+        let snippet: Option<Snippet> = match existing {
+            Some(existing) => {
+                let existing_paths: HashSet<&str> =
+                    existing.files.iter().map(|f| f.path.as_str()).collect();
+                let mut actions: Vec<Value> = files
+                    .iter()
+                    .map(|(filename, content)| {
+                        let action = if existing_paths.contains(filename.as_str()) {
+                            "update"
+                        } else {
+                            "create"
+                        };
+                        json!({"action": action, "file_path": filename, "content": content})
+                    })
+                    .collect();
+                for path in existing_paths {
+                    if !files.iter().any(|(filename, _)| filename == path) {
+                        actions.push(json!({"action": "delete", "file_path": path}));
+                    }
+                }
+                let body = json!({
+                    "title": title(entry),
+                    "description": entry.description.as_deref().unwrap_or_default(),
+                    "visibility": visibility,
+                    "files": actions,
+                });
+                let path = format!("/snippets/{}", existing.id);
+                self.send(self.request(reqwest::Method::PUT, &path).json(&body))
+                    .await?
+            }
+            None => {
+                let files: Vec<Value> = files
+                    .iter()
+                    .map(|(filename, content)| json!({"file_path": filename, "content": content}))
+                    .collect();
+                let body = json!({
+                    "title": title(entry),
+                    "description": entry.description.as_deref().unwrap_or_default(),
+                    "visibility": visibility,
+                    "files": files,
+                });
+                self.send(self.request(reqwest::Method::POST, "/snippets").json(&body))
+                    .await?
+            }
+        };
+
+        snippet
+            .map(|s| s.id)
+            .ok_or_else(|| GistError::NotFound(format!("GitLab snippet for gist {}", entry.id)))
+    }
+}
+
+/// Creates or updates a GitLab snippet for every gist of the archive, recording the snippet
+/// ids in the manifest so later runs update the same snippets.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `gitlab` - The GitLab instance to mirror to
+/// * `signing_key` - Key the manifest is signed with, when it is
+///
+/// Returns the number of mirrored gists.
+pub async fn mirror(
+    folder: &Path,
+    gitlab: &GitLab,
+    signing_key: Option<&SecretKey>,
+) -> Result<usize, GistError> {
+    let mut manifest = Manifest::load(folder)?;
+    let mut mirrored = 0;
+
+    for entry in manifest.gists.values_mut() {
+        let files = store::read_gist_files(folder, entry).map(|files| {
+            files
+                .into_iter()
+                .map(|(filename, content)| {
+                    (filename, String::from_utf8_lossy(&content).into_owned())
+                })
+                .collect::<Vec<_>>()
+        });
+        let result = match files {
+            Ok(files) => {
+                let snippet_id = entry.mirrors.get(MIRROR_KEY).map(String::as_str);
+                gitlab.mirror_gist(entry, &files, snippet_id).await
+            }
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(snippet_id) => {
+                info!("Mirrored gist {} to snippet {}", entry.id, snippet_id);
+                entry
+                    .mirrors
+                    .insert(MIRROR_KEY.to_string(), snippet_id.to_string());
+                mirrored += 1;
+            }
+            Err(e) => error!("Failed to mirror gist {}: {}", entry.id, e),
+        }
+    }
+
+    manifest.save(folder, signing_key)?;
+    info!(
+        "Mirrored {} of {} gists to GitLab",
+        mirrored,
+        manifest.gists.len()
+    );
+    Ok(mirrored)
+}
+
+fn write_files(archive: &Folder, id: &str, files: &[(String, String)]) -> std::io::Result<()> {
+    for (filename, content) in files {
+        store::write_file(archive, id, filename, content.as_bytes(), Store::Plain)?;
+    }
+    Ok(())
+}
+
+/// Builds the manifest entry of a snippet kept in the archive as it is.
+fn snippet_entry(id: &str, snippet: &Snippet, files: &[(String, String)]) -> ManifestEntry {
+    let mut manifest_files: Vec<ManifestFile> = files
+        .iter()
+        .map(|(filename, content)| ManifestFile {
+            filename: filename.clone(),
+            language: highlight::language(filename, content),
+            size: content.len() as u32,
+            license: None,
+            sha256: None,
+            error: None,
+            binary: false,
+            mode: None,
+            gzip: false,
+            eol: None,
+        })
+        .collect();
+    manifest_files.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+    ManifestEntry {
+        id: id.to_string(),
+        description: Some(snippet.title.clone()),
+        html_url: snippet.web_url.clone(),
+        owner: snippet.author.username.clone(),
+        public: snippet.visibility == "public",
+        created_at: snippet.created_at.clone(),
+        updated_at: snippet.updated_at.clone(),
+        files: manifest_files,
+        mirrors: BTreeMap::from([(MIRROR_KEY.to_string(), snippet.id.to_string())]),
+        partial: false,
+        sparse: false,
+        comments: 0,
+        starred: false,
+        stale: false,
+    }
+}
+
+/// Imports the GitLab snippets of a user into the archive. Snippets are stored as
+/// `gitlab-<snippet id>` gists, or republished as GitHub gists first when a token is given.
+/// Snippets imported by an earlier run are updated in place rather than duplicated, and
+/// republished ones are left to the gist downloads.
+///
+/// # Arguments
+/// * `archive` - Archive folder containing downloaded gists
+/// * `gitlab` - The GitLab instance to import from, the token must belong to `user`
+/// * `user` - Only import the snippets authored by this user
+/// * `github_token` - Republish every new snippet as a GitHub gist with this token
+/// * `signing_key` - Key the manifest is signed with, when it is
+///
+/// Returns the number of imported snippets.
+pub async fn import(
+    archive: &Folder<'_>,
+    gitlab: &GitLab,
+    user: &str,
+    github_token: Option<&str>,
+    signing_key: Option<&SecretKey>,
+) -> Result<usize, GistError> {
+    let folder = archive.root;
+    let mut manifest = Manifest::load(folder)?;
+    let mut index = Index::open(folder)?;
+    let snippets: Vec<Snippet> = gitlab
+        .list_snippets()
+        .await?
+        .into_iter()
+        .filter(|s| s.author.username == user)
+        .collect();
+    info!("Found {} snippets of {}", snippets.len(), user);
+
+    let mut imported = 0;
+    for snippet in &snippets {
+        let files = match gitlab.snippet_files(snippet).await {
+            Ok(files) => files,
+            Err(e) => {
+                error!("Failed to fetch snippet {}: {}", snippet.id, e);
+                continue;
+            }
+        };
+        let snippet_id = snippet.id.to_string();
+        let existing = manifest
+            .gists
+            .values()
+            .find(|e| e.mirrors.get(MIRROR_KEY) == Some(&snippet_id))
+            .map(|e| e.id.clone());
+
+        let id = match (existing, github_token) {
+            // Republished snippets live on as gists, which are downloaded like any other
+            (Some(id), _) if !id.starts_with(SNIPPET_ID_PREFIX) => {
+                debug!("Snippet {} was republished as gist {}", snippet.id, id);
+                continue;
+            }
+            (None, Some(token)) => {
+                let public = snippet.visibility == "public";
+                let gist =
+                    match create_gist(&gitlab.client, token, Some(&snippet.title), public, &files)
+                        .await
+                    {
+                        Ok(gist) => gist,
+                        Err(e) => {
+                            error!("Failed to republish snippet {}: {}", snippet.id, e);
+                            continue;
+                        }
+                    };
+                info!("Republished snippet {} as {}", snippet.id, gist.html_url);
+                write_files(archive, &gist.id, &files)?;
+                let entry = manifest.insert(&gist);
+                entry.mirrors.insert(MIRROR_KEY.to_string(), snippet_id);
+                entry.inspect_files(folder);
+                gist.id
+            }
+            (existing, _) => {
+                let id = existing.unwrap_or_else(|| format!("{}{}", SNIPPET_ID_PREFIX, snippet.id));
+                write_files(archive, &id, &files)?;
+                let mut entry = snippet_entry(&id, snippet, &files);
+                entry.inspect_files(folder);
+                manifest.gists.insert(id.clone(), entry);
+                id
+            }
+        };
+        index.index_gist(folder, &manifest.gists[&id])?;
+        info!("Imported snippet {} as {}", snippet.id, id);
+        imported += 1;
+    }
+
+    manifest.save(folder, signing_key)?;
+    info!("Imported {} of {} snippets", imported, snippets.len());
+    Ok(imported)
+}
@@ -0,0 +1,130 @@
+use crate::filter::GistFilter;
+use crate::gist::GistError;
+use crate::log::debug;
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::output::Output;
+use crate::store;
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tokio::task::JoinSet;
+
+/// The lines of a file matching the pattern.
+#[derive(Serialize)]
+struct FileMatches {
+    gist_id: String,
+    filename: String,
+    description: Option<String>,
+    lines: Vec<LineMatch>,
+}
+
+#[derive(Serialize)]
+struct LineMatch {
+    line_number: usize,
+    line: String,
+}
+
+/// Matches every selected file of a gist against the pattern, skipping binary and
+/// unreadable files.
+fn grep_gist(
+    folder: &Path,
+    entry: &ManifestEntry,
+    filter: &GistFilter,
+    re: &Regex,
+) -> Vec<FileMatches> {
+    let mut matches = Vec::new();
+    for file in entry
+        .files
+        .iter()
+        .filter(|f| !f.binary && filter.matches_file(f))
+    {
+        let content = match store::read_file(folder, &entry.id, &file.filename) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) => {
+                debug!("Skipping {}/{}: {}", entry.id, file.filename, e);
+                continue;
+            }
+        };
+        let lines: Vec<LineMatch> = content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| re.is_match(line))
+            .map(|(n, line)| LineMatch {
+                line_number: n + 1,
+                line: line.to_string(),
+            })
+            .collect();
+        if !lines.is_empty() {
+            matches.push(FileMatches {
+                gist_id: entry.id.clone(),
+                filename: file.filename.clone(),
+                description: entry.description.clone(),
+                lines,
+            });
+        }
+    }
+    matches
+}
+
+/// Searches the text files of a local archive for a regular expression, one task per gist.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `pattern` - Regular expression matched against each line
+/// * `filter` - Restricts the files that are searched
+/// * `ignore_case` - Match case-insensitively
+/// * `output` - Where the matching files and lines are reported
+///
+/// Returns the number of matching lines.
+pub async fn grep(
+    folder: &str,
+    pattern: &str,
+    filter: GistFilter,
+    ignore_case: bool,
+    output: &Output,
+) -> Result<usize, GistError> {
+    let re = RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()?;
+    let folder = PathBuf::from(folder);
+    let manifest = Manifest::load(&folder)?;
+
+    let mut grep_set = JoinSet::new();
+    for entry in manifest.gists.into_values() {
+        if !filter.matches_gist(&entry) {
+            continue;
+        }
+        let folder = folder.clone();
+        let filter = filter.clone();
+        let re = re.clone();
+        grep_set.spawn_blocking(move || grep_gist(&folder, &entry, &filter, &re));
+    }
+
+    let mut results = Vec::new();
+    while let Some(res) = grep_set.join_next().await {
+        results.extend(res.map_err(std::io::Error::other)?);
+    }
+    // Tasks finish in any order, keep the output stable between runs
+    results.sort_by(|a, b| a.gist_id.cmp(&b.gist_id));
+
+    // Matching lines as `id/filename:line:text` like ripgrep, under the description of
+    // their gist
+    output.write(&results, |results| {
+        let mut gist_id = None;
+        for file in results {
+            if gist_id != Some(&file.gist_id) {
+                let description = file.description.as_deref().unwrap_or("<no description>");
+                println!("{} - {}", file.gist_id, description);
+                gist_id = Some(&file.gist_id);
+            }
+            for line in &file.lines {
+                println!(
+                    "{}/{}:{}:{}",
+                    file.gist_id, file.filename, line.line_number, line.line
+                );
+            }
+        }
+    })?;
+
+    Ok(results.iter().map(|file| file.lines.len()).sum())
+}
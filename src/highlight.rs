@@ -0,0 +1,158 @@
+use crate::gist::GistError;
+use std::path::Path;
+use std::sync::OnceLock;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::{Theme, ThemeSet};
+use syntect::html::highlighted_html_for_string;
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
+
+// Theme used when rendering files as HTML
+const HTML_THEME: &str = "InspiredGitHub";
+// Themes used when rendering files in a terminal with a dark or a light background
+const TERMINAL_THEME: &str = "base16-ocean.dark";
+const LIGHT_TERMINAL_THEME: &str = "base16-ocean.light";
+
+// Theme chosen with --theme, used instead of the defaults for HTML and terminals alike
+static CHOSEN_THEME: OnceLock<Theme> = OnceLock::new();
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme_set() -> &'static ThemeSet {
+    static THEME_SET: OnceLock<ThemeSet> = OnceLock::new();
+    THEME_SET.get_or_init(ThemeSet::load_defaults)
+}
+
+fn theme(name: &str) -> &'static Theme {
+    &theme_set().themes[name]
+}
+
+/// Highlights with the given theme instead of the defaults: one of the themes bundled
+/// with syntect by name, or a TextMate `.tmTheme` file by path.
+pub fn use_theme(name_or_path: &str) -> Result<(), GistError> {
+    let theme = match theme_set().themes.get(name_or_path) {
+        Some(theme) => theme.clone(),
+        None if Path::new(name_or_path).is_file() => {
+            ThemeSet::get_theme(name_or_path).map_err(|e| {
+                GistError::Config(format!("could not load theme {}: {}", name_or_path, e))
+            })?
+        }
+        None => {
+            let names: Vec<&str> = theme_set().themes.keys().map(String::as_str).collect();
+            return Err(GistError::NotFound(format!(
+                "theme {} (neither a file nor one of: {})",
+                name_or_path,
+                names.join(", ")
+            )));
+        }
+    };
+    let _ = CHOSEN_THEME.set(theme);
+    Ok(())
+}
+
+fn html_theme() -> &'static Theme {
+    CHOSEN_THEME.get().unwrap_or_else(|| theme(HTML_THEME))
+}
+
+/// Whether the terminal has a light background, as told by the `COLORFGBG` variable many
+/// terminals set to their foreground and background colors, e.g. `0;15` for black on
+/// white. Dark is assumed when it isn't set.
+fn light_terminal() -> bool {
+    std::env::var("COLORFGBG")
+        .ok()
+        .and_then(|colors| colors.rsplit(';').next()?.parse::<u8>().ok())
+        .is_some_and(|background| background == 7 || background >= 9)
+}
+
+fn terminal_theme() -> &'static Theme {
+    CHOSEN_THEME
+        .get()
+        .unwrap_or_else(|| match light_terminal() {
+            true => theme(LIGHT_TERMINAL_THEME),
+            false => theme(TERMINAL_THEME),
+        })
+}
+
+/// Picks a syntax definition based on the file extension, falling back to the first line
+/// (shebangs, modelines) and finally to plain text.
+fn find_syntax(filename: &str, code: &str) -> &'static SyntaxReference {
+    let ss = syntax_set();
+    Path::new(filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(|ext| ss.find_syntax_by_extension(ext))
+        .or_else(|| ss.find_syntax_by_first_line(code))
+        .unwrap_or_else(|| ss.find_syntax_plain_text())
+}
+
+/// Returns the name of the language of a file, `None` for plain text.
+pub fn language(filename: &str, code: &str) -> Option<String> {
+    let syntax = find_syntax(filename, code);
+    (syntax.name != syntax_set().find_syntax_plain_text().name).then(|| syntax.name.clone())
+}
+
+/// Renders the content of a file as highlighted HTML.
+pub fn to_html(filename: &str, code: &str) -> String {
+    let syntax = find_syntax(filename, code);
+    highlighted_html_for_string(code, syntax_set(), syntax, html_theme())
+        .unwrap_or_else(|_| format!("<pre>{}</pre>", escape_html(code)))
+}
+
+/// Renders the content of a file with terminal color escapes, one string per line without
+/// its line ending.
+pub fn to_terminal(filename: &str, code: &str) -> Vec<String> {
+    let syntax = find_syntax(filename, code);
+    let mut highlighter = HighlightLines::new(syntax, terminal_theme());
+    LinesWithEndings::from(code)
+        .map(
+            |line| match highlighter.highlight_line(line, syntax_set()) {
+                Ok(ranges) => {
+                    let escaped = as_24_bit_terminal_escaped(&ranges, false);
+                    format!("{}\x1b[0m", escaped.trim_end_matches(['\n', '\r']))
+                }
+                Err(_) => line.trim_end_matches(['\n', '\r']).to_string(),
+            },
+        )
+        .collect()
+}
+
+/// A piece of a highlighted line with its foreground color as RGB.
+pub type ColoredPiece = ((u8, u8, u8), String);
+
+/// Splits the content of a file into highlighted pieces, one list per line.
+pub fn to_colored_lines(filename: &str, code: &str) -> Vec<Vec<ColoredPiece>> {
+    let syntax = find_syntax(filename, code);
+    let mut highlighter = HighlightLines::new(syntax, terminal_theme());
+    LinesWithEndings::from(code)
+        .map(
+            |line| match highlighter.highlight_line(line, syntax_set()) {
+                Ok(ranges) => ranges
+                    .into_iter()
+                    .map(|(style, text)| {
+                        let color = (style.foreground.r, style.foreground.g, style.foreground.b);
+                        (color, text.trim_end_matches(['\n', '\r']).to_string())
+                    })
+                    .collect(),
+                Err(_) => vec![((255, 255, 255), line.trim_end_matches(['\n', '\r']).into())],
+            },
+        )
+        .collect()
+}
+
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,92 @@
+use crate::log::{debug, warn};
+use crate::manifest::{ChangeSummary, ManifestEntry};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+/// Quotes a value for the shell running the hook.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Runs a hook through `sh -c`, logging instead of failing when it doesn't succeed so a
+/// broken hook never interrupts a download.
+async fn run(command: &str, env: &[(&str, String)]) {
+    debug!("Running hook: {}", command);
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .envs(env.iter().map(|(name, value)| (name, value)))
+        .status()
+        .await;
+    match status {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("Hook `{}` failed with {}", command, status),
+        Err(e) => warn!("Hook `{}` could not be started: {}", command, e),
+    }
+}
+
+/// Runs the hook given for every downloaded gist. `{gist_id}` and `{path}` in the command
+/// are replaced by the (quoted) id and folder of the gist, and the metadata of the gist is
+/// passed in `GIST_*` environment variables.
+pub async fn run_gist_hook(command: &str, folder: &Path, entry: &ManifestEntry) {
+    let path = folder.join(&entry.id).display().to_string();
+    let command = command
+        .replace("{gist_id}", &quote(&entry.id))
+        .replace("{path}", &quote(&path));
+    let files: Vec<&str> = entry.files.iter().map(|f| f.filename.as_str()).collect();
+    let env = [
+        ("GIST_ID", entry.id.clone()),
+        ("GIST_PATH", path),
+        (
+            "GIST_DESCRIPTION",
+            entry.description.clone().unwrap_or_default(),
+        ),
+        ("GIST_OWNER", entry.owner.clone()),
+        ("GIST_PUBLIC", entry.public.to_string()),
+        ("GIST_HTML_URL", entry.html_url.clone()),
+        ("GIST_CREATED_AT", entry.created_at.clone()),
+        ("GIST_UPDATED_AT", entry.updated_at.clone()),
+        ("GIST_FILES", files.join("\n")),
+        ("GIST_LANGUAGES", entry.languages().join("\n")),
+    ];
+    run(&command, &env).await;
+}
+
+/// Runs the hook given for the end of a download. `{path}` in the command is replaced by
+/// the (quoted) archive folder, and what changed is passed in `LOCAL_GIST_*` environment
+/// variables.
+pub async fn run_done_hook(command: &str, folder: &Path, changes: &ChangeSummary) {
+    let path = folder.display().to_string();
+    let command = command.replace("{path}", &quote(&path));
+    let env = [
+        ("LOCAL_GIST_FOLDER", path),
+        ("LOCAL_GIST_ADDED", changes.added.to_string()),
+        ("LOCAL_GIST_UPDATED", changes.updated.to_string()),
+        ("LOCAL_GIST_REMOVED", changes.removed.to_string()),
+        ("LOCAL_GIST_FAILED", changes.failed.to_string()),
+    ];
+    run(&command, &env).await;
+}
+
+/// Runs the filter hook for a gist, passing its metadata as JSON on stdin. Returns whether
+/// the gist should be downloaded, which is when the hook exits successfully.
+pub async fn run_filter_hook(command: &str, entry: &ManifestEntry) -> std::io::Result<bool> {
+    let metadata = serde_json::to_vec(entry)?;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // A hook deciding without reading its input closes the pipe early, that's fine
+    if let Err(e) = stdin.write_all(&metadata).await {
+        debug!(
+            "Filter hook didn't read the metadata of {}: {}",
+            entry.id, e
+        );
+    }
+    drop(stdin);
+    Ok(child.wait().await?.success())
+}
@@ -0,0 +1,128 @@
+use crate::filter::GistFilter;
+use crate::gist::GistError;
+use crate::log::{info, warn};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::markdown::{fence, is_image, language_tag, yaml_string};
+use crate::paths::portable_name;
+use crate::store;
+use std::fmt::Write as _;
+use std::path::Path;
+
+// Page of a Hugo leaf bundle, the files of the gist being its resources
+const BUNDLE_PAGE: &str = "index.md";
+
+/// Renders the page of a gist: YAML front matter with its title, dates and language tags,
+/// then its description and every text file as a fenced code block Hugo highlights, or
+/// image, with a link to the file in the bundle. `contents` holds the text of each file in the
+/// archive.
+fn render(entry: &ManifestEntry, contents: &[Option<String>]) -> String {
+    let title = entry
+        .description
+        .as_deref()
+        .and_then(|description| description.lines().next())
+        .filter(|line| !line.trim().is_empty())
+        .unwrap_or(&entry.id);
+    let mut out = String::new();
+    let _ = writeln!(out, "---");
+    let _ = writeln!(out, "title: {}", yaml_string(title.trim()));
+    let _ = writeln!(out, "date: {}", yaml_string(&entry.created_at));
+    let _ = writeln!(out, "lastmod: {}", yaml_string(&entry.updated_at));
+    let _ = writeln!(out, "tags:");
+    for language in entry.languages() {
+        let _ = writeln!(out, "  - {}", yaml_string(&language_tag(language)));
+    }
+    let _ = writeln!(out, "gist_id: {}", yaml_string(&entry.id));
+    let _ = writeln!(out, "gist_url: {}", yaml_string(&entry.html_url));
+    let _ = writeln!(out, "---\n");
+    if let Some(description) = entry
+        .description
+        .as_deref()
+        .filter(|description| description.trim() != title.trim())
+    {
+        let _ = writeln!(out, "{}\n", description.trim_end());
+    }
+
+    for (file, content) in entry.files.iter().zip(contents) {
+        let name = portable_name(&file.filename);
+        let _ = writeln!(out, "### [{}](<{}>)\n", file.filename, name);
+        match content {
+            Some(content) => {
+                let language = file.language.as_deref().map(language_tag);
+                let fence = fence(content);
+                let _ = writeln!(out, "{}{}", fence, language.unwrap_or_default());
+                let _ = writeln!(out, "{}", content.trim_end_matches('\n'));
+                let _ = writeln!(out, "{}\n", fence);
+            }
+            None if is_image(file) => {
+                let _ = writeln!(out, "![{}](<{}>)\n", file.filename, name);
+            }
+            None if file.binary => {
+                let _ = writeln!(out, "Binary file, see the link above.\n");
+            }
+            None => {
+                let _ = writeln!(out, "Not in the archive.\n");
+            }
+        }
+    }
+    let _ = writeln!(out, "[View on GitHub]({})", entry.html_url);
+    out
+}
+
+/// Writes a Hugo leaf bundle per gist, `<out>/<gist id>/index.md` next to the files of
+/// the gist, for a static site to publish. Secret gists are left out unless
+/// `include_secret` is set, since the site is likely public.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `out` - Content directory of the site the bundles are written into, e.g.
+///   `content/snippets`
+/// * `include_secret` - Also export secret gists
+/// * `filter` - Selects the gists to export
+/// * `follow_symlinks` - Write through symlinks inside `out` instead of refusing them
+///
+/// Returns the number of exported gists.
+pub fn export_hugo(
+    folder: &str,
+    out: &str,
+    include_secret: bool,
+    filter: &GistFilter,
+    follow_symlinks: bool,
+) -> Result<usize, GistError> {
+    let folder = Path::new(folder);
+    let out = Path::new(out);
+    let mut manifest = Manifest::load(folder)?;
+    manifest
+        .gists
+        .retain(|_, entry| (entry.public || include_secret) && filter.matches_gist(entry));
+
+    for entry in manifest.gists.values() {
+        let bundle = out.join(&entry.id);
+        store::check_inside(out, &bundle, follow_symlinks)?;
+        std::fs::create_dir_all(&bundle)?;
+        let mut contents = Vec::new();
+        for file in &entry.files {
+            let content = match store::read_file(folder, &entry.id, &file.filename) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("Skipping {}/{}: {}", entry.id, file.filename, e);
+                    contents.push(None);
+                    continue;
+                }
+            };
+            // A file named like the page would replace it
+            let name = portable_name(&file.filename);
+            if !name.eq_ignore_ascii_case(BUNDLE_PAGE) {
+                std::fs::write(bundle.join(name.as_ref()), &content)?;
+            }
+            contents.push((!file.binary).then(|| String::from_utf8_lossy(&content).into_owned()));
+        }
+        std::fs::write(bundle.join(BUNDLE_PAGE), render(entry, &contents))?;
+    }
+    info!(
+        "Exported {} gists as page bundles to {}",
+        manifest.gists.len(),
+        out.display()
+    );
+
+    Ok(manifest.gists.len())
+}
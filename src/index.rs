@@ -0,0 +1,201 @@
+use crate::gist::GistError;
+use crate::log::{debug, info};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::store;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use std::path::Path;
+
+// Name of the SQLite full-text index kept at the root of the archive folder
+const INDEX_FILE: &str = "index.db";
+
+/// A single full-text search result.
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub gist_id: String,
+    pub filename: String,
+    pub description: String,
+    pub snippet: String,
+    pub score: f64,
+}
+
+/// SQLite FTS5 index over the descriptions, filenames and file contents of an archive.
+pub struct Index {
+    conn: Connection,
+}
+
+/// Turns free text into an FTS5 query matching all the terms, so punctuation in the
+/// input is never interpreted as query syntax.
+fn fts_query(text: &str) -> String {
+    text.split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Picks the only candidate, failing when there are several.
+fn only<'a>(
+    query: &str,
+    candidates: Vec<&'a ManifestEntry>,
+) -> Result<Option<&'a ManifestEntry>, GistError> {
+    match candidates.as_slice() {
+        [] => Ok(None),
+        [entry] => Ok(Some(entry)),
+        _ => {
+            let ids: Vec<&str> = candidates.iter().map(|entry| entry.id.as_str()).collect();
+            Err(GistError::Ambiguous(format!(
+                "{} matches {}",
+                query,
+                ids.join(", ")
+            )))
+        }
+    }
+}
+
+/// Finds the archived gist a user means, trying in turn the exact id, an id prefix, a
+/// part of the description and finally the full-text index. Fails rather than guessing
+/// when several gists match at the same step.
+pub fn resolve_gist<'a>(
+    folder: &Path,
+    manifest: &'a Manifest,
+    query: &str,
+) -> Result<&'a ManifestEntry, GistError> {
+    if let Some(entry) = manifest.gists.get(query) {
+        return Ok(entry);
+    }
+    let by_id = manifest
+        .gists
+        .values()
+        .filter(|entry| entry.id.starts_with(query))
+        .collect();
+    if let Some(entry) = only(query, by_id)? {
+        return Ok(entry);
+    }
+    let needle = query.to_lowercase();
+    let by_description = manifest
+        .gists
+        .values()
+        .filter(|entry| {
+            entry
+                .description
+                .as_deref()
+                .is_some_and(|d| d.to_lowercase().contains(&needle))
+        })
+        .collect();
+    if let Some(entry) = only(query, by_description)? {
+        return Ok(entry);
+    }
+
+    let index = Index::open(folder)?;
+    let mut by_content: Vec<&ManifestEntry> = Vec::new();
+    for hit in index.search(query, 50)? {
+        if let Some(entry) = manifest.gists.get(&hit.gist_id) {
+            if !by_content.iter().any(|e| e.id == entry.id) {
+                by_content.push(entry);
+            }
+        }
+    }
+    only(query, by_content)?.ok_or_else(|| GistError::NotFound(format!("gist matching {}", query)))
+}
+
+impl Index {
+    pub fn open(folder: &Path) -> Result<Self, GistError> {
+        std::fs::create_dir_all(folder)?;
+        let conn = Connection::open(folder.join(INDEX_FILE))?;
+        conn.execute_batch(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS files USING fts5(
+                gist_id UNINDEXED,
+                description,
+                filename,
+                content,
+                tokenize = 'porter unicode61'
+            );",
+        )?;
+        Ok(Index { conn })
+    }
+
+    /// Replaces the indexed content of a gist with the files currently on disk.
+    pub fn index_gist(&mut self, folder: &Path, entry: &ManifestEntry) -> Result<(), GistError> {
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM files WHERE gist_id = ?1", params![entry.id])?;
+        for file in &entry.files {
+            let content = match store::read_file(folder, &entry.id, &file.filename) {
+                // Only the name of binary files is searchable
+                Ok(_) if file.binary => String::new(),
+                Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+                Err(e) => {
+                    debug!(
+                        "Indexing {}/{} without content: {}",
+                        entry.id, file.filename, e
+                    );
+                    String::new()
+                }
+            };
+            tx.execute(
+                "INSERT INTO files (gist_id, description, filename, content) VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    entry.id,
+                    entry.description.as_deref().unwrap_or_default(),
+                    file.filename,
+                    content
+                ],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Removes the indexed content of a gist.
+    pub fn remove_gist(&mut self, id: &str) -> Result<(), GistError> {
+        self.conn
+            .execute("DELETE FROM files WHERE gist_id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Merges the segments of the full-text index and rewrites the database without its
+    /// free pages. Returns the bytes reclaimed.
+    pub fn compact(&mut self, folder: &Path) -> Result<u64, GistError> {
+        let path = folder.join(INDEX_FILE);
+        let before = std::fs::metadata(&path)?.len();
+        self.conn
+            .execute_batch("INSERT INTO files (files) VALUES ('optimize'); VACUUM;")?;
+        let after = std::fs::metadata(&path)?.len();
+        Ok(before.saturating_sub(after))
+    }
+
+    /// Drops the whole index and rebuilds it from the manifest.
+    pub fn rebuild(&mut self, folder: &Path, manifest: &Manifest) -> Result<(), GistError> {
+        self.conn.execute("DELETE FROM files", [])?;
+        for entry in manifest.gists.values() {
+            self.index_gist(folder, entry)?;
+        }
+        info!("Indexed {} gists", manifest.gists.len());
+        Ok(())
+    }
+
+    /// Returns the best matching files for a free text query, best match first.
+    pub fn search(&self, text: &str, limit: u32) -> Result<Vec<SearchHit>, GistError> {
+        let mut stmt = self.conn.prepare(
+            "SELECT gist_id, filename, description,
+                    snippet(files, 3, '[', ']', '...', 12),
+                    bm25(files)
+             FROM files
+             WHERE files MATCH ?1
+             ORDER BY bm25(files)
+             LIMIT ?2",
+        )?;
+        let hits = stmt
+            .query_map(params![fts_query(text), limit], |row| {
+                Ok(SearchHit {
+                    gist_id: row.get(0)?,
+                    filename: row.get(1)?,
+                    description: row.get(2)?,
+                    snippet: row.get(3)?,
+                    // bm25() is lower for better matches, flip it so higher means better
+                    score: -row.get::<_, f64>(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(hits)
+    }
+}
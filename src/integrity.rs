@@ -0,0 +1,176 @@
+use crate::gist::GistError;
+use crate::log::warn;
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::store::{self, sha256_hex};
+use minisign::{PublicKey, SecretKey, SignatureBox};
+use serde::Serialize;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+// Password of an encrypted signing key, downloads don't stop to ask for it
+const PASSWORD_ENV: &str = "LOCAL_GIST_SIGNING_PASSWORD";
+
+/// Outcome of checking an archive against its manifest.
+#[derive(Debug, Default, Serialize)]
+pub struct VerifyReport {
+    /// Files whose content matches the checksum recorded in the manifest
+    pub verified: usize,
+    /// Files without a recorded checksum, or encrypted ones read without the identity
+    pub unchecked: usize,
+    /// Files listed in the manifest but missing from the archive, as `id/filename`
+    pub missing: Vec<String>,
+    /// Files whose content no longer matches their checksum, as `id/filename`
+    pub mismatched: Vec<String>,
+    /// Files without the mode recorded for them, e.g. scripts no longer executable, as
+    /// `id/filename`
+    pub modes: Vec<String>,
+    /// Whether the signature of the manifest was checked, and held
+    pub signed: bool,
+}
+
+impl VerifyReport {
+    pub fn print(&self) {
+        println!(
+            "Verified {} files, {} missing, {} mismatched, {} with another mode, {} without checksum{}",
+            self.verified,
+            self.missing.len(),
+            self.mismatched.len(),
+            self.modes.len(),
+            self.unchecked,
+            if self.signed { ", signature valid" } else { "" }
+        );
+    }
+}
+
+fn signature_path(folder: &Path) -> PathBuf {
+    folder.join(format!("{}.minisig", MANIFEST_FILE))
+}
+
+fn signing_error(path: &Path, e: minisign::PError) -> GistError {
+    GistError::Signature(format!("{}: {}", path.display(), e))
+}
+
+/// Loads a minisign secret key, decrypting it with the password in
+/// `LOCAL_GIST_SIGNING_PASSWORD` when it is encrypted.
+pub fn load_secret_key(path: &Path) -> Result<SecretKey, GistError> {
+    let key_box = std::fs::read_to_string(path)?.into();
+    match std::env::var(PASSWORD_ENV) {
+        Ok(password) => SecretKey::from_box(key_box, Some(password)),
+        Err(_) => SecretKey::from_unencrypted_box(key_box),
+    }
+    .map_err(|e| match std::env::var_os(PASSWORD_ENV) {
+        Some(_) => signing_error(path, e),
+        None => GistError::Signature(format!(
+            "{}: {}, set {} if it is encrypted",
+            path.display(),
+            e,
+            PASSWORD_ENV
+        )),
+    })
+}
+
+/// Fails when the manifest of the folder is signed but no key was given to sign it again,
+/// since changing it would leave a signature `verify` rejects.
+pub fn check_signable(folder: &Path, signing_key: Option<&SecretKey>) -> Result<(), GistError> {
+    if signing_key.is_none() && signature_path(folder).exists() {
+        return Err(GistError::Signature(format!(
+            "{} is signed, pass --sign-manifest to change it",
+            folder.join(MANIFEST_FILE).display()
+        )));
+    }
+    Ok(())
+}
+
+/// Signs the manifest just written with the key of `--sign-manifest`, when given.
+pub fn sign_written(folder: &Path, signing_key: Option<&SecretKey>) -> Result<(), GistError> {
+    match signing_key {
+        Some(key) => sign_manifest(folder, key),
+        None => Ok(()),
+    }
+}
+
+/// Writes a detached minisign signature of the manifest next to it, `manifest.json.minisig`,
+/// so a copy of the archive can be checked against the checksums it records.
+fn sign_manifest(folder: &Path, key: &SecretKey) -> Result<(), GistError> {
+    let manifest = folder.join(MANIFEST_FILE);
+    let signature = minisign::sign(None, key, File::open(&manifest)?, None, None)
+        .map_err(|e| signing_error(&manifest, e))?;
+    let path = signature_path(folder);
+    let tmp_path = path.with_extension("minisig.tmp");
+    std::fs::write(&tmp_path, signature.into_string())?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Checks that every file of the manifest is in the archive with the recorded checksum,
+/// and first that the manifest itself was signed with `public_key` when given.
+///
+/// Files that failed to download are left out, the manifest records them as missing.
+pub fn verify(folder: &Path, public_key: Option<&Path>) -> Result<VerifyReport, GistError> {
+    let mut report = VerifyReport::default();
+    let manifest_path = folder.join(MANIFEST_FILE);
+    if !manifest_path.exists() {
+        return Err(GistError::NotFound(format!(
+            "{} has no {}",
+            folder.display(),
+            MANIFEST_FILE
+        )));
+    }
+    if let Some(public_key) = public_key {
+        let key = PublicKey::from_file(public_key).map_err(|e| signing_error(public_key, e))?;
+        let path = signature_path(folder);
+        let signature = SignatureBox::from_file(&path).map_err(|e| signing_error(&path, e))?;
+        minisign::verify(
+            &key,
+            &signature,
+            File::open(&manifest_path)?,
+            true,
+            false,
+            false,
+        )
+        .map_err(|e| signing_error(&manifest_path, e))?;
+        report.signed = true;
+    }
+
+    let manifest = Manifest::load(folder)?;
+    // Sparse gists have no files in the archive to check
+    for entry in manifest.gists.values().filter(|entry| !entry.sparse) {
+        for file in entry.files.iter().filter(|file| file.error.is_none()) {
+            let name = format!("{}/{}", entry.id, file.filename);
+            let Some(expected) = &file.sha256 else {
+                report.unchecked += 1;
+                continue;
+            };
+            let recorded = file
+                .mode
+                .as_deref()
+                .map(|mode| u32::from_str_radix(mode, 8));
+            if let (Some(Ok(recorded)), Some(mode)) = (
+                recorded,
+                store::file_mode(folder, &entry.id, &file.filename),
+            ) {
+                if mode != recorded {
+                    warn!("{} has mode {:o} instead of {:o}", name, mode, recorded);
+                    report.modes.push(name.clone());
+                }
+            }
+            match store::read_file(folder, &entry.id, &file.filename) {
+                Ok(content) if sha256_hex(&content) == *expected => report.verified += 1,
+                Ok(_) => {
+                    warn!("{} doesn't match its checksum", name);
+                    report.mismatched.push(name);
+                }
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    warn!("{} is missing", name);
+                    report.missing.push(name);
+                }
+                Err(e) => {
+                    warn!("{} can't be checked: {}", name, e);
+                    report.unchecked += 1;
+                }
+            }
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,109 @@
+use std::path::Path;
+
+// Only the top of regular source files is checked for a license header
+const HEADER_BYTES: usize = 4096;
+
+/// Distinctive phrases of common licenses, checked in order. A license matches when all of
+/// its phrases appear in the whitespace-normalized, lowercased text.
+const LICENSE_PHRASES: &[(&str, &[&str])] = &[
+    (
+        "AGPL-3.0",
+        &["gnu affero general public license", "version 3"],
+    ),
+    (
+        "LGPL-3.0",
+        &["gnu lesser general public license", "version 3"],
+    ),
+    (
+        "LGPL-2.1",
+        &["gnu lesser general public license", "version 2.1"],
+    ),
+    ("GPL-3.0", &["gnu general public license", "version 3"]),
+    ("GPL-2.0", &["gnu general public license", "version 2"]),
+    ("Apache-2.0", &["apache license", "version 2.0"]),
+    ("MPL-2.0", &["mozilla public license", "2.0"]),
+    (
+        "BSD-3-Clause",
+        &[
+            "redistribution and use in source and binary forms",
+            "neither the name",
+        ],
+    ),
+    (
+        "BSD-2-Clause",
+        &["redistribution and use in source and binary forms"],
+    ),
+    (
+        "ISC",
+        &["permission to use, copy, modify, and/or distribute this software for any purpose"],
+    ),
+    (
+        "MIT",
+        &["permission is hereby granted, free of charge, to any person obtaining a copy"],
+    ),
+    (
+        "Unlicense",
+        &["this is free and unencumbered software released into the public domain"],
+    ),
+    ("CC0-1.0", &["cc0 1.0 universal"]),
+    ("WTFPL", &["do what the fuck you want to public license"]),
+];
+
+fn is_license_file(filename: &str) -> bool {
+    let stem = Path::new(filename)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_uppercase();
+    matches!(
+        stem.as_str(),
+        "LICENSE" | "LICENCE" | "COPYING" | "UNLICENSE"
+    )
+}
+
+/// Returns how much of the start of a file `detect` looks at, all of it for license files.
+pub fn detected_len(filename: &str) -> u64 {
+    match is_license_file(filename) {
+        true => u64::MAX,
+        false => HEADER_BYTES as u64,
+    }
+}
+
+fn spdx_identifier(text: &str) -> Option<String> {
+    text.lines().find_map(|line| {
+        let (_, rest) = line.split_once("SPDX-License-Identifier:")?;
+        let id = rest
+            .trim()
+            .trim_end_matches("*/")
+            .trim_end_matches("-->")
+            .trim();
+        (!id.is_empty()).then(|| id.to_string())
+    })
+}
+
+/// Detects the license of a file from an SPDX tag or the wording of well-known licenses.
+///
+/// Dedicated license files (`LICENSE`, `COPYING`, ...) are checked in full, other files
+/// only in their header so code merely mentioning a license is not misreported.
+pub fn detect(filename: &str, content: &[u8]) -> Option<String> {
+    let content = if is_license_file(filename) {
+        content
+    } else {
+        &content[..content.len().min(HEADER_BYTES)]
+    };
+    let text = String::from_utf8_lossy(content);
+
+    if let Some(id) = spdx_identifier(&text) {
+        return Some(id);
+    }
+
+    let normalized = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    LICENSE_PHRASES
+        .iter()
+        .find(|(_, phrases)| phrases.iter().all(|p| normalized.contains(p)))
+        .map(|(id, _)| id.to_string())
+}
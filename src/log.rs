@@ -0,0 +1,35 @@
+// The logging macros used across the crate. Built with the default `tracing` feature they
+// are the ones of `tracing`; without it they compile to nothing, so nothing is logged and
+// neither `tracing` nor a subscriber is pulled in.
+
+#[cfg(feature = "tracing")]
+pub use tracing::{debug, error, info, warn, Instrument};
+
+#[cfg(not(feature = "tracing"))]
+mod disabled {
+    // Stands in for every level. Still type checks the arguments, so values only logged
+    // don't become unused
+    macro_rules! discard {
+        ($($arg:tt)+) => {
+            if false {
+                let _ = format_args!($($arg)+);
+            }
+        };
+    }
+
+    pub(crate) use discard;
+
+    /// Stands in for `tracing::Instrument`, futures run as they are without spans.
+    pub trait Instrument: Sized {
+        fn in_current_span(self) -> Self {
+            self
+        }
+    }
+
+    impl<T: std::future::Future> Instrument for T {}
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use disabled::{
+    discard as debug, discard as error, discard as info, discard as warn, Instrument,
+};
@@ -1,94 +1,1557 @@
+use crate::log::{debug, error, info, warn, Instrument};
 use anyhow::Result;
-use clap::Parser;
-use cli::{Cli, Commands};
-use gist::{download_gist, list_gists, Gists};
-use std::path::PathBuf;
+use autotune::{AutoTune, Concurrency};
+use backup::Retention;
+use chrono::{DateTime, SecondsFormat, Utc};
+use cli::{Cli, Commands, ConfigAction, ExportFormat, ImportService, MirrorService, TrashAction};
+use conflict::Resolution;
+use filter::GistFilter;
+use gist::{
+    download_gist, find_gists, list_gists, DownloadOutcome, GistError, GistPages, Gists, LocalCopy,
+    MemoryBudget, Retry, WriteOptions,
+};
+use index::Index;
+use manifest::{ArchiveSettings, ChangeSummary, Manifest, ManifestEntry};
+use minisign::SecretKey;
+use output::Output;
+use progress::Progress;
+use reqwest::StatusCode;
+use serde::Serialize;
+use stats::Stats;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use std::sync::Arc;
-use std::time::Duration;
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use store::{Eol, Store};
+use table::{Column, GroupBy};
+use target::{MirrorOptions, Target};
+use tokio::sync::Semaphore;
 use tokio::task::JoinSet;
-use tokio::{runtime::Handle, sync::Semaphore};
-use tracing::{debug, error, info, Level};
-use tracing_subscriber;
+use tokio_util::sync::CancellationToken;
 
+mod auth;
+mod autotune;
+mod avatars;
+mod backup;
+mod browser;
+mod checkpoint;
 mod cli;
+mod clipboard;
+mod comments;
+mod config;
+mod conflict;
+mod crypt;
+mod etag;
+mod export;
+mod exporter;
+mod filter;
+mod gc;
 mod gist;
+mod git;
+mod gitea;
+mod gitlab;
+mod grep;
+mod highlight;
+mod hooks;
+mod hugo;
+mod index;
+mod integrity;
+mod license;
+mod log;
+mod manifest;
+mod markdown;
+mod metrics;
+mod obsidian;
+mod opengist;
+mod output;
+mod paths;
+mod picker;
+mod progress;
+mod readme;
+mod restore;
+mod s3;
+mod secrets;
+mod selfupdate;
+mod serve;
+mod sftp;
+mod snippets;
+mod spool;
+mod stats;
+mod store;
+mod table;
+mod target;
+mod telemetry;
+mod trash;
+mod tui;
+mod view;
+mod webdav;
+
+// File in the firehose folder keeping the time of the last poll of the feed
+const FIREHOSE_STATE: &str = ".firehose-since";
+// File in the archive folder holding the time `sync` last completed without failures
+const SYNC_STATE: &str = ".sync-since";
+// Longest time before the end of `--max-duration` new downloads stop being scheduled
+const DEADLINE_MARGIN: Duration = Duration::from_secs(60);
+
+// Exit codes telling the class of a failure, see the README. 0 is success.
+const EXIT_FAILURE: u8 = 1;
+const EXIT_PARTIAL: u8 = 2;
+const EXIT_AUTH: u8 = 3;
+const EXIT_RATE_LIMITED: u8 = 4;
+const EXIT_NOT_FOUND: u8 = 5;
+const EXIT_IO: u8 = 6;
+const EXIT_RESUMABLE: u8 = 7;
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        // .with_thread_ids(true)
-        .with_thread_names(true)
-        .with_max_level(Level::INFO)
-        .init();
+async fn main() -> ExitCode {
+    match run().await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// Maps an error to the exit code of its class, from the first `GistError` or I/O error
+/// in its chain.
+fn exit_code(error: &anyhow::Error) -> u8 {
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<GistError>() {
+            return match error {
+                GistError::Partial(_) => EXIT_PARTIAL,
+                GistError::RateLimited(_) | GistError::SecondaryRateLimited(_) => EXIT_RATE_LIMITED,
+                GistError::Auth(_) => EXIT_AUTH,
+                GistError::NotFound(_) => EXIT_NOT_FOUND,
+                GistError::Io(_) => EXIT_IO,
+                GistError::Budget(_) | GistError::Deadline(_) | GistError::Cancelled => {
+                    EXIT_RESUMABLE
+                }
+                GistError::Request(e) => match e.status() {
+                    Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => EXIT_AUTH,
+                    Some(StatusCode::TOO_MANY_REQUESTS) => EXIT_RATE_LIMITED,
+                    Some(StatusCode::NOT_FOUND) => EXIT_NOT_FOUND,
+                    _ => EXIT_FAILURE,
+                },
+                _ => EXIT_FAILURE,
+            };
+        }
+        if cause.is::<std::io::Error>() {
+            return EXIT_IO;
+        }
+    }
+    EXIT_FAILURE
+}
 
-    let cli: Cli = Cli::parse();
+async fn run() -> Result<()> {
+    let cli: Cli = config::parse_cli()?;
+
+    // Log lines would draw over the terminal UI
+    let logs = !matches!(cli.command, Commands::Tui { .. });
+    let _telemetry = telemetry::init(logs)?;
+    // Stops with the runtime at the end of the run
+    if let Some(interval) = cli.metrics {
+        tokio::spawn(metrics::report(Duration::from_secs(interval.max(1))));
+    }
+    if let Some(identity) = &cli.identity {
+        crypt::load_identities(identity)?;
+    }
+    if let Some(theme) = &cli.theme {
+        highlight::use_theme(theme)?;
+    }
+    let output = Output::new(cli.format, cli.output_query.as_deref())?;
+    let follow_symlinks = cli.follow_symlinks;
+    auth::use_git_credential(!cli.no_git_credential);
+    gist::wait_for_reset(!cli.no_wait);
+    if let Some(max) = cli.max_requests {
+        gist::limit_requests(max);
+    }
+    let client = gist::client(cli.user_agent.as_deref())?;
+    let signing_key = cli
+        .sign_manifest
+        .as_deref()
+        .map(integrity::load_secret_key)
+        .transpose()?;
+    let uses_github = match &cli.command {
+        Commands::Stats { local, .. } => !local,
+        Commands::Download { .. }
+        | Commands::Firehose { .. }
+        | Commands::Sync { .. }
+        | Commands::Materialize { .. }
+        | Commands::Refresh { .. }
+        | Commands::List { .. }
+        | Commands::Find { .. }
+        | Commands::Tui { .. }
+        | Commands::Describe { .. }
+        | Commands::Import { .. } => true,
+        _ => false,
+    };
+    if uses_github {
+        auth::check_token(&client).await;
+    }
 
     match cli.command {
         Commands::Download {
             username,
+            following_of,
+            org,
+            no_owner_dirs,
+            folder,
+            concurrency,
+            limit,
+            per_page,
+            store,
+            compress_files,
+            eol,
+            encrypt,
+            target,
+            git_commit,
+            post_hook,
+            done_hook,
+            filter_hook,
+            interactive,
+            skip_binary,
+            chmod_exec,
+            readme,
+            updated_since,
+            max_memory,
+            prune,
+            force,
+            keep_versions,
+            comments,
+            avatars,
+            sparse,
+            resolve,
+            max_duration,
+            open,
+            open_with,
+            filter,
+        } => {
+            let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+            let options = DownloadOptions {
+                limit,
+                per_page,
+                archive: ArchiveSettings {
+                    store,
+                    encrypt: encrypt.as_ref().map(ToString::to_string),
+                    compress_files,
+                    eol: eol.map(Eol::resolve),
+                    keep_versions,
+                },
+                write: WriteOptions {
+                    skip_binary,
+                    chmod_exec: filter::glob_set(&chmod_exec)?,
+                    follow_symlinks,
+                    ..WriteOptions::default()
+                },
+                filter_hook,
+                post_hook,
+                interactive,
+                readme,
+                updated_since,
+                memory_budget: max_memory.map(MemoryBudget::new),
+                prune,
+                force,
+                comments,
+                avatars,
+                sparse,
+                resolve,
+                max_duration,
+                cancel: cancel_on_ctrl_c(),
+                ..DownloadOptions::new(
+                    &client,
+                    concurrency,
+                    filter,
+                    follow_symlinks,
+                    signing_key.clone(),
+                )
+            };
+            match target {
+                Some(target) => {
+                    // --following-of conflicts with --target, so the username was given
+                    let username = username.unwrap_or_default();
+                    let target = Arc::new(Target::parse(&client, &target)?);
+                    handle_mirror(&username, target, &options).await?
+                }
+                None => {
+                    let changes = match (following_of, org, username) {
+                        (Some(user), _, _) => {
+                            let accounts = gist::list_following(&client, &user).await?;
+                            info!("{} follows {} accounts", user, accounts.len());
+                            handle_accounts(accounts, &folder, !no_owner_dirs, &options).await
+                        }
+                        (None, Some(org), _) => {
+                            let accounts = gist::list_org_members(&client, &org).await?;
+                            info!("{} has {} members", org, accounts.len());
+                            handle_accounts(accounts, &folder, !no_owner_dirs, &options).await
+                        }
+                        (None, None, username) => {
+                            let username = username.unwrap_or_default();
+                            info!("Fetching gists for user: {username}");
+                            let pages = GistPages::new(&client, &username, options.per_page)
+                                .since(options.updated_since);
+                            handle_download(pages, folder.clone(), &options, None).await?
+                        }
+                    };
+                    output.write(&changes, |_| {})?;
+                    if git_commit {
+                        git::commit_archive(Path::new(&folder), &changes)?;
+                    }
+                    if let Some(done_hook) = done_hook {
+                        hooks::run_done_hook(&done_hook, Path::new(&folder), &changes).await;
+                    }
+                    if open || open_with.is_some() {
+                        let folder = Path::new(&folder);
+                        let manifest = Manifest::load(folder)?;
+                        let ids: Vec<&String> = manifest.gists.keys().collect();
+                        open_downloaded(folder, &manifest, &ids, open_with.as_deref())?;
+                    }
+                    if changes.failed > 0 {
+                        let failed = format!("{} downloads failed", changes.failed);
+                        return Err(GistError::Partial(failed).into());
+                    }
+                }
+            }
+        }
+        Commands::Firehose {
+            folder,
+            concurrency,
+            interval,
+            once,
+            filter,
+        } => {
+            let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+            let options = DownloadOptions::new(
+                &client,
+                concurrency,
+                filter,
+                follow_symlinks,
+                signing_key.clone(),
+            );
+            handle_firehose(folder, Duration::from_secs(interval), once, &options).await?
+        }
+        Commands::Sync {
+            username,
+            folder,
+            concurrency,
+            interval,
+            once,
+            resolve,
+            max_duration,
+            compress_files,
+            eol,
+            target,
+            git_commit,
+            post_hook,
+            filter,
+        } => {
+            let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+            let options = DownloadOptions {
+                archive: ArchiveSettings {
+                    compress_files,
+                    eol: eol.map(Eol::resolve),
+                    ..ArchiveSettings::default()
+                },
+                post_hook,
+                resolve,
+                max_duration,
+                cancel: cancel_on_ctrl_c(),
+                ..DownloadOptions::new(
+                    &client,
+                    concurrency,
+                    filter,
+                    follow_symlinks,
+                    signing_key.clone(),
+                )
+            };
+            let interval = Duration::from_secs(interval);
+            match target {
+                Some(target) => {
+                    let target = Arc::new(Target::parse(&client, &target)?);
+                    handle_sync_mirror(&username, target, interval, once, &options).await?
+                }
+                None => {
+                    handle_sync(
+                        &username, folder, interval, once, git_commit, &options, &output,
+                    )
+                    .await?
+                }
+            }
+        }
+        Commands::Materialize {
+            ids,
             folder,
             concurrency,
+            open,
+            open_with,
+            filter,
+        } => {
+            let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+            let open_with = (open || open_with.is_some()).then_some(open_with);
+            let options = DownloadOptions::new(
+                &client,
+                Concurrency::Fixed(concurrency),
+                filter,
+                follow_symlinks,
+                signing_key.clone(),
+            );
+            handle_materialize(&ids, folder, open_with, &options).await?
+        }
+        Commands::Refresh { folder } => {
+            handle_refresh(&client, folder, signing_key.as_ref(), &output).await?
+        }
+        Commands::List {
+            username,
+            web: true,
+            ..
+        } => {
+            for username in username {
+                browser::open_url(&format!("https://gist.github.com/{}", username))?
+            }
+        }
+        Commands::List {
+            username,
+            starred,
+            group_by,
+            limit,
+            per_page,
+            web: false,
+            mut columns,
+            folder,
+        } => {
+            let mut gists: Gists = Vec::new();
+            if starred {
+                info!("Listing the first {:?} starred gists", limit);
+                gists.append(&mut GistPages::starred(&client, per_page).collect(limit).await?);
+            }
+            for username in &username {
+                info!("Listing the first {:?} gists for user: {}", limit, username);
+                gists.append(
+                    &mut list_gists(
+                        &client,
+                        username,
+                        limit,
+                        per_page,
+                        &CancellationToken::new(),
+                    )
+                    .await?,
+                );
+            }
+            let entries: Vec<ManifestEntry> = gists.iter().map(ManifestEntry::from).collect();
+            // Gists of several owners say whose they are
+            let with_owner = starred || username.len() > 1;
+            if with_owner && !columns.is_empty() && !columns.contains(&Column::Owner) {
+                columns.insert(1.min(columns.len()), Column::Owner);
+            }
+            let links = table::Links::new(folder.as_deref().map(Path::new));
+            let print = |entries: &[ManifestEntry], with_owner: bool| match columns.is_empty() {
+                true => table::print_lines(entries, with_owner, links.as_ref()),
+                false => table::print(entries, &columns, links.as_ref()),
+            };
+            match group_by {
+                Some(GroupBy::Owner) => {
+                    let mut groups: BTreeMap<String, Vec<ManifestEntry>> = BTreeMap::new();
+                    for entry in entries {
+                        groups.entry(entry.owner.clone()).or_default().push(entry);
+                    }
+                    output.write(&groups, |groups| {
+                        for (owner, entries) in groups {
+                            println!("{} ({} gists)", owner, entries.len());
+                            print(entries, false);
+                        }
+                    })?;
+                }
+                None => output.write(&entries, |entries| print(entries, with_owner))?,
+            }
+        }
+        Commands::Find {
+            username,
+            query,
+            limit,
+        } => {
+            info!("Searching the gists of {} for: {}", username, query);
+            let gists: Gists = find_gists(&client, &username, &query, limit, |gist| {
+                output.write_record(&ManifestEntry::from(gist), |_| {
+                    println!("{} {}", gist.html_url, gist)
+                })
+            })
+            .await?;
+            if output.buffers_records() {
+                let entries: Vec<ManifestEntry> = gists.iter().map(ManifestEntry::from).collect();
+                output.write(&entries, |_| {})?;
+            }
+            info!("Found {} matching gists", gists.len());
+        }
+        Commands::Stats {
+            username,
+            local,
+            top_size,
+            top_recent,
+            folder,
+        } => {
+            handle_stats(
+                &client, username, local, top_size, top_recent, folder, &output,
+            )
+            .await?
+        }
+        Commands::Search {
+            query,
+            folder,
             limit,
-        } => handle_download(username, folder, concurrency, limit).await?,
-        Commands::List { username, limit } => {
-            info!("Listing the first {:?} gists for user: {}", limit, username);
-            let gists: Gists = list_gists(&username, limit).await?;
-            for gist in gists {
-                info!("{}", gist);
+            reindex,
+            web,
+        } => handle_search(query, folder, limit, reindex, web, &output)?,
+        Commands::Tui {
+            folder,
+            github_token,
+        } => {
+            let github_token = match github_token {
+                Some(token) => Some(token),
+                None => auth::github_token(&client).await?,
+            };
+            tui::tui(
+                &client,
+                Path::new(&folder),
+                github_token,
+                follow_symlinks,
+                signing_key,
+            )
+            .await?
+        }
+        Commands::View {
+            gist,
+            folder,
+            no_pager,
+        } => view::view(Path::new(&folder), &gist, no_pager)?,
+        Commands::Open { gist, folder } => {
+            let folder = Path::new(&folder);
+            let manifest = Manifest::load(folder)?;
+            let entry = index::resolve_gist(folder, &manifest, &gist)?;
+            browser::open_url(&entry.html_url)?;
+        }
+        Commands::Grep {
+            pattern,
+            folder,
+            filter,
+            ignore_case,
+        } => {
+            let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+            let matches = grep::grep(&folder, &pattern, filter, ignore_case, &output).await?;
+            // Like grep and ripgrep, so scripts can tell when nothing matched
+            if matches == 0 {
+                anyhow::bail!("No line matches {}", pattern);
+            }
+            info!("{} matching lines", matches);
+        }
+        Commands::ScanSecrets {
+            folder,
+            min_severity,
+        } => {
+            secrets::scan_secrets(&folder, min_severity, &output).await?;
+        }
+        Commands::Checkout { id, folder } => {
+            let folder = Path::new(&folder);
+            let mut manifest = Manifest::load(folder)?;
+            let Some(entry) = manifest.gists.get_mut(&id) else {
+                anyhow::bail!("Gist {} is not in the archive", id);
+            };
+            let gzipped = entry.files.iter().any(|file| file.gzip);
+            if gzipped {
+                integrity::check_signable(folder, signing_key.as_ref())?;
+            }
+            store::checkout(&store::Folder::new(folder, follow_symlinks), entry)?;
+            // Gzipped files were written plain in their place
+            if gzipped {
+                entry.files.iter_mut().for_each(|file| file.gzip = false);
+                manifest.save(folder, signing_key.as_ref())?;
+            }
+        }
+        Commands::Cat {
+            id,
+            filename,
+            folder,
+        } => {
+            let content = read_gist_file(&client, &id, filename.as_deref(), &folder, false).await?;
+            std::io::Write::write_all(&mut std::io::stdout(), &content)?;
+        }
+        Commands::Copy {
+            id,
+            filename,
+            folder,
+        } => {
+            let prompt = std::io::IsTerminal::is_terminal(&std::io::stdin());
+            let content =
+                read_gist_file(&client, &id, filename.as_deref(), &folder, prompt).await?;
+            clipboard::copy(&String::from_utf8_lossy(&content))?;
+        }
+        Commands::Describe {
+            id,
+            description,
+            from_file,
+            folder,
+        } => {
+            let description = match from_file {
+                Some(path) if path.as_os_str() == "-" => std::io::read_to_string(std::io::stdin())?,
+                Some(path) => std::fs::read_to_string(path)?,
+                None => description.unwrap_or_default(),
+            };
+            let Some(token) = auth::github_token(&client).await? else {
+                anyhow::bail!("describe needs a GitHub token");
+            };
+            let folder = Path::new(&folder);
+            let mut manifest = Manifest::load(folder)?;
+            // Refused before changing the gist on GitHub, when the archive can't follow
+            if manifest.gists.contains_key(&id) {
+                integrity::check_signable(folder, signing_key.as_ref())?;
+            }
+            // Files usually end with a newline that isn't part of the description
+            let gist =
+                gist::update_description(&client, &token, &id, description.trim_end()).await?;
+            if let Some(entry) = manifest.gists.get_mut(&gist.id) {
+                entry.description = gist.description.clone();
+                manifest.save(folder, signing_key.as_ref())?;
+            }
+            output.write(&ManifestEntry::from(&gist), |_| {
+                println!("Updated the description of {}", gist)
+            })?;
+        }
+        Commands::Backup {
+            folder,
+            snapshots,
+            keep_daily,
+            keep_weekly,
+            encrypt,
+        } => {
+            let folder = Path::new(&folder);
+            let retention = Retention {
+                keep_daily,
+                keep_weekly,
+            };
+            backup::backup(
+                folder,
+                &backup::snapshots_dir(folder, snapshots.as_deref()),
+                retention,
+                encrypt.as_ref(),
+            )?;
+        }
+        Commands::Restore {
+            from,
+            snapshot,
+            at,
+            snapshots,
+            folder,
+        } => {
+            let folder = Path::new(&folder);
+            let archive = store::Folder::new(folder, follow_symlinks);
+            let report = match from {
+                Some(from) if !snapshot => {
+                    restore::restore_export(Path::new(&from), &archive, signing_key.as_ref())?
+                }
+                _ => restore::restore_snapshot(
+                    &backup::snapshots_dir(folder, snapshots.as_deref()),
+                    at,
+                    &archive,
+                    signing_key.as_ref(),
+                )?,
+            };
+            if !report.unrecoverable.is_empty() {
+                anyhow::bail!(
+                    "{} files could not be restored: {}",
+                    report.unrecoverable.len(),
+                    report.unrecoverable.join(", ")
+                );
             }
         }
+        Commands::Export { format } => match format {
+            ExportFormat::Tar {
+                folder,
+                out,
+                filter,
+            } => {
+                let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+                export::export_tar(&folder, &out, &filter)?;
+            }
+            ExportFormat::Zip {
+                folder,
+                out,
+                per_gist,
+                filter,
+            } => {
+                let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+                export::export_zip(&folder, &out, per_gist, &filter)?;
+            }
+            ExportFormat::Sqlite {
+                folder,
+                out,
+                filter,
+            } => {
+                let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+                export::export_sqlite(&folder, &out, &filter)?;
+            }
+            ExportFormat::Parquet {
+                folder,
+                out,
+                filter,
+            } => {
+                let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+                export::export_parquet(&folder, &out, &filter)?;
+            }
+            ExportFormat::Obsidian {
+                folder,
+                out,
+                filter,
+            } => {
+                let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+                obsidian::export_vault(&folder, &out, &filter, follow_symlinks)?;
+            }
+            ExportFormat::Snippets {
+                folder,
+                out,
+                editor,
+                filter,
+            } => {
+                let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+                snippets::export_snippets(&folder, &out, editor, &filter)?;
+            }
+            ExportFormat::Hugo {
+                folder,
+                out,
+                include_secret,
+                filter,
+            } => {
+                let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+                hugo::export_hugo(&folder, &out, include_secret, &filter, follow_symlinks)?;
+            }
+            ExportFormat::Plugin {
+                name,
+                folder,
+                filter,
+                args,
+            } => {
+                let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+                let mut plugin = exporter::PluginExporter::start(&name, &args)?;
+                exporter::export_with(&folder, &filter, &mut plugin)?;
+            }
+            ExportFormat::Plugins => {
+                output.write(&exporter::discover_plugins(), |plugins| {
+                    for plugin in plugins {
+                        println!(
+                            "{}\t{}\t{}",
+                            plugin.name, plugin.version, plugin.description
+                        );
+                    }
+                })?;
+            }
+            ExportFormat::Repo {
+                folder,
+                out,
+                ssh,
+                ssh_key,
+                depth,
+                filter,
+            } => {
+                if let Some(key) = ssh_key.as_deref().filter(|key| !key.is_file()) {
+                    anyhow::bail!("SSH key {} not found", key.display());
+                }
+                let filter = GistFilter::new(&filter.language, filter.filename.as_deref())?;
+                let remote = git::GistRemote { ssh, ssh_key };
+                export::export_repo(&folder, &out, &filter, &remote, depth)?;
+            }
+        },
+        Commands::Import { service } => match service {
+            ImportService::Gitlab {
+                user,
+                folder,
+                gitlab_token,
+                gitlab_url,
+                republish,
+                github_token,
+            } => {
+                let github_token = match (republish, github_token) {
+                    (true, None) => match auth::github_token(&client).await? {
+                        Some(token) => Some(token),
+                        None => anyhow::bail!("--republish needs a GitHub token"),
+                    },
+                    (true, token) => token,
+                    (false, _) => None,
+                };
+                let gitlab = gitlab::GitLab::new(&client, &gitlab_url, &gitlab_token)?;
+                let archive = store::Folder::new(Path::new(&folder), follow_symlinks);
+                gitlab::import(
+                    &archive,
+                    &gitlab,
+                    &user,
+                    github_token.as_deref(),
+                    signing_key.as_ref(),
+                )
+                .await?;
+            }
+        },
+        Commands::Mirror { service } => match service {
+            MirrorService::Gitlab {
+                folder,
+                gitlab_token,
+                gitlab_url,
+            } => {
+                let gitlab = gitlab::GitLab::new(&client, &gitlab_url, &gitlab_token)?;
+                gitlab::mirror(Path::new(&folder), &gitlab, signing_key.as_ref()).await?;
+            }
+            MirrorService::Gitea {
+                folder,
+                gitea_token,
+                gitea_url,
+            } => {
+                let gitea = gitea::Gitea::new(&client, &gitea_url, &gitea_token)?;
+                gitea::mirror(Path::new(&folder), &gitea, signing_key.as_ref()).await?;
+            }
+            MirrorService::Opengist {
+                folder,
+                opengist_url,
+                opengist_user,
+                opengist_password,
+            } => {
+                let opengist =
+                    opengist::Opengist::new(&opengist_url, &opengist_user, &opengist_password)?;
+                opengist::mirror(Path::new(&folder), &opengist, signing_key.as_ref()).await?;
+            }
+        },
+        Commands::Serve { folder, port } => serve::serve(&folder, port).await?,
+        Commands::Gc {
+            folder,
+            cache_max_age,
+        } => {
+            let report = gc::gc(Path::new(&folder), cache_max_age)?;
+            output.write(&report, gc::GcReport::print)?;
+        }
+        Commands::Verify { folder, public_key } => {
+            let report = integrity::verify(Path::new(&folder), public_key.as_deref())?;
+            output.write(&report, integrity::VerifyReport::print)?;
+            let damaged = report.missing.len() + report.mismatched.len() + report.modes.len();
+            if damaged > 0 {
+                let damaged = format!("{} files missing or modified", damaged);
+                return Err(GistError::Checksum(damaged).into());
+            }
+        }
+        Commands::Trash { action } => match action {
+            TrashAction::Empty { folder, older_than } => {
+                let report = trash::empty(Path::new(&folder), older_than)?;
+                output.write(&report, trash::EmptyReport::print)?;
+            }
+        },
+        Commands::Config { action } => handle_config(action, &output)?,
+        Commands::SelfUpdate { check } => selfupdate::self_update(&client, check).await?,
     }
     Ok(())
 }
 
-async fn handle_download(
-    username: String,
-    folder: String,
-    concurrency: usize,
+/// Settings shared by downloads into the folder and into a remote target.
+#[derive(Clone)]
+struct DownloadOptions {
+    /// Client the requests to GitHub are sent with
+    client: reqwest::Client,
+    concurrency: Concurrency,
     limit: Option<u32>,
-) -> Result<()> {
-    info!("Fetching gists for user: {username}");
-    let gists: Vec<gist::Gist> = list_gists(&username, limit).await?;
-    let number_of_files: &usize = &gists.iter().map(|g| g.files.len()).sum::<usize>();
+    per_page: u32,
+    /// Settings of the archive given on the command line, the rest comes from its manifest
+    archive: ArchiveSettings,
+    /// How the files are written into the archive, set from `archive` by `for_archive`
+    write: WriteOptions,
+    filter: GistFilter,
+    filter_hook: Option<String>,
+    post_hook: Option<String>,
+    interactive: bool,
+    readme: bool,
+    updated_since: Option<DateTime<Utc>>,
+    memory_budget: Option<MemoryBudget>,
+    prune: bool,
+    /// Fetch the files unchanged since the last download too
+    force: bool,
+    /// Earlier versions of changed files kept per gist, none when 0, set by `for_archive`
+    keep_versions: usize,
+    /// Save the comments of the gists next to their files
+    comments: bool,
+    /// Cache the avatars of owners and commenters for the HTML pages
+    avatars: bool,
+    /// Only record the metadata of the gists whose files weren't downloaded yet
+    sparse: bool,
+    /// What to do with files edited in the archive that would be replaced, asked when not set
+    resolve: Option<Resolution>,
+    /// Time a run may take, new downloads stop being scheduled shortly before it ends
+    max_duration: Option<Duration>,
+    /// Stops the run like the time limit once cancelled, by Ctrl-C on `download` and `sync`,
+    /// along with the listing and the downloads in flight
+    cancel: CancellationToken,
+    /// Key of `--sign-manifest`, signing the manifest every time it is written
+    signing_key: Option<SecretKey>,
+}
 
-    let abs_path = PathBuf::from(&folder)
-        .canonicalize()
-        .unwrap_or_else(|_| PathBuf::from(&folder));
+impl DownloadOptions {
+    /// Options downloading the gists selected by the filter with the given concurrency,
+    /// writing through symlinks in the archive when `follow_symlinks` is set and signing its
+    /// manifest with `signing_key` when given, the rest left to what a plain `download` does.
+    fn new(
+        client: &reqwest::Client,
+        concurrency: Concurrency,
+        filter: GistFilter,
+        follow_symlinks: bool,
+        signing_key: Option<SecretKey>,
+    ) -> Self {
+        DownloadOptions {
+            client: client.clone(),
+            concurrency,
+            limit: None,
+            per_page: gist::MAX_PER_PAGE,
+            archive: ArchiveSettings::default(),
+            write: WriteOptions {
+                follow_symlinks,
+                ..WriteOptions::default()
+            },
+            filter,
+            filter_hook: None,
+            post_hook: None,
+            interactive: false,
+            readme: false,
+            updated_since: None,
+            memory_budget: None,
+            prune: false,
+            force: false,
+            keep_versions: 0,
+            comments: false,
+            avatars: false,
+            sparse: false,
+            resolve: None,
+            max_duration: None,
+            cancel: CancellationToken::new(),
+            signing_key,
+        }
+    }
 
-    let semaphore = Arc::new(Semaphore::new(concurrency));
-    let mut download_set = JoinSet::new();
-    let mut monitor_set = JoinSet::new();
+    /// Returns the options writing into an archive whose manifest recorded these settings,
+    /// those given on the command line taking precedence.
+    fn for_archive(&self, recorded: &ArchiveSettings) -> Result<DownloadOptions, GistError> {
+        let archive = self.archive.or(recorded);
+        let store = archive.store.unwrap_or_default();
+        if archive.compress_files.is_some() && store != Store::Plain {
+            return Err(GistError::Config(format!(
+                "the archive uses --store {}, --compress-files needs plain files",
+                format!("{:?}", store).to_lowercase()
+            )));
+        }
+        let encrypt = archive
+            .encrypt
+            .as_deref()
+            .map(crypt::parse_recipient)
+            .transpose()
+            .map_err(GistError::Config)?;
+        Ok(DownloadOptions {
+            write: WriteOptions {
+                store,
+                encrypt,
+                compress_from: archive.compress_files,
+                eol: archive.eol,
+                ..self.write.clone()
+            },
+            keep_versions: archive.keep_versions.unwrap_or_default(),
+            archive,
+            ..self.clone()
+        })
+    }
+}
 
-    monitor_set.spawn(async move { monitor_tasks().await });
+/// Keeps the gists of a listing page selected by the filter and the filter hook.
+async fn select_page(
+    mut gists: Vec<gist::Gist>,
+    options: &DownloadOptions,
+) -> Result<Vec<gist::Gist>> {
+    gists.retain(|g| options.filter.matches_gist(&ManifestEntry::from(g)));
 
-    info!("Found {} gists", gists.len());
+    if let Some(filter_hook) = &options.filter_hook {
+        let mut selected = Vec::with_capacity(gists.len());
+        for gist in gists {
+            if hooks::run_filter_hook(filter_hook, &ManifestEntry::from(&gist)).await? {
+                selected.push(gist);
+            } else {
+                debug!("Filter hook skipped gist {}", gist.id);
+            }
+        }
+        gists = selected;
+    }
 
-    for gist in gists {
-        let sem = Arc::clone(&semaphore);
-        let folder = folder.clone();
+    Ok(gists)
+}
+
+/// Fetches the whole listing, keeping the gists selected by the filter, the filter hook
+/// and then the user when picking interactively.
+async fn select_gists(pages: GistPages, options: &DownloadOptions) -> Result<Vec<gist::Gist>> {
+    let gists: Vec<gist::Gist> = pages.collect(options.limit).await?;
+    let gists = select_page(gists, options).await?;
 
-        download_set.spawn(async move {
+    if options.interactive && !gists.is_empty() {
+        let lines: Vec<String> = gists.iter().map(ToString::to_string).collect();
+        let Some(picked) = picker::pick(&lines)? else {
+            info!("Nothing picked, not downloading anything");
+            return Ok(Vec::new());
+        };
+        let mut gists: Vec<Option<gist::Gist>> = gists.into_iter().map(Some).collect();
+        return Ok(picked.into_iter().filter_map(|i| gists[i].take()).collect());
+    }
+
+    Ok(gists)
+}
+
+/// Outcome of a download task: the gist, and what happened to its files or why it failed
+/// altogether.
+type DownloadResult = (gist::Gist, Result<DownloadOutcome, GistError>);
+
+/// Whether only the metadata of a gist is recorded: with `--sparse`, unless its files were
+/// downloaded already, which are then kept up to date.
+fn metadata_only(manifest: &Manifest, id: &str, options: &DownloadOptions) -> bool {
+    options.sparse && manifest.gists.get(id).is_none_or(|entry| entry.sparse)
+}
+
+/// Returns what the archive holds of a gist already, or `None` when the gist is skipped
+/// since the download would replace files edited in the archive.
+async fn local_copy(
+    gist: &gist::Gist,
+    folder: &str,
+    manifest: &Manifest,
+    options: &DownloadOptions,
+) -> Option<LocalCopy> {
+    let sparse = metadata_only(manifest, &gist.id, options);
+    let Some(entry) = manifest.gists.get(&gist.id) else {
+        return Some(LocalCopy {
+            sparse,
+            ..LocalCopy::default()
+        });
+    };
+    let mut local = LocalCopy {
+        unchanged: match options.force {
+            true => HashSet::new(),
+            false => entry.unchanged_files(gist, Path::new(folder)),
+        },
+        keep_as: (options.keep_versions > 0 && entry.updated_at != gist.updated_at)
+            .then(|| entry.updated_at.clone()),
+        edited: HashSet::new(),
+        sparse,
+    };
+    if sparse {
+        return Some(local);
+    }
+    let edited = conflict::edited_files(Path::new(folder), entry, &local.unchanged);
+    if edited.is_empty() {
+        return Some(local);
+    }
+    match conflict::resolve(
+        &options.client,
+        Path::new(folder),
+        gist,
+        &edited,
+        options.resolve,
+    )
+    .await
+    {
+        Resolution::Local => {
+            local.edited = gist
+                .files
+                .keys()
+                .filter(|name| edited.iter().any(|edited| paths::same_name(name, edited)))
+                .cloned()
+                .collect();
+        }
+        Resolution::Remote => {}
+        Resolution::Skip => return None,
+    }
+    Some(local)
+}
+
+/// Starts downloading a gist, the task returning the gist once its files are written.
+fn spawn_download(
+    download_set: &mut JoinSet<DownloadResult>,
+    semaphore: &Arc<Semaphore>,
+    tuner: Option<&Arc<AutoTune>>,
+    gist: gist::Gist,
+    local: LocalCopy,
+    folder: &str,
+    options: &DownloadOptions,
+) {
+    if !local.unchanged.is_empty() {
+        debug!(
+            "Gist {} has {} unchanged files, not fetching them again",
+            gist.id,
+            local.unchanged.len()
+        );
+    }
+    let sem = Arc::clone(semaphore);
+    let tuner = tuner.cloned();
+    let folder = folder.to_string();
+    let write = options.write.clone();
+    let memory_budget = options.memory_budget.clone();
+    let cancel = options.cancel.clone();
+    let client = options.client.clone();
+    let (with_comments, with_avatars, force) = (options.comments, options.avatars, options.force);
+    let sparse = local.sparse;
+
+    download_set.spawn(
+        async move {
             let _permit = sem.acquire().await;
+            // Files are spooled to disk as they download, the budget still caps the gists
+            // in flight like it does the uploads to a target, which hold them in memory
+            let _memory = match &memory_budget {
+                Some(budget) if !sparse => {
+                    let size = gist.files.values().map(|f| f.size as u64).sum();
+                    budget.reserve(size).await
+                }
+                _ => None,
+            };
 
-            if let Err(e) = download_gist(&gist, &folder).await {
-                error!("Failed to download gist {}: {}", gist.id, e);
-                return;
+            let archive = store::Folder::new(Path::new(&folder), write.follow_symlinks);
+            let started = Instant::now();
+            let result = match sparse {
+                true => Ok(DownloadOutcome::default()),
+                false => download_gist(&client, &gist, &folder, &write, &local, &cancel).await,
+            };
+            if with_avatars && result.is_ok() {
+                let owner = &gist.owner;
+                avatars::download(&client, &archive, &owner.login, &owner.avatar_url, force).await;
+            }
+            if with_comments && gist.comments > 0 && result.is_ok() {
+                comments::download(&client, &archive, &gist, force, with_avatars).await;
+            }
+            let complete = result
+                .as_ref()
+                .is_ok_and(|outcome| outcome.failed.is_empty());
+            if let Some(tuner) = &tuner {
+                tuner.record(started.elapsed(), complete);
+            }
+            if complete {
+                info!("Successfully downloaded gist: {}", gist.id);
+            }
+            (gist, result)
+        }
+        // Keeps the gist spans under the span of the run when they are exported
+        .in_current_span(),
+    );
+}
+
+/// What became of the downloads of a run so far.
+struct Tally {
+    progress: Progress,
+    /// Gists recorded in the manifest
+    downloaded: Vec<String>,
+    /// Gists whose download failed, waiting for the retry pass
+    failed: Vec<gist::Gist>,
+    /// Gists whose download failed for good
+    abandoned: Vec<gist::Gist>,
+}
+
+impl Tally {
+    fn new(progress: Progress) -> Self {
+        Tally {
+            progress,
+            downloaded: Vec::new(),
+            failed: Vec::new(),
+            abandoned: Vec::new(),
+        }
+    }
+
+    /// Records a finished download in the manifest. A failed one is kept for the retry
+    /// pass when `retry` is set and retrying can help, and given up otherwise.
+    async fn finish(
+        &mut self,
+        (gist, result): DownloadResult,
+        manifest: &mut Manifest,
+        folder: &str,
+        options: &DownloadOptions,
+        retry: bool,
+    ) {
+        match result {
+            Ok(outcome) => {
+                self.progress.complete(&gist);
+                record_download(
+                    manifest,
+                    folder,
+                    gist,
+                    &outcome,
+                    options,
+                    &mut self.downloaded,
+                )
+                .await
+            }
+            // Retrying can't help when the gist was deleted or the token was refused
+            Err(e) if retry && e.retry() != Retry::Never => {
+                warn!("Failed to download gist {}, will retry: {}", gist.id, e);
+                self.failed.push(gist);
             }
+            Err(e) => self.give_up(gist, e),
+        }
+    }
 
-            info!("Successfully downloaded gist: {}", gist.id);
-        });
+    /// Counts a gist whose download failed for good.
+    fn give_up(&mut self, gist: gist::Gist, error: GistError) {
+        self.progress.complete(&gist);
+        error!("Failed to download gist {}: {}", gist.id, error);
+        self.abandoned.push(gist);
+    }
+}
+
+/// Records a downloaded gist in the manifest and runs the post-download hook for it.
+async fn record_download(
+    manifest: &mut Manifest,
+    folder: &str,
+    gist: gist::Gist,
+    outcome: &DownloadOutcome,
+    options: &DownloadOptions,
+    downloaded: &mut Vec<String>,
+) {
+    for (filename, error) in &outcome.failed {
+        warn!(
+            "Gist {} is partial, {} failed: {}",
+            gist.id, filename, error
+        );
+    }
+    let sparse = metadata_only(manifest, &gist.id, options);
+    // Kept files keep the checksum of the version downloaded, so they still show as edited
+    let kept: Vec<(String, Option<String>)> = manifest
+        .gists
+        .get(&gist.id)
+        .map(|entry| {
+            entry
+                .files
+                .iter()
+                .filter(|file| {
+                    outcome
+                        .kept
+                        .iter()
+                        .any(|kept| paths::same_name(kept, &file.filename))
+                })
+                .map(|file| (file.filename.clone(), file.sha256.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    let entry = manifest.insert(&gist);
+    entry.sparse = sparse;
+    entry.record_outcome(outcome);
+    entry.inspect_files(Path::new(folder));
+    for file in &mut entry.files {
+        if let Some((_, sha256)) = kept
+            .iter()
+            .find(|(name, _)| paths::same_name(name, &file.filename))
+        {
+            file.sha256 = sha256.clone();
+        }
+    }
+    if options.readme {
+        if let Err(e) = readme::write_readme(
+            &store::Folder::new(Path::new(folder), options.write.follow_symlinks),
+            entry,
+        ) {
+            warn!("Could not write the README of gist {}: {}", gist.id, e);
+        }
+    }
+    if options.keep_versions > 0 {
+        if let Err(e) = store::prune_versions(Path::new(folder), &gist.id, options.keep_versions) {
+            warn!("Could not prune the versions of gist {}: {}", gist.id, e);
+        }
+    }
+    if let Some(post_hook) = &options.post_hook {
+        hooks::run_gist_hook(post_hook, Path::new(folder), entry).await;
+    }
+    downloaded.push(gist.id);
+}
+
+/// One account of a multi-account run, with its part of the concurrency of the run.
+struct AccountShare {
+    login: String,
+    /// Permits the downloads of the account run within, `None` with `auto` concurrency
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+/// Downloads the gists of the listing into the folder, recording them in its manifest
+/// and index, and returns how the archive changed. With an account share, the downloads
+/// run within the permits of that account instead of the concurrency of the options, and
+/// only the gists of that account are pruned.
+#[cfg_attr(feature = "otel", tracing::instrument(skip_all, fields(folder = %folder)))]
+async fn handle_download(
+    pages: GistPages,
+    folder: String,
+    options: &DownloadOptions,
+    share: Option<AccountShare>,
+) -> Result<ChangeSummary> {
+    let mut pages = pages.limit(options.limit).cancel_on(&options.cancel);
+    let abs_path = PathBuf::from(&folder)
+        .canonicalize()
+        .unwrap_or_else(|_| PathBuf::from(&folder));
+
+    let permits = share.as_ref().and_then(|share| share.semaphore.clone());
+    let tuner = (permits.is_none() && options.concurrency == Concurrency::Auto)
+        .then(|| Arc::new(AutoTune::new()));
+    let semaphore = match (permits, &tuner) {
+        (Some(permits), _) => permits,
+        (None, Some(tuner)) => tuner.semaphore(),
+        (None, None) => Arc::new(Semaphore::new(options.concurrency.initial())),
+    };
+    let mut download_set = JoinSet::new();
+
+    let mut manifest = Manifest::load_writable(Path::new(&folder), options.signing_key.as_ref())?;
+    let previous = manifest.clone();
+    let options = &options.for_archive(&manifest.settings)?;
+    manifest.settings = options.archive.clone();
+    let mut number_of_files: usize = 0;
+    let mut number_of_gists: usize = 0;
+    // Set when the listing stops before its end, after which the gists listed so far are
+    // still downloaded and a checkpoint lets the next run continue the listing
+    let mut interrupted: Option<GistError> = None;
+    let mut listed_ids: HashSet<String> = HashSet::new();
+    let mut tally = Tally::new(Progress::new(
+        share.as_ref().map(|share| share.login.as_str()),
+    ));
+    // New downloads stop being scheduled a tenth of the time limit (at most a minute) before
+    // it ends, so those in flight can finish, or once the run is cancelled, which also stops
+    // those in flight and the listing; the gists not scheduled are left for the next run
+    // A limit too far away to be reached is no limit
+    let stop_at = options
+        .max_duration
+        .and_then(|limit| Instant::now().checked_add(limit - (limit / 10).min(DEADLINE_MARGIN)));
+    let stopped = || match options.max_duration {
+        _ if options.cancel.is_cancelled() => Some(GistError::Cancelled),
+        Some(limit) if stop_at.is_some_and(|stop_at| Instant::now() >= stop_at) => {
+            Some(GistError::Deadline(limit.as_secs()))
+        }
+        _ => None,
+    };
+    let mut unscheduled: Vec<gist::Gist> = Vec::new();
+
+    if options.interactive {
+        // Picking needs the whole listing up front
+        for gist in select_gists(pages.clone(), options).await? {
+            let Some(local) = local_copy(&gist, &folder, &manifest, options).await else {
+                continue;
+            };
+            number_of_files += gist.files.len();
+            number_of_gists += 1;
+            tally.progress.add(&gist);
+            spawn_download(
+                &mut download_set,
+                &semaphore,
+                tuner.as_ref(),
+                gist,
+                local,
+                &folder,
+                options,
+            );
+        }
+    } else {
+        // Downloads start as soon as their listing page arrives, instead of after the
+        // whole listing, and finished ones are recorded between pages
+        let mut listed: usize = 0;
+        let mut resumed: Gists = Vec::new();
+        if let Some(checkpoint) = pages
+            .resumable()
+            .then(|| checkpoint::load(Path::new(&folder), &pages))
+            .flatten()
+        {
+            info!(
+                "Continuing the listing of {} from page {}, where the previous run stopped",
+                pages.path(),
+                checkpoint.next_page
+            );
+            pages.resume_at(checkpoint.next_page);
+            listed = checkpoint.listed.len();
+            listed_ids.extend(checkpoint.listed);
+            resumed = checkpoint.pending;
+        }
+        for gist in resumed {
+            if stopped().is_some() {
+                unscheduled.push(gist);
+                continue;
+            }
+            let Some(local) = local_copy(&gist, &folder, &manifest, options).await else {
+                continue;
+            };
+            number_of_files += gist.files.len();
+            number_of_gists += 1;
+            tally.progress.add(&gist);
+            spawn_download(
+                &mut download_set,
+                &semaphore,
+                tuner.as_ref(),
+                gist,
+                local,
+                &folder,
+                options,
+            );
+        }
+        loop {
+            if let Some(e) = stopped() {
+                interrupted = Some(e);
+                break;
+            }
+            let mut page = match pages.next_page().await {
+                Ok(Some(page)) => page,
+                Ok(None) => break,
+                Err(e) => {
+                    error!(
+                        "Listing stopped at page {}: {}",
+                        pages.next_page_number(),
+                        e
+                    );
+                    interrupted = Some(e);
+                    break;
+                }
+            };
+            if let Some(limit) = options.limit {
+                page.truncate((limit as usize).saturating_sub(listed));
+            }
+            listed += page.len();
+            listed_ids.extend(page.iter().map(|gist| gist.id.clone()));
+            for gist in select_page(page, options).await? {
+                if stopped().is_some() {
+                    unscheduled.push(gist);
+                    continue;
+                }
+                let Some(local) = local_copy(&gist, &folder, &manifest, options).await else {
+                    continue;
+                };
+                number_of_files += gist.files.len();
+                number_of_gists += 1;
+                tally.progress.add(&gist);
+                spawn_download(
+                    &mut download_set,
+                    &semaphore,
+                    tuner.as_ref(),
+                    gist,
+                    local,
+                    &folder,
+                    options,
+                );
+            }
+            while let Some(res) = download_set.try_join_next() {
+                tally
+                    .finish(res?, &mut manifest, &folder, options, true)
+                    .await;
+            }
+            if options.limit.is_some_and(|limit| listed >= limit as usize) {
+                break;
+            }
+        }
     }
 
-    info!("All the tasks have been created");
+    info!(
+        "Found {} gists, all the tasks have been created",
+        number_of_gists
+    );
 
     // Waits until one of the tasks in the set completes and returns its output.
     // Returns None if the set is empty.
-
     while let Some(res) = download_set.join_next().await {
-        res?
+        tally
+            .finish(res?, &mut manifest, &folder, options, true)
+            .await;
+    }
+
+    // Gists that failed get a second chance once everything else is done, since most
+    // failures are transient
+    if !unscheduled.is_empty() && interrupted.is_none() {
+        interrupted = stopped();
+    }
+    if !tally.failed.is_empty() && !gist::budget_spent() && stopped().is_none() {
+        info!("Retrying {} failed gists", tally.failed.len());
+        if let Err(e) = gist::wait_for_rate_limit(&options.client, &options.cancel).await {
+            warn!("Could not check the rate limit: {}", e);
+        }
+        for gist in std::mem::take(&mut tally.failed) {
+            // Nothing was written by the failed try, so edited files are still to settle
+            let Some(local) = local_copy(&gist, &folder, &manifest, options).await else {
+                tally.progress.complete(&gist);
+                continue;
+            };
+            spawn_download(
+                &mut download_set,
+                &semaphore,
+                tuner.as_ref(),
+                gist,
+                local,
+                &folder,
+                options,
+            );
+        }
+        while let Some(res) = download_set.join_next().await {
+            tally
+                .finish(res?, &mut manifest, &folder, options, false)
+                .await;
+        }
     }
 
-    monitor_set.abort_all();
+    tally.progress.finish();
+    // With the budget spent, no time left or the run cancelled, the gists still failing or waiting for their
+    // retry are left for the next run, which the checkpoint lets continue from here
+    if gist::budget_spent() && interrupted.is_none() {
+        interrupted = gist::max_requests().map(GistError::Budget);
+    }
+    if !tally.failed.is_empty() && interrupted.is_none() {
+        interrupted = stopped();
+    }
+    let mut abandoned = tally.abandoned;
+    abandoned.append(&mut tally.failed);
+    abandoned.append(&mut unscheduled);
+    let downloaded = tally.downloaded;
+
+    // Gists of the archive missing from the complete listing were deleted on GitHub
+    // In a folder shared by several accounts, the gists of the others aren't in the listing
+    let pruned: Vec<String> = match options.prune && interrupted.is_none() {
+        true => manifest
+            .gists
+            .values()
+            .filter(|entry| !listed_ids.contains(&entry.id))
+            .filter(|entry| {
+                share
+                    .as_ref()
+                    .is_none_or(|share| share.login.eq_ignore_ascii_case(&entry.owner))
+            })
+            .map(|entry| entry.id.clone())
+            .collect(),
+        false => Vec::new(),
+    };
+    for id in &pruned {
+        info!("Pruning gist {}, it is no longer on GitHub", id);
+        store::discard_gist(Path::new(&folder), id)?;
+        manifest.gists.remove(id);
+    }
+
+    manifest.save(Path::new(&folder), options.signing_key.as_ref())?;
+
+    let mut index = Index::open(Path::new(&folder))?;
+    for id in &downloaded {
+        index.index_gist(Path::new(&folder), &manifest.gists[id])?;
+    }
+    for id in &pruned {
+        index.remove_gist(id)?;
+    }
+
+    if let Some(e) = interrupted {
+        info!(
+            "Stopped early after {} requests to GitHub: {} so far, {} gists failed or left",
+            gist::requests_sent(),
+            manifest.changes_since(&previous),
+            abandoned.len()
+        );
+        if pages.resumable() && pages.next_page_number() > 1 {
+            let listed = listed_ids.into_iter().collect();
+            checkpoint::save(Path::new(&folder), &pages, listed, abandoned)?;
+            info!(
+                "Saved the downloads so far, the next run continues the listing from page {}",
+                pages.next_page_number()
+            );
+        }
+        return Err(e.into());
+    }
+    if pages.resumable() && !options.interactive {
+        checkpoint::remove(Path::new(&folder), &pages)?;
+    }
 
     info!(
         "Download complete: {} files downloaded to {}",
@@ -96,16 +1559,748 @@ async fn handle_download(
         abs_path.display()
     );
 
+    let mut changes = manifest.changes_since(&previous);
+    let partial = downloaded.iter().filter(|id| manifest.gists[*id].partial);
+    changes.failed = abandoned.len() + partial.count();
+    Ok(changes)
+}
+
+/// Downloads the gists of each account into a subfolder of `folder` named after it, or
+/// into `folder` itself without `owner_dirs`, and returns the changes of all of them. An
+/// account failing doesn't stop the others.
+///
+/// With a fixed concurrency, as many accounts as there are permits are downloaded at once,
+/// each within its own share of the permits, so an enormous account can't hold up the
+/// others. The permits of an account that is done go to the next one, or once every
+/// account was started, to those still running. With `auto`, or when the accounts share
+/// the manifest of `folder`, they are downloaded one after the other.
+async fn handle_accounts(
+    accounts: Vec<String>,
+    folder: &str,
+    owner_dirs: bool,
+    options: &DownloadOptions,
+) -> ChangeSummary {
+    let permits = match options.concurrency {
+        Concurrency::Fixed(n) => n,
+        Concurrency::Auto => 1,
+    };
+    let at_once = match owner_dirs {
+        true => permits.min(accounts.len()).max(1),
+        false => 1,
+    };
+    let mut shares: Vec<usize> = (0..at_once)
+        .map(|i| permits / at_once + usize::from(i < permits % at_once))
+        .collect();
+
+    let mut changes = ChangeSummary::default();
+    let mut pending = accounts.into_iter();
+    let mut running = Vec::new();
+    loop {
+        while let Some(permits) = shares.pop() {
+            let Some(login) = pending.next() else {
+                shares.push(permits);
+                break;
+            };
+            let semaphore = Arc::new(Semaphore::new(permits));
+            let share = AccountShare {
+                login: login.clone(),
+                semaphore: match options.concurrency {
+                    Concurrency::Fixed(_) => Some(Arc::clone(&semaphore)),
+                    Concurrency::Auto => None,
+                },
+            };
+            let folder = match owner_dirs {
+                true => Path::new(folder)
+                    .join(&login)
+                    .to_string_lossy()
+                    .into_owned(),
+                false => folder.to_string(),
+            };
+            let download = Box::pin(download_account(folder, options, share));
+            running.push((semaphore, permits, download));
+        }
+        // Nothing is left to start, the permits go to the accounts still running
+        let count = running.len().max(1);
+        for (i, permits) in shares.drain(..).enumerate() {
+            if let Some((semaphore, share, _)) = running.get_mut(i % count) {
+                semaphore.add_permits(permits);
+                *share += permits;
+            }
+        }
+        if running.is_empty() {
+            break;
+        }
+
+        let (i, summary) = std::future::poll_fn(|cx| {
+            for (i, (_, _, download)) in running.iter_mut().enumerate() {
+                if let Poll::Ready(summary) = download.as_mut().poll(cx) {
+                    return Poll::Ready((i, summary));
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+        let (_, permits, _) = running.swap_remove(i);
+        shares.push(permits);
+        changes.added += summary.added;
+        changes.updated += summary.updated;
+        changes.removed += summary.removed;
+        changes.failed += summary.failed;
+    }
+    changes
+}
+
+/// Downloads the gists of an account into `folder`.
+async fn download_account(
+    folder: String,
+    options: &DownloadOptions,
+    share: AccountShare,
+) -> ChangeSummary {
+    let login = share.login.clone();
+    info!("Fetching gists for user: {login}");
+    let pages =
+        GistPages::new(&options.client, &login, options.per_page).since(options.updated_since);
+    match handle_download(pages, folder, options, Some(share)).await {
+        Ok(summary) => {
+            info!("Downloaded the gists of {}: {}", login, summary);
+            summary
+        }
+        Err(e) => {
+            error!("Failed to download the gists of {}: {}", login, e);
+            ChangeSummary {
+                failed: 1,
+                ..ChangeSummary::default()
+            }
+        }
+    }
+}
+
+/// Polls the public gists feed, archiving the gists published or updated since the
+/// previous poll. The time of the last poll is kept in `.firehose-since` in the folder, so
+/// a restart picks up where it stopped.
+async fn handle_firehose(
+    folder: String,
+    interval: Duration,
+    once: bool,
+    options: &DownloadOptions,
+) -> Result<()> {
+    let state = Path::new(&folder).join(FIREHOSE_STATE);
+    loop {
+        let since = load_cursor(&state);
+        // Taken before listing, so gists published while this poll runs aren't missed
+        let started = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        info!("Polling public gists updated since {:?}", since);
+
+        let pages = GistPages::public(&options.client, gist::MAX_PER_PAGE).since(since);
+        match handle_download(pages, folder.clone(), options, None).await {
+            Ok(changes) => {
+                info!("Firehose poll done: {}", changes);
+                save_cursor(&state, &started)?;
+            }
+            Err(e) if !once => error!("Firehose poll failed, retrying later: {}", e),
+            Err(e) => return Err(e),
+        }
+        if once {
+            return Ok(());
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Returns the time saved in a cursor file, `None` when there is none yet.
+fn load_cursor(path: &Path) -> Option<DateTime<Utc>> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|since| DateTime::parse_from_rfc3339(since.trim()).ok())
+        .map(|since| since.with_timezone(&Utc))
+}
+
+/// Saves the time of a cursor file, replacing the previous one atomically so an
+/// interrupted run never leaves it half written.
+fn save_cursor(path: &Path, time: &str) -> Result<(), GistError> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, time)?;
+    std::fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Outcome of a sync, written as one line for the scripts running it.
+#[derive(Debug, Serialize)]
+struct SyncSummary {
+    username: String,
+    /// Time the gists were listed from, `None` for the first sync
+    since: Option<String>,
+    /// Time the next sync lists from
+    cursor: Option<String>,
+    #[serde(flatten)]
+    changes: ChangeSummary,
+}
+
+impl fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "username={} since={} cursor={} added={} updated={} removed={} failed={}",
+            self.username,
+            self.since.as_deref().unwrap_or("-"),
+            self.cursor.as_deref().unwrap_or("-"),
+            self.changes.added,
+            self.changes.updated,
+            self.changes.removed,
+            self.changes.failed
+        )
+    }
+}
+
+/// Mirrors the gists of a user into a target at every interval. Unlike the folder, the
+/// target keeps no cursor: the whole listing is fetched each time, and gists matching the
+/// manifest of the target are skipped. With `once`, failures end the run with their exit
+/// code, otherwise the next sync tries again.
+async fn handle_sync_mirror(
+    username: &str,
+    target: Arc<Target>,
+    interval: Duration,
+    once: bool,
+    options: &DownloadOptions,
+) -> Result<()> {
+    loop {
+        info!("Syncing the gists of {} to {}", username, target);
+        match handle_mirror(username, Arc::clone(&target), options).await {
+            Ok(()) => {}
+            Err(e) if !once && !options.cancel.is_cancelled() => {
+                error!("Sync failed, retrying later: {}", e);
+            }
+            Err(e) => return Err(e),
+        }
+        if once {
+            return Ok(());
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = options.cancel.cancelled() => return Ok(()),
+        }
+    }
+}
+
+/// Downloads the gists of a user updated since the previous sync. The time of the last
+/// sync without failures is kept in `.sync-since` in the folder, so a sync with failures is
+/// picked up again by the next one. Each sync writes a one-line summary on stdout; with
+/// `once`, failures end the run with their exit code.
+async fn handle_sync(
+    username: &str,
+    folder: String,
+    interval: Duration,
+    once: bool,
+    git_commit: bool,
+    options: &DownloadOptions,
+    output: &Output,
+) -> Result<()> {
+    let state = Path::new(&folder).join(SYNC_STATE);
+    loop {
+        let since = load_cursor(&state);
+        // Taken before listing, so gists updated while this sync runs aren't missed
+        let started = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        info!(
+            "Syncing the gists of {} updated since {:?}",
+            username, since
+        );
+
+        let pages = GistPages::new(&options.client, username, options.per_page).since(since);
+        let result = handle_download(pages, folder.clone(), options, None).await;
+        let changes = match result {
+            Ok(changes) => changes,
+            Err(e) if !once && !options.cancel.is_cancelled() => {
+                error!("Sync failed, retrying later: {}", e);
+                tokio::time::sleep(interval).await;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if changes.failed == 0 {
+            save_cursor(&state, &started)?;
+        }
+        if git_commit {
+            git::commit_archive(Path::new(&folder), &changes)?;
+        }
+        let since = since.map(|since| since.to_rfc3339_opts(SecondsFormat::Secs, true));
+        let summary = SyncSummary {
+            username: username.to_string(),
+            // After failures the cursor stays where it was
+            cursor: match changes.failed {
+                0 => Some(started),
+                _ => since.clone(),
+            },
+            since,
+            changes,
+        };
+        output.write(&summary, |summary| println!("{}", summary))?;
+        if once {
+            if changes.failed > 0 {
+                let failed = format!("{} downloads failed", changes.failed);
+                return Err(GistError::Partial(failed).into());
+            }
+            return Ok(());
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = options.cancel.cancelled() => return Ok(()),
+        }
+    }
+}
+
+/// Returns a token cancelled by Ctrl-C, so a run stops listing and downloading, keeps the
+/// files written so far and saves what it has instead of leaving files half written. A
+/// second Ctrl-C quits at once.
+fn cancel_on_ctrl_c() -> CancellationToken {
+    let cancel = CancellationToken::new();
+    let token = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        warn!("Stopping and saving the downloads so far, press Ctrl-C again to quit now");
+        token.cancel();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+    cancel
+}
+
+/// Opens what `--open` shows after downloading gists into `folder`: the gist when `ids` has
+/// a single one, its file when it has only one written as a plain file, the folder otherwise.
+fn open_downloaded(
+    folder: &Path,
+    manifest: &Manifest,
+    ids: &[&String],
+    with: Option<&str>,
+) -> Result<(), GistError> {
+    let path = match ids {
+        [id] if folder.join(id).is_dir() => match manifest.gists.get(*id) {
+            Some(ManifestEntry { files, .. }) if files.len() == 1 => {
+                store::plain_file(folder, id, &files[0].filename).unwrap_or_else(|| folder.join(id))
+            }
+            _ => folder.join(id),
+        },
+        _ => folder.to_path_buf(),
+    };
+    browser::open_path(&path, with)
+}
+
+/// Downloads the files of gists archived with `--sparse`: the given ones, or every sparse
+/// gist matching the filter. Their current version is looked up on GitHub first. `open` is
+/// set when the result is opened afterwards, with the command to open it with if any.
+async fn handle_materialize(
+    ids: &[String],
+    folder: String,
+    open: Option<Option<String>>,
+    options: &DownloadOptions,
+) -> Result<()> {
+    let client = &options.client;
+    let mut manifest = Manifest::load_writable(Path::new(&folder), options.signing_key.as_ref())?;
+    let ids: Vec<String> = match ids.is_empty() {
+        true => manifest
+            .gists
+            .values()
+            .filter(|entry| entry.sparse && options.filter.matches_gist(entry))
+            .map(|entry| entry.id.clone())
+            .collect(),
+        false => {
+            if let Some(id) = ids.iter().find(|id| !manifest.gists.contains_key(*id)) {
+                anyhow::bail!("Gist {} is not in the archive", id);
+            }
+            ids.to_vec()
+        }
+    };
+    // Written the way the archive was downloaded, not plain into an encrypted or compressed one
+    let options = options.for_archive(&manifest.settings)?;
+
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.initial()));
+    let mut lookups = JoinSet::new();
+    for id in ids {
+        let sem = Arc::clone(&semaphore);
+        let client = client.clone();
+        lookups.spawn(async move {
+            let _permit = sem.acquire().await;
+            let result = gist::get_gist(&client, &id).await;
+            (id, result)
+        });
+    }
+    let mut download_set = JoinSet::new();
+    let mut failed: usize = 0;
+    while let Some(joined) = lookups.join_next().await {
+        match joined? {
+            (_, Ok(gist)) => {
+                let Some(local) = local_copy(&gist, &folder, &manifest, &options).await else {
+                    continue;
+                };
+                spawn_download(
+                    &mut download_set,
+                    &semaphore,
+                    None,
+                    gist,
+                    local,
+                    &folder,
+                    &options,
+                )
+            }
+            (id, Err(e)) => {
+                error!("Failed to look up gist {}: {}", id, e);
+                failed += 1;
+            }
+        }
+    }
+
+    let mut downloaded: Vec<String> = Vec::new();
+    while let Some(joined) = download_set.join_next().await {
+        match joined? {
+            (gist, Ok(outcome)) => {
+                if !outcome.failed.is_empty() {
+                    failed += 1;
+                }
+                record_download(
+                    &mut manifest,
+                    &folder,
+                    gist,
+                    &outcome,
+                    &options,
+                    &mut downloaded,
+                )
+                .await
+            }
+            (gist, Err(e)) => {
+                error!("Failed to download gist {}: {}", gist.id, e);
+                failed += 1;
+            }
+        }
+    }
+    manifest.save(Path::new(&folder), options.signing_key.as_ref())?;
+
+    let mut index = Index::open(Path::new(&folder))?;
+    for id in &downloaded {
+        index.index_gist(Path::new(&folder), &manifest.gists[id])?;
+    }
+    info!("Materialized {} gists", downloaded.len());
+    if let Some(with) = open {
+        let ids: Vec<&String> = downloaded.iter().collect();
+        open_downloaded(Path::new(&folder), &manifest, &ids, with.as_deref())?;
+    }
+    if failed > 0 {
+        let failed = format!("{} gists failed", failed);
+        return Err(GistError::Partial(failed).into());
+    }
+    Ok(())
+}
+
+/// What `refresh` changed in the archive.
+#[derive(Serialize)]
+struct RefreshSummary {
+    /// Gists found on GitHub
+    refreshed: usize,
+    /// Gists whose metadata changed
+    changed: usize,
+    /// Gists updated on GitHub since their files were downloaded
+    stale: usize,
+    /// Gists no longer listed by GitHub, left in the archive
+    missing: usize,
+}
+
+/// Updates the metadata of the archived gists from the listings of their owners, and their
+/// stars from the gists starred by the owner of the token, without fetching any file. The
+/// manifest and the search index are updated, gists updated on GitHub since are marked to
+/// be downloaded again.
+async fn handle_refresh(
+    client: &reqwest::Client,
+    folder: String,
+    signing_key: Option<&SecretKey>,
+    output: &Output,
+) -> Result<()> {
+    let mut manifest = Manifest::load_writable(Path::new(&folder), signing_key)?;
+    let owners: BTreeSet<String> = manifest
+        .gists
+        .values()
+        // Snippets imported from GitLab aren't on GitHub
+        .filter(|entry| !entry.id.starts_with(gitlab::SNIPPET_ID_PREFIX))
+        .map(|entry| entry.owner.clone())
+        .collect();
+
+    let mut summary = RefreshSummary {
+        refreshed: 0,
+        changed: 0,
+        stale: 0,
+        missing: 0,
+    };
+    let mut changed: Vec<String> = Vec::new();
+    let mut listed: HashSet<String> = HashSet::new();
+    for owner in &owners {
+        info!("Refreshing the gists of {}", owner);
+        let gists = GistPages::new(client, owner, gist::MAX_PER_PAGE)
+            .collect(None)
+            .await?;
+        for gist in gists {
+            let Some(entry) = manifest.gists.get_mut(&gist.id) else {
+                continue;
+            };
+            let was_stale = entry.stale;
+            if entry.refresh(&gist) {
+                changed.push(gist.id.clone());
+            }
+            if entry.stale && !was_stale {
+                summary.stale += 1;
+            }
+            listed.insert(gist.id);
+        }
+    }
+
+    match auth::has_token() {
+        true => {
+            let starred: HashSet<String> = GistPages::starred(client, gist::MAX_PER_PAGE)
+                .collect(None)
+                .await?
+                .into_iter()
+                .map(|gist| gist.id)
+                .collect();
+            for entry in manifest.gists.values_mut() {
+                let star = starred.contains(&entry.id);
+                if entry.starred != star {
+                    entry.starred = star;
+                    changed.push(entry.id.clone());
+                }
+            }
+        }
+        false => warn!("Not refreshing the stars of the gists, no token was given"),
+    }
+    changed.sort_unstable();
+    changed.dedup();
+
+    summary.refreshed = listed.len();
+    summary.changed = changed.len();
+    summary.missing = manifest
+        .gists
+        .values()
+        .filter(|entry| !entry.id.starts_with(gitlab::SNIPPET_ID_PREFIX))
+        .filter(|entry| !listed.contains(&entry.id))
+        .count();
+    manifest.save(Path::new(&folder), signing_key)?;
+
+    // Descriptions are searchable
+    let mut index = Index::open(Path::new(&folder))?;
+    for id in &changed {
+        index.index_gist(Path::new(&folder), &manifest.gists[id])?;
+    }
+
+    output.write(&summary, |summary| {
+        println!(
+            "Refreshed {} gists: {} changed, {} updated on GitHub since downloaded, {} no longer listed",
+            summary.refreshed, summary.changed, summary.stale, summary.missing
+        )
+    })?;
+    Ok(())
+}
+
+fn handle_config(action: ConfigAction, output: &Output) -> Result<()> {
+    let mut config = config::Config::load()?;
+    match action {
+        ConfigAction::Get { key } => match config.get(&key) {
+            Some(value) => output.write(&value, |value| println!("{}", value))?,
+            None => anyhow::bail!("{} is not set", key),
+        },
+        ConfigAction::Set { key, value } => {
+            config.set(&key, &value)?;
+            config.save()?;
+        }
+        ConfigAction::Unset { key } => {
+            if !config.unset(&key)? {
+                anyhow::bail!("{} is not set", key);
+            }
+            config.save()?;
+        }
+        ConfigAction::List => {
+            let settings: BTreeMap<String, String> = config.list().into_iter().collect();
+            output.write(&settings, |settings| {
+                for (key, value) in settings {
+                    println!("{} = {}", key, value);
+                }
+            })?
+        }
+        ConfigAction::Path => output.write(&config::config_path(), |path| {
+            println!("{}", path.display())
+        })?,
+    }
     Ok(())
 }
 
-async fn monitor_tasks() {
-    let handle = Handle::current();
+async fn handle_mirror(
+    username: &str,
+    target: Arc<Target>,
+    options: &DownloadOptions,
+) -> Result<()> {
+    let mut manifest = target::load_manifest(&target).await?;
+    // Secret gists stay encrypted to the recipient recorded by the first upload
+    let options = &options.for_archive(&manifest.settings)?;
+    manifest.settings = options.archive.clone();
+
+    info!("Fetching gists for user: {username}");
+    let pages =
+        GistPages::new(&options.client, username, options.per_page).since(options.updated_since);
+    let gists = select_gists(pages, options).await?;
+    info!("Found {} gists", gists.len());
+
+    let mirror = MirrorOptions {
+        concurrency: options.concurrency.initial(),
+        write: options.write.clone(),
+        resolve: options.resolve,
+        memory_budget: options.memory_budget.clone(),
+    };
+    target::mirror(&options.client, target, manifest, gists, &mirror).await?;
+
+    Ok(())
+}
+
+async fn handle_stats(
+    client: &reqwest::Client,
+    username: Option<String>,
+    local: bool,
+    top_size: usize,
+    top_recent: usize,
+    folder: String,
+    output: &Output,
+) -> Result<()> {
+    let entries: Vec<ManifestEntry> = match username {
+        Some(username) if !local => list_gists(
+            client,
+            &username,
+            None,
+            gist::MAX_PER_PAGE,
+            &CancellationToken::new(),
+        )
+        .await?
+        .iter()
+        .map(ManifestEntry::from)
+        .collect(),
+        _ => Manifest::load(Path::new(&folder))?
+            .gists
+            .into_values()
+            .collect(),
+    };
+
+    let stats = Stats::from_entries(&entries, top_size, top_recent);
+    output.write(&stats, Stats::print)?;
+
+    Ok(())
+}
+
+/// Asks on the terminal which of the files of a gist to use.
+fn prompt_file<'a>(id: &str, filenames: &[&'a str]) -> Result<&'a str, GistError> {
+    eprintln!("Gist {} has several files:", id);
+    for (number, filename) in filenames.iter().enumerate() {
+        eprintln!("  {}) {}", number + 1, filename);
+    }
     loop {
-        let metrics = handle.metrics();
-        debug!("Number of workers: {}", metrics.num_workers());
-        debug!("Number of alive tasks: {}", metrics.num_alive_tasks());
-        debug!("Global queue depth: {}", metrics.global_queue_depth());
-        tokio::time::sleep(Duration::from_millis(250)).await;
+        eprint!("File [1-{}]: ", filenames.len());
+        let mut answer = String::new();
+        if std::io::stdin().read_line(&mut answer)? == 0 {
+            return Err(GistError::NotFound("file choice on stdin".to_string()));
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(number) if (1..=filenames.len()).contains(&number) => {
+                return Ok(filenames[number - 1])
+            }
+            _ => continue,
+        }
+    }
+}
+
+/// Picks the file of a gist to use, the only one when no filename is given. Gists with
+/// several files need the filename, unless `prompt` allows asking for it.
+fn pick_file<'a>(
+    id: &str,
+    filenames: &[&'a str],
+    filename: Option<&str>,
+    prompt: bool,
+) -> Result<&'a str, GistError> {
+    match (filename, filenames) {
+        (Some(filename), _) => filenames
+            .iter()
+            .find(|f| **f == filename)
+            .copied()
+            .ok_or_else(|| GistError::NotFound(format!("file {} in gist {}", filename, id))),
+        (None, [only]) => Ok(only),
+        (None, _) if prompt => prompt_file(id, filenames),
+        (None, _) => Err(GistError::Ambiguous(format!(
+            "{} has several files, pick one of {}",
+            id,
+            filenames.join(", ")
+        ))),
     }
 }
+
+/// Reads a file of a gist from the archive when the gist was downloaded, and fetches it
+/// from GitHub otherwise.
+async fn read_gist_file(
+    client: &reqwest::Client,
+    id: &str,
+    filename: Option<&str>,
+    folder: &str,
+    prompt: bool,
+) -> Result<Vec<u8>, GistError> {
+    let folder = Path::new(folder);
+    let manifest = Manifest::load(folder)?;
+    match manifest.gists.get(id) {
+        Some(entry) if !entry.sparse => {
+            let filenames: Vec<&str> = entry.files.iter().map(|f| f.filename.as_str()).collect();
+            let filename = pick_file(id, &filenames, filename, prompt)?;
+            Ok(store::read_file(folder, id, filename)?)
+        }
+        _ => {
+            debug!("Gist {} isn't archived, fetching it", id);
+            let gist = gist::get_gist(client, id).await?;
+            let filenames: Vec<&str> = gist.files.keys().map(String::as_str).collect();
+            let filename = pick_file(id, &filenames, filename, prompt)?;
+            gist::fetch_file(client, &gist.files[filename]).await
+        }
+    }
+}
+
+fn handle_search(
+    query: String,
+    folder: String,
+    limit: u32,
+    reindex: bool,
+    web: bool,
+    output: &Output,
+) -> Result<()> {
+    let folder = Path::new(&folder);
+    let manifest = Manifest::load(folder)?;
+    let mut index = Index::open(folder)?;
+    if reindex {
+        index.rebuild(folder, &manifest)?;
+    }
+
+    let hits = index.search(&query, limit)?;
+    output.write(&hits, |hits| {
+        println!("Found {} matches for: {}", hits.len(), query);
+        for hit in hits {
+            println!(
+                "{} {} - {} ({:.2})",
+                hit.gist_id, hit.filename, hit.description, hit.score
+            );
+            println!("    {}", hit.snippet.replace('\n', " "));
+        }
+    })?;
+
+    if web {
+        let best = hits
+            .first()
+            .and_then(|hit| manifest.gists.get(&hit.gist_id));
+        match best {
+            Some(entry) => browser::open_url(&entry.html_url)?,
+            None => info!("Nothing to open"),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,416 @@
+use crate::gist::{DownloadOutcome, Gist, GistError};
+use crate::integrity;
+use crate::license;
+use crate::paths;
+use crate::spool;
+use crate::store::{self, Eol, Store};
+use minisign::SecretKey;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Name of the manifest file kept at the root of the archive folder
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Metadata about every gist stored in a local archive, keyed by gist id.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    /// How the files of the archive are written, as recorded by the last download
+    #[serde(default, skip_serializing_if = "ArchiveSettings::is_empty")]
+    pub settings: ArchiveSettings,
+    pub gists: BTreeMap<String, ManifestEntry>,
+}
+
+/// How the files of an archive are written. Recorded in the manifest by `download`, so
+/// `sync`, `materialize` and later downloads write new files the same way instead of
+/// mixing plain files into a compressed or encrypted archive. Unset ones are the defaults.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<Store>,
+    /// Recipient the files of secret gists are encrypted to (age1...)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypt: Option<String>,
+    /// Size from which text files are written gzip-compressed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compress_files: Option<u64>,
+    /// Line endings text files are converted to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eol: Option<Eol>,
+    /// Earlier versions of changed files kept per gist
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_versions: Option<usize>,
+}
+
+impl ArchiveSettings {
+    fn is_empty(&self) -> bool {
+        *self == ArchiveSettings::default()
+    }
+
+    /// Returns these settings, given on the command line, with the unset ones taken from
+    /// those recorded for the archive.
+    pub fn or(&self, recorded: &ArchiveSettings) -> ArchiveSettings {
+        ArchiveSettings {
+            store: self.store.or(recorded.store),
+            encrypt: self.encrypt.clone().or_else(|| recorded.encrypt.clone()),
+            compress_files: self.compress_files.or(recorded.compress_files),
+            eol: self.eol.or(recorded.eol),
+            keep_versions: self.keep_versions.or(recorded.keep_versions),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub id: String,
+    pub description: Option<String>,
+    pub html_url: String,
+    pub owner: String,
+    pub public: bool,
+    pub created_at: String,
+    pub updated_at: String,
+    pub files: Vec<ManifestFile>,
+    /// Ids of the copies of this gist on other services, keyed by service name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub mirrors: BTreeMap<String, String>,
+    /// Set when some files of the gist failed to download, see their `error`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub partial: bool,
+    /// Set when only the metadata of the gist was downloaded, with `--sparse`, until its
+    /// files are downloaded with `materialize`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sparse: bool,
+    /// Number of comments on the gist
+    #[serde(default)]
+    pub comments: u32,
+    /// Set when the gist is starred by the owner of the token, as of the last `refresh`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub starred: bool,
+    /// Set by `refresh` when the gist was updated on GitHub after its files were downloaded,
+    /// so the next download fetches them all again
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ManifestFile {
+    pub filename: String,
+    pub language: Option<String>,
+    pub size: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Why the file couldn't be downloaded, when it couldn't
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Set for images and other files that aren't text
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub binary: bool,
+    /// Permission bits (octal) applied to the file, e.g. `755` for scripts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    /// Set for files written gzip-compressed as `<name>.gz` by `--compress-files`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub gzip: bool,
+    /// Line endings the file was written with by `--eol`, which its checksum is of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub eol: Option<Eol>,
+}
+
+/// How the gists of a manifest changed between two versions of it.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct ChangeSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    /// Gists that failed to download or were only partly downloaded, and accounts whose
+    /// gists couldn't be listed
+    pub failed: usize,
+}
+
+impl fmt::Display for ChangeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} added, {} updated, {} removed",
+            self.added, self.updated, self.removed
+        )?;
+        if self.failed > 0 {
+            write!(f, ", {} failed", self.failed)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<&Gist> for ManifestEntry {
+    fn from(gist: &Gist) -> Self {
+        let mut files: Vec<ManifestFile> = gist
+            .files
+            .values()
+            .map(|f| ManifestFile {
+                filename: f.filename.clone(),
+                language: f.language.clone(),
+                size: f.size,
+                license: None,
+                sha256: None,
+                error: None,
+                binary: false,
+                mode: None,
+                gzip: false,
+                eol: None,
+            })
+            .collect();
+        files.sort_by(|a, b| a.filename.cmp(&b.filename));
+
+        ManifestEntry {
+            id: gist.id.clone(),
+            description: gist.description.clone(),
+            html_url: gist.html_url.clone(),
+            owner: gist.owner.login.clone(),
+            public: gist.public,
+            created_at: gist.created_at.clone(),
+            updated_at: gist.updated_at.clone(),
+            files,
+            mirrors: BTreeMap::new(),
+            partial: false,
+            sparse: false,
+            comments: gist.comments,
+            starred: false,
+            stale: false,
+        }
+    }
+}
+
+impl ManifestEntry {
+    /// Returns the languages of the files in this gist, without duplicates.
+    pub fn languages(&self) -> Vec<&str> {
+        let mut languages: Vec<&str> = self
+            .files
+            .iter()
+            .filter_map(|f| f.language.as_deref())
+            .collect();
+        languages.sort_unstable();
+        languages.dedup();
+        languages
+    }
+
+    /// Returns the license of the gist, preferring a dedicated license file over headers.
+    pub fn license(&self) -> Option<&str> {
+        self.files
+            .iter()
+            .filter(|f| f.license.is_some())
+            .min_by_key(|f| !f.filename.to_uppercase().starts_with("LICEN"))
+            .and_then(|f| f.license.as_deref())
+    }
+
+    /// Reads the downloaded files of the gist and records their digests and licenses.
+    pub fn inspect_files(&mut self, folder: &Path) {
+        let id = self.id.clone();
+        self.inspect_with(|filename| Inspection::of_file(folder, &id, filename).ok());
+    }
+
+    /// Records what happened to the files of the gist when downloading it: skipped files
+    /// are left out, binary and gzipped ones flagged, executable ones get their mode, and
+    /// failed ones get their error and mark the gist as partial. Unchanged and kept files
+    /// keep what was recorded for them before.
+    pub fn record_outcome(&mut self, outcome: &DownloadOutcome) {
+        self.files
+            .retain(|file| !outcome.skipped.contains(&file.filename));
+        for file in &mut self.files {
+            if outcome.unchanged.contains(&file.filename) || outcome.kept.contains(&file.filename) {
+                continue;
+            }
+            file.error = outcome.failed.get(&file.filename).cloned();
+            file.binary = outcome.binary.contains(&file.filename);
+            file.gzip = outcome.gzipped.contains(&file.filename);
+            file.eol = outcome
+                .eol
+                .filter(|_| outcome.converted.contains(&file.filename));
+            file.mode = outcome
+                .executable
+                .contains(&file.filename)
+                .then(|| format!("{:o}", store::EXECUTABLE_MODE));
+        }
+        self.partial = !outcome.failed.is_empty();
+    }
+
+    /// Returns the files of the gist that needn't be fetched again: the gist wasn't updated
+    /// since this entry was recorded, and the file has the same size, downloaded fine and
+    /// is still in the archive.
+    pub fn unchanged_files(&self, gist: &Gist, folder: &Path) -> HashSet<String> {
+        if self.stale || self.updated_at != gist.updated_at {
+            return HashSet::new();
+        }
+        self.files
+            .iter()
+            .filter(|file| file.error.is_none())
+            .filter_map(|file| {
+                let (name, remote) = gist
+                    .files
+                    .iter()
+                    .find(|(name, _)| paths::same_name(name, &file.filename))?;
+                (remote.size == file.size && store::contains(folder, &self.id, &file.filename))
+                    .then(|| name.clone())
+            })
+            .collect()
+    }
+
+    /// Updates the metadata of the entry from the gist as listed, leaving its files alone, and
+    /// returns whether anything changed. A gist updated since is marked stale.
+    pub fn refresh(&mut self, gist: &Gist) -> bool {
+        let before = (
+            self.description.clone(),
+            self.public,
+            self.updated_at.clone(),
+            self.comments,
+        );
+        if self.updated_at != gist.updated_at {
+            self.stale = true;
+        }
+        self.description = gist.description.clone();
+        self.public = gist.public;
+        self.created_at = gist.created_at.clone();
+        self.updated_at = gist.updated_at.clone();
+        self.comments = gist.comments;
+        before
+            != (
+                self.description.clone(),
+                self.public,
+                self.updated_at.clone(),
+                self.comments,
+            )
+    }
+
+    /// Records the checksum and detected license of every file, reading the contents
+    /// with `read`.
+    pub fn inspect_with(&mut self, inspect: impl Fn(&str) -> Option<Inspection>) {
+        for file in &mut self.files {
+            let inspection = inspect(&file.filename);
+            file.sha256 = inspection.as_ref().map(|i| i.sha256.clone());
+            file.license = inspection.and_then(|i| i.license);
+        }
+    }
+}
+
+/// What the content of a file tells about it: its digest and license.
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    pub sha256: String,
+    pub license: Option<String>,
+}
+
+impl Inspection {
+    /// Inspects content in memory.
+    pub fn of(filename: &str, content: &[u8]) -> Inspection {
+        Inspection {
+            sha256: store::sha256_hex(content),
+            license: license::detect(filename, content),
+        }
+    }
+
+    /// Inspects a file of the archive, reading it in chunks and keeping only the part the
+    /// license is detected in.
+    pub fn of_file(folder: &Path, id: &str, filename: &str) -> io::Result<Inspection> {
+        let content = store::open_file(folder, id, filename)?;
+        let (sha256, head) = spool::digest_with_head(content, license::detected_len(filename))?;
+        Ok(Inspection {
+            sha256,
+            license: license::detect(filename, &head),
+        })
+    }
+}
+
+fn manifest_path(folder: &Path) -> PathBuf {
+    folder.join(MANIFEST_FILE)
+}
+
+impl Manifest {
+    /// Loads the manifest from an archive folder.
+    ///
+    /// A missing manifest is treated as an empty archive.
+    pub fn load(folder: &Path) -> Result<Self, GistError> {
+        let path = manifest_path(folder);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let text = std::fs::read_to_string(path)?;
+        serde_json::from_str(&text).map_err(GistError::Manifest)
+    }
+
+    /// Loads the manifest of an archive about to be changed, failing before anything is
+    /// changed when the manifest is signed and no key was given to sign it again.
+    pub fn load_writable(
+        folder: &Path,
+        signing_key: Option<&SecretKey>,
+    ) -> Result<Self, GistError> {
+        integrity::check_signable(folder, signing_key)?;
+        Manifest::load(folder)
+    }
+
+    /// Writes the manifest to the archive folder, replacing the previous one atomically,
+    /// and signs it with the key of `--sign-manifest`, when given. A signed manifest isn't
+    /// replaced without the key.
+    pub fn save(&self, folder: &Path, signing_key: Option<&SecretKey>) -> Result<(), GistError> {
+        integrity::check_signable(folder, signing_key)?;
+        std::fs::create_dir_all(folder)?;
+        let path = manifest_path(folder);
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, self.to_json()?)?;
+        std::fs::rename(tmp_path, path)?;
+        integrity::sign_written(folder, signing_key)
+    }
+
+    /// Compares the gists with an earlier version of the manifest, a gist counts as updated
+    /// when its update time changed.
+    pub fn changes_since(&self, previous: &Manifest) -> ChangeSummary {
+        let mut changes = ChangeSummary::default();
+        for (id, entry) in &self.gists {
+            match previous.gists.get(id) {
+                None => changes.added += 1,
+                Some(old) if old.updated_at != entry.updated_at => changes.updated += 1,
+                Some(_) => {}
+            }
+        }
+        changes.removed = previous
+            .gists
+            .keys()
+            .filter(|id| !self.gists.contains_key(*id))
+            .count();
+        changes
+    }
+
+    pub fn to_json(&self) -> Result<String, GistError> {
+        serde_json::to_string_pretty(self).map_err(GistError::Manifest)
+    }
+
+    /// Records a downloaded gist, returning its entry so callers can add local details.
+    ///
+    /// The mirrors and star of a gist already in the manifest are kept, and so are the
+    /// binary flag and mode of its files until `record_outcome` says otherwise.
+    pub fn insert(&mut self, gist: &Gist) -> &mut ManifestEntry {
+        let mut entry = ManifestEntry::from(gist);
+        if let Some(previous) = self.gists.remove(&gist.id) {
+            entry.mirrors = previous.mirrors;
+            entry.starred = previous.starred;
+            for file in &mut entry.files {
+                if let Some(old) = previous
+                    .files
+                    .iter()
+                    .find(|old| paths::same_name(&old.filename, &file.filename))
+                {
+                    file.binary = old.binary;
+                    file.gzip = old.gzip;
+                    file.eol = old.eol;
+                    file.mode = old.mode.clone();
+                }
+            }
+        }
+        self.gists.insert(gist.id.clone(), entry);
+        self.gists
+            .get_mut(&gist.id)
+            .expect("entry was just inserted")
+    }
+}
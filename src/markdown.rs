@@ -0,0 +1,46 @@
+use crate::manifest::ManifestFile;
+use std::path::Path;
+
+// Extensions of the binary files embedded in pages as images
+const IMAGE_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "svg", "webp"];
+
+/// Whether the file is an image that Markdown pages can embed.
+pub fn is_image(file: &ManifestFile) -> bool {
+    file.binary
+        && Path::new(&file.filename)
+            .extension()
+            .is_some_and(|extension| {
+                let extension = extension.to_string_lossy().to_ascii_lowercase();
+                IMAGE_EXTENSIONS.contains(&extension.as_str())
+            })
+}
+
+/// Returns the tag of a language, lowercase with only the characters tags allow, e.g.
+/// `cpp` for C++ and `jupyter-notebook` for Jupyter Notebook.
+pub fn language_tag(language: &str) -> String {
+    language
+        .to_lowercase()
+        .replace('+', "p")
+        .replace('#', "sharp")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_'))
+        .collect()
+}
+
+/// Quotes a YAML scalar, JSON strings being valid YAML.
+pub fn yaml_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// Returns a code fence longer than any run of backticks in the content.
+pub fn fence(content: &str) -> String {
+    let longest = content
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    "`".repeat(longest.max(2) + 1)
+}
@@ -0,0 +1,56 @@
+use crate::log::info;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+
+// Counters updated by the downloads, read by the reporter
+static FILES: AtomicU64 = AtomicU64::new(0);
+static BYTES: AtomicU64 = AtomicU64::new(0);
+// Requests left in the rate limit window as last reported by GitHub, -1 until known
+static RATE_REMAINING: AtomicI64 = AtomicI64::new(-1);
+
+/// Counts a downloaded file of `bytes` bytes.
+pub fn record_file(bytes: usize) {
+    FILES.fetch_add(1, Ordering::Relaxed);
+    BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+}
+
+/// Keeps the number of requests left in the rate limit window from a response.
+pub fn record_rate_limit(remaining: Option<&str>) {
+    if let Some(remaining) = remaining.and_then(|r| r.parse::<i64>().ok()) {
+        RATE_REMAINING.store(remaining, Ordering::Relaxed);
+    }
+}
+
+/// Returns the requests left in the rate limit window, once GitHub reported it.
+pub fn rate_remaining() -> Option<i64> {
+    Some(RATE_REMAINING.load(Ordering::Relaxed)).filter(|&n| n >= 0)
+}
+
+/// Logs download throughput, runtime load and the rate limit every `interval`, until the
+/// task running it is dropped.
+pub async fn report(interval: Duration) {
+    let handle = Handle::current();
+    let (mut files, mut bytes) = (0, 0);
+    let mut last = Instant::now();
+    loop {
+        tokio::time::sleep(interval).await;
+        let elapsed = last.elapsed().as_secs_f64();
+        last = Instant::now();
+        let (now_files, now_bytes) = (FILES.load(Ordering::Relaxed), BYTES.load(Ordering::Relaxed));
+        let runtime = handle.metrics();
+        let rate_remaining = match RATE_REMAINING.load(Ordering::Relaxed) {
+            -1 => "unknown".to_string(),
+            remaining => remaining.to_string(),
+        };
+        info!(
+            "metrics: {:.1} files/s, {:.1} KiB/s, {} tasks in flight, queue depth {}, rate limit remaining {}",
+            (now_files - files) as f64 / elapsed,
+            (now_bytes - bytes) as f64 / 1024.0 / elapsed,
+            runtime.num_alive_tasks(),
+            runtime.global_queue_depth(),
+            rate_remaining
+        );
+        (files, bytes) = (now_files, now_bytes);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::filter::GistFilter;
+use crate::gist::GistError;
+use crate::log::{info, warn};
+use crate::manifest::{Manifest, ManifestEntry, ManifestFile};
+use crate::markdown::{fence, is_image, language_tag, yaml_string};
+use crate::paths::portable_name;
+use crate::store;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::path::Path;
+
+// Folder of the vault the images of gists are copied into, a subfolder per gist
+const ATTACHMENTS_DIR: &str = "attachments";
+// Longest note title taken from a description, in characters
+const MAX_TITLE_LEN: usize = 100;
+// Extensions of the files embedded as Markdown rather than as code blocks
+const MARKDOWN_EXTENSIONS: [&str; 2] = ["md", "markdown"];
+
+fn extension(filename: &str) -> Option<String> {
+    Path::new(filename)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+}
+
+/// Returns the title of the note of a gist: the first line of its description without the
+/// characters Obsidian doesn't allow in note names, or its id.
+fn title(entry: &ManifestEntry) -> String {
+    let description = entry
+        .description
+        .as_deref()
+        .and_then(|description| description.lines().next())
+        .unwrap_or_default();
+    let cleaned: String = description
+        .chars()
+        .map(|c| match c {
+            '[' | ']' | '#' | '^' | '|' | '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' => ' ',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+    let cleaned: String = cleaned.chars().take(MAX_TITLE_LEN).collect();
+    match cleaned.trim_end_matches('.').trim() {
+        "" => entry.id.clone(),
+        title => title.to_string(),
+    }
+}
+
+/// Renders the note of a gist: YAML front matter with its metadata and tags, its
+/// description when the title doesn't say it all and its link, then each file under a heading. `read` gives the content of a
+/// file, `None` when it isn't in the archive.
+fn render(
+    entry: &ManifestEntry,
+    title: &str,
+    read: impl Fn(&ManifestFile) -> Option<Vec<u8>>,
+) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "---");
+    let _ = writeln!(out, "id: {}", yaml_string(&entry.id));
+    let _ = writeln!(out, "owner: {}", yaml_string(&entry.owner));
+    let _ = writeln!(out, "source: {}", yaml_string(&entry.html_url));
+    let _ = writeln!(out, "public: {}", entry.public);
+    let _ = writeln!(out, "created: {}", yaml_string(&entry.created_at));
+    let _ = writeln!(out, "updated: {}", yaml_string(&entry.updated_at));
+    let _ = writeln!(out, "tags:");
+    let _ = writeln!(out, "  - gist");
+    for language in entry.languages() {
+        let _ = writeln!(out, "  - {}", yaml_string(&language_tag(language)));
+    }
+    let _ = writeln!(out, "---\n");
+    let _ = writeln!(out, "# {}\n", title);
+    if let Some(description) = entry
+        .description
+        .as_deref()
+        .filter(|description| !description.trim().is_empty() && description.trim() != title)
+    {
+        let _ = writeln!(out, "{}\n", description.trim_end());
+    }
+    let _ = writeln!(out, "[View on GitHub]({})\n", entry.html_url);
+
+    for file in &entry.files {
+        let _ = writeln!(out, "## {}\n", file.filename);
+        let extension = extension(&file.filename);
+        let Some(content) = read(file) else {
+            let _ = writeln!(out, "_Not in the archive._\n");
+            continue;
+        };
+        if is_image(file) {
+            let _ = writeln!(
+                out,
+                "![[{}/{}/{}]]\n",
+                ATTACHMENTS_DIR,
+                entry.id,
+                portable_name(&file.filename)
+            );
+            continue;
+        }
+        if file.binary {
+            let _ = writeln!(out, "_Binary file, not included._\n");
+            continue;
+        }
+        let content = String::from_utf8_lossy(&content);
+        if extension
+            .as_deref()
+            .is_some_and(|extension| MARKDOWN_EXTENSIONS.contains(&extension))
+        {
+            let _ = writeln!(out, "{}\n", content.trim_end());
+            continue;
+        }
+        let language = file
+            .language
+            .as_deref()
+            .map(language_tag)
+            .or(extension)
+            .unwrap_or_default();
+        let fence = fence(&content);
+        let _ = writeln!(out, "{}{}", fence, language);
+        let _ = writeln!(out, "{}", content.trim_end_matches('\n'));
+        let _ = writeln!(out, "{}\n", fence);
+    }
+    out
+}
+
+/// Writes every gist as a Markdown note of an Obsidian vault, so the notes app can browse
+/// and link the snippets. Notes are named after the description of their gist, with the id
+/// added when several gists share one; images are copied into `attachments/<id>/` and
+/// embedded. Other files of the vault are left alone.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `out` - Directory of the vault
+/// * `filter` - Selects the gists to export
+/// * `follow_symlinks` - Write through symlinks inside `out` instead of refusing them
+///
+/// Returns the number of exported gists.
+pub fn export_vault(
+    folder: &str,
+    out: &str,
+    filter: &GistFilter,
+    follow_symlinks: bool,
+) -> Result<usize, GistError> {
+    let folder = Path::new(folder);
+    let out = Path::new(out);
+    let mut manifest = Manifest::load(folder)?;
+    manifest.gists.retain(|_, entry| filter.matches_gist(entry));
+
+    // Titles are compared lowercase, as notes on case-insensitive filesystems would be
+    let titles: Vec<(&ManifestEntry, String)> = manifest
+        .gists
+        .values()
+        .map(|entry| (entry, title(entry)))
+        .collect();
+    let mut uses: HashMap<String, usize> = HashMap::new();
+    for (_, title) in &titles {
+        *uses.entry(title.to_lowercase()).or_default() += 1;
+    }
+
+    std::fs::create_dir_all(out)?;
+    for (entry, title) in &titles {
+        let title = match uses[&title.to_lowercase()] {
+            1 => title.clone(),
+            _ if *title == entry.id => title.clone(),
+            _ => format!("{} ({})", title, entry.id),
+        };
+        let read = |file: &ManifestFile| {
+            store::read_file(folder, &entry.id, &file.filename)
+                .inspect_err(|e| warn!("Skipping {}/{}: {}", entry.id, file.filename, e))
+                .ok()
+        };
+
+        let mut copied: HashSet<&str> = HashSet::new();
+        for file in entry.files.iter().filter(|file| is_image(file)) {
+            if let Some(content) = read(file) {
+                let dir = out.join(ATTACHMENTS_DIR).join(&entry.id);
+                let path = dir.join(portable_name(&file.filename).as_ref());
+                store::check_inside(out, &path, follow_symlinks)?;
+                std::fs::create_dir_all(&dir)?;
+                std::fs::write(path, content)?;
+                copied.insert(&file.filename);
+            }
+        }
+
+        // Binary files aren't read again, their content isn't part of the note
+        let note = render(entry, &title, |file| match file.binary {
+            true if is_image(file) => copied.contains(file.filename.as_str()).then(Vec::new),
+            true => Some(Vec::new()),
+            false => read(file),
+        });
+        let path = out.join(format!("{}.md", portable_name(&title)));
+        store::check_inside(out, &path, follow_symlinks)?;
+        std::fs::write(path, note)?;
+    }
+    info!(
+        "Exported {} gists to the vault in {}",
+        titles.len(),
+        out.display()
+    );
+
+    Ok(titles.len())
+}
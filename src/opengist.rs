@@ -0,0 +1,200 @@
+use crate::gist::GistError;
+use crate::log::{debug, error, info};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::store;
+use minisign::SecretKey;
+use reqwest::Url;
+use std::path::Path;
+use std::process::Output;
+use tokio::process::Command;
+
+// Key of opengist URLs in the mirrors of a manifest entry
+const MIRROR_KEY: &str = "opengist";
+// Pushing to this path of an opengist instance creates a new gist
+const INIT_PATH: &str = "init";
+
+/// An opengist instance, written to over git since creating gists is done by pushing.
+pub struct Opengist {
+    url: Url,
+    username: String,
+    password: String,
+}
+
+/// Runs git in a directory, failing with its error output when it doesn't succeed.
+async fn git(dir: &Path, args: &[&str]) -> Result<Output, GistError> {
+    let output = Command::new("git")
+        .current_dir(dir)
+        .args([
+            "-c",
+            "user.name=local-gist",
+            "-c",
+            "user.email=local-gist@localhost",
+        ])
+        .args(args)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(GistError::Git(format!(
+            "git {}: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output)
+}
+
+/// Finds the URL of the gist opengist created, which it reports in the push output.
+fn created_url(output: &Output) -> Option<String> {
+    String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .filter(|line| line.starts_with("remote:"))
+        .flat_map(str::split_whitespace)
+        .find(|word| word.starts_with("http://") || word.starts_with("https://"))
+        .map(str::to_string)
+}
+
+impl Opengist {
+    /// # Arguments
+    /// * `url` - Base URL of the instance, e.g. `https://opengist.example.com`
+    /// * `username` - Account the gists are created for
+    /// * `password` - Password of the account
+    pub fn new(url: &str, username: &str, password: &str) -> Result<Self, GistError> {
+        let mut url = Url::parse(url).map_err(|e| GistError::Target(e.to_string()))?;
+        if !url.path().ends_with('/') {
+            url.set_path(&format!("{}/", url.path()));
+        }
+        Ok(Opengist {
+            url,
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+
+    /// Returns the URL with the credentials of the account, for git to push with.
+    fn authenticated(&self, url: &str) -> Result<String, GistError> {
+        let mut url = Url::parse(url).map_err(|e| GistError::Target(e.to_string()))?;
+        let _ = url.set_username(&self.username);
+        let _ = url.set_password(Some(&self.password));
+        Ok(url.to_string())
+    }
+
+    /// Commits the files of a gist to the gist mirroring it, creating that gist on the first
+    /// run. Returns the URL of the gist.
+    async fn mirror_gist(
+        &self,
+        entry: &ManifestEntry,
+        files: &[(String, Vec<u8>)],
+        existing: Option<&str>,
+    ) -> Result<String, GistError> {
+        let dir = std::env::temp_dir().join(format!("local-gist-opengist-{}", entry.id));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        let result = async {
+            match existing {
+                Some(url) => {
+                    git(&dir, &["clone", "-q", &self.authenticated(url)?, "."]).await?;
+                    for file in std::fs::read_dir(&dir)? {
+                        let file = file?;
+                        if file.file_name() != ".git" {
+                            std::fs::remove_file(file.path())?;
+                        }
+                    }
+                }
+                None => {
+                    git(&dir, &["init", "-q"]).await?;
+                }
+            }
+            for (filename, content) in files {
+                std::fs::write(dir.join(filename), content)?;
+            }
+            git(&dir, &["add", "-A"]).await?;
+
+            let status = git(&dir, &["status", "--porcelain"]).await?;
+            if status.stdout.is_empty() {
+                debug!("Opengist copy of gist {} is up to date", entry.id);
+                return Ok(existing.unwrap_or_default().to_string());
+            }
+            let description = entry.description.as_deref().unwrap_or_default();
+            let message = match description {
+                "" => format!("Sync gist {}", entry.id),
+                description => description.to_string(),
+            };
+            git(&dir, &["commit", "-q", "-m", &message]).await?;
+
+            match existing {
+                Some(url) => {
+                    git(&dir, &["push", "-q", &self.authenticated(url)?, "HEAD"]).await?;
+                    Ok(url.to_string())
+                }
+                None => {
+                    let init = self
+                        .url
+                        .join(INIT_PATH)
+                        .map_err(|e| GistError::Target(e.to_string()))?;
+                    let output = git(
+                        &dir,
+                        &["push", &self.authenticated(init.as_str())?, "HEAD:master"],
+                    )
+                    .await?;
+                    created_url(&output).ok_or_else(|| {
+                        GistError::Target(format!(
+                            "opengist didn't report the URL of gist {}",
+                            entry.id
+                        ))
+                    })
+                }
+            }
+        }
+        .await;
+
+        std::fs::remove_dir_all(&dir)?;
+        result
+    }
+}
+
+/// Pushes every gist of the archive to an opengist instance, recording the gist URLs in
+/// the manifest so later runs update the same gists.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `opengist` - The opengist instance to mirror to
+/// * `signing_key` - Key the manifest is signed with, when it is
+///
+/// Returns the number of mirrored gists.
+pub async fn mirror(
+    folder: &Path,
+    opengist: &Opengist,
+    signing_key: Option<&SecretKey>,
+) -> Result<usize, GistError> {
+    let mut manifest = Manifest::load(folder)?;
+    let mut mirrored = 0;
+
+    for entry in manifest.gists.values_mut() {
+        let result = match store::read_gist_files(folder, entry) {
+            Ok(files) => {
+                let existing = entry.mirrors.get(MIRROR_KEY).map(String::as_str);
+                opengist.mirror_gist(entry, &files, existing).await
+            }
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(url) => {
+                info!("Mirrored gist {} to {}", entry.id, url);
+                entry.mirrors.insert(MIRROR_KEY.to_string(), url);
+                mirrored += 1;
+            }
+            Err(e) => error!("Failed to mirror gist {}: {}", entry.id, e),
+        }
+    }
+
+    manifest.save(folder, signing_key)?;
+    info!(
+        "Mirrored {} of {} gists to opengist",
+        mirrored,
+        manifest.gists.len()
+    );
+    Ok(mirrored)
+}
@@ -0,0 +1,120 @@
+use crate::cli::OutputFormat;
+use crate::gist::GistError;
+use jmespath::Expression;
+use serde::Serialize;
+use serde_json::Value;
+use std::io::{IsTerminal, Write};
+
+/// Version of the JSON written with `--format json` and `--format ndjson`. Fields may be
+/// added within a version, renaming or removing one raises it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Document written with `--format json`, the results are under `data`.
+#[derive(Serialize)]
+struct Document<'a, T: Serialize + ?Sized> {
+    version: u32,
+    data: &'a T,
+}
+
+/// Where commands report their results: log lines for people, or JSON on stdout for
+/// scripts, so these don't have to parse log text.
+pub struct Output {
+    format: OutputFormat,
+    /// JMESPath expression applied to the results, whose outcome is written instead
+    query: Option<Expression<'static>>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat, query: Option<&str>) -> Result<Self, GistError> {
+        let query = query.map(jmespath::compile).transpose()?;
+        Ok(Output { format, query })
+    }
+
+    /// Reports the results of a command. `human` logs them in the human format; in JSON
+    /// they are written as one document, in NDJSON as one line per element of a list (or
+    /// a single line for anything else). With a query its outcome is written as JSON,
+    /// or NDJSON when asked for, in any format.
+    pub fn write<T: Serialize + ?Sized>(
+        &self,
+        data: &T,
+        human: impl FnOnce(&T),
+    ) -> Result<(), GistError> {
+        if let Some(query) = &self.query {
+            let outcome = query.search(data)?;
+            let outcome = serde_json::to_value(&*outcome).map_err(GistError::Manifest)?;
+            return match self.format {
+                OutputFormat::Ndjson => write_lines(outcome),
+                _ => write_pretty(&outcome),
+            };
+        }
+        match self.format {
+            OutputFormat::Human => human(data),
+            OutputFormat::Json => write_pretty(&Document {
+                version: SCHEMA_VERSION,
+                data,
+            })?,
+            OutputFormat::Ndjson => {
+                write_lines(serde_json::to_value(data).map_err(GistError::Manifest)?)?
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports one result of a command that reports them as they come: logged in the human
+    /// format and written right away in NDJSON. JSON and queries wait for `write` with all
+    /// of them.
+    pub fn write_record<T: Serialize>(
+        &self,
+        record: &T,
+        human: impl FnOnce(&T),
+    ) -> Result<(), GistError> {
+        match self.format {
+            _ if self.buffers_records() => {}
+            OutputFormat::Human => human(record),
+            OutputFormat::Json => {}
+            OutputFormat::Ndjson => {
+                let line = serde_json::to_string(record).map_err(GistError::Manifest)?;
+                writeln!(std::io::stdout(), "{}", line)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether results reported with `write_record` still need to be written with `write`.
+    pub fn buffers_records(&self) -> bool {
+        self.format == OutputFormat::Json || self.query.is_some()
+    }
+}
+
+fn write_pretty<T: Serialize + ?Sized>(value: &T) -> Result<(), GistError> {
+    let mut stdout = std::io::stdout();
+    serde_json::to_writer_pretty(&mut stdout, value).map_err(GistError::Manifest)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Writes each element of a list on its own line, anything else on a single line.
+fn write_lines(value: Value) -> Result<(), GistError> {
+    let mut stdout = std::io::stdout();
+    match value {
+        Value::Array(records) => {
+            for record in records {
+                writeln!(stdout, "{}", record)?;
+            }
+        }
+        record => writeln!(stdout, "{}", record)?,
+    }
+    Ok(())
+}
+
+/// Whether stdout is a terminal able to show OSC 8 hyperlinks. Terminals without support
+/// ignore them, except the dumb ones and the Linux console which would print them.
+pub fn hyperlinks() -> bool {
+    let term = std::env::var("TERM").unwrap_or_default();
+    std::io::stdout().is_terminal() && term != "dumb" && term != "linux"
+}
+
+/// Wraps the text in an OSC 8 hyperlink to the URL.
+pub fn hyperlink(text: &str, url: &str) -> String {
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
@@ -0,0 +1,193 @@
+use crate::store::sha256_hex;
+use icu_normalizer::ComposingNormalizerBorrowed;
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+// Longest file name kept as is, leaving room under the 255 limit of NTFS and most Unix
+// filesystems for the `.zst` and `.age` extensions of the stores
+const MAX_NAME_LEN: usize = 240;
+// Number of hex digits of the SHA-256 of the full name added to rewritten names
+const HASH_SUFFIX_LEN: usize = 8;
+// Device names Windows reserves in every directory, with any extension
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+// Length from which Windows needs the extended-length prefix for a path
+#[cfg(windows)]
+const MAX_PATH: usize = 260;
+
+/// Returns the name in Unicode normalization form C, the composed form Linux and Windows
+/// tools produce, while macOS filesystems may hand back decomposed (NFD) names.
+pub fn nfc(name: &str) -> Cow<'_, str> {
+    ComposingNormalizerBorrowed::new_nfc().normalize(name)
+}
+
+/// Whether two file names are the same once normalized, however their accents are encoded.
+pub fn same_name(a: &str, b: &str) -> bool {
+    a == b || nfc(a) == nfc(b)
+}
+
+/// Returns the name a gist file is stored under, so the archive can be written to and
+/// copied between Windows, macOS and Unix filesystems. Names that are fine everywhere are
+/// kept.
+///
+/// Names are normalized to NFC, so a name typed on macOS and the same name typed on Linux
+/// are stored as one file. Characters Windows rejects are replaced with `_`, as are
+/// trailing dots and spaces which it drops; reserved device names (`CON`, `nul.txt`, ...)
+/// get a leading `_`; and names too long for the filesystem are truncated, keeping their
+/// extension. Every rewritten name gets a hash of the full name, so it can't clash with
+/// another name of the gist (`a:b` and `a_b`) and stays the same between runs.
+pub fn portable_name(name: &str) -> Cow<'_, str> {
+    match nfc(name) {
+        Cow::Borrowed(name) => portable(name),
+        Cow::Owned(name) => Cow::Owned(portable(&name).into_owned()),
+    }
+}
+
+fn portable(name: &str) -> Cow<'_, str> {
+    let invalid =
+        |c: char| c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '\\' | '|' | '?' | '*');
+    let stem = name.split('.').next().unwrap_or_default();
+    let reserved = RESERVED_NAMES
+        .iter()
+        .any(|reserved| stem.trim_end().eq_ignore_ascii_case(reserved));
+    let trailing = name.ends_with(['.', ' ']);
+    if !name.contains(invalid) && !reserved && !trailing && name.len() <= MAX_NAME_LEN {
+        return Cow::Borrowed(name);
+    }
+
+    let mut portable: String = name
+        .chars()
+        .map(|c| if invalid(c) { '_' } else { c })
+        .collect();
+    if reserved {
+        portable.insert(0, '_');
+    }
+    let kept = portable.trim_end_matches(['.', ' ']).len();
+    let trimmed = portable.len() - kept;
+    portable.truncate(kept);
+    portable.push_str(&"_".repeat(trimmed));
+    Cow::Owned(with_hash(
+        &portable,
+        &sha256_hex(name.as_bytes())[..HASH_SUFFIX_LEN],
+    ))
+}
+
+/// Adds the hash to a name as `<start>~<hash>.<extension>`, shortening the start so the
+/// name fits in `MAX_NAME_LEN` bytes.
+fn with_hash(name: &str, hash: &str) -> String {
+    let extension = match name.rsplit_once('.') {
+        // An extension longer than a few characters is part of the name
+        Some((start, extension)) if !start.is_empty() && extension.len() <= 16 => {
+            &name[name.len() - extension.len() - 1..]
+        }
+        _ => "",
+    };
+    let start = &name[..name.len() - extension.len()];
+    let mut end = start
+        .len()
+        .min(MAX_NAME_LEN - extension.len() - hash.len() - 1);
+    while !start.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}~{}{}", &start[..end], hash, extension)
+}
+
+/// Returns the path in the form that can be opened, which on Windows means the `\\?\`
+/// extended-length form for paths of 260 characters and more. Unchanged elsewhere.
+pub fn long_path(path: PathBuf) -> PathBuf {
+    #[cfg(windows)]
+    if path.as_os_str().len() >= MAX_PATH {
+        // The extended form skips normalization, so the path must be absolute with `\`
+        if let Ok(absolute) = std::path::absolute(&path) {
+            if !absolute.as_os_str().to_string_lossy().starts_with(r"\\") {
+                let mut extended = std::ffi::OsString::from(r"\\?\");
+                extended.push(absolute.as_os_str());
+                return PathBuf::from(extended);
+            }
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_names_fine_everywhere() {
+        for name in [
+            "script.sh",
+            "README.md",
+            ".env",
+            "notes v2.txt",
+            "console.log",
+        ] {
+            assert!(matches!(portable_name(name), Cow::Borrowed(_)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn prefixes_reserved_names() {
+        for name in ["CON", "nul.txt", "Com1.tar.gz", "lpt9", "AUX .md"] {
+            let portable = portable_name(name);
+            assert!(
+                portable.starts_with(&format!("_{}", &name[..3])),
+                "{}",
+                portable
+            );
+            let stem = portable.split('.').next().unwrap();
+            assert!(!RESERVED_NAMES.iter().any(|r| stem.eq_ignore_ascii_case(r)));
+        }
+        assert!(portable_name("nul.txt").ends_with(".txt"));
+    }
+
+    #[test]
+    fn replaces_trailing_dots_and_spaces() {
+        for name in ["notes.", "notes ", "notes. .", "run.sh  "] {
+            let portable = portable_name(name);
+            assert!(!portable.ends_with(['.', ' ']), "{:?}", portable);
+        }
+    }
+
+    #[test]
+    fn replaces_invalid_characters() {
+        let portable = portable_name("what?<a|b>*.txt");
+        assert!(!portable.contains(['?', '<', '|', '>', '*']));
+        assert!(portable.ends_with(".txt"));
+    }
+
+    #[test]
+    fn truncates_long_names_keeping_the_extension() {
+        let name = format!("{}.py", "a".repeat(300));
+        let portable = portable_name(&name);
+        assert!(portable.len() <= MAX_NAME_LEN);
+        assert!(portable.ends_with(".py"));
+        assert_eq!(portable, portable_name(&name), "the same between runs");
+
+        let name = "é".repeat(200);
+        let portable = portable_name(&name);
+        assert!(portable.len() <= MAX_NAME_LEN);
+        assert!(portable.starts_with('é'));
+    }
+
+    #[test]
+    fn rewritten_names_do_not_collide() {
+        assert_ne!(portable_name("a:b"), portable_name("a_b"));
+        assert_ne!(portable_name("a:b"), portable_name("a*b"));
+        assert_ne!(portable_name("notes."), portable_name("notes_"));
+        assert_ne!(portable_name("CON"), portable_name("_CON"));
+        let long = "x".repeat(300);
+        assert_ne!(
+            portable_name(&format!("{}1.txt", long)),
+            portable_name(&format!("{}2.txt", long))
+        );
+    }
+
+    #[test]
+    fn normalizes_to_nfc() {
+        assert_eq!(portable_name("cafe\u{301}.md"), "caf\u{e9}.md");
+        assert!(same_name("cafe\u{301}.md", "caf\u{e9}.md"));
+    }
+}
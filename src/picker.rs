@@ -0,0 +1,160 @@
+use crate::gist::GistError;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::BTreeSet;
+
+// Help shown under the list
+const HELP: &str = "type to filter  tab select  ctrl-a select all shown  enter confirm  esc cancel";
+
+struct Picker<'a> {
+    items: &'a [String],
+    matcher: SkimMatcherV2,
+    query: String,
+    // Indexes into `items` of the lines matching the query, best match first
+    visible: Vec<usize>,
+    selected: BTreeSet<usize>,
+    list: ListState,
+}
+
+impl Picker<'_> {
+    fn refilter(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                let score = match self.query.is_empty() {
+                    true => Some(0),
+                    false => self.matcher.fuzzy_match(item, &self.query),
+                };
+                score.map(|score| (score, i))
+            })
+            .collect();
+        // Stable, so lines keep the listing order among equal scores
+        scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        self.visible = scored.into_iter().map(|(_, i)| i).collect();
+        self.list.select((!self.visible.is_empty()).then_some(0));
+    }
+
+    fn current(&self) -> Option<usize> {
+        self.visible.get(self.list.selected()?).copied()
+    }
+
+    fn toggle(&mut self) {
+        if let Some(i) = self.current() {
+            if !self.selected.remove(&i) {
+                self.selected.insert(i);
+            }
+            self.list.select_next();
+            if self.list.selected() >= Some(self.visible.len()) {
+                self.list.select(Some(self.visible.len().saturating_sub(1)));
+            }
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [input, list, help] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let title = format!(
+            " {}/{} shown, {} selected ",
+            self.visible.len(),
+            self.items.len(),
+            self.selected.len()
+        );
+        frame.render_widget(
+            Paragraph::new(format!("> {}", self.query))
+                .block(Block::default().borders(Borders::ALL).title(title)),
+            input,
+        );
+
+        let items: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|&i| {
+                let mark = match self.selected.contains(&i) {
+                    true => Span::styled("[x] ", Style::new().fg(Color::Green)),
+                    false => Span::raw("[ ] "),
+                };
+                ListItem::new(Line::from(vec![mark, Span::raw(self.items[i].as_str())]))
+            })
+            .collect();
+        let widget = List::new(items)
+            .block(Block::default().borders(Borders::ALL))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(widget, list, &mut self.list);
+
+        frame.render_widget(
+            Paragraph::new(HELP).style(Style::new().fg(Color::DarkGray)),
+            help,
+        );
+    }
+
+    /// Returns the chosen indexes once the user confirms, `None` when they cancel.
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<Option<Vec<usize>>, GistError> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Esc => return Ok(None),
+                KeyCode::Char('c') if ctrl => return Ok(None),
+                KeyCode::Enter => {
+                    // Like skim, confirming without marking anything picks the current line
+                    if self.selected.is_empty() {
+                        self.selected.extend(self.current());
+                    }
+                    return Ok(Some(self.selected.iter().copied().collect()));
+                }
+                KeyCode::Char('a') if ctrl => self.selected.extend(self.visible.iter().copied()),
+                KeyCode::Tab => self.toggle(),
+                KeyCode::Down => self.list.select_next(),
+                KeyCode::Up => self.list.select_previous(),
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.refilter();
+                }
+                KeyCode::Char(c) => {
+                    self.query.push(c);
+                    self.refilter();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Lets the user fuzzy-find and mark lines in the terminal.
+///
+/// Returns the indexes of the chosen lines in order, or `None` when the user cancels.
+pub fn pick(items: &[String]) -> Result<Option<Vec<usize>>, GistError> {
+    let mut picker = Picker {
+        items,
+        matcher: SkimMatcherV2::default(),
+        query: String::new(),
+        visible: Vec::new(),
+        selected: BTreeSet::new(),
+        list: ListState::default(),
+    };
+    picker.refilter();
+
+    let mut terminal = ratatui::init();
+    let result = picker.run(&mut terminal);
+    ratatui::restore();
+    result
+}
@@ -0,0 +1,184 @@
+use crate::gist::Gist;
+use crate::log::info;
+use indicatif::{
+    HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
+use std::future::Future;
+use std::io::IsTerminal;
+#[cfg(feature = "tracing")]
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How often progress is logged when stderr isn't a terminal
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+// Layout of the progress bar in a terminal
+const BAR_TEMPLATE: &str = "{prefix}{bar:20} {pos}/{len} gists, {msg}";
+
+// Bars on screen, one per download running, with how many there are. Log lines are
+// written above them instead of through them
+static ACTIVE_BARS: Mutex<Option<(MultiProgress, usize)>> = Mutex::new(None);
+
+/// Log writer to stderr that keeps the progress bars, when shown, below the lines.
+#[cfg(feature = "tracing")]
+pub struct LogWriter;
+
+#[cfg(feature = "tracing")]
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let active = ACTIVE_BARS.lock().unwrap_or_else(|e| e.into_inner());
+        match active.as_ref() {
+            Some((bars, _)) => bars.suspend(|| std::io::stderr().write_all(buf))?,
+            None => std::io::stderr().write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// Tracks completed gists, files and bytes against the totals known from the listing, with
+/// a progress bar in a terminal and periodic log lines otherwise.
+pub struct Progress {
+    bar: Option<ProgressBar>,
+    /// Account the progress is of, when several are downloaded at once
+    label: Option<String>,
+    started: Instant,
+    last_log: Instant,
+    total_gists: u64,
+    total_files: u64,
+    total_bytes: u64,
+    done_gists: u64,
+    done_files: u64,
+    done_bytes: u64,
+}
+
+/// Hides the progress bars while `asking` talks with the user on the terminal, drawing them
+/// again once it's done.
+pub async fn without_bars<T>(asking: impl Future<Output = T>) -> T {
+    let draw_to = |target: fn() -> ProgressDrawTarget| {
+        let active = ACTIVE_BARS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((bars, _)) = active.as_ref() {
+            let _ = bars.clear();
+            bars.set_draw_target(target());
+        }
+    };
+    draw_to(ProgressDrawTarget::hidden);
+    let result = asking.await;
+    draw_to(ProgressDrawTarget::stderr);
+    result
+}
+
+fn gist_bytes(gist: &Gist) -> u64 {
+    gist.files.values().map(|file| file.size as u64).sum()
+}
+
+impl Progress {
+    /// Starts tracking a download, labelled with the account it is of when given, so the
+    /// downloads of several accounts running at once are told apart.
+    pub fn new(label: Option<&str>) -> Self {
+        let bar = std::io::stderr().is_terminal().then(|| {
+            let mut active = ACTIVE_BARS.lock().unwrap_or_else(|e| e.into_inner());
+            let (bars, count) = active.get_or_insert_with(|| {
+                (
+                    MultiProgress::with_draw_target(ProgressDrawTarget::stderr()),
+                    0,
+                )
+            });
+            *count += 1;
+            let bar = bars.add(ProgressBar::new(0));
+            bar.set_style(ProgressStyle::with_template(BAR_TEMPLATE).expect("valid template"));
+            if let Some(label) = label {
+                bar.set_prefix(format!("{} ", label));
+            }
+            bar
+        });
+        Progress {
+            bar,
+            label: label.map(str::to_string),
+            started: Instant::now(),
+            last_log: Instant::now(),
+            total_gists: 0,
+            total_files: 0,
+            total_bytes: 0,
+            done_gists: 0,
+            done_files: 0,
+            done_bytes: 0,
+        }
+    }
+
+    /// Counts a listed gist in the totals. The totals grow as listing pages arrive.
+    pub fn add(&mut self, gist: &Gist) {
+        self.total_gists += 1;
+        self.total_files += gist.files.len() as u64;
+        self.total_bytes += gist_bytes(gist);
+        if let Some(bar) = &self.bar {
+            bar.set_length(self.total_gists);
+        }
+    }
+
+    /// Counts a gist as done, whether it was downloaded or gave up on.
+    pub fn complete(&mut self, gist: &Gist) {
+        self.done_gists += 1;
+        self.done_files += gist.files.len() as u64;
+        self.done_bytes += gist_bytes(gist);
+        let status = self.status();
+        match &self.bar {
+            Some(bar) => {
+                bar.set_position(self.done_gists);
+                bar.set_message(status);
+            }
+            None if self.last_log.elapsed() >= LOG_INTERVAL => {
+                self.last_log = Instant::now();
+                info!(
+                    "Progress{}: {}/{} gists, {}",
+                    self.label
+                        .as_ref()
+                        .map_or(String::new(), |label| format!(" of {}", label)),
+                    self.done_gists,
+                    self.total_gists,
+                    status
+                );
+            }
+            None => {}
+        }
+    }
+
+    /// Describes files and bytes done, the throughput and the estimated time left, going
+    /// by the bytes left at the rate seen so far.
+    fn status(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let rate = self.done_bytes as f64 / elapsed.max(f64::EPSILON);
+        let left = self.total_bytes.saturating_sub(self.done_bytes);
+        let eta = match rate > 0.0 {
+            true => HumanDuration(Duration::from_secs_f64(left as f64 / rate)).to_string(),
+            false => "unknown".to_string(),
+        };
+        format!(
+            "{}/{} files, {}/{}, {}/s, ETA {}",
+            self.done_files,
+            self.total_files,
+            HumanBytes(self.done_bytes),
+            HumanBytes(self.total_bytes),
+            HumanBytes(rate as u64),
+            eta
+        )
+    }
+
+    /// Clears the progress bar once the downloads are over.
+    pub fn finish(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+            let mut active = ACTIVE_BARS.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((bars, count)) = active.as_mut() {
+                bars.remove(bar);
+                *count -= 1;
+                if *count == 0 {
+                    *active = None;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::log::debug;
+use crate::manifest::ManifestEntry;
+use crate::paths::{long_path, portable_name};
+use crate::store::Folder;
+use std::fmt::Write as _;
+use std::io;
+
+// Name of the generated file in each gist folder
+const README_FILE: &str = "README.md";
+
+/// Escapes the characters that would break a Markdown table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Renders the README of a gist: its description, dates, link and a table of its files.
+fn render(entry: &ManifestEntry) -> String {
+    let mut out = String::new();
+    let title = entry
+        .description
+        .as_deref()
+        .filter(|description| !description.trim().is_empty())
+        .unwrap_or(&entry.id);
+    let visibility = if entry.public { "public" } else { "secret" };
+    let _ = writeln!(out, "# {}\n", title.replace('\n', " "));
+    let _ = writeln!(out, "- Owner: {} ({})", entry.owner, visibility);
+    let _ = writeln!(out, "- Created: {}", entry.created_at);
+    let _ = writeln!(out, "- Updated: {}", entry.updated_at);
+    let _ = writeln!(out, "- URL: <{}>\n", entry.html_url);
+    let _ = writeln!(out, "| File | Language | Size |");
+    let _ = writeln!(out, "| --- | --- | ---: |");
+    for file in &entry.files {
+        let language = match &file.error {
+            Some(_) => "not downloaded",
+            None => file.language.as_deref().unwrap_or("-"),
+        };
+        let _ = writeln!(
+            out,
+            "| [{}](<{}>) | {} | {} |",
+            cell(&file.filename),
+            portable_name(&file.filename),
+            cell(language),
+            file.size
+        );
+    }
+    out
+}
+
+/// Writes a README describing the gist into its folder, so the archive makes sense when
+/// browsed as plain files. Left alone when the gist has a README of its own.
+pub fn write_readme(archive: &Folder, entry: &ManifestEntry) -> io::Result<()> {
+    if entry
+        .files
+        .iter()
+        .any(|f| f.filename.eq_ignore_ascii_case(README_FILE))
+    {
+        debug!(
+            "Gist {} has its own {}, not generating one",
+            entry.id, README_FILE
+        );
+        return Ok(());
+    }
+    let path = long_path(archive.root.join(&entry.id).join(README_FILE));
+    archive.check_inside(&path)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, render(entry))
+}
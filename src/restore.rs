@@ -0,0 +1,185 @@
+use crate::backup::{list_snapshots, read_object};
+use crate::gist::GistError;
+use crate::index::Index;
+use crate::log::{info, warn};
+use crate::manifest::{Manifest, MANIFEST_FILE};
+use crate::store::{self, sha256_hex, Folder, Store};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use minisign::SecretKey;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Outcome of a restore, listing the files that could not be recovered.
+#[derive(Debug, Default)]
+pub struct RestoreReport {
+    pub restored: usize,
+    pub unrecoverable: Vec<String>,
+}
+
+/// Writes the files of every gist in the manifest into the archive, validating their
+/// checksums, then merges the manifest into the archive's own, signed with `signing_key`
+/// when given, and indexes the gists.
+fn restore_files(
+    archive: &Folder,
+    signing_key: Option<&SecretKey>,
+    manifest: Manifest,
+    mut read: impl FnMut(&str, &str) -> std::io::Result<Vec<u8>>,
+) -> Result<RestoreReport, GistError> {
+    let folder = archive.root;
+    let mut report = RestoreReport::default();
+    for entry in manifest.gists.values() {
+        for file in &entry.files {
+            let name = format!("{}/{}", entry.id, file.filename);
+            let content = match read(&entry.id, &file.filename) {
+                Ok(content) => content,
+                Err(e) => {
+                    warn!("Unrecoverable {}: {}", name, e);
+                    report.unrecoverable.push(name);
+                    continue;
+                }
+            };
+            if let Some(expected) = &file.sha256 {
+                let actual = sha256_hex(&content);
+                if *expected != actual {
+                    warn!(
+                        "Unrecoverable {}: checksum mismatch (expected {}, got {})",
+                        name, expected, actual
+                    );
+                    report.unrecoverable.push(name);
+                    continue;
+                }
+            }
+            store::write_file(archive, &entry.id, &file.filename, &content, Store::Plain)?;
+            report.restored += 1;
+        }
+    }
+
+    let mut archive_manifest = Manifest::load(folder)?;
+    let mut index = Index::open(folder)?;
+    for (id, entry) in manifest.gists {
+        index.index_gist(folder, &entry)?;
+        archive_manifest.gists.insert(id, entry);
+    }
+    archive_manifest.save(folder, signing_key)?;
+
+    info!(
+        "Restored {} files into {}, {} unrecoverable",
+        report.restored,
+        folder.display(),
+        report.unrecoverable.len()
+    );
+    Ok(report)
+}
+
+/// Reads every entry of a .tar.gz or .zip export into memory, keyed by its path.
+fn read_export(from: &Path) -> Result<HashMap<String, Vec<u8>>, GistError> {
+    let mut entries = HashMap::new();
+    let is_zip = from.extension().is_some_and(|ext| ext == "zip");
+
+    if is_zip {
+        let mut zip = ZipArchive::new(File::open(from)?)?;
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i)?;
+            if file.is_dir() {
+                continue;
+            }
+            let mut content = Vec::new();
+            file.read_to_end(&mut content)?;
+            entries.insert(file.name()?.into_owned(), content);
+        }
+    } else {
+        let mut tar = tar::Archive::new(GzDecoder::new(File::open(from)?));
+        for entry in tar.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().into_owned();
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            entries.insert(name, content);
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Restores an archive from a tar.gz or zip export holding a manifest.
+///
+/// # Arguments
+/// * `from` - Path of the export
+/// * `archive` - Archive folder to restore the gists into
+/// * `signing_key` - Key the manifest of the archive is signed with, when it is
+pub fn restore_export(
+    from: &Path,
+    archive: &Folder,
+    signing_key: Option<&SecretKey>,
+) -> Result<RestoreReport, GistError> {
+    let mut entries = read_export(from)?;
+    let Some(manifest) = entries.remove(MANIFEST_FILE) else {
+        return Err(GistError::NotFound(format!(
+            "{} has no {}",
+            from.display(),
+            MANIFEST_FILE
+        )));
+    };
+    let manifest: Manifest = serde_json::from_slice(&manifest).map_err(GistError::Manifest)?;
+
+    restore_files(archive, signing_key, manifest, |id, filename| {
+        let name = format!("{}/{}", id, filename);
+        entries.remove(&name).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "missing from the backup")
+        })
+    })
+}
+
+/// Restores an archive as it was in a snapshot.
+///
+/// # Arguments
+/// * `snapshots` - Directory holding the snapshots
+/// * `at` - Use the newest snapshot taken at or before this time, the newest one when `None`
+/// * `archive` - Archive folder to restore the gists into
+/// * `signing_key` - Key the manifest of the archive is signed with, when it is
+pub fn restore_snapshot(
+    snapshots: &Path,
+    at: Option<DateTime<Utc>>,
+    archive: &Folder,
+    signing_key: Option<&SecretKey>,
+) -> Result<RestoreReport, GistError> {
+    let Some((path, mut snapshot)) = list_snapshots(snapshots)?
+        .into_iter()
+        .rev()
+        .find(|(_, s)| at.is_none_or(|at| s.created_at <= at))
+    else {
+        return Err(GistError::NotFound(format!(
+            "No snapshot found in {}",
+            snapshots.display()
+        )));
+    };
+    info!("Restoring snapshot {}", path.display());
+
+    // Snapshots always know the digest of every file, even for manifests written without one
+    for entry in snapshot.manifest.gists.values_mut() {
+        for file in &mut entry.files {
+            let name = format!("{}/{}", entry.id, file.filename);
+            if let Some(digest) = snapshot.files.get(&name) {
+                file.sha256.get_or_insert_with(|| digest.clone());
+            }
+        }
+    }
+
+    let files = snapshot.files;
+    restore_files(archive, signing_key, snapshot.manifest, |id, filename| {
+        let Some(digest) = files.get(&format!("{}/{}", id, filename)) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "missing from the backup",
+            ));
+        };
+        read_object(snapshots, digest)
+    })
+}
@@ -0,0 +1,264 @@
+use crate::gist::GistError;
+use crate::store::sha256_hex;
+use chrono::Utc;
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::{Client, Method, StatusCode, Url};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+// Region used when none is configured, also what most S3-compatible services accept
+const DEFAULT_REGION: &str = "us-east-1";
+
+#[derive(Clone)]
+struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+/// A bucket and key prefix in S3-compatible object storage, with requests signed using
+/// AWS Signature Version 4.
+#[derive(Clone)]
+pub struct S3Target {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    region: String,
+    /// Custom endpoint (MinIO, R2...), addressed path-style
+    endpoint: Option<Url>,
+    credentials: Credentials,
+}
+
+/// Reads a section of an AWS style INI file, returning its keys and values.
+fn ini_section(path: &PathBuf, section: &str) -> HashMap<String, String> {
+    let mut values = HashMap::new();
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return values;
+    };
+    let mut in_section = false;
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('#') || line.starts_with(';') || line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_section = name.trim() == section;
+        } else if let Some((key, value)) = line.split_once('=').filter(|_| in_section) {
+            values.insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    values
+}
+
+/// Returns the path of an AWS configuration file, overridable through `env`.
+fn aws_file(env: &str, name: &str) -> Option<PathBuf> {
+    std::env::var(env).map(PathBuf::from).ok().or_else(|| {
+        std::env::var("HOME")
+            .ok()
+            .map(|home| PathBuf::from(home).join(".aws").join(name))
+    })
+}
+
+/// Percent-encodes a key for use in a URI, keeping the `/` separators.
+fn uri_encode(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+impl S3Target {
+    /// Creates a target from the standard AWS environment variables, falling back to the
+    /// `~/.aws/credentials` and `~/.aws/config` files of `AWS_PROFILE`.
+    pub fn new(client: &Client, bucket: &str, prefix: &str) -> Result<Self, GistError> {
+        let profile = std::env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string());
+        let credentials_file = aws_file("AWS_SHARED_CREDENTIALS_FILE", "credentials")
+            .map(|path| ini_section(&path, &profile))
+            .unwrap_or_default();
+        let config_section = match profile.as_str() {
+            "default" => profile.clone(),
+            _ => format!("profile {}", profile),
+        };
+        let config_file = aws_file("AWS_CONFIG_FILE", "config")
+            .map(|path| ini_section(&path, &config_section))
+            .unwrap_or_default();
+        let lookup = |env: &[&str], key: &str| {
+            env.iter()
+                .find_map(|name| std::env::var(name).ok())
+                .or_else(|| credentials_file.get(key).cloned())
+                .or_else(|| config_file.get(key).cloned())
+        };
+
+        let (Some(access_key_id), Some(secret_access_key)) = (
+            lookup(&["AWS_ACCESS_KEY_ID"], "aws_access_key_id"),
+            lookup(&["AWS_SECRET_ACCESS_KEY"], "aws_secret_access_key"),
+        ) else {
+            return Err(GistError::Target(format!(
+                "no AWS credentials found for profile {}",
+                profile
+            )));
+        };
+        let endpoint = lookup(&["AWS_ENDPOINT_URL_S3", "AWS_ENDPOINT_URL"], "endpoint_url")
+            .map(|url| Url::parse(&url))
+            .transpose()
+            .map_err(|e| GistError::Target(format!("invalid endpoint URL: {}", e)))?;
+
+        Ok(S3Target {
+            client: client.clone(),
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            region: lookup(&["AWS_REGION", "AWS_DEFAULT_REGION"], "region")
+                .unwrap_or_else(|| DEFAULT_REGION.to_string()),
+            endpoint,
+            credentials: Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: lookup(&["AWS_SESSION_TOKEN"], "aws_session_token"),
+            },
+        })
+    }
+
+    /// Returns the URL of an object and the host it is signed for.
+    fn object_url(&self, key: &str) -> Result<(Url, String), GistError> {
+        let key = match self.prefix.as_str() {
+            "" => key.to_string(),
+            prefix => format!("{}/{}", prefix, key),
+        };
+        let url = match &self.endpoint {
+            Some(endpoint) => format!(
+                "{}/{}/{}",
+                endpoint.as_str().trim_end_matches('/'),
+                self.bucket,
+                uri_encode(&key)
+            ),
+            None => format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                self.bucket,
+                self.region,
+                uri_encode(&key)
+            ),
+        };
+        let url = Url::parse(&url).map_err(|e| GistError::Target(e.to_string()))?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(GistError::Target(format!("{} has no host", url))),
+        };
+        Ok((url, host))
+    }
+
+    /// Sends a request signed with AWS Signature Version 4.
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        payload: Vec<u8>,
+    ) -> Result<reqwest::Response, GistError> {
+        let (url, host) = self.object_url(key)?;
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = sha256_hex(&payload);
+
+        let mut headers = vec![
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect();
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method,
+            url.path(),
+            canonical_headers,
+            signed_headers,
+            payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+        let mut signing_key = format!("AWS4{}", self.credentials.secret_access_key).into_bytes();
+        for part in [date.as_str(), self.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac(&signing_key, part);
+        }
+        let signature: String = hmac(&signing_key, &string_to_sign)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.credentials.access_key_id, scope, signed_headers, signature
+        );
+
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("authorization", authorization);
+        // reqwest derives the host header from the URL
+        for (name, value) in headers.into_iter().filter(|(name, _)| *name != "host") {
+            request = request.header(name, value);
+        }
+        Ok(request.body(payload).send().await?)
+    }
+
+    /// Downloads an object, returning `None` when it doesn't exist.
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GistError> {
+        let response = self.send(Method::GET, key, Vec::new()).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes().await?.to_vec())),
+            status => Err(GistError::Target(format!(
+                "GET {} failed with {}: {}",
+                key,
+                status,
+                response.text().await.unwrap_or_default()
+            ))),
+        }
+    }
+
+    /// Uploads an object, replacing any existing one.
+    pub async fn put(&self, key: &str, content: Vec<u8>) -> Result<(), GistError> {
+        let response = self.send(Method::PUT, key, content).await?;
+        if !response.status().is_success() {
+            return Err(GistError::Target(format!(
+                "PUT {} failed with {}: {}",
+                key,
+                response.status(),
+                response.text().await.unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for S3Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "s3://{}/{}", self.bucket, self.prefix)
+    }
+}
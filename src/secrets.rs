@@ -0,0 +1,250 @@
+use crate::gist::GistError;
+use crate::log::debug;
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::output::Output;
+use crate::store;
+use clap::ValueEnum;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tokio::task::JoinSet;
+
+// Shannon entropy (bits per char) above which a long token is reported as a likely secret
+const ENTROPY_THRESHOLD: f64 = 4.5;
+// Shorter tokens don't carry enough characters for the entropy to mean anything
+const ENTROPY_MIN_LENGTH: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Severity::Low => "LOW",
+            Severity::Medium => "MEDIUM",
+            Severity::High => "HIGH",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+struct Detector {
+    name: &'static str,
+    severity: Severity,
+    regex: Regex,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Finding {
+    pub gist_id: String,
+    pub filename: String,
+    pub line_number: usize,
+    pub detector: &'static str,
+    pub severity: Severity,
+    pub redacted: String,
+}
+
+fn detectors() -> &'static [Detector] {
+    static DETECTORS: OnceLock<Vec<Detector>> = OnceLock::new();
+    DETECTORS.get_or_init(|| {
+        [
+            (
+                "AWS access key",
+                Severity::High,
+                r"\b(AKIA|ASIA)[0-9A-Z]{16}\b",
+            ),
+            (
+                "GitHub token",
+                Severity::High,
+                r"\bgh[pousr]_[A-Za-z0-9]{36,}\b",
+            ),
+            (
+                "GitHub fine-grained token",
+                Severity::High,
+                r"\bgithub_pat_[A-Za-z0-9_]{22,}\b",
+            ),
+            (
+                "Slack token",
+                Severity::High,
+                r"\bxox[abprs]-[A-Za-z0-9-]{10,}\b",
+            ),
+            (
+                "Google API key",
+                Severity::High,
+                r"\bAIza[0-9A-Za-z_\-]{35}\b",
+            ),
+            (
+                "Stripe live key",
+                Severity::High,
+                r"\b[sr]k_live_[0-9A-Za-z]{24,}\b",
+            ),
+            (
+                "Private key",
+                Severity::High,
+                r"-----BEGIN ([A-Z]+ )?PRIVATE KEY-----",
+            ),
+            (
+                "JSON web token",
+                Severity::Medium,
+                r"\beyJ[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}",
+            ),
+            (
+                "Credential assignment",
+                Severity::Medium,
+                r#"(?i)\b(api[_-]?key|secret|token|passw(or)?d)\b["']?\s*[:=]\s*["']?[^\s"']{8,}"#,
+            ),
+        ]
+        .into_iter()
+        .map(|(name, severity, pattern)| Detector {
+            name,
+            severity,
+            regex: Regex::new(pattern).expect("detector patterns are valid"),
+        })
+        .collect()
+    })
+}
+
+fn token_regex() -> &'static Regex {
+    static TOKEN: OnceLock<Regex> = OnceLock::new();
+    TOKEN.get_or_init(|| Regex::new(r"[A-Za-z0-9+/=_\-]{20,}").expect("token pattern is valid"))
+}
+
+/// Shannon entropy of a string in bits per character.
+fn entropy(s: &str) -> f64 {
+    let mut counts: HashMap<char, usize> = HashMap::new();
+    for c in s.chars() {
+        *counts.entry(c).or_default() += 1;
+    }
+    let len = s.chars().count() as f64;
+    counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Keeps just enough of a match to recognize it without printing the secret itself.
+fn redact(s: &str) -> String {
+    let prefix: String = s.chars().take(6).collect();
+    format!("{}...", prefix)
+}
+
+fn scan_line(line: &str) -> Vec<(&'static str, Severity, String)> {
+    let mut findings = Vec::new();
+    for detector in detectors() {
+        if let Some(m) = detector.regex.find(line) {
+            findings.push((detector.name, detector.severity, redact(m.as_str())));
+        }
+    }
+    // Only fall back to entropy when no specific detector recognized the line
+    if findings.is_empty() {
+        for m in token_regex().find_iter(line) {
+            if m.as_str().len() >= ENTROPY_MIN_LENGTH && entropy(m.as_str()) > ENTROPY_THRESHOLD {
+                findings.push(("High entropy string", Severity::Low, redact(m.as_str())));
+            }
+        }
+    }
+    findings
+}
+
+fn scan_gist(folder: &Path, entry: &ManifestEntry) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for file in &entry.files {
+        let content = match store::read_file(folder, &entry.id, &file.filename) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) => {
+                debug!("Skipping {}/{}: {}", entry.id, file.filename, e);
+                continue;
+            }
+        };
+        for (n, line) in content.lines().enumerate() {
+            for (detector, severity, redacted) in scan_line(line) {
+                findings.push(Finding {
+                    gist_id: entry.id.clone(),
+                    filename: file.filename.clone(),
+                    line_number: n + 1,
+                    detector,
+                    severity,
+                    redacted,
+                });
+            }
+        }
+    }
+    findings
+}
+
+/// Scans every file of a local archive for likely credentials, one task per gist.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `min_severity` - Findings below this severity are not reported
+/// * `output` - Where the findings are reported
+///
+/// Returns the reported findings, most severe first.
+pub async fn scan_secrets(
+    folder: &str,
+    min_severity: Severity,
+    output: &Output,
+) -> Result<Vec<Finding>, GistError> {
+    let folder = PathBuf::from(folder);
+    let manifest = Manifest::load(&folder)?;
+    let descriptions: HashMap<String, String> = manifest
+        .gists
+        .values()
+        .map(|g| {
+            let description = g.description.as_deref().unwrap_or("<no description>");
+            (g.id.clone(), description.to_string())
+        })
+        .collect();
+
+    let mut scan_set = JoinSet::new();
+    for entry in manifest.gists.into_values() {
+        let folder = folder.clone();
+        scan_set.spawn_blocking(move || scan_gist(&folder, &entry));
+    }
+
+    let mut findings = Vec::new();
+    while let Some(res) = scan_set.join_next().await {
+        let gist_findings = res.map_err(std::io::Error::other)?;
+        findings.extend(
+            gist_findings
+                .into_iter()
+                .filter(|f| f.severity >= min_severity),
+        );
+    }
+    findings.sort_by(|a, b| {
+        b.severity
+            .cmp(&a.severity)
+            .then_with(|| a.gist_id.cmp(&b.gist_id))
+            .then_with(|| a.filename.cmp(&b.filename))
+            .then_with(|| a.line_number.cmp(&b.line_number))
+    });
+
+    output.write(&findings, |findings| {
+        for f in findings {
+            println!(
+                "{} {}/{}:{} {} {} ({})",
+                f.severity,
+                f.gist_id,
+                f.filename,
+                f.line_number,
+                f.detector,
+                f.redacted,
+                descriptions[&f.gist_id]
+            );
+        }
+        println!("{} likely secrets found", findings.len());
+    })?;
+
+    Ok(findings)
+}
@@ -0,0 +1,165 @@
+use crate::gist::{self, GistError, ReleaseAsset};
+use crate::log::info;
+use crate::store;
+use flate2::read::GzDecoder;
+use reqwest::Client;
+use std::io::Read;
+use std::path::Path;
+
+// Repository the releases are published in
+const RELEASE_REPO: &str = "l1x/local-gist";
+// Names the binary may have inside a release archive
+const BINARY_NAMES: &[&str] = &[
+    "local-gist",
+    "local_gist",
+    "local-gist.exe",
+    "local_gist.exe",
+];
+
+/// Splits a version like `v1.2.3` into its numbers, for comparing.
+fn version_numbers(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// Picks the release asset built for this platform, the one naming both its OS and
+/// architecture, leaving out checksum files.
+fn platform_asset(assets: &[ReleaseAsset]) -> Option<&ReleaseAsset> {
+    let os = match std::env::consts::OS {
+        "macos" => vec!["macos", "darwin", "apple"],
+        "windows" => vec!["windows", "win"],
+        os => vec![os],
+    };
+    let arch = match std::env::consts::ARCH {
+        "x86_64" => vec!["x86_64", "amd64"],
+        "aarch64" => vec!["aarch64", "arm64"],
+        arch => vec![arch],
+    };
+    assets.iter().find(|asset| {
+        let name = asset.name.to_lowercase();
+        !name.ends_with(".sha256")
+            && !name.contains("sha256sums")
+            && os.iter().any(|os| name.contains(os))
+            && arch.iter().any(|arch| name.contains(arch))
+    })
+}
+
+/// Finds the published SHA-256 of an asset, from `<asset>.sha256` or a `SHA256SUMS` file.
+async fn published_checksum(
+    client: &Client,
+    assets: &[ReleaseAsset],
+    asset: &ReleaseAsset,
+) -> Result<String, GistError> {
+    let sums = assets
+        .iter()
+        .find(|a| a.name == format!("{}.sha256", asset.name))
+        .or_else(|| {
+            assets
+                .iter()
+                .find(|a| a.name.to_lowercase().contains("sha256sums"))
+        })
+        .ok_or_else(|| GistError::NotFound(format!("checksum of {}", asset.name)))?;
+    let sums = String::from_utf8_lossy(&gist::fetch_asset(client, sums).await?).into_owned();
+    // Either a bare digest or `<digest>  <name>` lines as written by sha256sum
+    sums.lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let digest = parts.next()?;
+            match parts.next() {
+                Some(name) if name.trim_start_matches('*') != asset.name => None,
+                _ => Some(digest.to_lowercase()),
+            }
+        })
+        .next()
+        .ok_or_else(|| GistError::NotFound(format!("checksum of {}", asset.name)))
+}
+
+/// Returns the binary out of a release asset, unpacking it from a .tar.gz archive.
+fn unpack(name: &str, content: Vec<u8>) -> Result<Vec<u8>, GistError> {
+    if !(name.ends_with(".tar.gz") || name.ends_with(".tgz")) {
+        return Ok(content);
+    }
+    let mut archive = tar::Archive::new(GzDecoder::new(content.as_slice()));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let is_binary = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| BINARY_NAMES.contains(&name));
+        if is_binary {
+            let mut binary = Vec::new();
+            entry.read_to_end(&mut binary)?;
+            return Ok(binary);
+        }
+    }
+    Err(GistError::NotFound(format!("binary in {}", name)))
+}
+
+/// Puts the new binary in place of the running one, through a file next to it so the
+/// swap is a rename. Windows can't replace a running binary, it is moved aside first.
+fn replace_binary(binary: &[u8]) -> Result<(), GistError> {
+    let current = std::env::current_exe()?;
+    let dir = current.parent().unwrap_or(Path::new("."));
+    let staged = dir.join(".local-gist.update");
+    std::fs::write(&staged, binary)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(
+            &staged,
+            std::fs::Permissions::from_mode(store::EXECUTABLE_MODE),
+        )?;
+    }
+    #[cfg(windows)]
+    std::fs::rename(&current, current.with_extension("old"))?;
+    std::fs::rename(&staged, &current)?;
+    Ok(())
+}
+
+/// Updates the running binary to the latest release when it is newer, after checking
+/// the downloaded artifact against its published SHA-256.
+///
+/// # Arguments
+/// * `check` - Only report whether an update is available
+pub async fn self_update(client: &Client, check: bool) -> Result<(), GistError> {
+    let current = env!("CARGO_PKG_VERSION");
+    let release = gist::latest_release(client, RELEASE_REPO).await?;
+    if version_numbers(&release.tag_name) <= version_numbers(current) {
+        info!("local-gist {} is up to date", current);
+        return Ok(());
+    }
+    if check {
+        info!(
+            "local-gist {} is available, {} is installed",
+            release.tag_name, current
+        );
+        return Ok(());
+    }
+
+    let asset = platform_asset(&release.assets).ok_or_else(|| {
+        GistError::NotFound(format!(
+            "release asset for {}-{} in {}",
+            std::env::consts::OS,
+            std::env::consts::ARCH,
+            release.tag_name
+        ))
+    })?;
+    let expected = published_checksum(client, &release.assets, asset).await?;
+    info!("Downloading {}", asset.name);
+    let content = gist::fetch_asset(client, asset).await?;
+    let actual = store::sha256_hex(&content);
+    if actual != expected {
+        return Err(GistError::Checksum(format!(
+            "{} has SHA-256 {}, the release publishes {}",
+            asset.name, actual, expected
+        )));
+    }
+
+    replace_binary(&unpack(&asset.name, content)?)?;
+    info!("Updated local-gist {} to {}", current, release.tag_name);
+    Ok(())
+}
@@ -0,0 +1,298 @@
+use crate::avatars;
+use crate::comments;
+use crate::gist::GistError;
+use crate::highlight::{escape_html, to_html};
+use crate::log::{error, info};
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::store;
+use axum::extract::{Path as UrlPath, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+struct AppState {
+    folder: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListQuery {
+    q: Option<String>,
+    language: Option<String>,
+}
+
+/// Serves a read-only web UI for browsing a local archive on localhost.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `port` - Port to listen on
+pub async fn serve(folder: &str, port: u16) -> Result<(), GistError> {
+    let state = Arc::new(AppState {
+        folder: PathBuf::from(folder),
+    });
+
+    let app = Router::new()
+        .route("/", get(list_page))
+        .route("/gists/{id}", get(gist_page))
+        .route("/api/gists", get(api_list))
+        .route("/api/gists/{id}", get(api_gist))
+        .route("/api/gists/{id}/files/{name}", get(api_file))
+        .route("/avatars/{name}", get(avatar))
+        .with_state(state);
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    info!("Serving {} on http://{}", folder, addr);
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+// The manifest is re-read on every request so a running download shows up without a restart
+fn load_manifest(state: &AppState) -> Result<Manifest, (StatusCode, String)> {
+    Manifest::load(&state.folder).map_err(|e| {
+        error!("Failed to load manifest: {}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })
+}
+
+fn matches_query(entry: &ManifestEntry, query: &str) -> bool {
+    let query = query.to_lowercase();
+    entry.id.contains(&query)
+        || entry
+            .description
+            .as_deref()
+            .is_some_and(|d| d.to_lowercase().contains(&query))
+        || entry
+            .files
+            .iter()
+            .any(|f| f.filename.to_lowercase().contains(&query))
+}
+
+/// Returns the gists matching the search and language filters, most recently updated first.
+fn filter_gists<'a>(manifest: &'a Manifest, query: &ListQuery) -> Vec<&'a ManifestEntry> {
+    let search = query.q.as_deref().unwrap_or_default();
+    let language = query.language.as_deref().unwrap_or_default();
+
+    let mut gists: Vec<&ManifestEntry> = manifest
+        .gists
+        .values()
+        .filter(|g| search.is_empty() || matches_query(g, search))
+        .filter(|g| language.is_empty() || g.languages().contains(&language))
+        .collect();
+    gists.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+    gists
+}
+
+fn find_gist<'a>(
+    manifest: &'a Manifest,
+    id: &str,
+) -> Result<&'a ManifestEntry, (StatusCode, String)> {
+    manifest
+        .gists
+        .get(id)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Gist not found".to_string()))
+}
+
+// Image of the cached avatar of `login`, nothing when it wasn't downloaded with --avatars
+fn avatar_img(state: &AppState, login: &str, size: u32, shown: u32) -> String {
+    avatars::cached(&state.folder, login, size).map_or(String::new(), |name| {
+        format!(
+            r#"<img class="avatar" src="/avatars/{}" alt="" width="{1}" height="{1}"> "#,
+            escape_html(&name),
+            shown
+        )
+    })
+}
+
+fn page(title: &str, body: &str) -> Html<String> {
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{}</title>
+<style>
+body {{ font-family: sans-serif; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; }}
+li {{ margin: 0.4rem 0; }}
+pre {{ padding: 0.8rem; overflow-x: auto; border: 1px solid #ddd; }}
+.meta {{ color: #666; font-size: 0.9rem; }}
+.comment {{ border-top: 1px solid #ddd; padding: 0.4rem 0; }}
+.avatar {{ vertical-align: middle; border-radius: 50%; }}
+</style>
+</head>
+<body>
+{}
+</body>
+</html>"#,
+        escape_html(title),
+        body
+    ))
+}
+
+async fn list_page(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let manifest = load_manifest(&state)?;
+    let gists = filter_gists(&manifest, &query);
+
+    let search = query.q.unwrap_or_default();
+    let language = query.language.unwrap_or_default();
+
+    let mut languages: Vec<&str> = manifest
+        .gists
+        .values()
+        .flat_map(|g| g.languages())
+        .collect();
+    languages.sort_unstable();
+    languages.dedup();
+
+    let mut options = String::from(r#"<option value="">All languages</option>"#);
+    for l in languages {
+        let selected = if l == language { " selected" } else { "" };
+        options.push_str(&format!(
+            r#"<option value="{0}"{1}>{0}</option>"#,
+            escape_html(l),
+            selected
+        ));
+    }
+
+    let mut items = String::new();
+    for g in &gists {
+        items.push_str(&format!(
+            r#"<li>{}<a href="/gists/{}">{}</a> <span class="meta">{} &middot; {}</span></li>"#,
+            avatar_img(&state, &g.owner, avatars::SMALL, 20),
+            escape_html(&g.id),
+            escape_html(g.description.as_deref().unwrap_or("<no description>")),
+            escape_html(
+                &g.files
+                    .iter()
+                    .map(|f| f.filename.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            escape_html(&g.updated_at)
+        ));
+    }
+
+    let body = format!(
+        r#"<h1>Gists</h1>
+<form method="get" action="/">
+<input type="search" name="q" value="{}" placeholder="Search descriptions and filenames">
+<select name="language">{}</select>
+<button type="submit">Filter</button>
+</form>
+<p class="meta">{} of {} gists</p>
+<ul>{}</ul>"#,
+        escape_html(&search),
+        options,
+        gists.len(),
+        manifest.gists.len(),
+        items
+    );
+
+    Ok(page("Gists", &body))
+}
+
+async fn gist_page(
+    State(state): State<Arc<AppState>>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Html<String>, (StatusCode, String)> {
+    let manifest = load_manifest(&state)?;
+    let gist = find_gist(&manifest, &id)?;
+
+    let description = gist.description.as_deref().unwrap_or("<no description>");
+    let mut body = format!(
+        r#"<p><a href="/">&larr; All gists</a></p>
+<h1>{}</h1>
+<p class="meta">{}{} &middot; {} &middot; created {} &middot; updated {} &middot; <a href="{}">GitHub</a></p>"#,
+        escape_html(description),
+        avatar_img(&state, &gist.owner, avatars::LARGE, 60),
+        escape_html(&gist.owner),
+        if gist.public { "public" } else { "secret" },
+        escape_html(&gist.created_at),
+        escape_html(&gist.updated_at),
+        escape_html(&gist.html_url)
+    );
+
+    // Only files recorded in the manifest are read, so the URL can't point outside the archive
+    for file in &gist.files {
+        let content = match store::read_file(&state.folder, &gist.id, &file.filename) {
+            Ok(bytes) => to_html(&file.filename, &String::from_utf8_lossy(&bytes)),
+            Err(e) => format!(
+                r#"<p class="meta">Could not read {}: {}</p>"#,
+                escape_html(&file.filename),
+                escape_html(&e.to_string())
+            ),
+        };
+        body.push_str(&format!(
+            "<h2>{}</h2>\n{}",
+            escape_html(&file.filename),
+            content
+        ));
+    }
+
+    if let Some(thread) = comments::load(&state.folder, &gist.id).filter(|c| !c.is_empty()) {
+        body.push_str(&format!(
+            "<h2>Comments</h2>\n{}",
+            comments::render_thread(&thread, |login| {
+                avatars::cached(&state.folder, login, avatars::SMALL)
+                    .map(|name| format!("/avatars/{}", name))
+            })
+        ));
+    }
+
+    Ok(page(description, &body))
+}
+
+async fn api_list(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<ManifestEntry>>, (StatusCode, String)> {
+    let manifest = load_manifest(&state)?;
+    let gists = filter_gists(&manifest, &query)
+        .into_iter()
+        .cloned()
+        .collect();
+    Ok(Json(gists))
+}
+
+async fn api_gist(
+    State(state): State<Arc<AppState>>,
+    UrlPath(id): UrlPath<String>,
+) -> Result<Json<ManifestEntry>, (StatusCode, String)> {
+    let manifest = load_manifest(&state)?;
+    Ok(Json(find_gist(&manifest, &id)?.clone()))
+}
+
+async fn api_file(
+    State(state): State<Arc<AppState>>,
+    UrlPath((id, name)): UrlPath<(String, String)>,
+) -> Result<Vec<u8>, (StatusCode, String)> {
+    let manifest = load_manifest(&state)?;
+    let gist = find_gist(&manifest, &id)?;
+    let Some(file) = gist.files.iter().find(|f| f.filename == name) else {
+        return Err((StatusCode::NOT_FOUND, "File not found".to_string()));
+    };
+
+    store::read_file(&state.folder, &gist.id, &file.filename).map_err(|e| {
+        error!("Failed to read {}/{}: {}", gist.id, file.filename, e);
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+    })
+}
+
+// Only names avatars are cached under are served, so the URL can't point outside the cache
+async fn avatar(
+    State(state): State<Arc<AppState>>,
+    UrlPath(name): UrlPath<String>,
+) -> Result<([(header::HeaderName, &'static str); 1], Vec<u8>), (StatusCode, String)> {
+    let not_found = || (StatusCode::NOT_FOUND, "Avatar not found".to_string());
+    let (path, content_type) = avatars::lookup(&state.folder, &name).ok_or_else(not_found)?;
+    let content = std::fs::read(path).map_err(|_| not_found())?;
+    Ok(([(header::CONTENT_TYPE, content_type)], content))
+}
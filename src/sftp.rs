@@ -0,0 +1,226 @@
+use crate::gist::GistError;
+use crate::spool;
+use crate::store::sha256_hex;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+// What sftp says when the file asked for doesn't exist
+const NOT_FOUND_MESSAGES: [&str; 2] = ["not found", "No such file"];
+
+/// A directory on a remote server, written over SFTP with the system `sftp` client so the
+/// usual keys, agent and `~/.ssh/config` apply, and servers only allowing SFTP work too.
+pub struct SftpTarget {
+    /// `user@host` or just `host`
+    destination: String,
+    port: Option<u16>,
+    /// Directory holding the archive on the server
+    path: String,
+}
+
+/// A local file holding what is sent to or read from the server, deleted when dropped.
+struct LocalFile(PathBuf);
+
+impl LocalFile {
+    fn new() -> Self {
+        let name = format!("local_gist-sftp-{}", spool::unique_name());
+        LocalFile(std::env::temp_dir().join(name))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for LocalFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Quotes a path for a command of an sftp batch, escaping quotes and backslashes, and
+/// the glob characters `get` and `put` would expand.
+fn quote(path: &str) -> String {
+    let mut quoted = String::with_capacity(path.len() + 2);
+    quoted.push('"');
+    for c in path.chars() {
+        if matches!(c, '"' | '\\' | '*' | '?' | '[' | ']') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl SftpTarget {
+    /// Creates a target from the `user@host[:port]/path` part of an `sftp://` URL. The path
+    /// is absolute, use `/~/path` for a directory below the home of the user.
+    pub fn new(location: &str) -> Result<Self, GistError> {
+        let (authority, path) = location.split_once('/').unwrap_or((location, ""));
+        let (destination, port) = match authority.rsplit_once(':') {
+            Some((destination, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| GistError::Target(format!("invalid port {}", port)))?;
+                (destination, Some(port))
+            }
+            None => (authority, None),
+        };
+        if destination.is_empty() {
+            return Err(GistError::Target(format!("{} has no host", location)));
+        }
+        let path = match path.strip_prefix('~') {
+            Some(home) => home.trim_matches('/').to_string(),
+            None => format!("/{}", path.trim_end_matches('/')),
+        };
+
+        Ok(SftpTarget {
+            destination: destination.to_string(),
+            port,
+            path,
+        })
+    }
+
+    fn remote_path(&self, key: &str) -> String {
+        match self.path.as_str() {
+            "" => key.to_string(),
+            path => format!("{}/{}", path.trim_end_matches('/'), key),
+        }
+    }
+
+    /// Runs sftp commands on the server, one per line, stopping at the first failing one
+    /// unless it starts with `-`.
+    async fn run(&self, commands: &[String]) -> Result<std::process::Output, GistError> {
+        let mut sftp = Command::new("sftp");
+        // Never wait for a password prompt, the transfers run unattended
+        sftp.args(["-q", "-o", "BatchMode=yes", "-b", "-"]);
+        if let Some(port) = self.port {
+            sftp.arg("-P").arg(port.to_string());
+        }
+        let mut child = sftp
+            .arg(&self.destination)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin.write_all(commands.join("\n").as_bytes()).await?;
+        stdin.write_all(b"\n").await?;
+        drop(stdin);
+        Ok(child.wait_with_output().await?)
+    }
+
+    fn failure(&self, action: &str, key: &str, output: &std::process::Output) -> GistError {
+        GistError::Target(format!(
+            "{} {} on {} failed: {}",
+            action,
+            key,
+            self.destination,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+
+    /// Fails on keys a batch line can't hold.
+    fn check_key(&self, key: &str) -> Result<(), GistError> {
+        match key.contains(['\n', '\r']) {
+            true => Err(GistError::Target(format!(
+                "{:?} can't be written over SFTP",
+                key
+            ))),
+            false => Ok(()),
+        }
+    }
+
+    /// Downloads a file, returning `None` when it doesn't exist.
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GistError> {
+        self.check_key(key)?;
+        let local = LocalFile::new();
+        let local_path = local.path().to_string_lossy();
+        let command = format!(
+            "get {} {}",
+            quote(&self.remote_path(key)),
+            quote(&local_path)
+        );
+        let output = self.run(&[command]).await?;
+        if output.status.success() {
+            return Ok(Some(std::fs::read(local.path())?));
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match NOT_FOUND_MESSAGES
+            .iter()
+            .any(|message| stderr.contains(message))
+        {
+            true => Ok(None),
+            false => Err(self.failure("Reading", key, &output)),
+        }
+    }
+
+    /// Uploads a file to a temporary file next to it, reads it back to check it has the
+    /// SHA-256 of the bytes sent, and only then renames it over the old one, so readers
+    /// never see a partial or corrupted file.
+    pub async fn put(&self, key: &str, content: Vec<u8>) -> Result<(), GistError> {
+        self.check_key(key)?;
+        let remote = self.remote_path(key);
+        let tmp = format!("{}.{}.tmp", remote, spool::unique_name());
+        let sent = LocalFile::new();
+        std::fs::write(sent.path(), &content)?;
+        let received = LocalFile::new();
+
+        // Every parent, the existing ones failing harmlessly
+        let mut commands: Vec<String> = remote
+            .match_indices('/')
+            .map(|(end, _)| &remote[..end])
+            .filter(|parent| !parent.is_empty())
+            .map(|parent| format!("-mkdir {}", quote(parent)))
+            .collect();
+        commands.push(format!(
+            "put {} {}",
+            quote(&sent.path().to_string_lossy()),
+            quote(&tmp)
+        ));
+        commands.push(format!(
+            "get {} {}",
+            quote(&tmp),
+            quote(&received.path().to_string_lossy())
+        ));
+        let output = self.run(&commands).await?;
+        if !output.status.success() {
+            let _ = self.run(&[format!("-rm {}", quote(&tmp))]).await;
+            return Err(self.failure("Writing", key, &output));
+        }
+
+        let expected = sha256_hex(&content);
+        let actual = sha256_hex(&std::fs::read(received.path())?);
+        if actual != expected {
+            let _ = self.run(&[format!("-rm {}", quote(&tmp))]).await;
+            return Err(GistError::Target(format!(
+                "{} on {} has SHA-256 {} instead of {}",
+                key, self.destination, actual, expected
+            )));
+        }
+
+        let output = self
+            .run(&[format!("rename {} {}", quote(&tmp), quote(&remote))])
+            .await?;
+        if !output.status.success() {
+            let _ = self.run(&[format!("-rm {}", quote(&tmp))]).await;
+            return Err(self.failure("Renaming", key, &output));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for SftpTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sftp://{}", self.destination)?;
+        if let Some(port) = self.port {
+            write!(f, ":{}", port)?;
+        }
+        match self.path.starts_with('/') {
+            true => write!(f, "{}", self.path),
+            false => write!(f, "/~/{}", self.path),
+        }
+    }
+}
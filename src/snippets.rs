@@ -0,0 +1,256 @@
+use crate::filter::GistFilter;
+use crate::gist::GistError;
+use crate::highlight::escape_html;
+use crate::log::{debug, info};
+use crate::manifest::{Manifest, ManifestEntry, ManifestFile};
+use crate::store;
+use clap::ValueEnum;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::Path;
+
+// Longest snippet prefix or abbreviation made from a description, in characters
+const MAX_PREFIX_LEN: usize = 40;
+// Group of the JetBrains live templates
+const TEMPLATE_GROUP: &str = "Gists";
+
+// GitHub language names whose VS Code language id isn't the lowercase name, other
+// languages use it with spaces as hyphens
+const VSCODE_LANGUAGES: [(&str, &str); 9] = [
+    ("Shell", "shellscript"),
+    ("C++", "cpp"),
+    ("C#", "csharp"),
+    ("F#", "fsharp"),
+    ("Batchfile", "bat"),
+    ("TeX", "latex"),
+    ("Vim Script", "viml"),
+    ("Text", "plaintext"),
+    ("Jupyter Notebook", "jupyter"),
+];
+
+// Contexts of JetBrains live templates by GitHub language name, templates of other
+// languages are available everywhere
+const JETBRAINS_CONTEXTS: [(&str, &str); 18] = [
+    ("Python", "Python"),
+    ("Java", "JAVA_CODE"),
+    ("Kotlin", "KOTLIN"),
+    ("JavaScript", "JAVASCRIPT"),
+    ("TypeScript", "TYPE_SCRIPT"),
+    ("Shell", "SHELL_SCRIPT"),
+    ("Go", "GO"),
+    ("Rust", "RUST_FILE"),
+    ("PHP", "PHP"),
+    ("Ruby", "RUBY"),
+    ("HTML", "HTML"),
+    ("CSS", "CSS"),
+    ("SQL", "SQL"),
+    ("XML", "XML"),
+    ("JSON", "JSON"),
+    ("YAML", "YAML"),
+    ("Markdown", "MARKDOWN"),
+    ("C++", "OC_SOURCE_FILE"),
+];
+const JETBRAINS_OTHER_CONTEXT: &str = "OTHER";
+
+/// Editor whose snippet format `export snippets` writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Editor {
+    /// A `.code-snippets` file of VS Code
+    Vscode,
+    /// A live template group of IntelliJ IDEA and the other JetBrains IDEs
+    Jetbrains,
+}
+
+/// A gist made into a snippet.
+struct Snippet {
+    id: String,
+    name: String,
+    prefix: String,
+    description: String,
+    language: Option<String>,
+    body: String,
+}
+
+/// Returns what is typed to insert the snippet: the description, or the file name without
+/// its extension, lowercase with words joined by hyphens.
+fn prefix(entry: &ManifestEntry, file: &ManifestFile) -> String {
+    let stem = Path::new(&file.filename)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let source = entry
+        .description
+        .as_deref()
+        .filter(|description| description.chars().any(char::is_alphanumeric))
+        .unwrap_or(&stem);
+    let words: Vec<String> = source
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+    let prefix: String = words.join("-").chars().take(MAX_PREFIX_LEN).collect();
+    match prefix.trim_end_matches('-') {
+        "" => entry.id.clone(),
+        prefix => prefix.to_string(),
+    }
+}
+
+/// Returns the values used more than once.
+fn repeated<'a>(values: impl Iterator<Item = &'a str>) -> HashSet<String> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    values
+        .filter(|value| !seen.insert(value))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the snippets of the gists made of a single text file in the archive, named after
+/// their description. The gist id is added to names and prefixes several gists share.
+fn collect(folder: &Path, manifest: &Manifest) -> Vec<Snippet> {
+    let mut snippets = Vec::new();
+    for entry in manifest.gists.values() {
+        let [file] = entry.files.as_slice() else {
+            debug!("Skipping gist {}, it has several files", entry.id);
+            continue;
+        };
+        if file.binary {
+            continue;
+        }
+        let Ok(content) = store::read_file(folder, &entry.id, &file.filename) else {
+            debug!("Skipping gist {}, its file isn't in the archive", entry.id);
+            continue;
+        };
+        let description = entry
+            .description
+            .as_deref()
+            .map(|description| description.trim().replace('\n', " "))
+            .unwrap_or_default();
+        snippets.push(Snippet {
+            id: entry.id.clone(),
+            name: match description.is_empty() {
+                true => file.filename.clone(),
+                false => description.clone(),
+            },
+            prefix: prefix(entry, file),
+            description,
+            language: file.language.clone(),
+            body: String::from_utf8_lossy(&content).trim_end().to_string(),
+        });
+    }
+
+    let names = repeated(snippets.iter().map(|snippet| snippet.name.as_str()));
+    let prefixes = repeated(snippets.iter().map(|snippet| snippet.prefix.as_str()));
+    for snippet in &mut snippets {
+        if names.contains(&snippet.name) {
+            snippet.name = format!("{} ({})", snippet.name, snippet.id);
+        }
+        if prefixes.contains(&snippet.prefix) {
+            snippet.prefix = format!("{}-{}", snippet.prefix, snippet.id);
+        }
+    }
+    snippets
+}
+
+fn vscode_language(language: &str) -> String {
+    VSCODE_LANGUAGES
+        .iter()
+        .find(|(name, _)| *name == language)
+        .map_or_else(
+            || language.to_lowercase().replace(' ', "-"),
+            |(_, id)| id.to_string(),
+        )
+}
+
+/// Renders the snippets as a VS Code `.code-snippets` file, each scoped to the language of
+/// its gist. `$` and `\` are escaped so the bodies are inserted as they are.
+fn render_vscode(snippets: &[Snippet]) -> Result<String, GistError> {
+    let mut file = Map::new();
+    for snippet in snippets {
+        let body: Vec<String> = snippet
+            .body
+            .lines()
+            .map(|line| line.replace('\\', "\\\\").replace('$', "\\$"))
+            .collect();
+        let mut definition = Map::new();
+        if let Some(language) = &snippet.language {
+            definition.insert("scope".to_string(), json!(vscode_language(language)));
+        }
+        definition.insert("prefix".to_string(), json!(snippet.prefix));
+        definition.insert("body".to_string(), json!(body));
+        definition.insert("description".to_string(), json!(snippet.description));
+        file.insert(snippet.name.clone(), Value::Object(definition));
+    }
+    Ok(serde_json::to_string_pretty(&file).map_err(std::io::Error::other)?)
+}
+
+/// Escapes an XML attribute value, keeping line breaks as the JetBrains IDEs write them.
+fn xml_attribute(value: &str) -> String {
+    escape_html(value).replace('\n', "&#10;")
+}
+
+/// Renders the snippets as a JetBrains live template group, each available in the context
+/// of the language of its gist. `$` is doubled so the bodies are inserted as they are.
+fn render_jetbrains(snippets: &[Snippet]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, r#"<templateSet group="{}">"#, TEMPLATE_GROUP);
+    for snippet in snippets {
+        let context = snippet
+            .language
+            .as_deref()
+            .and_then(|language| {
+                JETBRAINS_CONTEXTS
+                    .iter()
+                    .find(|(name, _)| *name == language)
+            })
+            .map_or(JETBRAINS_OTHER_CONTEXT, |(_, context)| context);
+        let _ = writeln!(
+            out,
+            r#"  <template name="{}" value="{}" description="{}" toReformat="false" toShortenFQNames="false">"#,
+            xml_attribute(&snippet.prefix),
+            xml_attribute(&snippet.body.replace('$', "$$")),
+            xml_attribute(&snippet.name)
+        );
+        let _ = writeln!(out, "    <context>");
+        let _ = writeln!(out, r#"      <option name="{}" value="true" />"#, context);
+        let _ = writeln!(out, "    </context>");
+        let _ = writeln!(out, "  </template>");
+    }
+    let _ = writeln!(out, "</templateSet>");
+    out
+}
+
+/// Converts the gists made of a single text file into snippets of an editor: a VS Code
+/// `.code-snippets` file, or a JetBrains live template group to put in the `templates`
+/// folder of the IDE configuration. Gists with several files are skipped.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `out` - Path of the snippet file to create
+/// * `editor` - Editor whose format is written
+/// * `filter` - Selects the gists to export
+///
+/// Returns the number of exported snippets.
+pub fn export_snippets(
+    folder: &str,
+    out: &str,
+    editor: Editor,
+    filter: &GistFilter,
+) -> Result<usize, GistError> {
+    let mut manifest = Manifest::load(Path::new(folder))?;
+    manifest.gists.retain(|_, entry| filter.matches_gist(entry));
+    let snippets = collect(Path::new(folder), &manifest);
+    let content = match editor {
+        Editor::Vscode => render_vscode(&snippets)?,
+        Editor::Jetbrains => render_jetbrains(&snippets),
+    };
+    std::fs::write(out, content)?;
+    info!(
+        "Exported {} of {} gists as snippets to {}",
+        snippets.len(),
+        manifest.gists.len(),
+        out
+    );
+
+    Ok(snippets.len())
+}
@@ -0,0 +1,239 @@
+use crate::gist;
+use crate::paths::long_path;
+use crate::store::Eol;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Directory inside the archive where files are written while they download, so only
+// complete files are moved into place
+pub const SPOOL_DIR: &str = ".partial";
+// Size of the chunks spooled files are read back in
+const CHUNK_LEN: usize = 64 * 1024;
+
+// Numbers the temporary files of the run, which downloads concurrently
+static NEXT: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a name for a temporary file that no other write of this or another run uses.
+pub fn unique_name() -> String {
+    format!(
+        "{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// A file being downloaded into the archive chunk by chunk, so its content is never held in
+/// memory as a whole. Its digest, start and whether it is valid UTF-8 are kept as it goes.
+/// The file is deleted when the download doesn't complete.
+pub struct Spool {
+    path: PathBuf,
+    file: BufWriter<File>,
+    hasher: Sha256,
+    len: u64,
+    head: Vec<u8>,
+    utf8: bool,
+    // Start of a UTF-8 sequence cut by the end of the last chunk
+    partial_char: Vec<u8>,
+}
+
+impl Spool {
+    /// Creates an empty spooled file in the archive folder.
+    pub fn create(folder: &Path) -> io::Result<Spool> {
+        let dir = long_path(folder.join(SPOOL_DIR));
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(unique_name());
+        Ok(Spool {
+            file: BufWriter::new(File::create(&path)?),
+            path,
+            hasher: Sha256::new(),
+            len: 0,
+            head: Vec::new(),
+            utf8: true,
+            partial_char: Vec::new(),
+        })
+    }
+
+    /// Appends a chunk of the content.
+    pub fn write(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.file.write_all(chunk)?;
+        self.hasher.update(chunk);
+        self.len += chunk.len() as u64;
+        let missing = gist::BINARY_SNIFF_LEN.saturating_sub(self.head.len());
+        self.head
+            .extend_from_slice(&chunk[..chunk.len().min(missing)]);
+        if self.utf8 {
+            let checked = match self.partial_char.is_empty() {
+                true => Cow::Borrowed(chunk),
+                false => {
+                    let mut bytes = std::mem::take(&mut self.partial_char);
+                    bytes.extend_from_slice(chunk);
+                    Cow::Owned(bytes)
+                }
+            };
+            match std::str::from_utf8(&checked) {
+                Ok(_) => {}
+                // Only cut at the end of the chunk, the next one completes it
+                Err(e) if e.error_len().is_none() => {
+                    self.partial_char = checked[e.valid_up_to()..].to_vec();
+                }
+                Err(_) => self.utf8 = false,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes what is left to the disk once the whole content was written.
+    pub fn finish(mut self) -> io::Result<Spooled> {
+        self.file.flush()?;
+        Ok(Spooled {
+            path: std::mem::take(&mut self.path),
+            sha256: hex(&std::mem::take(&mut self.hasher).finalize()),
+            len: self.len,
+            head: std::mem::take(&mut self.head),
+            utf8: self.utf8 && self.partial_char.is_empty(),
+        })
+    }
+}
+
+impl Drop for Spool {
+    fn drop(&mut self) {
+        // Emptied by `finish`
+        if !self.path.as_os_str().is_empty() {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The complete content of a downloaded file, spooled in the archive until it is moved or
+/// encoded into place. Deleted when dropped otherwise.
+pub struct Spooled {
+    path: PathBuf,
+    pub sha256: String,
+    pub len: u64,
+    head: Vec<u8>,
+    utf8: bool,
+}
+
+impl Spooled {
+    /// Tells whether the content is binary, like `gist::is_binary` for content in memory.
+    pub fn is_binary(&self, content_type: Option<&str>) -> bool {
+        gist::declared_binary(content_type) || gist::sniffs_binary(&self.head) || !self.utf8
+    }
+
+    /// Tells whether the content starts with these bytes.
+    pub fn starts_with(&self, prefix: &[u8]) -> bool {
+        self.head.starts_with(prefix)
+    }
+
+    /// Opens the content for reading.
+    pub fn open(&self) -> io::Result<File> {
+        File::open(&self.path)
+    }
+
+    /// Moves the content to `path`, replacing the file there. When the spool is on another
+    /// filesystem, e.g. a gist folder linked to another drive, it is copied next to `path`
+    /// first, so the file is replaced at once all the same.
+    pub fn persist(&self, path: &Path) -> io::Result<()> {
+        if std::fs::rename(&self.path, path).is_ok() {
+            return Ok(());
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let tmp = path.with_file_name(format!("{}.{}.tmp", name, unique_name()));
+        std::fs::copy(&self.path, &tmp)?;
+        std::fs::rename(&tmp, path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&tmp);
+        })
+    }
+
+    /// Returns the content with every line ending converted, spooled next to this one.
+    pub fn convert_eol(self, folder: &Path, eol: Eol) -> io::Result<Spooled> {
+        let mut converted = Spool::create(folder)?;
+        let mut file = self.open()?;
+        let mut buffer = vec![0; CHUNK_LEN];
+        // A `\r` ending a chunk may start a `\r\n` the next one ends
+        let mut carried_cr = false;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            let mut chunk = Vec::with_capacity(read + 1);
+            if carried_cr {
+                chunk.push(b'\r');
+            }
+            chunk.extend_from_slice(&buffer[..read]);
+            carried_cr = chunk.last() == Some(&b'\r');
+            if carried_cr {
+                chunk.pop();
+            }
+            converted.write(&eol.apply(&chunk))?;
+        }
+        if carried_cr {
+            converted.write(b"\r")?;
+        }
+        converted.finish()
+    }
+}
+
+impl Drop for Spooled {
+    fn drop(&mut self) {
+        // Gone already once moved into place
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Returns the hex encoded SHA-256 digest of what the reader reads, reading it in chunks.
+pub fn digest(reader: impl Read) -> io::Result<String> {
+    digest_with_head(reader, 0).map(|(sha256, _)| sha256)
+}
+
+/// Returns the digest of what the reader reads, with up to `head_len` bytes of its start.
+pub fn digest_with_head(mut reader: impl Read, head_len: u64) -> io::Result<(String, Vec<u8>)> {
+    let mut hasher = Sha256::new();
+    let mut head = Vec::new();
+    let mut buffer = vec![0; CHUNK_LEN];
+    loop {
+        let read = match reader.read(&mut buffer)? {
+            0 => break,
+            read => &buffer[..read],
+        };
+        hasher.update(read);
+        let missing = head_len.saturating_sub(head.len() as u64);
+        head.extend_from_slice(&read[..read.len().min(missing as usize)]);
+    }
+    Ok((hex(&hasher.finalize()), head))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Spools content already in memory, since writes into the archive take their content
+/// from a spool.
+pub fn from_bytes(folder: &Path, content: &[u8]) -> io::Result<Spooled> {
+    let mut spool = Spool::create(folder)?;
+    spool.write(content)?;
+    spool.finish()
+}
+
+/// Deletes the spooled files an interrupted run left behind, returning how many and their
+/// size.
+pub fn remove_leftovers(folder: &Path) -> io::Result<(usize, u64)> {
+    let dir = long_path(folder.join(SPOOL_DIR));
+    let (mut removed, mut bytes) = (0, 0);
+    if !dir.exists() {
+        return Ok((removed, bytes));
+    }
+    for file in std::fs::read_dir(&dir)? {
+        let file = file?;
+        bytes += file.metadata()?.len();
+        std::fs::remove_file(file.path())?;
+        removed += 1;
+    }
+    std::fs::remove_dir(&dir)?;
+    Ok((removed, bytes))
+}
@@ -0,0 +1,174 @@
+use crate::manifest::ManifestEntry;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Width of the longest bar in the language histogram
+const HISTOGRAM_WIDTH: usize = 30;
+
+#[derive(Debug, Default, Serialize)]
+pub struct LanguageStats {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GistSummary {
+    pub id: String,
+    pub description: Option<String>,
+    pub bytes: u64,
+    pub updated_at: String,
+}
+
+impl From<&ManifestEntry> for GistSummary {
+    fn from(entry: &ManifestEntry) -> Self {
+        GistSummary {
+            id: entry.id.clone(),
+            description: entry.description.clone(),
+            bytes: entry.files.iter().map(|f| u64::from(f.size)).sum(),
+            updated_at: entry.updated_at.clone(),
+        }
+    }
+}
+
+/// Aggregated numbers about a set of gists, either remote or from a local archive.
+#[derive(Debug, Default, Serialize)]
+pub struct Stats {
+    pub gists: usize,
+    pub files: usize,
+    pub total_bytes: u64,
+    pub public: usize,
+    pub secret: usize,
+    pub languages: BTreeMap<String, LanguageStats>,
+    /// Number of gists per detected license, only known for local archives
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub licenses: BTreeMap<String, usize>,
+    pub oldest: Option<String>,
+    pub newest: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub largest: Vec<GistSummary>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recent: Vec<GistSummary>,
+}
+
+impl Stats {
+    /// # Arguments
+    /// * `entries` - Gists to aggregate
+    /// * `top_size` - Number of largest gists (by aggregate file size) to report
+    /// * `top_recent` - Number of most recently updated gists to report
+    pub fn from_entries(entries: &[ManifestEntry], top_size: usize, top_recent: usize) -> Self {
+        let mut stats = Stats::default();
+        for entry in entries {
+            stats.gists += 1;
+            if entry.public {
+                stats.public += 1;
+            } else {
+                stats.secret += 1;
+            }
+            // GitHub timestamps are ISO 8601 in UTC, so they order lexicographically
+            if stats.oldest.as_ref().is_none_or(|o| entry.created_at < *o) {
+                stats.oldest = Some(entry.created_at.clone());
+            }
+            if stats.newest.as_ref().is_none_or(|n| entry.created_at > *n) {
+                stats.newest = Some(entry.created_at.clone());
+            }
+            if let Some(license) = entry.license() {
+                *stats.licenses.entry(license.to_string()).or_default() += 1;
+            }
+            for file in &entry.files {
+                stats.files += 1;
+                stats.total_bytes += u64::from(file.size);
+                let language = file.language.as_deref().unwrap_or("Unknown");
+                let language_stats = stats.languages.entry(language.to_string()).or_default();
+                language_stats.files += 1;
+                language_stats.bytes += u64::from(file.size);
+            }
+        }
+
+        stats.largest = entries.iter().map(GistSummary::from).collect();
+        stats.largest.sort_by_key(|g| Reverse(g.bytes));
+        stats.largest.truncate(top_size);
+
+        stats.recent = entries.iter().map(GistSummary::from).collect();
+        stats.recent.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        stats.recent.truncate(top_recent);
+
+        stats
+    }
+
+    pub fn print(&self) {
+        println!("Gists: {}", self.gists);
+        println!("Files: {}", self.files);
+        println!("Total size: {}", format_bytes(self.total_bytes));
+        println!("Public: {}, secret: {}", self.public, self.secret);
+        println!(
+            "Oldest: {}, newest: {}",
+            self.oldest.as_deref().unwrap_or("-"),
+            self.newest.as_deref().unwrap_or("-")
+        );
+
+        let mut languages: Vec<(&String, &LanguageStats)> = self.languages.iter().collect();
+        languages.sort_by(|a, b| b.1.files.cmp(&a.1.files).then(a.0.cmp(b.0)));
+        let max_files = languages.first().map_or(1, |(_, l)| l.files.max(1));
+        for (language, language_stats) in languages {
+            let bar = "#".repeat((language_stats.files * HISTOGRAM_WIDTH).div_ceil(max_files));
+            println!(
+                "{:<16} {:>6} files {:>10} {}",
+                language,
+                language_stats.files,
+                format_bytes(language_stats.bytes),
+                bar
+            );
+        }
+
+        if !self.licenses.is_empty() {
+            let unlicensed = self.gists - self.licenses.values().sum::<usize>();
+            println!("Licenses:");
+            for (license, count) in &self.licenses {
+                println!("{:<16} {:>6} gists", license, count);
+            }
+            println!("{:<16} {:>6} gists", "None detected", unlicensed);
+        }
+
+        if !self.largest.is_empty() {
+            println!("Largest gists:");
+            for gist in &self.largest {
+                println!("{:>10} {}", format_bytes(gist.bytes), gist);
+            }
+        }
+        if !self.recent.is_empty() {
+            println!("Recently updated gists:");
+            for gist in &self.recent {
+                println!("{} {}", gist.updated_at, gist);
+            }
+        }
+    }
+}
+
+impl fmt::Display for GistSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} - {}",
+            self.id,
+            self.description.as_deref().unwrap_or("<no description>")
+        )
+    }
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 KiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
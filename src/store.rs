@@ -0,0 +1,780 @@
+use crate::crypt::{self, AGE_EXTENSION};
+use crate::gist::GistError;
+use crate::log::{debug, info};
+use crate::manifest::ManifestEntry;
+use crate::paths::{long_path, nfc, portable_name};
+use crate::spool::{self, Spooled};
+use crate::trash;
+use age::x25519::Recipient;
+use clap::ValueEnum;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+// Directory inside the archive holding the content store
+const STORE_DIR: &str = ".store";
+// Directory inside the archive holding content-addressed objects for deduplication
+const OBJECTS_DIR: &str = ".objects";
+// Directory inside the archive holding earlier versions of changed files, a folder per
+// gist holding a folder per version
+const VERSIONS_DIR: &str = ".versions";
+// Favours ratio over speed, gist files are small and written once
+const ZSTD_LEVEL: i32 = 19;
+// Extension of the plain files written gzip-compressed by `--compress-files`
+const GZIP_EXTENSION: &str = "gz";
+// Mode given to scripts so they can be run straight from the archive
+pub const EXECUTABLE_MODE: u32 = 0o755;
+
+/// How downloaded file contents are kept in the archive.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Store {
+    /// Plain files in a folder per gist
+    #[default]
+    Plain,
+    /// Zstd-compressed files in the content store, materialized with `checkout`
+    Compressed,
+    /// Files stored once by SHA-256 and hardlinked into the folder of every gist using them
+    Dedup,
+}
+
+/// Line endings text files are written with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Eol {
+    /// Unix line endings, `\n`
+    Lf,
+    /// Windows line endings, `\r\n`
+    Crlf,
+    /// Those of the platform running the download
+    Native,
+}
+
+impl Eol {
+    /// Returns `Lf` or `Crlf`, `Native` being the line endings of the platform.
+    pub fn resolve(self) -> Eol {
+        match self {
+            Eol::Native if cfg!(windows) => Eol::Crlf,
+            Eol::Native => Eol::Lf,
+            eol => eol,
+        }
+    }
+
+    /// Returns the content with every line ending converted. Lone `\r` are left alone.
+    pub fn apply(self, content: &[u8]) -> Vec<u8> {
+        let mut converted = Vec::with_capacity(content.len());
+        for (i, &byte) in content.iter().enumerate() {
+            match byte {
+                b'\r' if content.get(i + 1) == Some(&b'\n') => {}
+                b'\n' if self.resolve() == Eol::Crlf => converted.extend_from_slice(b"\r\n"),
+                byte => converted.push(byte),
+            }
+        }
+        converted
+    }
+}
+
+/// Returns the path of a file named `name` in `dir`. When there is none, an entry whose
+/// name only differs in its Unicode normalization is taken instead, as in archives copied
+/// from a macOS filesystem that decomposed the names.
+fn resolve(dir: PathBuf, name: &str) -> PathBuf {
+    let path = long_path(dir.join(name));
+    if path.symlink_metadata().is_ok() {
+        return path;
+    }
+    let Ok(entries) = std::fs::read_dir(long_path(dir)) else {
+        return path;
+    };
+    entries
+        .filter_map(Result::ok)
+        .find(|entry| nfc(&entry.file_name().to_string_lossy()) == name)
+        .map_or(path, |entry| entry.path())
+}
+
+// Where the files of a gist are kept, relative to the root of the archive with `/`
+// separators, whichever backend holds it
+
+fn plain_key(id: &str, filename: &str) -> String {
+    format!("{}/{}", id, portable_name(filename))
+}
+
+fn compressed_key(id: &str, filename: &str) -> String {
+    format!("{}/{}/{}.zst", STORE_DIR, id, portable_name(filename))
+}
+
+fn gzipped_key(id: &str, filename: &str) -> String {
+    format!("{}/{}.{}", id, portable_name(filename), GZIP_EXTENSION)
+}
+
+fn encrypted_key(id: &str, filename: &str) -> String {
+    format!("{}/{}.{}", id, portable_name(filename), AGE_EXTENSION)
+}
+
+fn plain_path(folder: &Path, id: &str, filename: &str) -> PathBuf {
+    key_path(folder, &plain_key(id, filename))
+}
+
+/// Returns the path of a gist file written as a plain file, `None` when it's encrypted, in
+/// the content store or not in the archive.
+pub fn plain_file(folder: &Path, id: &str, filename: &str) -> Option<PathBuf> {
+    Some(plain_path(folder, id, filename)).filter(|path| path.is_file())
+}
+
+fn compressed_path(folder: &Path, id: &str, filename: &str) -> PathBuf {
+    key_path(folder, &compressed_key(id, filename))
+}
+
+fn gzipped_path(folder: &Path, id: &str, filename: &str) -> PathBuf {
+    key_path(folder, &gzipped_key(id, filename))
+}
+
+fn encrypted_path(folder: &Path, id: &str, filename: &str) -> PathBuf {
+    key_path(folder, &encrypted_key(id, filename))
+}
+
+/// Returns the hex encoded SHA-256 digest of the content.
+pub fn sha256_hex(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn object_path(folder: &Path, digest: &str) -> PathBuf {
+    long_path(folder.join(OBJECTS_DIR).join(&digest[..2]).join(digest))
+}
+
+/// Fails when writing `path` would go through a symlink inside the archive folder, which
+/// could make the write land outside of it, unless `follow_symlinks` is set by
+/// `--follow-symlinks` for archives whose gist folders are deliberately linked elsewhere.
+/// The folder itself may be a symlink.
+pub fn check_inside(folder: &Path, path: &Path, follow_symlinks: bool) -> io::Result<()> {
+    if follow_symlinks {
+        return Ok(());
+    }
+    // Nothing inside a folder that doesn't exist yet can be a symlink
+    let Ok(root) = folder.canonicalize() else {
+        return Ok(());
+    };
+    let linked = path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink());
+    let outside = match path.ancestors().skip(1).find(|dir| dir.exists()) {
+        Some(dir) => !dir.canonicalize()?.starts_with(&root),
+        None => false,
+    };
+    if linked || outside {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} leads outside of {} through a symlink, pass --follow-symlinks to write there",
+                path.display(),
+                folder.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// How a file of the archive holds the content of a gist file.
+#[derive(Clone, Copy)]
+enum Encoding {
+    Plain,
+    Gzip,
+    Age,
+    Zstd,
+}
+
+/// Reads the content a stored file holds, decoded as it is read.
+fn decode(stored: Box<dyn Read>, encoding: Encoding) -> io::Result<Box<dyn Read>> {
+    let stored = BufReader::new(stored);
+    Ok(match encoding {
+        Encoding::Plain => Box::new(stored),
+        Encoding::Gzip => Box::new(GzDecoder::new(stored)),
+        Encoding::Age => Box::new(crypt::decrypt_reader(stored)?),
+        Encoding::Zstd => Box::new(zstd::Decoder::with_buffer(stored)?),
+    })
+}
+
+/// Opens a file of the archive for reading the content it holds, decoded as it is read.
+fn decoded(path: &Path, encoding: Encoding) -> io::Result<Box<dyn Read>> {
+    decode(Box::new(File::open(path)?), encoding)
+}
+
+/// Where the files of an archive are written: the archive folder, or the remote storage
+/// gists are mirrored to. Files are addressed by their path relative to the root of the
+/// archive with `/` separators, so both get the same names, trash and encryption.
+pub trait Backend {
+    /// Opens the file at `key` for reading it as it is stored, `None` when there is none.
+    fn open(&self, key: &str) -> io::Result<Option<Box<dyn Read>>>;
+
+    /// Writes the file at `key` through `encode`, replacing the one there at once.
+    fn write(
+        &self,
+        key: &str,
+        encode: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> io::Result<()>;
+
+    /// Moves the content of a downloaded file to `key`, replacing the file there.
+    fn persist(&self, key: &str, content: &Spooled) -> io::Result<()> {
+        self.write(key, &mut |output| {
+            io::copy(&mut content.open()?, output).map(|_| ())
+        })
+    }
+
+    /// Moves the file at `key` into the trash of the run. Does nothing when there is none.
+    fn discard(&self, key: &str) -> io::Result<()>;
+}
+
+/// Returns the path of the file at `key` in the archive folder.
+fn key_path(folder: &Path, key: &str) -> PathBuf {
+    let (dir, name) = key.rsplit_once('/').unwrap_or(("", key));
+    let dir = dir
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .fold(folder.to_path_buf(), |dir, part| dir.join(part));
+    resolve(dir, name)
+}
+
+/// The archive folder, writes staying inside of it unless symlinks are followed.
+#[derive(Clone, Copy)]
+pub struct Folder<'a> {
+    pub root: &'a Path,
+    pub follow_symlinks: bool,
+}
+
+impl<'a> Folder<'a> {
+    pub fn new(root: &'a Path, follow_symlinks: bool) -> Self {
+        Folder {
+            root,
+            follow_symlinks,
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        key_path(self.root, key)
+    }
+
+    /// Fails when writing `path` would leave the folder through a symlink, unless symlinks
+    /// are followed.
+    pub fn check_inside(&self, path: &Path) -> io::Result<()> {
+        check_inside(self.root, path, self.follow_symlinks)
+    }
+
+    /// Creates the missing folders of a path of the archive, once it's known to stay inside.
+    fn create_parent(&self, path: &Path) -> io::Result<()> {
+        self.check_inside(path)?;
+        match path.parent() {
+            Some(parent) => std::fs::create_dir_all(parent),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Backend for Folder<'_> {
+    fn open(&self, key: &str) -> io::Result<Option<Box<dyn Read>>> {
+        match File::open(self.path(key)) {
+            Ok(file) => Ok(Some(Box::new(file))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(
+        &self,
+        key: &str,
+        encode: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let path = self.path(key);
+        self.create_parent(&path)?;
+        encode_into(&path, encode)
+    }
+
+    fn persist(&self, key: &str, content: &Spooled) -> io::Result<()> {
+        let path = self.path(key);
+        self.create_parent(&path)?;
+        // Replaces a hardlink rather than writing through it, which would change the
+        // shared object
+        content.persist(&path)
+    }
+
+    fn discard(&self, key: &str) -> io::Result<()> {
+        let path = self.path(key);
+        self.check_inside(&path)?;
+        trash::discard(self.root, &path)
+    }
+}
+
+/// Moves the file at `key` to the trash before it is replaced, unless it already holds
+/// the content with this digest once decoded.
+fn trash_replaced(
+    backend: &dyn Backend,
+    key: &str,
+    sha256: &str,
+    encoding: Encoding,
+) -> io::Result<()> {
+    let current = match backend.open(key) {
+        Ok(Some(stored)) => decode(stored, encoding).and_then(spool::digest),
+        Ok(None) => return Ok(()),
+        Err(e) => Err(e),
+    };
+    match current {
+        Ok(current) if current == sha256 => Ok(()),
+        _ => backend.discard(key),
+    }
+}
+
+/// Returns the digest of the content of a gist file as the backend holds it, plain or
+/// encrypted, `None` when it holds neither.
+pub fn digest_stored(
+    backend: &dyn Backend,
+    id: &str,
+    filename: &str,
+) -> io::Result<Option<String>> {
+    let stored = [
+        (plain_key(id, filename), Encoding::Plain),
+        (encrypted_key(id, filename), Encoding::Age),
+    ];
+    for (key, encoding) in stored {
+        if let Some(stored) = backend.open(&key)? {
+            return decode(stored, encoding).and_then(spool::digest).map(Some);
+        }
+    }
+    Ok(None)
+}
+
+/// Writes a file of the archive through `encode` into a temporary file next to it, which
+/// then replaces it at once, so an interrupted write never leaves half a file.
+fn encode_into(
+    path: &Path,
+    encode: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!("{}.{}.tmp", name, spool::unique_name()));
+    let written = File::create(&tmp).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        encode(&mut writer)?;
+        writer.flush()
+    });
+    match written.and_then(|_| std::fs::rename(&tmp, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp);
+            Err(e)
+        }
+    }
+}
+
+/// Moves the files of a gist to the trash, plain, in the content store and their versions.
+pub fn discard_gist(folder: &Path, id: &str) -> io::Result<()> {
+    trash::discard(folder, &long_path(folder.join(id)))?;
+    trash::discard(folder, &long_path(folder.join(STORE_DIR).join(id)))?;
+    trash::discard(folder, &long_path(folder.join(VERSIONS_DIR).join(id)))
+}
+
+/// Copies a gist file into `.versions/<id>/<version>` before different content replaces
+/// it, the way it is stored, so compressed and encrypted files stay so. Does nothing when
+/// the file isn't in the archive or this version of it was already kept.
+pub fn keep_version(archive: &Folder, id: &str, filename: &str, version: &str) -> io::Result<()> {
+    let folder = archive.root;
+    let versions = folder
+        .join(VERSIONS_DIR)
+        .join(id)
+        .join(&*portable_name(version));
+    let stored = [
+        plain_path(folder, id, filename),
+        gzipped_path(folder, id, filename),
+        encrypted_path(folder, id, filename),
+        compressed_path(folder, id, filename),
+    ];
+    for path in stored.iter().filter(|path| path.exists()) {
+        let target = long_path(versions.join(path.file_name().unwrap_or_default()));
+        if target.exists() {
+            continue;
+        }
+        archive.create_parent(&target)?;
+        debug!("Keeping {} as {}", path.display(), target.display());
+        std::fs::copy(path, &target)?;
+    }
+    Ok(())
+}
+
+/// Deletes the oldest versions kept of a gist's files beyond the `keep` newest.
+pub fn prune_versions(folder: &Path, id: &str, keep: usize) -> io::Result<()> {
+    let Ok(entries) = std::fs::read_dir(long_path(folder.join(VERSIONS_DIR).join(id))) else {
+        return Ok(());
+    };
+    // Versions are named after `updated_at`, so they sort oldest first
+    let mut versions: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    versions.sort();
+    let excess = versions.len().saturating_sub(keep);
+    for version in &versions[..excess] {
+        debug!("Deleting version {}", version.display());
+        std::fs::remove_dir_all(version)?;
+    }
+    Ok(())
+}
+
+/// Writes the content of a gist file into the archive using the given store. A different
+/// file it replaces is moved to the trash.
+pub fn write_file(
+    archive: &Folder,
+    id: &str,
+    filename: &str,
+    content: &[u8],
+    store: Store,
+) -> io::Result<()> {
+    let spooled = spool::from_bytes(archive.root, content)?;
+    write_spooled(archive, id, filename, &spooled, store)
+}
+
+/// Writes a downloaded gist file into the archive using the given store, moving or encoding
+/// it into place from its spool. A different file it replaces is moved to the trash.
+pub fn write_spooled(
+    archive: &Folder,
+    id: &str,
+    filename: &str,
+    content: &Spooled,
+    store: Store,
+) -> io::Result<()> {
+    match store {
+        Store::Plain => {
+            // The gzipped file of an earlier, bigger version would be read instead
+            let gzipped = gzipped_key(id, filename);
+            trash_replaced(archive, &gzipped, &content.sha256, Encoding::Gzip)?;
+            let gzipped = archive.path(&gzipped);
+            if gzipped.exists() {
+                archive.check_inside(&gzipped)?;
+                std::fs::remove_file(&gzipped)?;
+            }
+            write_plain(archive, id, filename, content)
+        }
+        Store::Compressed => {
+            let key = compressed_key(id, filename);
+            trash_replaced(archive, &key, &content.sha256, Encoding::Zstd)?;
+            archive.write(&key, &mut |output| {
+                zstd::stream::copy_encode(content.open()?, output, ZSTD_LEVEL)
+            })?;
+            // A checked out copy is read instead of the store, it gets the new content too
+            if archive.path(&plain_key(id, filename)).exists() {
+                write_plain(archive, id, filename, content)?;
+            }
+            Ok(())
+        }
+        Store::Dedup => write_deduplicated(archive, id, filename, content),
+    }
+}
+
+/// Writes a downloaded gist file as a plain file in the folder of its gist. A different
+/// file it replaces is moved to the trash.
+pub fn write_plain(
+    backend: &dyn Backend,
+    id: &str,
+    filename: &str,
+    content: &Spooled,
+) -> io::Result<()> {
+    let key = plain_key(id, filename);
+    trash_replaced(backend, &key, &content.sha256, Encoding::Plain)?;
+    backend.persist(&key, content)
+}
+
+/// Writes a downloaded gist file gzip-compressed as `<name>.gz`, for big mirrors on small
+/// disks. Reads decompress it transparently.
+pub fn write_gzipped(
+    archive: &Folder,
+    id: &str,
+    filename: &str,
+    content: &Spooled,
+) -> io::Result<()> {
+    let key = gzipped_key(id, filename);
+    trash_replaced(archive, &key, &content.sha256, Encoding::Gzip)?;
+    // The plain file of an earlier, smaller version would be read instead
+    let plain = plain_key(id, filename);
+    trash_replaced(archive, &plain, &content.sha256, Encoding::Plain)?;
+    let plain = archive.path(&plain);
+    if plain.exists() {
+        archive.check_inside(&plain)?;
+        std::fs::remove_file(&plain)?;
+    }
+    archive.write(&key, &mut |output| {
+        let mut encoder = GzEncoder::new(output, Compression::best());
+        io::copy(&mut content.open()?, &mut encoder)?;
+        encoder.finish().map(|_| ())
+    })
+}
+
+/// Writes a downloaded gist file age-encrypted to the recipient, next to where the plain
+/// file would be. The file it replaces is moved to the trash unless it decrypts to the
+/// same content.
+pub fn write_encrypted(
+    backend: &dyn Backend,
+    id: &str,
+    filename: &str,
+    content: &Spooled,
+    recipient: &Recipient,
+) -> io::Result<()> {
+    let key = encrypted_key(id, filename);
+    trash_replaced(backend, &key, &content.sha256, Encoding::Age)?;
+    backend.write(&key, &mut |output| {
+        crypt::encrypt_to(recipient, &mut content.open()?, output)
+    })
+}
+
+/// Stores the content once under its digest and hardlinks it into the gist folder,
+/// falling back to a copy where hardlinks aren't supported (e.g. across filesystems).
+fn write_deduplicated(
+    archive: &Folder,
+    id: &str,
+    filename: &str,
+    content: &Spooled,
+) -> io::Result<()> {
+    let object = object_path(archive.root, &content.sha256);
+    if !object.exists() {
+        archive.create_parent(&object)?;
+        content.persist(&object)?;
+    }
+
+    let key = plain_key(id, filename);
+    trash_replaced(archive, &key, &content.sha256, Encoding::Plain)?;
+    let path = archive.path(&key);
+    archive.create_parent(&path)?;
+    if path.exists() {
+        std::fs::remove_file(&path)?;
+    }
+    if let Err(e) = std::fs::hard_link(&object, &path) {
+        debug!("Copying {} instead of hardlinking: {}", path.display(), e);
+        std::fs::copy(&object, &path)?;
+    }
+    Ok(())
+}
+
+/// Deletes the objects of the deduplicated store no gist uses anymore, those whose digest
+/// isn't in `referenced` and that aren't linked from a gist folder, and temporary files left
+/// by interrupted writes. Returns the number of files deleted and the bytes they took.
+pub fn remove_orphaned_objects(
+    folder: &Path,
+    referenced: &HashSet<String>,
+) -> io::Result<(usize, u64)> {
+    let objects = folder.join(OBJECTS_DIR);
+    let (mut removed, mut bytes) = (0, 0);
+    if !objects.exists() {
+        return Ok((removed, bytes));
+    }
+    for prefix in std::fs::read_dir(&objects)? {
+        for object in std::fs::read_dir(prefix?.path())? {
+            let object = object?;
+            let metadata = object.metadata()?;
+            let name = object.file_name().to_string_lossy().into_owned();
+            #[cfg(unix)]
+            let linked = std::os::unix::fs::MetadataExt::nlink(&metadata) > 1;
+            #[cfg(not(unix))]
+            let linked = false;
+            if name.ends_with(".tmp") || !(referenced.contains(&name) || linked) {
+                debug!("Removing orphaned object {}", name);
+                std::fs::remove_file(object.path())?;
+                removed += 1;
+                bytes += metadata.len();
+            }
+        }
+    }
+    Ok((removed, bytes))
+}
+
+/// Sets the permission bits of the plain or gzipped file of a gist file, if there is one.
+/// Files only kept in the content store get their mode on `checkout`. Does nothing outside
+/// of Unix.
+pub fn set_mode(archive: &Folder, id: &str, filename: &str, mode: u32) -> io::Result<()> {
+    for path in [
+        archive.path(&plain_key(id, filename)),
+        archive.path(&gzipped_key(id, filename)),
+    ] {
+        archive.check_inside(&path)?;
+        #[cfg(unix)]
+        if path.exists() {
+            use std::os::unix::fs::{MetadataExt, PermissionsExt};
+            // The mode of a hardlink is that of the shared object of the content store, so
+            // the gist gets its own copy instead of making the object executable for all
+            if path.metadata()?.nlink() > 1 {
+                let tmp = path.with_extension("mode.tmp");
+                std::fs::copy(&path, &tmp)?;
+                std::fs::rename(&tmp, &path)?;
+            }
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = (path, mode);
+    }
+    Ok(())
+}
+
+/// Returns the permission bits of a gist file written as a plain or gzipped file, `None`
+/// when it is stored otherwise, not in the archive or the platform has no Unix modes.
+pub fn file_mode(folder: &Path, id: &str, filename: &str) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        [
+            plain_path(folder, id, filename),
+            gzipped_path(folder, id, filename),
+        ]
+        .iter()
+        .find_map(|path| path.metadata().ok())
+        .map(|metadata| metadata.permissions().mode() & 0o777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (folder, id, filename);
+        None
+    }
+}
+
+/// Tells whether a gist file is in the archive, whichever store it was written with.
+pub fn contains(folder: &Path, id: &str, filename: &str) -> bool {
+    plain_path(folder, id, filename).exists()
+        || gzipped_path(folder, id, filename).exists()
+        || encrypted_path(folder, id, filename).exists()
+        || compressed_path(folder, id, filename).exists()
+}
+
+/// Reads the content of a gist file from the archive, whichever store it was written with.
+///
+/// Plain files win over the content store, so a checked out gist reads what is on disk.
+/// Gzipped files are decompressed, encrypted files are decrypted with the identities
+/// loaded by `crypt::load_identities`.
+pub fn read_file(folder: &Path, id: &str, filename: &str) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    open_file(folder, id, filename)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Opens a gist file of the archive for reading its content, decoded as it is read, like
+/// `read_file` does.
+pub fn open_file(folder: &Path, id: &str, filename: &str) -> io::Result<Box<dyn Read>> {
+    let stored = [
+        (plain_path(folder, id, filename), Encoding::Plain),
+        (gzipped_path(folder, id, filename), Encoding::Gzip),
+        (encrypted_path(folder, id, filename), Encoding::Age),
+        (compressed_path(folder, id, filename), Encoding::Zstd),
+    ];
+    match stored.into_iter().find(|(path, _)| path.exists()) {
+        Some((path, encoding)) => decoded(&path, encoding),
+        None => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{}/{} is not in the archive", id, filename),
+        )),
+    }
+}
+
+/// Returns the digest of the content of a gist file of the archive, reading it in chunks.
+pub fn digest_file(folder: &Path, id: &str, filename: &str) -> io::Result<String> {
+    spool::digest(open_file(folder, id, filename)?)
+}
+
+/// Reads every file of a gist listed in the manifest, returning filenames and contents.
+pub fn read_gist_files(folder: &Path, entry: &ManifestEntry) -> io::Result<Vec<(String, Vec<u8>)>> {
+    entry
+        .files
+        .iter()
+        .map(|f| {
+            Ok((
+                f.filename.clone(),
+                read_file(folder, &entry.id, &f.filename)?,
+            ))
+        })
+        .collect()
+}
+
+/// Materializes the files of a gist as plain files in its folder.
+///
+/// Returns the number of files written.
+pub fn checkout(archive: &Folder, entry: &ManifestEntry) -> Result<usize, GistError> {
+    for file in &entry.files {
+        let content = read_file(archive.root, &entry.id, &file.filename)?;
+        write_file(archive, &entry.id, &file.filename, &content, Store::Plain)?;
+        if let Some(mode) = file.mode.as_deref() {
+            match u32::from_str_radix(mode, 8) {
+                Ok(mode) => set_mode(archive, &entry.id, &file.filename, mode)?,
+                Err(e) => debug!("Ignoring mode {} of {}: {}", mode, file.filename, e),
+            }
+        }
+    }
+    info!(
+        "Checked out {} files to {}",
+        entry.files.len(),
+        archive.root.join(&entry.id).display()
+    );
+    Ok(entry.files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str, filename: &str) -> ManifestEntry {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "description": null,
+            "html_url": format!("https://gist.github.com/{}", id),
+            "owner": "someone",
+            "public": true,
+            "created_at": "2024-01-01T00:00:00Z",
+            "updated_at": "2024-01-01T00:00:00Z",
+            "files": [{ "filename": filename, "language": null, "size": 0 }],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_a_compressed_file_downloaded_again_after_checkout() {
+        let folder =
+            std::env::temp_dir().join(format!("local_gist-store-{}", spool::unique_name()));
+        std::fs::create_dir_all(&folder).unwrap();
+
+        let archive = Folder::new(&folder, false);
+        write_file(&archive, "abc", "notes.txt", b"first", Store::Compressed).unwrap();
+        checkout(&archive, &entry("abc", "notes.txt")).unwrap();
+        write_file(&archive, "abc", "notes.txt", b"second", Store::Compressed).unwrap();
+
+        let content = read_file(&folder, "abc", "notes.txt");
+        std::fs::remove_dir_all(&folder).unwrap();
+        assert_eq!(content.unwrap(), b"second");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn writes_through_a_symlinked_gist_folder_only_when_following_symlinks() {
+        let folder =
+            std::env::temp_dir().join(format!("local_gist-store-{}", spool::unique_name()));
+        let elsewhere = folder.with_extension("elsewhere");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::create_dir_all(&elsewhere).unwrap();
+        std::os::unix::fs::symlink(&elsewhere, folder.join("abc")).unwrap();
+
+        let refused = write_file(
+            &Folder::new(&folder, false),
+            "abc",
+            "a.txt",
+            b"a",
+            Store::Plain,
+        );
+        let followed = write_file(
+            &Folder::new(&folder, true),
+            "abc",
+            "b.txt",
+            b"b",
+            Store::Plain,
+        );
+        let written = elsewhere.join("b.txt").exists();
+        std::fs::remove_dir_all(&folder).unwrap();
+        std::fs::remove_dir_all(&elsewhere).unwrap();
+        assert!(refused.is_err());
+        assert!(followed.is_ok());
+        assert!(written);
+    }
+}
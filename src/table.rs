@@ -0,0 +1,202 @@
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::output::{hyperlink, hyperlinks};
+use crate::stats::format_bytes;
+use clap::ValueEnum;
+use reqwest::Url;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A column of the gist table of `list`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Column {
+    Id,
+    /// Login of the owner
+    Owner,
+    Description,
+    /// Number of files
+    Files,
+    /// Total size of the files
+    Size,
+    /// Public or secret
+    Visibility,
+    Created,
+    Updated,
+}
+
+impl Column {
+    fn header(self) -> &'static str {
+        match self {
+            Column::Id => "ID",
+            Column::Owner => "OWNER",
+            Column::Description => "DESCRIPTION",
+            Column::Files => "FILES",
+            Column::Size => "SIZE",
+            Column::Visibility => "VISIBILITY",
+            Column::Created => "CREATED",
+            Column::Updated => "UPDATED",
+        }
+    }
+
+    fn cell(self, entry: &ManifestEntry) -> String {
+        match self {
+            Column::Id => entry.id.clone(),
+            Column::Owner => entry.owner.clone(),
+            Column::Description => entry
+                .description
+                .as_deref()
+                .unwrap_or_default()
+                .replace('\n', " "),
+            Column::Files => entry.files.len().to_string(),
+            Column::Size => format_bytes(size(entry)),
+            Column::Visibility => match entry.public {
+                true => "public".to_string(),
+                false => "secret".to_string(),
+            },
+            Column::Created => entry.created_at.clone(),
+            Column::Updated => entry.updated_at.clone(),
+        }
+    }
+
+    /// Whether the column holds numbers, aligned to the right.
+    fn numeric(self) -> bool {
+        matches!(self, Column::Files | Column::Size)
+    }
+}
+
+fn size(entry: &ManifestEntry) -> u64 {
+    entry.files.iter().map(|file| file.size as u64).sum()
+}
+
+/// What the gists of `list` are grouped by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GroupBy {
+    Owner,
+}
+
+/// Hyperlinks of the gists of a listing in a terminal that shows them: ids link to the
+/// page of the gist on GitHub, descriptions to its folder when it's in a local archive.
+pub struct Links {
+    /// `file://` URLs of the folders of the archived gists, by id
+    local: HashMap<String, String>,
+}
+
+impl Links {
+    /// Returns the links of a listing, with the gists archived in `folder` when given, or
+    /// `None` when stdout isn't a terminal showing hyperlinks.
+    pub fn new(folder: Option<&Path>) -> Option<Links> {
+        if !hyperlinks() {
+            return None;
+        }
+        let mut local = HashMap::new();
+        if let Some(folder) = folder.and_then(|folder| folder.canonicalize().ok()) {
+            let manifest = Manifest::load(&folder).unwrap_or_default();
+            for id in manifest.gists.keys() {
+                let dir = folder.join(id);
+                if let Some(url) = dir
+                    .is_dir()
+                    .then(|| Url::from_file_path(&dir).ok())
+                    .flatten()
+                {
+                    local.insert(id.clone(), url.to_string());
+                }
+            }
+        }
+        Some(Links { local })
+    }
+
+    fn id(&self, entry: &ManifestEntry, text: &str) -> String {
+        hyperlink(text, &entry.html_url)
+    }
+
+    fn description(&self, entry: &ManifestEntry, text: &str) -> String {
+        match self.local.get(&entry.id) {
+            Some(url) => hyperlink(text, url),
+            None => text.to_string(),
+        }
+    }
+}
+
+/// Prints a line per gist, `id - description (files)`, after the login of its owner when
+/// the gists have several owners.
+pub fn print_lines(entries: &[ManifestEntry], with_owner: bool, links: Option<&Links>) {
+    for entry in entries {
+        let files: Vec<&str> = entry.files.iter().map(|f| f.filename.as_str()).collect();
+        let description = entry.description.as_deref().unwrap_or("<no description>");
+        let (id, description) = match links {
+            Some(links) => (
+                links.id(entry, &entry.id),
+                links.description(entry, description),
+            ),
+            None => (entry.id.clone(), description.to_string()),
+        };
+        let line = format!("{} - {} ({})", id, description, files.join(", "));
+        match with_owner {
+            true => println!("{}: {}", entry.owner, line),
+            false => println!("{}", line),
+        }
+    }
+}
+
+/// Prints the gists as a table with the given columns, each as wide as its widest cell,
+/// and a line of totals.
+pub fn print(entries: &[ManifestEntry], columns: &[Column], links: Option<&Links>) {
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .map(|entry| columns.iter().map(|column| column.cell(entry)).collect())
+        .collect();
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain([column.header().len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    // Links go around the text of cells once they are padded, so they don't count towards
+    // the widths
+    let line = |cells: Vec<&str>, entry: Option<&ManifestEntry>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(columns.iter().zip(&widths))
+            .map(|(cell, (column, width))| {
+                let padded = match column.numeric() {
+                    true => format!("{:>width$}", cell, width = width),
+                    false => format!("{:<width$}", cell, width = width),
+                };
+                let linked = match (links, entry, column) {
+                    _ if cell.is_empty() => return padded,
+                    (Some(links), Some(entry), Column::Id) => links.id(entry, cell),
+                    (Some(links), Some(entry), Column::Description) => {
+                        links.description(entry, cell)
+                    }
+                    _ => return padded,
+                };
+                format!("{}{}", linked, &padded[cell.len()..])
+            })
+            .collect();
+        padded.join("  ").trim_end().to_string()
+    };
+
+    println!(
+        "{}",
+        line(columns.iter().map(|column| column.header()).collect(), None)
+    );
+    for (entry, row) in entries.iter().zip(&rows) {
+        println!(
+            "{}",
+            line(row.iter().map(String::as_str).collect(), Some(entry))
+        );
+    }
+    let public = entries.iter().filter(|entry| entry.public).count();
+    println!(
+        "{} gists ({} public, {} secret), {} files, {}",
+        entries.len(),
+        public,
+        entries.len() - public,
+        entries.iter().map(|entry| entry.files.len()).sum::<usize>(),
+        format_bytes(entries.iter().map(size).sum())
+    );
+}
@@ -0,0 +1,382 @@
+use crate::conflict::Resolution;
+use crate::gist::{fetch_files, FetchedFile, Gist, GistError, MemoryBudget, WriteOptions};
+use crate::log::{debug, error, info, warn};
+use crate::manifest::{Inspection, Manifest, ManifestEntry, MANIFEST_FILE};
+use crate::s3::S3Target;
+use crate::sftp::SftpTarget;
+use crate::spool;
+use crate::store::{self, Backend};
+use crate::trash::{self, TRASH_DIR};
+use crate::webdav::WebDavTarget;
+use reqwest::Client;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// A remote location gists are mirrored to instead of the local archive folder, laid out
+/// the same way: `manifest.json` next to a `<gist id>/<filename>` object per file.
+pub enum Target {
+    /// S3-compatible object storage (AWS S3, MinIO, Cloudflare R2...)
+    S3(S3Target),
+    /// A WebDAV collection (Nextcloud, ownCloud...)
+    WebDav(WebDavTarget),
+    /// A directory on a server reachable over SSH
+    Sftp(SftpTarget),
+}
+
+impl Target {
+    /// Parses a target URL such as `s3://bucket/prefix`, `webdav://host/path` or
+    /// `sftp://user@host/path`, with `webdav://` speaking HTTPS and `webdav+http://` plain HTTP.
+    pub fn parse(client: &Client, url: &str) -> Result<Self, GistError> {
+        match url.split_once("://") {
+            Some(("s3", rest)) => {
+                let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+                Ok(Target::S3(S3Target::new(client, bucket, prefix)?))
+            }
+            Some(("webdav", rest)) => Ok(Target::WebDav(WebDavTarget::new(
+                client,
+                &format!(
+                "https://{}",
+                rest
+            ))?)),
+            Some(("webdav+http", rest)) => Ok(Target::WebDav(WebDavTarget::new(
+                client,
+                &format!(
+                "http://{}",
+                rest
+            ))?)),
+            Some(("sftp", rest)) => Ok(Target::Sftp(SftpTarget::new(rest)?)),
+            _ => Err(GistError::Target(format!(
+                "unsupported target {}, expected s3://bucket/prefix, webdav://host/path or sftp://user@host/path",
+                url
+            ))),
+        }
+    }
+
+    /// Reads an object of the target, returning `None` when it doesn't exist.
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, GistError> {
+        match self {
+            Target::S3(s3) => s3.get(key).await,
+            Target::WebDav(webdav) => webdav.get(key).await,
+            Target::Sftp(sftp) => sftp.get(key).await,
+        }
+    }
+
+    /// Writes an object of the target, replacing any existing one.
+    pub async fn put(&self, key: &str, content: Vec<u8>) -> Result<(), GistError> {
+        match self {
+            Target::S3(s3) => s3.put(key, content).await,
+            Target::WebDav(webdav) => webdav.put(key, content).await,
+            Target::Sftp(sftp) => sftp.put(key, content).await,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::S3(s3) => write!(f, "{}", s3),
+            Target::WebDav(webdav) => write!(f, "{}", webdav),
+            Target::Sftp(sftp) => write!(f, "{}", sftp),
+        }
+    }
+}
+
+/// Returns true when the target already holds this version of the gist. Binary files left
+/// out by `--skip-binary` aren't in the manifest, the update time tells of any other change.
+fn is_unchanged(manifest: &Manifest, gist: &Gist) -> bool {
+    manifest.gists.get(&gist.id).is_some_and(|entry| {
+        entry.updated_at == gist.updated_at
+            && entry
+                .files
+                .iter()
+                .all(|f| gist.files.contains_key(&f.filename))
+    })
+}
+
+/// A target seen as an archive, so gists are written into it through the store the same
+/// way as into the folder. Runs on a blocking thread, waiting for each request.
+struct Remote {
+    target: Arc<Target>,
+    runtime: Handle,
+}
+
+impl Remote {
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        self.runtime
+            .block_on(self.target.get(key))
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+
+    fn put(&self, key: &str, content: Vec<u8>) -> io::Result<()> {
+        self.runtime
+            .block_on(self.target.put(key, content))
+            .map_err(|e| io::Error::other(e.to_string()))
+    }
+}
+
+impl Backend for Remote {
+    fn open(&self, key: &str) -> io::Result<Option<Box<dyn Read>>> {
+        Ok(self
+            .get(key)?
+            .map(|content| Box::new(io::Cursor::new(content)) as Box<dyn Read>))
+    }
+
+    fn write(
+        &self,
+        key: &str,
+        encode: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut content = Vec::new();
+        encode(&mut content)?;
+        self.put(key, content)
+    }
+
+    // Objects can't be moved, so a copy goes to the trash and the object is left for the
+    // write replacing it
+    fn discard(&self, key: &str) -> io::Result<()> {
+        let Some(content) = self.get(key)? else {
+            return Ok(());
+        };
+        let trashed = format!("{}/{}/{}", TRASH_DIR, trash::run_name(), key);
+        debug!("Copying {} to {}", key, trashed);
+        self.put(&trashed, content)
+    }
+}
+
+/// How gists are mirrored into a target.
+#[derive(Clone)]
+pub struct MirrorOptions {
+    /// Number of gists uploaded at the same time
+    pub concurrency: usize,
+    /// Whether binary files are left out and secret gists encrypted to a recipient
+    pub write: WriteOptions,
+    /// What to do with files edited in the target that would be replaced, the gist being
+    /// left as it is when not set
+    pub resolve: Option<Resolution>,
+    /// Caps the files held in memory by concurrent uploads
+    pub memory_budget: Option<MemoryBudget>,
+}
+
+/// What became of the files of an uploaded gist.
+struct Uploaded {
+    /// Digest and license of each uploaded or kept file
+    inspected: HashMap<String, Inspection>,
+    /// Binary files, flagged in the manifest
+    binary: Vec<String>,
+    /// Binary files left out by `--skip-binary`
+    skipped: Vec<String>,
+}
+
+/// Returns the files of a gist edited in the target since they were uploaded, their content
+/// no longer matching the checksum of the manifest, that an upload would write again.
+fn edited_files(remote: &Remote, gist: &Gist, entry: &ManifestEntry) -> Vec<String> {
+    entry
+        .files
+        .iter()
+        .filter(|file| gist.files.contains_key(&file.filename))
+        .filter(|file| {
+            let Some(sha256) = &file.sha256 else {
+                return false;
+            };
+            store::digest_stored(remote, &entry.id, &file.filename)
+                .is_ok_and(|current| current.is_some_and(|current| current != *sha256))
+        })
+        .map(|file| file.filename.clone())
+        .collect()
+}
+
+/// Writes the fetched files of a gist into the target through the store: under their
+/// portable names, encrypted when the gist is secret and a recipient is given, and moving
+/// the content they replace to the trash of the target. Files edited in the target are
+/// handled as `resolve` says; without it the gist is left as it is, returning `None`.
+fn write_gist(
+    remote: &Remote,
+    gist: &Gist,
+    files: Vec<(String, FetchedFile)>,
+    entry: Option<&ManifestEntry>,
+    options: &MirrorOptions,
+) -> Result<Option<Uploaded>, GistError> {
+    let edited = entry.map_or_else(Vec::new, |entry| edited_files(remote, gist, entry));
+    let kept = match options.resolve {
+        _ if edited.is_empty() => Vec::new(),
+        Some(Resolution::Local) => edited,
+        Some(Resolution::Remote) => Vec::new(),
+        Some(Resolution::Skip) | None => {
+            warn!(
+                "Leaving gist {} as it is, files edited in {} would be replaced: {} (pass --resolve to replace or keep them)",
+                gist.id,
+                remote.target,
+                edited.join(", ")
+            );
+            return Ok(None);
+        }
+    };
+
+    // Uploads are spooled like downloads, as writes take their content from a spool
+    let scratch = std::env::temp_dir().join("local-gist");
+    let mut uploaded = Uploaded {
+        inspected: HashMap::new(),
+        binary: Vec::new(),
+        skipped: Vec::new(),
+    };
+    for (filename, fetched) in files {
+        if fetched.binary && options.write.skip_binary {
+            debug!("Skipping binary file {}/{}", gist.id, filename);
+            uploaded.skipped.push(filename);
+            continue;
+        }
+        if fetched.binary {
+            uploaded.binary.push(filename.clone());
+        }
+        if kept.contains(&filename) {
+            debug!(
+                "Keeping {}/{} edited in {}",
+                gist.id, filename, remote.target
+            );
+            let recorded = entry
+                .and_then(|entry| entry.files.iter().find(|file| file.filename == filename))
+                .and_then(|file| {
+                    Some(Inspection {
+                        sha256: file.sha256.clone()?,
+                        license: file.license.clone(),
+                    })
+                });
+            if let Some(recorded) = recorded {
+                uploaded.inspected.insert(filename, recorded);
+            }
+            continue;
+        }
+        let inspection = Inspection::of(&filename, &fetched.content);
+        let content = spool::from_bytes(&scratch, &fetched.content)?;
+        match &options.write.encrypt {
+            Some(recipient) if !gist.public => {
+                store::write_encrypted(remote, &gist.id, &filename, &content, recipient)?
+            }
+            _ => store::write_plain(remote, &gist.id, &filename, &content)?,
+        }
+        uploaded.inspected.insert(filename, inspection);
+    }
+    Ok(Some(uploaded))
+}
+
+/// Fetches the files of a gist and writes them into the target, returning `None` when the
+/// gist was left as it is for its edited files.
+async fn upload_gist(
+    client: &Client,
+    target: &Arc<Target>,
+    gist: Arc<Gist>,
+    entry: Option<ManifestEntry>,
+    options: MirrorOptions,
+) -> Result<Option<Uploaded>, GistError> {
+    let files = fetch_files(client, &gist).await?;
+    let remote = Remote {
+        target: Arc::clone(target),
+        runtime: Handle::current(),
+    };
+    tokio::task::spawn_blocking(move || write_gist(&remote, &gist, files, entry.as_ref(), &options))
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// Reads the manifest stored in the target, an empty one when there is none yet.
+pub async fn load_manifest(target: &Target) -> Result<Manifest, GistError> {
+    match target.get(MANIFEST_FILE).await? {
+        Some(bytes) => serde_json::from_slice(&bytes).map_err(GistError::Manifest),
+        None => Ok(Manifest::default()),
+    }
+}
+
+/// Mirrors gists into the target. Gists whose update time and files match the manifest
+/// stored in the target are skipped, the others are uploaded, then the manifest is updated,
+/// also when uploads failed.
+///
+/// # Arguments
+/// * `client` - Client the gists are fetched from GitHub with
+/// * `target` - Where to mirror the gists
+/// * `manifest` - The manifest stored in the target, as read by `load_manifest`
+/// * `gists` - The gists to mirror
+/// * `options` - How the gists are uploaded
+///
+/// Returns the number of uploaded gists, or `GistError::Partial` when some failed.
+pub async fn mirror(
+    client: &Client,
+    target: Arc<Target>,
+    mut manifest: Manifest,
+    gists: Vec<Gist>,
+    options: &MirrorOptions,
+) -> Result<usize, GistError> {
+    let semaphore = Arc::new(Semaphore::new(options.concurrency));
+    let mut upload_set = JoinSet::new();
+    let mut skipped = 0;
+
+    for gist in gists {
+        if is_unchanged(&manifest, &gist) {
+            debug!("Skipping unchanged gist {}", gist.id);
+            skipped += 1;
+            continue;
+        }
+        let sem = Arc::clone(&semaphore);
+        let target = Arc::clone(&target);
+        let client = client.clone();
+        let entry = manifest.gists.get(&gist.id).cloned();
+        let options = options.clone();
+
+        let gist = Arc::new(gist);
+
+        upload_set.spawn(async move {
+            let _permit = sem.acquire().await;
+            // The files of a gist are held in memory until they are uploaded
+            let _memory = match &options.memory_budget {
+                Some(budget) => {
+                    let size = gist.files.values().map(|f| f.size as u64).sum();
+                    budget.reserve(size).await
+                }
+                None => None,
+            };
+            let result = upload_gist(&client, &target, Arc::clone(&gist), entry, options).await;
+            (gist, result)
+        });
+    }
+
+    let (mut uploaded, mut edited, mut failed) = (0, 0, 0);
+    while let Some(res) = upload_set.join_next().await {
+        let (gist, result) = res.map_err(std::io::Error::other)?;
+        match result {
+            Ok(Some(files)) => {
+                info!("Uploaded gist {} to {}", gist.id, target);
+                let entry = manifest.insert(&gist);
+                entry
+                    .files
+                    .retain(|file| !files.skipped.contains(&file.filename));
+                for file in &mut entry.files {
+                    file.binary = files.binary.contains(&file.filename);
+                }
+                entry.inspect_with(|filename| files.inspected.get(filename).cloned());
+                uploaded += 1;
+            }
+            Ok(None) => edited += 1,
+            Err(e) => {
+                error!("Failed to upload gist {}: {}", gist.id, e);
+                failed += 1;
+            }
+        }
+    }
+
+    target
+        .put(MANIFEST_FILE, manifest.to_json()?.into_bytes())
+        .await?;
+    info!(
+        "Mirrored to {}: {} gists uploaded, {} unchanged, {} left with edited files, {} failed",
+        target, uploaded, skipped, edited, failed
+    );
+
+    if failed > 0 {
+        return Err(GistError::Partial(format!("{} uploads failed", failed)));
+    }
+    Ok(uploaded)
+}
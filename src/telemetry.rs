@@ -0,0 +1,98 @@
+use crate::gist::GistError;
+#[cfg(feature = "tracing")]
+use crate::progress::LogWriter;
+#[cfg(feature = "tracing")]
+use tracing::level_filters::LevelFilter;
+#[cfg(feature = "tracing")]
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+
+// Name spans are reported under
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "local-gist";
+
+/// Keeps the span exporter alive, flushing the spans left when dropped at the end of the run.
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Could not export the remaining spans: {}", e);
+            }
+        }
+    }
+}
+
+/// Builds the OTLP exporter when an endpoint is configured through the standard
+/// `OTEL_EXPORTER_OTLP_ENDPOINT` or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` variables.
+#[cfg(feature = "otel")]
+fn otlp_provider() -> Result<Option<opentelemetry_sdk::trace::SdkTracerProvider>, GistError> {
+    let configured = [
+        "OTEL_EXPORTER_OTLP_ENDPOINT",
+        "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+    ]
+    .iter()
+    .any(|name| std::env::var_os(name).is_some_and(|value| !value.is_empty()));
+    if !configured {
+        return Ok(None);
+    }
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .build()?;
+    let resource = opentelemetry_sdk::Resource::builder()
+        .with_service_name(SERVICE_NAME)
+        .build();
+    Ok(Some(
+        opentelemetry_sdk::trace::SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build(),
+    ))
+}
+
+/// Sets up logging to stderr, unless `logs` is false. Built with the `otel` feature, spans
+/// are also exported over OTLP when an endpoint is configured, even without logs. Built
+/// without the `tracing` feature, there is nothing to set up.
+#[cfg(feature = "tracing")]
+pub fn init(logs: bool) -> Result<Telemetry, GistError> {
+    let max_level = match logs {
+        true => LevelFilter::INFO,
+        false => LevelFilter::OFF,
+    };
+    let fmt = tracing_subscriber::fmt::layer()
+        // .with_thread_ids(true)
+        .with_thread_names(true)
+        // Keep stdout free for machine readable output, and the progress bar readable
+        .with_writer(|| LogWriter)
+        .with_filter(max_level);
+    let registry = tracing_subscriber::registry().with(fmt);
+
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::TracerProvider;
+
+        let provider = otlp_provider()?;
+        let otel = provider.as_ref().map(|provider| {
+            tracing_opentelemetry::layer()
+                .with_tracer(provider.tracer(SERVICE_NAME))
+                .with_filter(LevelFilter::INFO)
+        });
+        registry.with(otel).init();
+        Ok(Telemetry { provider })
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        registry.init();
+        Ok(Telemetry {})
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn init(_logs: bool) -> Result<Telemetry, GistError> {
+    Ok(Telemetry {})
+}
@@ -0,0 +1,115 @@
+use crate::gist::GistError;
+use crate::log::debug;
+use crate::stats::format_bytes;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Serialize;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+// Directory inside the archive holding replaced and pruned content, a folder per run
+pub const TRASH_DIR: &str = ".trash";
+// Name of the folder of a run in the trash, the time the run first discarded something
+const RUN_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// What emptying the trash reclaimed.
+#[derive(Debug, Default, Serialize)]
+pub struct EmptyReport {
+    pub runs: usize,
+    pub bytes: u64,
+}
+
+impl EmptyReport {
+    pub fn print(&self) {
+        println!(
+            "Emptied {} runs from the trash, {} reclaimed",
+            self.runs,
+            format_bytes(self.bytes)
+        );
+    }
+}
+
+/// Returns the name of the folder of the current run in the trash, the same for the whole
+/// run.
+pub fn run_name() -> &'static str {
+    static RUN: OnceLock<String> = OnceLock::new();
+    RUN.get_or_init(|| Utc::now().format(RUN_FORMAT).to_string())
+}
+
+fn run_dir(folder: &Path) -> PathBuf {
+    folder.join(TRASH_DIR).join(run_name())
+}
+
+/// Moves a file or folder of the archive into the trash, at the same place relative to the
+/// folder, instead of deleting it. Does nothing when there is nothing at `path`.
+pub fn discard(folder: &Path, path: &Path) -> io::Result<()> {
+    if path.symlink_metadata().is_err() {
+        return Ok(());
+    }
+    let relative = path.strip_prefix(folder).unwrap_or(path);
+    let mut target = run_dir(folder).join(relative);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Replaced twice in the same run, e.g. by a retry
+    let mut copy = 1;
+    while target.symlink_metadata().is_ok() {
+        copy += 1;
+        let name = relative.file_name().unwrap_or_default().to_string_lossy();
+        target.set_file_name(format!("{}.{}", name, copy));
+    }
+    debug!("Moving {} to {}", path.display(), target.display());
+    std::fs::rename(path, target)
+}
+
+fn size_of(path: &Path) -> u64 {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => std::fs::read_dir(path)
+            .map(|entries| entries.flatten().map(|e| size_of(&e.path())).sum())
+            .unwrap_or_default(),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Deletes the runs in the trash of the archive for good, only those older than
+/// `older_than` when given.
+pub fn empty(folder: &Path, older_than: Option<Duration>) -> Result<EmptyReport, GistError> {
+    let mut report = EmptyReport::default();
+    let trash = folder.join(TRASH_DIR);
+    if !trash.exists() {
+        debug!("The trash of {} is empty", folder.display());
+        return Ok(report);
+    }
+    // An age too long to count back from keeps everything, rather than emptying it all
+    let cutoff = older_than.map(|age| {
+        chrono::Duration::from_std(age)
+            .ok()
+            .and_then(|age| Utc::now().checked_sub_signed(age))
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    });
+    for entry in std::fs::read_dir(&trash)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let discarded_at: Option<DateTime<Utc>> = NaiveDateTime::parse_from_str(&name, RUN_FORMAT)
+            .ok()
+            .map(|time| time.and_utc());
+        // Runs that can't be dated are only removed when emptying everything
+        let expired = match (cutoff, discarded_at) {
+            (None, _) => true,
+            (Some(cutoff), Some(discarded_at)) => discarded_at < cutoff,
+            (Some(_), None) => false,
+        };
+        if !expired {
+            continue;
+        }
+        report.bytes += size_of(&path);
+        report.runs += 1;
+        match path.is_dir() {
+            true => std::fs::remove_dir_all(&path)?,
+            false => std::fs::remove_file(&path)?,
+        }
+    }
+    Ok(report)
+}
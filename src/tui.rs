@@ -0,0 +1,447 @@
+use crate::browser;
+use crate::gist::{self, GistError, LocalCopy, WriteOptions};
+use crate::highlight;
+use crate::index::Index;
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::store;
+use arboard::Clipboard;
+use minisign::SecretKey;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use reqwest::Client;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+
+// Maximum number of search index hits looked at when filtering the list
+const SEARCH_LIMIT: u32 = 500;
+// Help shown in the status line when there is no message
+const HELP: &str = "/ search  tab next file  d download  o open  c copy  s star  x delete  q quit";
+
+/// The files of the selected gist, loaded once per selection.
+struct Preview {
+    id: String,
+    files: Vec<(String, Vec<u8>)>,
+}
+
+struct App {
+    client: Client,
+    folder: PathBuf,
+    // How downloads write into the archive
+    write: WriteOptions,
+    // Key of `--sign-manifest`, signing the manifest after each download
+    signing_key: Option<SecretKey>,
+    token: Option<String>,
+    manifest: Manifest,
+    index: Index,
+    query: String,
+    searching: bool,
+    // Ids of the gists matching the query, in list order
+    visible: Vec<String>,
+    list: ListState,
+    preview: Option<Preview>,
+    file: usize,
+    scroll: u16,
+    status: String,
+    confirm_delete: bool,
+    // Kept for the whole session, the clipboard content lives as long as its owner
+    clipboard: Option<Clipboard>,
+}
+
+impl App {
+    fn new(
+        client: &Client,
+        folder: &Path,
+        token: Option<String>,
+        follow_symlinks: bool,
+        signing_key: Option<SecretKey>,
+    ) -> Result<Self, GistError> {
+        let mut app = App {
+            client: client.clone(),
+            folder: folder.to_path_buf(),
+            write: WriteOptions {
+                follow_symlinks,
+                ..WriteOptions::default()
+            },
+            signing_key,
+            token,
+            manifest: Manifest::load(folder)?,
+            index: Index::open(folder)?,
+            query: String::new(),
+            searching: false,
+            visible: Vec::new(),
+            list: ListState::default(),
+            preview: None,
+            file: 0,
+            scroll: 0,
+            status: String::new(),
+            confirm_delete: false,
+            clipboard: None,
+        };
+        app.refilter();
+        Ok(app)
+    }
+
+    fn selected(&self) -> Option<&ManifestEntry> {
+        let id = self.visible.get(self.list.selected()?)?;
+        self.manifest.gists.get(id)
+    }
+
+    /// Recomputes the visible gists: those whose id, description or filenames contain the
+    /// query, plus those the search index finds it in, most recently updated first.
+    fn refilter(&mut self) {
+        let query = self.query.to_lowercase();
+        let mut content_hits: Vec<String> = Vec::new();
+        if !query.trim().is_empty() {
+            match self.index.search(&query, SEARCH_LIMIT) {
+                Ok(hits) => content_hits = hits.into_iter().map(|hit| hit.gist_id).collect(),
+                Err(e) => self.status = e.to_string(),
+            }
+        }
+        let mut visible: Vec<&ManifestEntry> = self
+            .manifest
+            .gists
+            .values()
+            .filter(|entry| {
+                query.is_empty()
+                    || entry.id.contains(&query)
+                    || entry
+                        .description
+                        .as_deref()
+                        .is_some_and(|d| d.to_lowercase().contains(&query))
+                    || entry
+                        .files
+                        .iter()
+                        .any(|f| f.filename.to_lowercase().contains(&query))
+                    || content_hits.contains(&entry.id)
+            })
+            .collect();
+        visible.sort_by(|a, b| b.updated_at.cmp(&a.updated_at));
+        self.visible = visible.into_iter().map(|entry| entry.id.clone()).collect();
+        self.list.select((!self.visible.is_empty()).then_some(0));
+        self.select_changed();
+    }
+
+    fn select_changed(&mut self) {
+        self.file = 0;
+        self.scroll = 0;
+        self.confirm_delete = false;
+    }
+
+    fn move_selection(&mut self, down: bool) {
+        if self.visible.is_empty() {
+            return;
+        }
+        let last = self.visible.len() - 1;
+        let current = self.list.selected().unwrap_or(0);
+        let next = match down {
+            true => (current + 1).min(last),
+            false => current.saturating_sub(1),
+        };
+        self.list.select(Some(next));
+        self.select_changed();
+    }
+
+    /// Loads the files of the selected gist unless they already are.
+    fn load_preview(&mut self) {
+        let Some(entry) = self.selected() else {
+            self.preview = None;
+            return;
+        };
+        if self.preview.as_ref().is_some_and(|p| p.id == entry.id) {
+            return;
+        }
+        let id = entry.id.clone();
+        let files = match store::read_gist_files(&self.folder, entry) {
+            Ok(files) => files,
+            Err(e) => {
+                self.status = format!("Can't read {}: {}", id, e);
+                Vec::new()
+            }
+        };
+        self.preview = Some(Preview { id, files });
+    }
+
+    fn current_file(&self) -> Option<&(String, Vec<u8>)> {
+        self.preview.as_ref()?.files.get(self.file)
+    }
+
+    fn token(&self) -> Result<&str, GistError> {
+        self.token
+            .as_deref()
+            .ok_or_else(|| GistError::NotFound("GitHub token, pass --github-token".into()))
+    }
+
+    /// Downloads the selected gist again, updating the archive, manifest and index.
+    async fn download(&mut self, id: &str) -> Result<String, GistError> {
+        let gist = gist::get_gist(&self.client, id).await?;
+        let folder = self.folder.to_string_lossy().into_owned();
+        let outcome = gist::download_gist(
+            &self.client,
+            &gist,
+            &folder,
+            &self.write,
+            &LocalCopy::default(),
+            &CancellationToken::new(),
+        )
+        .await?;
+        let entry = self.manifest.insert(&gist);
+        entry.record_outcome(&outcome);
+        entry.inspect_files(&self.folder);
+        self.manifest
+            .save(&self.folder, self.signing_key.as_ref())?;
+        self.index
+            .index_gist(&self.folder, &self.manifest.gists[id])?;
+        self.preview = None;
+        match outcome.failed.len() {
+            0 => Ok(format!("Downloaded {}", id)),
+            count => Ok(format!("Downloaded {}, {} files failed", id, count)),
+        }
+    }
+
+    fn copy(&mut self) -> Result<String, GistError> {
+        let Some((filename, content)) = self.current_file().cloned() else {
+            return Ok("Nothing to copy".into());
+        };
+        if self.clipboard.is_none() {
+            self.clipboard = Some(Clipboard::new()?);
+        }
+        let clipboard = self.clipboard.as_mut().expect("clipboard was just set");
+        clipboard.set_text(String::from_utf8_lossy(&content))?;
+        Ok(format!("Copied {}", filename))
+    }
+
+    /// Handles a key outside of the search field. Returns false to quit.
+    async fn on_key(&mut self, key: KeyCode) -> bool {
+        let id = self.selected().map(|entry| entry.id.clone());
+        if self.confirm_delete {
+            self.confirm_delete = false;
+            self.status = match (key, &id) {
+                (KeyCode::Char('y'), Some(id)) => match self.token() {
+                    Ok(token) => match gist::delete_gist(&self.client, token, id).await {
+                        Ok(()) => format!("Deleted {} on GitHub, the archived copy is kept", id),
+                        Err(e) => e.to_string(),
+                    },
+                    Err(e) => e.to_string(),
+                },
+                _ => "Not deleted".into(),
+            };
+            return true;
+        }
+
+        let result = match (key, &id) {
+            (KeyCode::Char('q'), _) => return false,
+            (KeyCode::Esc, _) if self.query.is_empty() => return false,
+            (KeyCode::Esc, _) => {
+                self.query.clear();
+                self.refilter();
+                Ok(String::new())
+            }
+            (KeyCode::Char('/'), _) => {
+                self.searching = true;
+                Ok(String::new())
+            }
+            (KeyCode::Down | KeyCode::Char('j'), _) => {
+                self.move_selection(true);
+                Ok(String::new())
+            }
+            (KeyCode::Up | KeyCode::Char('k'), _) => {
+                self.move_selection(false);
+                Ok(String::new())
+            }
+            (KeyCode::PageDown, _) => {
+                self.scroll = self.scroll.saturating_add(20);
+                Ok(String::new())
+            }
+            (KeyCode::PageUp, _) => {
+                self.scroll = self.scroll.saturating_sub(20);
+                Ok(String::new())
+            }
+            (KeyCode::Tab, _) => {
+                let count = self.preview.as_ref().map_or(0, |p| p.files.len());
+                if count > 0 {
+                    self.file = (self.file + 1) % count;
+                    self.scroll = 0;
+                }
+                Ok(String::new())
+            }
+            (KeyCode::Char('d'), Some(id)) => self.download(id).await,
+            (KeyCode::Char('o'), Some(id)) => {
+                let url = self.manifest.gists[id].html_url.clone();
+                browser::open_url(&url).map(|()| format!("Opened {}", url))
+            }
+            (KeyCode::Char('c'), Some(_)) => self.copy(),
+            (KeyCode::Char('s'), Some(id)) => match self.token() {
+                Ok(token) => gist::star_gist(&self.client, token, id)
+                    .await
+                    .map(|()| format!("Starred {}", id)),
+                Err(e) => Err(e),
+            },
+            (KeyCode::Char('x'), Some(id)) => {
+                self.confirm_delete = true;
+                Ok(format!("Delete {} on GitHub? y/n", id))
+            }
+            _ => return true,
+        };
+        self.status = result.unwrap_or_else(|e| e.to_string());
+        true
+    }
+
+    fn on_search_key(&mut self, key: KeyCode) {
+        match key {
+            KeyCode::Enter => self.searching = false,
+            KeyCode::Esc => {
+                self.searching = false;
+                self.query.clear();
+                self.refilter();
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.refilter();
+            }
+            KeyCode::Char(c) => {
+                self.query.push(c);
+                self.refilter();
+            }
+            _ => {}
+        }
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [left, right] =
+            Layout::horizontal([Constraint::Percentage(35), Constraint::Percentage(65)])
+                .areas(main);
+
+        let items: Vec<ListItem> = self
+            .visible
+            .iter()
+            .map(|id| {
+                let entry = &self.manifest.gists[id];
+                let description = entry.description.as_deref().unwrap_or_default();
+                let title = match description {
+                    "" => entry.files.first().map_or("", |f| f.filename.as_str()),
+                    description => description,
+                };
+                ListItem::new(Line::from(vec![
+                    Span::styled(
+                        format!("{:.8} ", entry.id),
+                        Style::new().fg(Color::DarkGray),
+                    ),
+                    Span::raw(title.to_string()),
+                ]))
+            })
+            .collect();
+        let search = match (self.searching, self.query.is_empty()) {
+            (false, true) => format!(" {} gists ", self.visible.len()),
+            _ => format!(" /{} ({}) ", self.query, self.visible.len()),
+        };
+        let list = List::new(items)
+            .block(Block::default().borders(Borders::ALL).title(search))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, left, &mut self.list);
+
+        let count = self.preview.as_ref().map_or(0, |p| p.files.len());
+        let (title, lines) = match self.current_file() {
+            Some((filename, content)) => {
+                let title = format!(" {} ({}/{}) ", filename, self.file + 1, count);
+                (title, preview_lines(filename, content))
+            }
+            None => (String::new(), Vec::new()),
+        };
+        let preview = Paragraph::new(lines)
+            .block(Block::default().borders(Borders::ALL).title(title))
+            .scroll((self.scroll, 0));
+        frame.render_widget(preview, right);
+
+        let status_text = match self.status.as_str() {
+            "" => HELP,
+            status => status,
+        };
+        frame.render_widget(
+            Paragraph::new(status_text).style(Style::new().fg(Color::DarkGray)),
+            status,
+        );
+    }
+}
+
+/// Highlights a file for the preview pane, with line numbers.
+fn preview_lines(filename: &str, content: &[u8]) -> Vec<Line<'static>> {
+    let Ok(code) = std::str::from_utf8(content) else {
+        return vec![Line::from(format!("(binary, {} bytes)", content.len()))];
+    };
+    let lines = highlight::to_colored_lines(filename, code);
+    let width = lines.len().to_string().len();
+    lines
+        .into_iter()
+        .enumerate()
+        .map(|(number, pieces)| {
+            let gutter = Span::styled(
+                format!("{:>width$} │ ", number + 1),
+                Style::new().fg(Color::DarkGray),
+            );
+            let pieces = pieces.into_iter().map(|((r, g, b), text)| {
+                Span::styled(
+                    text.replace('\t', "    "),
+                    Style::new().fg(Color::Rgb(r, g, b)),
+                )
+            });
+            Line::from(std::iter::once(gutter).chain(pieces).collect::<Vec<_>>())
+        })
+        .collect()
+}
+
+async fn run_app(terminal: &mut DefaultTerminal, app: &mut App) -> Result<(), GistError> {
+    loop {
+        app.load_preview();
+        terminal.draw(|frame| app.draw(frame))?;
+        if !event::poll(Duration::from_millis(250))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        if app.searching {
+            app.on_search_key(key.code);
+        } else {
+            if !app.confirm_delete {
+                app.status.clear();
+            }
+            if !app.on_key(key.code).await {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Runs an interactive browser over the archive: a searchable list of the gists next to a
+/// highlighted preview of their files, with keys to download a gist again, open it in the
+/// browser, copy a file, and star or delete the gist on GitHub.
+///
+/// # Arguments
+/// * `client` - Client the requests to GitHub are sent with
+/// * `folder` - Directory containing downloaded gists
+/// * `token` - GitHub token, needed to star and delete gists
+/// * `follow_symlinks` - Write through symlinks inside the folder when downloading a gist
+/// * `signing_key` - Key the manifest is signed with, when it is
+pub async fn tui(
+    client: &Client,
+    folder: &Path,
+    token: Option<String>,
+    follow_symlinks: bool,
+    signing_key: Option<SecretKey>,
+) -> Result<(), GistError> {
+    let mut app = App::new(client, folder, token, follow_symlinks, signing_key)?;
+    let mut terminal = ratatui::init();
+    let result = run_app(&mut terminal, &mut app).await;
+    ratatui::restore();
+    result
+}
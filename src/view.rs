@@ -0,0 +1,121 @@
+use crate::gist::GistError;
+use crate::highlight;
+use crate::index;
+use crate::log::debug;
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::store;
+use std::fmt::Write as _;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Pager used when $PAGER isn't set, -R lets the color escapes through
+const DEFAULT_PAGER: &str = "less -R";
+// Escapes used for the parts of the output that aren't file content
+const DIM: &str = "\x1b[2m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders a gist as a metadata header followed by its files with line numbers, colored
+/// when `color` is set.
+fn render(folder: &Path, entry: &ManifestEntry, color: bool) -> Result<String, GistError> {
+    let (bold, dim, reset) = match color {
+        true => (BOLD, DIM, RESET),
+        false => ("", "", ""),
+    };
+    let mut out = String::new();
+    let description = entry.description.as_deref().unwrap_or_default();
+    let visibility = if entry.public { "public" } else { "secret" };
+    let _ = writeln!(out, "{}{}{} {}", bold, entry.id, reset, description);
+    let _ = writeln!(
+        out,
+        "{}owner:{}   {} ({})",
+        dim, reset, entry.owner, visibility
+    );
+    let _ = writeln!(out, "{}created:{} {}", dim, reset, entry.created_at);
+    let _ = writeln!(out, "{}updated:{} {}", dim, reset, entry.updated_at);
+    let _ = writeln!(out, "{}url:{}     {}", dim, reset, entry.html_url);
+
+    for (filename, content) in store::read_gist_files(folder, entry)? {
+        let _ = writeln!(out);
+        let Ok(code) = std::str::from_utf8(&content) else {
+            let _ = writeln!(
+                out,
+                "{}── {}{} (binary, {} bytes)",
+                dim,
+                filename,
+                reset,
+                content.len()
+            );
+            continue;
+        };
+        let language = highlight::language(&filename, code).unwrap_or_else(|| "text".into());
+        let _ = writeln!(
+            out,
+            "{}── {}{}{} {}({}){}",
+            dim, reset, bold, filename, dim, language, reset
+        );
+        let lines = match color {
+            true => highlight::to_terminal(&filename, code),
+            false => code.lines().map(str::to_string).collect(),
+        };
+        let width = lines.len().to_string().len();
+        for (number, line) in lines.iter().enumerate() {
+            let _ = writeln!(out, "{}{:>width$} │{} {}", dim, number + 1, reset, line);
+        }
+    }
+    Ok(out)
+}
+
+/// Writes the output through `$PAGER`, or straight to stdout when the pager can't start.
+fn page(output: &str) -> Result<(), GistError> {
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|pager| !pager.is_empty())
+        .unwrap_or_else(|| DEFAULT_PAGER.to_string());
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(&pager)
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            debug!("Pager `{}` could not be started: {}", pager, e);
+            std::io::stdout().write_all(output.as_bytes())?;
+            return Ok(());
+        }
+    };
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Quitting the pager before the end closes the pipe, that's not an error
+    if let Err(e) = stdin.write_all(output.as_bytes()) {
+        debug!("Pager stopped reading: {}", e);
+    }
+    drop(stdin);
+    child.wait()?;
+    Ok(())
+}
+
+/// Shows a downloaded gist with a metadata header, line numbers and syntax highlighting.
+/// In a terminal the output is colored and goes through `$PAGER` (`less -R` by default);
+/// otherwise plain text is written to stdout. `NO_COLOR` turns the colors off.
+///
+/// # Arguments
+/// * `folder` - Directory containing downloaded gists
+/// * `query` - Id, id prefix, part of the description or search terms of the gist
+/// * `no_pager` - Write to stdout even in a terminal
+pub fn view(folder: &Path, query: &str, no_pager: bool) -> Result<(), GistError> {
+    let manifest = Manifest::load(folder)?;
+    let entry = index::resolve_gist(folder, &manifest, query)?;
+
+    let terminal = std::io::stdout().is_terminal();
+    let color = terminal && std::env::var_os("NO_COLOR").is_none();
+    let output = render(folder, entry, color)?;
+
+    if terminal && !no_pager {
+        page(&output)
+    } else {
+        std::io::stdout().write_all(output.as_bytes())?;
+        Ok(())
+    }
+}
@@ -0,0 +1,182 @@
+use crate::gist::GistError;
+use crate::log::warn;
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode, Url};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Attempts made for every request before giving up
+const ATTEMPTS: u32 = 3;
+// Delay before the first retry, doubled for every following one
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// A collection on a WebDAV server such as Nextcloud or ownCloud.
+pub struct WebDavTarget {
+    client: Client,
+    /// URL of the collection holding the archive, always ending with `/`
+    base: Url,
+    username: Option<String>,
+    password: Option<String>,
+    /// Collections known to exist, so every upload doesn't recreate its parents
+    collections: Mutex<HashSet<String>>,
+}
+
+/// Percent-encodes a path segment.
+fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+impl WebDavTarget {
+    /// Creates a target for an `http(s)` collection URL. Credentials are taken from the
+    /// URL, or from the `WEBDAV_USERNAME` and `WEBDAV_PASSWORD` environment variables.
+    pub fn new(client: &Client, url: &str) -> Result<Self, GistError> {
+        let mut base = Url::parse(url).map_err(|e| GistError::Target(e.to_string()))?;
+        let username = match base.username() {
+            "" => std::env::var("WEBDAV_USERNAME").ok(),
+            username => Some(username.to_string()),
+        };
+        let password = base
+            .password()
+            .map(str::to_string)
+            .or_else(|| std::env::var("WEBDAV_PASSWORD").ok());
+        // Credentials are sent as basic auth, never as part of the logged URL
+        let _ = base.set_username("");
+        let _ = base.set_password(None);
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+
+        Ok(WebDavTarget {
+            client: client.clone(),
+            base,
+            username,
+            password,
+            collections: Mutex::new(HashSet::new()),
+        })
+    }
+
+    fn url(&self, path: &str) -> Result<Url, GistError> {
+        let path = path
+            .split('/')
+            .map(encode_segment)
+            .collect::<Vec<_>>()
+            .join("/");
+        self.base
+            .join(&path)
+            .map_err(|e| GistError::Target(e.to_string()))
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match &self.username {
+            Some(username) => request.basic_auth(username, self.password.as_ref()),
+            None => request,
+        }
+    }
+
+    /// Sends a request, retrying with exponential backoff on network and server errors.
+    async fn send(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Response, GistError> {
+        let url = self.url(path)?;
+        let mut delay = RETRY_DELAY;
+        for attempt in 1..=ATTEMPTS {
+            let mut request = self.request(method.clone(), url.clone());
+            if let Some(body) = &body {
+                request = request.body(body.clone());
+            }
+            match request.send().await {
+                Ok(response) if !response.status().is_server_error() || attempt == ATTEMPTS => {
+                    return Ok(response)
+                }
+                Ok(response) => warn!(
+                    "{} {} failed with {}, retrying in {:?}",
+                    method,
+                    path,
+                    response.status(),
+                    delay
+                ),
+                Err(e) if attempt == ATTEMPTS => return Err(e.into()),
+                Err(e) => warn!("{} {} failed: {}, retrying in {:?}", method, path, e, delay),
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+        unreachable!("the last attempt always returns")
+    }
+
+    /// Creates the collections leading to `path`, ignoring those that already exist.
+    async fn make_collections(&self, path: &str) -> Result<(), GistError> {
+        let mkcol = Method::from_bytes(b"MKCOL").expect("MKCOL is a valid method");
+        let mut collection = String::new();
+        // Start from the base collection itself, which may not exist yet either
+        let segments = path.split('/').filter(|s| !s.is_empty());
+        for segment in std::iter::once("").chain(segments) {
+            if !segment.is_empty() {
+                collection.push_str(segment);
+                collection.push('/');
+            }
+            if self.collections.lock().unwrap().contains(&collection) {
+                continue;
+            }
+            let response = self.send(mkcol.clone(), &collection, None).await?;
+            // 405 Method Not Allowed is the answer for an existing collection
+            if !response.status().is_success()
+                && response.status() != StatusCode::METHOD_NOT_ALLOWED
+            {
+                return Err(GistError::Target(format!(
+                    "MKCOL {} failed with {}",
+                    collection,
+                    response.status()
+                )));
+            }
+            self.collections.lock().unwrap().insert(collection.clone());
+        }
+        Ok(())
+    }
+
+    /// Downloads a file, returning `None` when it doesn't exist.
+    pub async fn get(&self, path: &str) -> Result<Option<Vec<u8>>, GistError> {
+        let response = self.send(Method::GET, path, None).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.bytes().await?.to_vec())),
+            status => Err(GistError::Target(format!(
+                "GET {} failed with {}",
+                path, status
+            ))),
+        }
+    }
+
+    /// Uploads a file, creating its parent collections first.
+    pub async fn put(&self, path: &str, content: Vec<u8>) -> Result<(), GistError> {
+        let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        self.make_collections(parent).await?;
+        let response = self.send(Method::PUT, path, Some(content)).await?;
+        if !response.status().is_success() {
+            return Err(GistError::Target(format!(
+                "PUT {} failed with {}",
+                path,
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for WebDavTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.base)
+    }
+}